@@ -0,0 +1,17 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate ext4;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 60 {
+        return;
+    }
+    let mut core = [0u8; 60];
+    core.copy_from_slice(&data[..60]);
+    let rest = data[60..].to_vec();
+    let _ = ext4::parse::extent_tree(
+        core,
+        |_| Ok(rest.clone()), // every further level is the rest of the input
+        None, // no checksums
+        );
+});
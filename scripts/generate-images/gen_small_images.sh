@@ -0,0 +1,251 @@
+#!/bin/sh
+# Images which can be built without root, using only e2fsprogs.
+set -eu
+
+T=$(mktemp -d)
+trap 'rm -r "$T"' EXIT
+
+# A journal with three committed, unreplayed transactions:
+#  1. block 3000 := 'A' * 1024
+#  2. block 3001 := 'B' * 1024, block 3000 := 'C' * 1024
+#  3. revoke block 3001
+python3 -c "open('$T/a', 'wb').write(b'A' * 1024)"
+python3 -c "open('$T/bc', 'wb').write(b'B' * 1024 + b'C' * 1024)"
+rm -f journal.img
+mkfs.ext4 -q -F -b 1024 -O has_journal,metadata_csum -U 6a6f7572-6e61-4c00-8000-000000000000 \
+  -E hash_seed=6a6f7572-6e61-4c00-8000-000000000001 journal.img 4096
+for cmd in "jw -b 3000 $T/a" "jw -b 3001,3000 $T/bc" "jw -r 3001"; do
+  printf 'jo -c\n%s\njc\n' "$cmd" | debugfs -w journal.img
+done
+
+# Symlinks, for exercising path resolution:
+#  /a/b/file, /a/up -> .., /a/abs -> /a/b, /a/rel -> b/file, /a/chain -> rel,
+#  /a/loop -> loop, /top -> a/abs, and /a/long -> a target too long to fit in the inode
+mkdir -p "$T/links/a/b"
+echo hello > "$T/links/a/b/file"
+ln -s .. "$T/links/a/up"
+ln -s /a/b "$T/links/a/abs"
+ln -s b/file "$T/links/a/rel"
+ln -s rel "$T/links/a/chain"
+ln -s loop "$T/links/a/loop"
+ln -s a/abs "$T/links/top"
+ln -s ./././././././././././././././././././././././././././././././b/file "$T/links/a/long"
+rm -f links.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal -U 6c696e6b-7300-4000-8000-000000000000 \
+  -E hash_seed=6c696e6b-7300-4000-8000-000000000001 -d "$T/links" links.img 1024
+
+# Deleted files:
+#  <12> deleted.txt, 'D' * 3000, killed without discarding its extents
+#  <13> journalled.txt, 'J' * 5000, deleted as the kernel would, but its inode table block
+#       is in an (already checkpointed) journal transaction from before the deletion
+#  <14> kept.txt, 'K' * 10
+mkdir -p "$T/deleted"
+python3 -c "open('$T/deleted/deleted.txt', 'wb').write(b'D' * 3000)"
+python3 -c "open('$T/deleted/journalled.txt', 'wb').write(b'J' * 5000)"
+python3 -c "open('$T/deleted/kept.txt', 'wb').write(b'K' * 10)"
+touch -d @1500000000 "$T/deleted"/* "$T/deleted"
+rm -f deleted.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O has_journal,metadata_csum \
+  -U 64656c65-7465-4400-8000-000000000000 -E hash_seed=64656c65-7465-4400-8000-000000000001 \
+  -d "$T/deleted" deleted.img 4096
+ITABLE=$(debugfs -R 'imap <13>' deleted.img 2>/dev/null | sed -n 's/.*located at block \([0-9]*\),.*/\1/p')
+dd if=deleted.img of="$T/itable" bs=1024 skip="$ITABLE" count=1 status=none
+printf 'jo\njw -b %s %s\njc\n' "$ITABLE" "$T/itable" | debugfs -w deleted.img
+e2fsck -fy deleted.img
+printf '%s\n' 'kill_file <12>' 'unlink /deleted.txt' 'sif <12> links_count 0' \
+  'kill_file <13>' 'unlink /journalled.txt' 'sif <13> links_count 0' 'sif <13> size 0' \
+  'sif <13> blocks 0' 'sif <13> block[0] 0xf30a' | E2FSPROGS_FAKE_TIME=1500000000 debugfs -w deleted.img
+
+# Distribution metadata, in the usual places:
+#  /etc/os-release -> ../usr/lib/os-release, /etc/passwd, /etc/group,
+#  /var/lib/dpkg/status (one installed package, one removed), /var/lib/rpm -> ../../usr/lib/sysimage/rpm
+mkdir -p "$T/distro/etc" "$T/distro/usr/lib/sysimage/rpm" "$T/distro/var/lib/dpkg"
+cat > "$T/distro/usr/lib/os-release" <<'END'
+# comments are allowed
+PRETTY_NAME="Debian GNU/Linux 12 (bookworm)"
+NAME="Debian GNU/Linux"
+VERSION_ID="12"
+VERSION_CODENAME=bookworm
+ID=debian
+ID_LIKE='ubuntu mint'
+HOME_URL="https://www.debian.org/ \"quoted\""
+END
+ln -s ../usr/lib/os-release "$T/distro/etc/os-release"
+printf '%s\n' 'root:x:0:0:root:/root:/bin/bash' 'bad line' \
+  'faux:x:1000:1000:Chris West,,,:/home/faux:/bin/zsh' > "$T/distro/etc/passwd"
+printf '%s\n' 'root:x:0:' 'sudo:x:27:faux,other' 'faux:x:1000:' > "$T/distro/etc/group"
+printf '%s\n' 'Package: libc6' 'Status: install ok installed' 'Architecture: amd64' \
+  'Source: glibc (2.36-9)' 'Version: 2.36-9' 'Description: GNU C Library' ' continued' '' \
+  'Package: gone' 'Status: deinstall ok config-files' 'Version: 1.0' > "$T/distro/var/lib/dpkg/status"
+python3 -c "open('$T/distro/usr/lib/sysimage/rpm/rpmdb.sqlite', 'wb').write(b'S' * 4096)"
+ln -s ../../usr/lib/sysimage/rpm "$T/distro/var/lib/rpm"
+rm -f distro.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal -U 64697374-726f-4000-8000-000000000000 \
+  -E hash_seed=64697374-726f-4000-8000-000000000001 -d "$T/distro" distro.img 1024
+
+# fscrypt, faked by flagging plaintext as encrypted, as making the real thing needs a kernel:
+#  /plain/ok, 'ok\n', and /secret, which is "encrypted", as is its /secret/file, 'hidden\n'
+mkdir -p "$T/encrypted/plain" "$T/encrypted/secret"
+echo ok > "$T/encrypted/plain/ok"
+echo hidden > "$T/encrypted/secret/file"
+rm -f encrypted.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal,encrypt -U 656e6372-7970-4000-8000-000000000000 \
+  -E hash_seed=656e6372-7970-4000-8000-000000000001 -d "$T/encrypted" encrypted.img 1024
+printf '%s\n' 'sif /secret/file flags 0x80800' 'sif /secret flags 0x80800' | debugfs -w encrypted.img
+
+# Small block groups, so there are backup superblocks, in groups 1 and 3, to scan for
+rm -f scan.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -g 1024 -O ^has_journal -L scan -U 7363616e-0000-4000-8000-000000000000 \
+  -E hash_seed=7363616e-0000-4000-8000-000000000001 scan.img 4096
+
+# A file in /lost+found, whose old name is still in the journal:
+#  /dir/precious.txt, 'P' * 100, with /dir's block journalled, then /dir wiped, so
+#  e2fsck reconnects it as /lost+found/#13; /dir's block, then /lost+found's, naming
+#  it #13, are journalled again after
+mkdir -p "$T/lost/dir"
+python3 -c "open('$T/lost/dir/precious.txt', 'wb').write(b'P' * 100)"
+touch -d @1500000000 "$T/lost/dir"/* "$T/lost/dir" "$T/lost"
+rm -f lost.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O has_journal,metadata_csum -U 6c6f7374-0000-4000-8000-000000000000 \
+  -E hash_seed=6c6f7374-0000-4000-8000-000000000001 -d "$T/lost" lost.img 4096
+DIRBLK=$(debugfs -R 'bmap /dir 0' lost.img 2>/dev/null)
+dd if=lost.img of="$T/dirblk" bs=1024 skip="$DIRBLK" count=1 status=none
+printf 'jo\njw -b %s %s\njc\n' "$DIRBLK" "$T/dirblk" | debugfs -w lost.img
+e2fsck -fy lost.img
+echo 'clri /dir' | debugfs -w lost.img
+E2FSPROGS_FAKE_TIME=1500000000 e2fsck -fy lost.img || test $? -eq 1
+LFBLK=$(debugfs -R 'bmap /lost+found 0' lost.img 2>/dev/null)
+dd if=lost.img of="$T/lfblk" bs=1024 skip="$LFBLK" count=1 status=none
+cat "$T/dirblk" "$T/lfblk" > "$T/logged"
+printf 'jo\njw -b %s,%s %s\njc\n' "$DIRBLK" "$LFBLK" "$T/logged" | debugfs -w lost.img
+E2FSPROGS_FAKE_TIME=1500000000 e2fsck -fy lost.img
+
+# The same files, as two filesystems laid out differently, for fingerprinting:
+#  /dir/file, 'F' * 5000, /dir/link -> file, and /empty; layout.img has 1k blocks and
+#  256 byte inodes, layout-4k.img has 4k blocks, 128 byte inodes, and a journal
+mkdir -p "$T/layout/dir" "$T/layout/empty"
+python3 -c "open('$T/layout/dir/file', 'wb').write(b'F' * 5000)"
+ln -s file "$T/layout/dir/link"
+touch -h -d @1500000000 "$T/layout/dir"/* "$T/layout/dir" "$T/layout/empty" "$T/layout"
+rm -f layout.img layout-4k.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -I 256 -O ^has_journal -U 6c61796f-7574-4000-8000-000000000000 \
+  -E hash_seed=6c61796f-7574-4000-8000-000000000001 -d "$T/layout" layout.img 1024
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 4096 -I 128 -O has_journal -U 6c61796f-7574-4000-8000-000000000002 \
+  -E hash_seed=6c61796f-7574-4000-8000-000000000003 -d "$T/layout" layout-4k.img 2048
+
+# Two files claiming the same block, as a damaged, or deduplicated, filesystem has:
+#  /one, 'O' * 1024, and /two, 'T' * 1024, until its extent is pointed at /one's block
+mkdir -p "$T/shared"
+python3 -c "open('$T/shared/one', 'wb').write(b'O' * 1024)"
+python3 -c "open('$T/shared/two', 'wb').write(b'T' * 1024)"
+touch -d @1500000000 "$T/shared"/* "$T/shared"
+rm -f shared.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal -U 73686172-6564-4000-8000-000000000000 \
+  -E hash_seed=73686172-6564-4000-8000-000000000001 -d "$T/shared" shared.img 1024
+ONE=$(debugfs -R 'bmap /one 0' shared.img 2>/dev/null)
+echo "sif /two block[5] $ONE" | debugfs -w shared.img
+
+# A directory big enough to be worth indexing, which e2fsck -D does:
+#  /big, with file-000 to file-199, all empty, and the directory /big/sub
+mkdir -p "$T/htree/big/sub"
+python3 -c "[open('$T/htree/big/file-%03d' % i, 'w') for i in range(200)]"
+touch -d @1500000000 "$T/htree/big"/* "$T/htree/big" "$T/htree"
+rm -f htree.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -N 256 -O ^has_journal -U 68747265-6500-4000-8000-000000000000 \
+  -E hash_seed=68747265-6500-4000-8000-000000000001 -d "$T/htree" htree.img 1024
+E2FSPROGS_FAKE_TIME=1500000000 e2fsck -fyD htree.img || test $? -eq 1
+
+# A directory too wide to hold all its children's inodes at once:
+#  /wide, with file-0000 to file-2999, all empty
+mkdir -p "$T/wide/wide"
+python3 -c "[open('$T/wide/wide/file-%04d' % i, 'w') for i in range(3000)]"
+touch -d @1500000000 "$T/wide/wide"/* "$T/wide/wide" "$T/wide"
+rm -f wide.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -N 3072 -O ^has_journal -U 77696465-0000-4000-8000-000000000000 \
+  -E hash_seed=77696465-0000-4000-8000-000000000001 -d "$T/wide" wide.img 2048
+
+# Names which aren't utf-8, as a latin-1 system would write them:
+#  /caf\xe9, 'latin-1\n', /café (in utf-8), 'utf-8\n', and /link -> caf\xe9,
+#  with the xattr user.caf\xe9 = 'latin-1'
+mkdir -p "$T/names"
+python3 -c "
+import os
+open(b'$T/names/caf\xe9', 'w').write('latin-1\n')
+open('$T/names/café', 'w').write('utf-8\n')
+os.symlink(b'caf\xe9', b'$T/names/link')
+"
+touch -h -d @1500000000 "$T/names"/* "$T/names"
+rm -f names.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal -U 6e616d65-7300-4000-8000-000000000000 \
+  -E hash_seed=6e616d65-7300-4000-8000-000000000001 -d "$T/names" names.img 1024
+printf 'ea_set /link user.caf\351 latin-1\n' | E2FSPROGS_FAKE_TIME=1500000000 debugfs -w names.img
+
+# The same files, with 2k, and 64k, blocks, as other architectures' mkfs can make:
+#  /data, bytes 0 to 255 800 times, /dir with file-000 to file-199, all empty, and
+#  /dir/link -> ../data; blocks-64k.img has no metadata_csum, so the unused records
+#  filling /lost+found's second block have the encoded length
+mkdir -p "$T/blocks/dir"
+python3 -c "open('$T/blocks/data', 'wb').write(bytes(range(256)) * 800)"
+python3 -c "[open('$T/blocks/dir/file-%03d' % i, 'w') for i in range(200)]"
+ln -s ../data "$T/blocks/dir/link"
+touch -h -d @1500000000 "$T/blocks/dir"/* "$T/blocks/dir" "$T/blocks"/* "$T/blocks"
+rm -f blocks-2k.img blocks-64k.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 2048 -O ^has_journal -U 626c6b32-6b00-4000-8000-000000000000 \
+  -E hash_seed=626c6b32-6b00-4000-8000-000000000001 -d "$T/blocks" blocks-2k.img 1024
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 65536 -O ^has_journal,^metadata_csum -U 626c6b36-346b-4000-8000-000000000000 \
+  -E hash_seed=626c6b36-346b-4000-8000-000000000001 -d "$T/blocks" blocks-64k.img 64
+
+# Revision 0, as the oldest ext2 filesystems are: no features, so no extents, and fixed
+# 128 byte inodes: /dir/hello.txt, "hello\n", and /link -> dir/hello.txt
+mkdir -p "$T/old/dir"
+echo hello > "$T/old/dir/hello.txt"
+ln -s dir/hello.txt "$T/old/link"
+touch -h -d @1500000000 "$T/old/dir"/* "$T/old/dir" "$T/old"/* "$T/old"
+rm -f old.img
+E2FSPROGS_FAKE_TIME=1500000000 mke2fs -q -F -t ext2 -r 0 -b 1024 -U 6f6c6400-0000-4000-8000-000000000000 \
+  -d "$T/old" old.img 1024
+
+# Block maps, as ext2 and ext3 find a file's blocks, instead of extents:
+#  /big, bytes 0 to 250 repeated, 300 blocks and a bit, through the single and double
+#  indirect blocks, /sparse, 'triple\n' at 70MiB, past the triple indirect block, and
+#  /dir/hello.txt, "hello\n", with /long -> a target too long to fit in the inode
+mkdir -p "$T/indirect/dir"
+python3 -c "open('$T/indirect/big', 'wb').write(bytes(i % 251 for i in range(300 * 1024 + 100)))"
+python3 -c "f = open('$T/indirect/sparse', 'wb'); f.seek(70 * 1024 * 1024); f.write(b'triple\n')"
+echo hello > "$T/indirect/dir/hello.txt"
+ln -s ./././././././././././././././././././././././././././././././dir/hello.txt "$T/indirect/long"
+touch -h -d @1500000000 "$T/indirect/dir"/* "$T/indirect/dir" "$T/indirect"/* "$T/indirect"
+rm -f indirect.img
+E2FSPROGS_FAKE_TIME=1500000000 mke2fs -q -F -t ext2 -b 1024 -U 696e6469-7265-4000-8000-000000000000 \
+  -E hash_seed=696e6469-7265-4000-8000-000000000001 \
+  -d "$T/indirect" indirect.img 1024
+
+# ext3: no extents, so even the journal is found through its block map, with one
+# committed, unreplayed transaction, block 3000 := 'A' * 1024, and /file, 'E' * 2000
+mkdir -p "$T/ext3"
+python3 -c "open('$T/ext3/file', 'wb').write(b'E' * 2000)"
+touch -d @1500000000 "$T/ext3"/* "$T/ext3"
+rm -f ext3.img
+E2FSPROGS_FAKE_TIME=1500000000 mke2fs -q -F -t ext3 -b 1024 -U 65787433-0000-4000-8000-000000000000 \
+  -E hash_seed=65787433-0000-4000-8000-000000000001 -d "$T/ext3" ext3.img 4096
+printf 'jo -c\njw -b 3000 %s\njc\n' "$T/a" | E2FSPROGS_FAKE_TIME=1500000000 debugfs -w ext3.img
+
+# A directory linked inside itself, which a walk would follow forever:
+#  /a/file, 'x\n', and /a/self -> /a, as a second hard link to the directory
+mkdir -p "$T/cycle/a"
+echo x > "$T/cycle/a/file"
+touch -d @1500000000 "$T/cycle/a"/* "$T/cycle/a" "$T/cycle"
+rm -f cycle.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -O ^has_journal -U 6379636c-6500-4000-8000-000000000000 \
+  -E hash_seed=6379636c-6500-4000-8000-000000000001 -d "$T/cycle" cycle.img 1024
+echo 'ln /a /a/self' | E2FSPROGS_FAKE_TIME=1500000000 debugfs -w cycle.img
+
+# A fast symlink with an xattr block, which i_blocks counts, as 128 byte inodes have no
+# room for xattrs: /link -> target, with user.note = 'kept out of the inode'
+mkdir -p "$T/ealink"
+ln -s target "$T/ealink/link"
+touch -h -d @1500000000 "$T/ealink"/* "$T/ealink"
+rm -f ealink.img
+E2FSPROGS_FAKE_TIME=1500000000 mkfs.ext4 -q -F -b 1024 -I 128 -O ^has_journal -U 65616c69-6e6b-4000-8000-000000000000 \
+  -E hash_seed=65616c69-6e6b-4000-8000-000000000001 -d "$T/ealink" ealink.img 1024
+printf 'ea_set /link user.note "kept out of the inode"\n' | E2FSPROGS_FAKE_TIME=1500000000 debugfs -w ealink.img
@@ -0,0 +1,80 @@
+use std::io;
+
+use positioned_io2::ReadAt;
+
+/// The most read from the device at once; `read_at` returns short for anything bigger.
+const MAX_READ: usize = 1024 * 1024;
+
+/// Wraps a device opened with `O_DIRECT`, which only accepts reads where the offset,
+/// length and memory address are all multiples of the logical sector size. Every read
+/// goes through a suitably aligned bounce buffer, so the rest of the crate can read
+/// whatever it likes.
+///
+/// Opening the device with `O_DIRECT` is up to you: on Linux, use
+/// `std::os::unix::fs::OpenOptionsExt::custom_flags` with `libc::O_DIRECT`.
+pub struct AlignedReader<R> {
+    inner: R,
+    sector_size: usize,
+}
+
+impl<R> AlignedReader<R> {
+    /// `sector_size` must be a power of two: usually 512 or 4096.
+    /// See `blockdev --getss`, or `/sys/block/*/queue/logical_block_size`.
+    pub fn new(inner: R, sector_size: usize) -> AlignedReader<R> {
+        assert!(
+            sector_size.is_power_of_two(),
+            "sector size must be a power of two, not {}",
+            sector_size
+        );
+        AlignedReader { inner, sector_size }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: ReadAt> ReadAt for AlignedReader<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let sector = self.sector_size as u64;
+        let wanted = buf.len().min(MAX_READ);
+        let start = pos - pos % sector;
+        let skip = (pos - start) as usize;
+        let len = round_up(skip + wanted, self.sector_size);
+
+        // over-allocate, so there's an aligned region of `len` somewhere inside
+        let mut bounce = vec![0u8; len + self.sector_size];
+        let misaligned = bounce.as_ptr() as usize % self.sector_size;
+        let offset = if 0 == misaligned {
+            0
+        } else {
+            self.sector_size - misaligned
+        };
+        let bounce = &mut bounce[offset..offset + len];
+
+        let mut filled = 0;
+        while filled < len {
+            match self
+                .inner
+                .read_at(start + filled as u64, &mut bounce[filled..])
+            {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let available = filled.saturating_sub(skip).min(wanted);
+        buf[..available].copy_from_slice(&bounce[skip..skip + available]);
+        Ok(available)
+    }
+}
+
+fn round_up(value: usize, multiple: usize) -> usize {
+    (value + multiple - 1) / multiple * multiple
+}
@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// The inode of a directory's parent, from its `..` entry, which must be there. The
+    /// filesystem's root is its own parent. Only the first block is read.
+    pub fn parent_of(&self, dir: &Inode) -> Result<u32, Error> {
+        ensure!(
+            FileType::Directory == dir.stat.extracted_type,
+            not_found(format!("<{}> isn't a directory", dir.number))
+        );
+        dir.parent(&self.inner)
+    }
+
+    /// A directory's parent, its parent's parent, and so on, up to, and including, the
+    /// root (as `with_root` sets it); empty for the root itself. Fails if the `..`s go
+    /// round in a loop, or reach the filesystem's root without passing `with_root`'s.
+    pub fn ancestry(&self, dir: &Inode) -> Result<Vec<u32>, Error> {
+        let mut ancestors = Vec::new();
+        let mut seen = HashSet::new();
+        seen.insert(dir.number);
+        let mut current = dir.number;
+        while self.root_inode != current {
+            let parent = if current == dir.number {
+                self.parent_of(dir)?
+            } else {
+                self.parent_of(&self.load_inode(current)?)?
+            };
+            // only the filesystem's own root is its own parent
+            ensure!(
+                parent != current,
+                not_found(format!(
+                    "<{}> isn't inside the root, <{}>",
+                    dir.number, self.root_inode
+                ))
+            );
+            ensure!(
+                seen.insert(parent),
+                assumption_failed(format!(
+                    "<{}>'s ancestry loops back to <{}>: {:?}",
+                    dir.number, parent, ancestors
+                ))
+            );
+            ancestors.push(parent);
+            current = parent;
+        }
+        Ok(ancestors)
+    }
+}
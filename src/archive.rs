@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::Enhanced;
+use crate::Event;
+use crate::Inode;
+use crate::SuperBlock;
+use crate::WalkOptions;
+
+/// One file, directory, or other thing, on its way into an archive.
+pub struct ArchiveEntry<'a> {
+    /// Relative to the directory being exported, with no leading or trailing `/`.
+    pub path: &'a str,
+    pub inode: &'a Inode,
+    /// The symlink target, device numbers, and so on.
+    pub enhanced: &'a Enhanced,
+    /// If this inode has already been given to the sink under another path, that path.
+    pub hard_link_to: Option<&'a str>,
+}
+
+/// Somewhere to put the entries of a filesystem, e.g. an archive format.
+///
+/// `SuperBlock::export` walks the filesystem and calls this for each entry, so
+/// converting to a new format only needs this implementing.
+pub trait ArchiveSink {
+    /// Called once for each entry, with parents before their children. For regular
+    /// files, `content` reads the data (even for hard links); for everything else, it's empty.
+    /// Entries the format can't represent can be skipped.
+    fn add(&mut self, entry: &ArchiveEntry, content: &mut dyn Read) -> Result<(), Error>;
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Give everything under `path` to `sink`, but not `path` itself.
+    pub fn export<S: ArchiveSink + ?Sized>(&self, path: &str, sink: &mut S) -> Result<(), Error> {
+        self.export_with_options(path, sink, &WalkOptions::default())
+    }
+
+    /// `export`, in the order `options` asks for; sorting makes the archive the same
+    /// each time the same tree is exported, wherever it came from.
+    pub fn export_with_options<S: ArchiveSink + ?Sized>(
+        &self,
+        path: &str,
+        sink: &mut S,
+        options: &WalkOptions,
+    ) -> Result<(), Error> {
+        let top = self.resolve_path(path)?.inode;
+        let mut links: HashMap<u32, String> = HashMap::new();
+        let mut events = self.events_with_options(top, "", options, &());
+
+        loop {
+            let (path, inode, enhanced) = match events.next_event()? {
+                Event::EnterDir {
+                    path,
+                    inode,
+                    enhanced,
+                }
+                | Event::Entry {
+                    path,
+                    inode,
+                    enhanced,
+                } => (path, inode, enhanced),
+                Event::LeaveDir { .. } => continue,
+                Event::Corruption { path, error } => {
+                    return Err(error.context(format!("loading {}", path)))
+                }
+                Event::End => break,
+            };
+
+            // the paths all start with a '/'; the top itself is ""
+            let path = match path.strip_prefix('/') {
+                Some(path) => path,
+                None => continue,
+            };
+
+            let linked = match enhanced {
+                Enhanced::Directory(_) => false,
+                _ => inode.stat.link_count > 1,
+            };
+            let hard_link_to = if linked {
+                links.get(&inode.number).cloned()
+            } else {
+                None
+            };
+
+            let entry = ArchiveEntry {
+                path,
+                inode: &inode,
+                enhanced: &enhanced,
+                hard_link_to: hard_link_to.as_deref(),
+            };
+            match enhanced {
+                Enhanced::RegularFile => sink.add(&entry, &mut self.open(&inode)?)?,
+                _ => sink.add(&entry, &mut io::empty())?,
+            }
+
+            if linked && hard_link_to.is_none() {
+                links.insert(inode.number, path.to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,169 +1,412 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io;
+use std::sync::Mutex;
 
 use anyhow::ensure;
 use anyhow::Error;
-use byteorder::{LittleEndian, ReadBytesExt};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use positioned_io2::ReadAt;
 
 use crate::assumption_failed;
 use crate::not_found;
+use crate::parse::ext4_style_crc16;
+use crate::parse::ext4_style_crc32c_le;
+use crate::read_le16;
+use crate::read_le32;
 
-const EXT4_BLOCK_GROUP_INODES_UNUSED: u16 = 0b1;
-const EXT4_BLOCK_GROUP_BLOCKS_UNUSED: u16 = 0b10;
+bitflags! {
+    pub struct BlockGroupFlags: u16 {
+        const INODE_UNINIT  = 0x0001; /* Inode table/bitmap not in use */
+        const BLOCK_UNINIT  = 0x0002; /* Block bitmap not in use */
+        const ITABLE_ZEROED = 0x0004; /* On-disk itable initialized to zero */
+    }
+}
 
-#[derive(Debug)]
-struct Entry {
-    inode_table_block: u64,
-    max_inode_number: u32,
+#[cfg(feature = "serde")]
+impl serde::Serialize for BlockGroupFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.bits())
+    }
+}
+
+/// How group descriptor checksums are computed on this filesystem, if at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupChecksum {
+    None,
+    /// The older `uninit_bg` / `gdt_csum` crc16, seeded with the raw UUID.
+    Crc16([u8; 16]),
+    /// `metadata_csum`'s crc32c, seeded with the checksum of the UUID.
+    Crc32c(u32),
+}
+
+/// A block group descriptor, as read from the group descriptor table.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockGroup {
+    pub number: u32,
+    /// The first block covered by this group.
+    pub first_block: u64,
+    /// The last block covered by this group, inclusive.
+    pub last_block: u64,
+    pub block_bitmap: u64,
+    pub inode_bitmap: u64,
+    pub inode_table: u64,
+    /// The length of the inode table, in blocks.
+    pub inode_table_blocks: u64,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub used_dirs_count: u32,
+    pub itable_unused: u32,
+    pub flags: BlockGroupFlags,
+    pub exclude_bitmap: u64,
+    /// Only present with `metadata_csum`.
+    pub block_bitmap_checksum: Option<u32>,
+    /// Only present with `metadata_csum`.
+    pub inode_bitmap_checksum: Option<u32>,
+    /// The on-disc descriptor checksum.
+    pub checksum: u16,
+    /// What we think the checksum should be; `None` if the filesystem doesn't checksum groups.
+    pub computed_checksum: Option<u16>,
+}
+
+impl BlockGroup {
+    /// `None` if the filesystem doesn't have group descriptor checksums.
+    pub fn checksum_valid(&self) -> Option<bool> {
+        self.computed_checksum
+            .map(|computed| computed == self.checksum)
+    }
 }
 
+/// The group descriptor table, which is read a block at a time, as groups are needed, so
+/// opening a huge filesystem doesn't have to read, and keep, every descriptor.
 #[derive(Debug)]
 pub struct BlockGroups {
-    groups: Vec<Entry>,
+    /// Where the table starts, in bytes.
+    table_pos: u64,
+    count: u32,
+    desc_size: usize,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inode_table_blocks: u64,
+    checksum: GroupChecksum,
+    /// The descriptors which have been read so far.
+    loaded: Mutex<HashMap<u32, BlockGroup>>,
     inodes_per_group: u32,
+    pub blocks_count: u64,
     pub block_size: u32,
     pub inode_size: u16,
 }
 
 impl BlockGroups {
-    pub fn new<R>(
-        mut inner: R,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        table_pos: u64,
         blocks_count: u64,
+        s_first_data_block: u32,
+        s_blocks_per_group: u32,
         s_desc_size: u16,
         s_inodes_per_group: u32,
         block_size: u32,
         inode_size: u16,
-    ) -> Result<BlockGroups, Error>
-    where
-        R: io::Read + io::Seek,
-    {
-        let blocks_count = usize::try_from(blocks_count)?;
-
-        let mut groups = Vec::with_capacity(blocks_count);
-
-        for block in 0..blocks_count {
-            //            let bg_block_bitmap_lo =
-            inner.read_u32::<LittleEndian>()?; /* Blocks bitmap block */
-            //            let bg_inode_bitmap_lo =
-            inner.read_u32::<LittleEndian>()?; /* Inodes bitmap block */
-            let bg_inode_table_lo = inner.read_u32::<LittleEndian>()?; /* Inodes table block */
-            //            let bg_free_blocks_count_lo =
-            inner.read_u16::<LittleEndian>()?; /* Free blocks count */
-            let bg_free_inodes_count_lo = inner.read_u16::<LittleEndian>()?; /* Free inodes count */
-            //            let bg_used_dirs_count_lo =
-            inner.read_u16::<LittleEndian>()?; /* Directories count */
-            let bg_flags = inner.read_u16::<LittleEndian>()?; /* EXT4_BG_flags (INODE_UNINIT, etc) */
-            //            let bg_exclude_bitmap_lo =
-            inner.read_u32::<LittleEndian>()?; /* Exclude bitmap for snapshots */
-            //            let bg_block_bitmap_csum_lo =
-            inner.read_u16::<LittleEndian>()?; /* crc32c(s_uuid+grp_num+bbitmap) LE */
-            //            let bg_inode_bitmap_csum_lo =
-            inner.read_u16::<LittleEndian>()?; /* crc32c(s_uuid+grp_num+ibitmap) LE */
-            //            let bg_itable_unused_lo =
-            inner.read_u16::<LittleEndian>()?; /* Unused inodes count */
-            //            let bg_checksum =
-            inner.read_u16::<LittleEndian>()?; /* crc16(sb_uuid+group+desc) */
-
-            //            let bg_block_bitmap_hi =
-            if s_desc_size < 4 {
-                None
-            } else {
-                Some(inner.read_u32::<LittleEndian>()?) /* Blocks bitmap block MSB */
-            };
-            //            let bg_inode_bitmap_hi =
-            if s_desc_size < 4 + 4 {
-                None
-            } else {
-                Some(inner.read_u32::<LittleEndian>()?) /* Inodes bitmap block MSB */
-            };
-            let bg_inode_table_hi = if s_desc_size < 4 + 4 + 4 {
-                None
-            } else {
-                Some(inner.read_u32::<LittleEndian>()?) /* Inodes table block MSB */
-            };
-            //            let bg_free_blocks_count_hi =
-            if s_desc_size < 4 + 4 + 4 + 2 {
-                None
-            } else {
-                Some(inner.read_u16::<LittleEndian>()?) /* Free blocks count MSB */
-            };
-            let bg_free_inodes_count_hi = if s_desc_size < 4 + 4 + 4 + 2 + 2 {
-                None
-            } else {
-                Some(inner.read_u16::<LittleEndian>()?) /* Free inodes count MSB */
-            };
-
-            //          let bg_used_dirs_count_hi =
-            //              inner.read_u16::<LittleEndian>()?; /* Directories count MSB */
-            //          let bg_itable_unused_hi =
-            //              inner.read_u16::<LittleEndian>()?; /* Unused inodes count MSB */
-            //          let bg_exclude_bitmap_hi =
-            //              inner.read_u32::<LittleEndian>()?; /* Exclude bitmap block MSB */
-            //          let bg_block_bitmap_csum_hi =
-            //              inner.read_u16::<LittleEndian>()?; /* crc32c(s_uuid+grp_num+bbitmap) BE */
-            //          let bg_inode_bitmap_csum_hi =
-            //              inner.read_u16::<LittleEndian>()?; /* crc32c(s_uuid+grp_num+ibitmap) BE */
-            if s_desc_size > 16 + 32 {
-                inner.seek(io::SeekFrom::Current(i64::from(s_desc_size - 32 - 16)))?;
-            }
+        checksum: GroupChecksum,
+        limits: &crate::Limits,
+    ) -> Result<BlockGroups, Error> {
+        let groups_count =
+            (blocks_count - u64::from(s_first_data_block) + u64::from(s_blocks_per_group) - 1)
+                / u64::from(s_blocks_per_group);
+        limits.check(
+            "number of block groups",
+            groups_count,
+            u64::from(limits.max_groups),
+        )?;
+        let groups_count = u32::try_from(groups_count)?;
+
+        let desc_size = if 0 == s_desc_size {
+            32
+        } else {
+            usize::from(s_desc_size)
+        };
+
+        ensure!(
+            desc_size >= 32,
+            assumption_failed(format!("group descriptors are too short: {}", desc_size))
+        );
 
-            let inode_table_block =
-                u64::from(bg_inode_table_lo) | ((u64::from(bg_inode_table_hi.unwrap_or(0))) << 32);
-            let free_inodes_count = u32::from(bg_free_inodes_count_lo)
-                | ((u32::from(bg_free_inodes_count_hi.unwrap_or(0))) << 16);
+        let inode_table_blocks =
+            (u64::from(s_inodes_per_group) * u64::from(inode_size) + u64::from(block_size) - 1)
+                / u64::from(block_size);
 
-            let unallocated = bg_flags & EXT4_BLOCK_GROUP_INODES_UNUSED != 0
-                || bg_flags & EXT4_BLOCK_GROUP_BLOCKS_UNUSED != 0;
+        Ok(BlockGroups {
+            table_pos,
+            count: groups_count,
+            desc_size,
+            first_data_block: s_first_data_block,
+            blocks_per_group: s_blocks_per_group,
+            inode_table_blocks,
+            checksum,
+            loaded: Mutex::new(HashMap::new()),
+            inodes_per_group: s_inodes_per_group,
+            blocks_count,
+            block_size,
+            inode_size,
+        })
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn inodes_per_group(&self) -> u32 {
+        self.inodes_per_group
+    }
+
+    /// Where `group`'s descriptor is, in bytes, and how long it is.
+    pub fn descriptor_pos(&self, group: u32) -> (u64, usize) {
+        let offset = self.table_pos + u64::from(group) * self.desc_size as u64;
+        (offset, self.desc_size)
+    }
+
+    /// The group whose part of the inode table holds `inode`.
+    pub fn group_of_inode(&self, inode: u32) -> Result<u32, Error> {
+        ensure!(0 != inode, not_found("there is no inode zero"));
+        let group = (inode - 1) / self.inodes_per_group;
+        ensure!(
+            group < self.count,
+            not_found(format!(
+                "inode <{}> would be in group {}, of {}",
+                inode, group, self.count
+            ))
+        );
+        Ok(group)
+    }
+
+    /// The group covering `block`. With 1k blocks, block zero is in none.
+    pub fn group_of_block(&self, block: u64) -> Result<u32, Error> {
+        ensure!(
+            block >= u64::from(self.first_data_block) && block < self.blocks_count,
+            not_found(format!(
+                "block {} isn't in any group ({} to {})",
+                block,
+                self.first_data_block,
+                self.blocks_count - 1
+            ))
+        );
+        Ok(u32::try_from(
+            (block - u64::from(self.first_data_block)) / u64::from(self.blocks_per_group),
+        )?)
+    }
 
-            if free_inodes_count > s_inodes_per_group {
+    /// The first block after the end of `group`, or of the filesystem.
+    pub fn end_of_group(&self, group: u32) -> u64 {
+        std::cmp::min(
+            u64::from(self.first_data_block)
+                + (u64::from(group) + 1) * u64::from(self.blocks_per_group),
+            self.blocks_count,
+        )
+    }
+
+    pub fn get<R: ReadAt>(&self, inner: R, group: u32) -> Result<BlockGroup, Error> {
+        ensure!(
+            group < self.count,
+            not_found(format!(
+                "there is no block group {} (of {})",
+                group, self.count
+            ))
+        );
+
+        if let Some(found) = self.loaded.lock().expect("poisoned").get(&group) {
+            return Ok(found.clone());
+        }
+
+        // read the whole block of the table holding this group, as its neighbours
+        // are likely to be wanted next
+        let per_block = u32::try_from(self.block_size as usize / self.desc_size)?.max(1);
+        let first = group / per_block * per_block;
+        let last = self.count.min(first + per_block);
+        let mut data = vec![0u8; usize::try_from(last - first)? * self.desc_size];
+        inner.read_exact_at(
+            self.table_pos + u64::from(first) * self.desc_size as u64,
+            &mut data,
+        )?;
+
+        let mut parsed = Vec::with_capacity(data.len() / self.desc_size);
+        for (number, data) in (first..last).zip(data.chunks(self.desc_size)) {
+            let first_block = u64::from(self.first_data_block)
+                + u64::from(number) * u64::from(self.blocks_per_group);
+            let last_block = std::cmp::min(
+                first_block + u64::from(self.blocks_per_group) - 1,
+                self.blocks_count - 1,
+            );
+
+            let group = parse_descriptor(
+                data,
+                number,
+                first_block,
+                last_block,
+                self.inode_table_blocks,
+                self.checksum,
+            );
+
+            if group.free_inodes_count > self.inodes_per_group {
                 return Err(crate::parse_error(format!(
                     "too many free inodes in group {}: {} > {}",
-                    block, free_inodes_count, s_inodes_per_group
+                    number, group.free_inodes_count, self.inodes_per_group
                 )));
             }
 
-            let max_inode_number = if unallocated {
-                0
-            } else {
-                // can't use free inodes here, as there can be unallocated ranges in the middle;
-                // would have to parse the bitmap to work that out and it doesn't seem worth
-                // the effort
-                s_inodes_per_group
-            };
-
-            groups.push(Entry {
-                inode_table_block,
-                max_inode_number,
-            });
+            parsed.push(group);
         }
 
-        Ok(BlockGroups {
-            groups,
-            inodes_per_group: s_inodes_per_group,
-            block_size,
-            inode_size,
-        })
+        let found = parsed[usize::try_from(group - first)?].clone();
+        self.loaded
+            .lock()
+            .expect("poisoned")
+            .extend(parsed.into_iter().map(|group| (group.number, group)));
+        Ok(found)
     }
 
-    pub fn index_of(&self, inode: u32) -> Result<u64, Error> {
+    pub fn index_of<R: ReadAt>(&self, inner: R, inode: u32) -> Result<u64, Error> {
         ensure!(0 != inode, not_found("there is no inode zero"));
 
         let inode = inode - 1;
         let group_number = inode / self.inodes_per_group;
-        let group = &self.groups[usize::try_from(group_number)?];
+        let group = self.get(inner, group_number)?;
         let inode_index_in_group = inode % self.inodes_per_group;
+
+        let unallocated = group
+            .flags
+            .intersects(BlockGroupFlags::INODE_UNINIT | BlockGroupFlags::BLOCK_UNINIT);
+
+        let max_inode_number = if unallocated {
+            0
+        } else {
+            // can't use free inodes here, as there can be unallocated ranges in the middle;
+            // would have to parse the bitmap to work that out and it doesn't seem worth
+            // the effort
+            self.inodes_per_group
+        };
+
         ensure!(
-            inode_index_in_group < group.max_inode_number,
+            inode_index_in_group < max_inode_number,
             assumption_failed(format!(
                 "inode <{}> number must fit in group: {} is greater than {} for group {}",
                 inode + 1,
                 inode_index_in_group,
-                group.max_inode_number,
+                max_inode_number,
                 group_number
             ))
         );
-        let block = group.inode_table_block;
+        let block = group.inode_table;
         Ok(block * u64::from(self.block_size)
             + u64::from(inode_index_in_group) * u64::from(self.inode_size))
     }
 }
+
+fn parse_descriptor(
+    data: &[u8],
+    number: u32,
+    first_block: u64,
+    last_block: u64,
+    inode_table_blocks: u64,
+    checksum: GroupChecksum,
+) -> BlockGroup {
+    let long = data.len() >= 64;
+
+    let bg_block_bitmap_lo = read_le32(&data[0x00..0x04]); /* Blocks bitmap block */
+    let bg_inode_bitmap_lo = read_le32(&data[0x04..0x08]); /* Inodes bitmap block */
+    let bg_inode_table_lo = read_le32(&data[0x08..0x0C]); /* Inodes table block */
+    let bg_free_blocks_count_lo = read_le16(&data[0x0C..0x0E]); /* Free blocks count */
+    let bg_free_inodes_count_lo = read_le16(&data[0x0E..0x10]); /* Free inodes count */
+    let bg_used_dirs_count_lo = read_le16(&data[0x10..0x12]); /* Directories count */
+    let bg_flags = read_le16(&data[0x12..0x14]); /* EXT4_BG_flags (INODE_UNINIT, etc) */
+    let bg_exclude_bitmap_lo = read_le32(&data[0x14..0x18]); /* Exclude bitmap for snapshots */
+    let bg_block_bitmap_csum_lo = read_le16(&data[0x18..0x1A]); /* crc32c(s_uuid+grp_num+bbitmap) LE */
+    let bg_inode_bitmap_csum_lo = read_le16(&data[0x1A..0x1C]); /* crc32c(s_uuid+grp_num+ibitmap) LE */
+    let bg_itable_unused_lo = read_le16(&data[0x1C..0x1E]); /* Unused inodes count */
+    let bg_checksum = read_le16(&data[0x1E..0x20]); /* crc16(sb_uuid+group+desc) */
+
+    let hi16 = |offset: usize| {
+        if long {
+            read_le16(&data[offset..offset + 2])
+        } else {
+            0
+        }
+    };
+    let hi32 = |offset: usize| {
+        if long {
+            read_le32(&data[offset..offset + 4])
+        } else {
+            0
+        }
+    };
+
+    let bg_block_bitmap_hi = hi32(0x20); /* Blocks bitmap block MSB */
+    let bg_inode_bitmap_hi = hi32(0x24); /* Inodes bitmap block MSB */
+    let bg_inode_table_hi = hi32(0x28); /* Inodes table block MSB */
+    let bg_free_blocks_count_hi = hi16(0x2C); /* Free blocks count MSB */
+    let bg_free_inodes_count_hi = hi16(0x2E); /* Free inodes count MSB */
+    let bg_used_dirs_count_hi = hi16(0x30); /* Directories count MSB */
+    let bg_itable_unused_hi = hi16(0x32); /* Unused inodes count MSB */
+    let bg_exclude_bitmap_hi = hi32(0x34); /* Exclude bitmap block MSB */
+    let bg_block_bitmap_csum_hi = hi16(0x38); /* crc32c(s_uuid+grp_num+bbitmap) BE */
+    let bg_inode_bitmap_csum_hi = hi16(0x3A); /* crc32c(s_uuid+grp_num+ibitmap) BE */
+
+    let join16 = |lo: u16, hi: u16| u32::from(lo) | (u32::from(hi) << 16);
+    let join32 = |lo: u32, hi: u32| u64::from(lo) | (u64::from(hi) << 32);
+
+    let has_bitmap_checksums = matches!(checksum, GroupChecksum::Crc32c(_));
+
+    BlockGroup {
+        number,
+        first_block,
+        last_block,
+        block_bitmap: join32(bg_block_bitmap_lo, bg_block_bitmap_hi),
+        inode_bitmap: join32(bg_inode_bitmap_lo, bg_inode_bitmap_hi),
+        inode_table: join32(bg_inode_table_lo, bg_inode_table_hi),
+        inode_table_blocks,
+        free_blocks_count: join16(bg_free_blocks_count_lo, bg_free_blocks_count_hi),
+        free_inodes_count: join16(bg_free_inodes_count_lo, bg_free_inodes_count_hi),
+        used_dirs_count: join16(bg_used_dirs_count_lo, bg_used_dirs_count_hi),
+        itable_unused: join16(bg_itable_unused_lo, bg_itable_unused_hi),
+        flags: BlockGroupFlags::from_bits_truncate(bg_flags),
+        exclude_bitmap: join32(bg_exclude_bitmap_lo, bg_exclude_bitmap_hi),
+        block_bitmap_checksum: if has_bitmap_checksums {
+            Some(join16(bg_block_bitmap_csum_lo, bg_block_bitmap_csum_hi))
+        } else {
+            None
+        },
+        inode_bitmap_checksum: if has_bitmap_checksums {
+            Some(join16(bg_inode_bitmap_csum_lo, bg_inode_bitmap_csum_hi))
+        } else {
+            None
+        },
+        checksum: bg_checksum,
+        computed_checksum: descriptor_checksum(data, number, checksum),
+    }
+}
+
+// c.f. ext4_group_desc_csum; the checksum field itself is skipped (crc16) or zeroed (crc32c)
+fn descriptor_checksum(data: &[u8], number: u32, checksum: GroupChecksum) -> Option<u16> {
+    let mut group = [0u8; 4];
+    LittleEndian::write_u32(&mut group, number);
+
+    match checksum {
+        GroupChecksum::None => None,
+        GroupChecksum::Crc32c(seed) => {
+            let mut data = data.to_vec();
+            data[0x1E] = 0;
+            data[0x1F] = 0;
+            let computed = ext4_style_crc32c_le(seed, &group);
+            let computed = ext4_style_crc32c_le(computed, &data);
+            Some(u16::try_from(computed & 0xFFFF).expect("masked"))
+        }
+        GroupChecksum::Crc16(uuid) => {
+            let computed = ext4_style_crc16(!0, &uuid);
+            let computed = ext4_style_crc16(computed, &group);
+            let computed = ext4_style_crc16(computed, &data[..0x1E]);
+            let computed = ext4_style_crc16(computed, &data[0x20..]);
+            Some(computed)
+        }
+    }
+}
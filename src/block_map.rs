@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::extents::load_data_extents;
+use crate::indirect::load_block_map;
+use crate::sharing::has_data_blocks;
+use crate::DataExtent;
+use crate::InodeFlags;
+use crate::SuperBlock;
+
+/// What a block of the filesystem holds; see `SuperBlock::block_map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum BlockUse {
+    /// Before the filesystem proper, for a boot loader: block 0, with 1k blocks.
+    Boot,
+    /// A copy of the superblock; with bigger blocks, after the boot sector, in the same block.
+    Superblock,
+    /// A copy of the group descriptors, or the blocks reserved for them to grow into.
+    GroupDescriptors,
+    BlockBitmap,
+    InodeBitmap,
+    InodeTable,
+    Journal,
+    /// An inode's content: a file's data, a directory's entries, or a long symlink's target.
+    Data(u32),
+    /// An inode's extent tree, below the root in the inode; or, without one, its indirect
+    /// blocks.
+    ExtentTree(u32),
+    /// Not allocated, by the bitmaps.
+    Free,
+    /// Allocated, but not to anything above, e.g. an xattr block, or an unlinked file's data.
+    Unknown,
+}
+
+/// Consecutive blocks which hold the same thing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockRun {
+    /// The first block.
+    pub start: u64,
+    /// In blocks.
+    pub len: u64,
+    pub usage: BlockUse,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// What every block of the filesystem holds, as runs, in order, from block 0 to the end,
+    /// e.g. to draw the filesystem, or to image only its metadata.
+    ///
+    /// Each group's bitmaps, and the whole tree from the root, are read. Like
+    /// `shared_extents`, only the inodes reachable from the root, and the journal, are
+    /// owners; blocks claimed twice go to whichever inode is visited last.
+    pub fn block_map(&self) -> Result<Vec<BlockRun>, Error> {
+        let mut runs = Runs::new(self.groups.blocks_count);
+
+        for range in self.unallocated_ranges()? {
+            runs.paint(range, BlockUse::Free);
+        }
+
+        runs.paint(0..u64::from(self.raw.s_first_data_block), BlockUse::Boot);
+        for number in 0..self.groups.count() {
+            let backup = self.superblock_backup(number)?;
+            if !backup.is_empty() {
+                runs.paint(backup.start..backup.start + 1, BlockUse::Superblock);
+                runs.paint(backup.start + 1..backup.end, BlockUse::GroupDescriptors);
+            }
+
+            let group = self.groups.get(&self.inner, number)?;
+            runs.paint(one(group.block_bitmap), BlockUse::BlockBitmap);
+            runs.paint(one(group.inode_bitmap), BlockUse::InodeBitmap);
+            runs.paint(
+                group.inode_table..group.inode_table + group.inode_table_blocks,
+                BlockUse::InodeTable,
+            );
+        }
+
+        let mut visited = HashSet::new();
+        self.walk(&self.load_inode(2)?, "", &mut |fs, _, inode, _| {
+            if !visited.insert(inode.number) || !has_data_blocks(inode) {
+                return Ok(true);
+            }
+
+            // in the inode itself, so no blocks of its own
+            if inode.flags.contains(InodeFlags::INLINE_DATA) {
+                return Ok(true);
+            }
+
+            // the extent tree's blocks below the root, or the indirect blocks
+            let mut tree = Vec::new();
+            let mut load_block = |block: u64| {
+                tree.push(block);
+                fs.load_disc_bytes(block)
+            };
+            let extents = if inode.flags.contains(InodeFlags::EXTENTS) {
+                load_data_extents(
+                    &mut load_block,
+                    inode.core,
+                    inode.checksum_prefix,
+                    &inode.limits,
+                )?
+            } else {
+                load_block_map(
+                    &mut load_block,
+                    inode.core,
+                    fs.groups.block_size,
+                    inode.stat.size,
+                    &inode.limits,
+                )?
+            };
+            for block in tree {
+                runs.paint(one(block), BlockUse::ExtentTree(inode.number));
+            }
+            for extent in extents {
+                runs.paint(extent_range(&extent), BlockUse::Data(inode.number));
+            }
+            Ok(true)
+        })?;
+
+        if let Some(journal) = self.journal_inode.filter(|&inode| 0 != inode) {
+            for extent in self.data_extents(&self.load_inode(journal)?)? {
+                runs.paint(extent_range(&extent), BlockUse::Journal);
+            }
+        }
+
+        Ok(runs.into_runs())
+    }
+}
+
+fn one(block: u64) -> Range<u64> {
+    block..block + 1
+}
+
+fn extent_range(extent: &DataExtent) -> Range<u64> {
+    extent.physical..extent.physical + u64::from(extent.len)
+}
+
+/// Every block, as runs which later paints split: from each run's first block, to its end,
+/// and what's in it.
+struct Runs {
+    end: u64,
+    runs: BTreeMap<u64, (u64, BlockUse)>,
+}
+
+impl Runs {
+    fn new(end: u64) -> Runs {
+        let mut runs = BTreeMap::new();
+        if end > 0 {
+            runs.insert(0, (end, BlockUse::Unknown));
+        }
+        Runs { end, runs }
+    }
+
+    fn paint(&mut self, range: Range<u64>, usage: BlockUse) {
+        // damage can point anywhere
+        let range = range.start.min(self.end)..range.end.min(self.end);
+        if range.is_empty() {
+            return;
+        }
+
+        let overlapping = self
+            .runs
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, &(end, _))| end > range.start)
+            .map(|(&start, &run)| (start, run))
+            .collect::<Vec<_>>();
+        for (start, (end, was)) in overlapping {
+            self.runs.remove(&start);
+            if start < range.start {
+                self.runs.insert(start, (range.start, was));
+            }
+            if end > range.end {
+                self.runs.insert(range.end, (end, was));
+            }
+        }
+        self.runs.insert(range.start, (range.end, usage));
+    }
+
+    fn into_runs(self) -> Vec<BlockRun> {
+        let mut merged: Vec<BlockRun> = Vec::new();
+        for (start, (end, usage)) in self.runs {
+            match merged.last_mut() {
+                Some(last) if last.usage == usage => last.len += end - start,
+                _ => merged.push(BlockRun {
+                    start,
+                    len: end - start,
+                    usage,
+                }),
+            }
+        }
+        merged
+    }
+}
@@ -0,0 +1,814 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+#[cfg(feature = "rayon")]
+use std::sync::atomic::AtomicUsize;
+#[cfg(feature = "rayon")]
+use std::sync::atomic::Ordering;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::parse::inode_checksum;
+use crate::parse::inode_checksum_prefix;
+use crate::read_le16;
+use crate::read_le32;
+use crate::sharing::has_data_blocks;
+use crate::sharing::overlaps;
+use crate::BlockGroupFlags;
+use crate::CreatorOs;
+use crate::DataExtent;
+use crate::Enhanced;
+use crate::FileType;
+use crate::Inode;
+use crate::ParseError;
+use crate::SuperBlock;
+
+/// The stages of a check, in the order they run. These roughly follow `e2fsck`'s passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Phase {
+    /// The superblock, and the journal.
+    Superblock,
+    /// The group descriptors.
+    Groups,
+    /// Every inode the bitmaps say is in use, on its own, as `verify` checks them.
+    Inodes,
+    /// Every inode reachable from the root, and the directories linking them.
+    Tree,
+    /// Link counts, and inodes which are allocated but unreachable.
+    References,
+    /// The list of inodes to be cleaned up at the next mount.
+    Orphans,
+    /// `/lost+found`.
+    LostAndFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Severity {
+    /// Worth knowing about, but the kernel would be fine with it.
+    Warning,
+    /// The filesystem is damaged.
+    Error,
+}
+
+/// Something wrong, or suspicious, about a filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Finding {
+    pub phase: Phase,
+    pub severity: Severity,
+    /// The inode concerned, if any.
+    pub inode: Option<u32>,
+    pub message: String,
+    /// Where the damage is, if it's in one place.
+    pub region: Option<Region>,
+}
+
+/// Where the bytes a finding is about are in the image, for patching tools, or looking at
+/// in a hex editor. Only the primary copy of anything is covered, not any backups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Region {
+    /// From the start of the image.
+    pub offset: u64,
+    pub len: u64,
+    /// What should be there instead, all `len` bytes, when that's certain: a checksum
+    /// which doesn't match what it covers, if what it covers is otherwise fine.
+    pub fix: Option<Vec<u8>>,
+}
+
+/// What we learnt about each reachable inode while walking the tree.
+#[derive(Default)]
+struct Seen {
+    /// Entries pointing at this inode, not counting `.` and `..`.
+    references: u32,
+    link_count: u16,
+    /// Directories only: the number of directories inside.
+    subdirectories: Option<u32>,
+}
+
+/// What the walk of the tree needs of an inode.
+struct Loaded {
+    link_count: u16,
+    file_type: FileType,
+    /// `None` if it has no data blocks.
+    extents: Option<Result<Vec<DataExtent>, Error>>,
+}
+
+struct Checker<'a, R> {
+    fs: &'a SuperBlock<R>,
+    findings: Vec<Finding>,
+    /// Inodes `check_parallel` loaded ahead of the walk, which takes them from here.
+    prefetched: HashMap<u32, Result<Loaded, Error>>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Check the filesystem's consistency, without modifying it. Problems with the
+    /// filesystem are returned as findings; an `Err` means the check itself couldn't run.
+    pub fn check(&self) -> Result<Vec<Finding>, Error> {
+        let mut checker = Checker::new(self);
+        checker.superblock();
+        for number in 0..self.groups.count() {
+            let found = group_findings(self, number)?;
+            checker.findings.extend(found);
+        }
+        checker.finish()
+    }
+
+    /// `check`, with the work spread over rayon's threads: each group's descriptor is
+    /// checked, and the inodes its bitmap says are in use are loaded, with their checksums
+    /// and extent trees verified, on a thread of its own. The walk of the tree, which then
+    /// mostly only has to list the directories, is still made on this thread. Only as many
+    /// inodes, and extents, as `Limits::max_extent_entries` allows are held for it; it
+    /// loads any more itself. The findings are the same as `check`'s, in the same order.
+    #[cfg(feature = "rayon")]
+    pub fn check_parallel(&self) -> Result<Vec<Finding>, Error>
+    where
+        R: Sync,
+    {
+        use rayon::prelude::*;
+
+        let budget = AtomicUsize::new(self.options.limits.max_extent_entries);
+        let groups = (0..self.groups.count())
+            .into_par_iter()
+            .map(|number| {
+                Ok((
+                    group_findings(self, number)?,
+                    prefetch(self, number, &budget),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut checker = Checker::new(self);
+        checker.superblock();
+        for (found, loaded) in groups {
+            checker.findings.extend(found);
+            checker.prefetched.extend(loaded);
+        }
+        checker.finish()
+    }
+}
+
+/// The problems with a group's descriptor.
+pub(crate) fn group_findings<R>(fs: &SuperBlock<R>, number: u32) -> Result<Vec<Finding>, Error>
+where
+    R: ReadAt,
+{
+    let groups = &fs.groups;
+    let first_block = groups.get(&fs.inner, 0)?.first_block;
+    let blocks_count = groups.blocks_count;
+    let inside = |block: u64, len: u64| block >= first_block && block + len <= blocks_count;
+
+    let group = groups.get(&fs.inner, number)?;
+    let (offset, len) = groups.descriptor_pos(number);
+    let descriptor = Region {
+        offset,
+        len: len as u64,
+        fix: None,
+    };
+    let mut messages = Vec::new();
+
+    if let Some(computed) = group.computed_checksum {
+        if computed != group.checksum {
+            // bg_checksum
+            let region = Region {
+                offset: offset + 0x1E,
+                len: 2,
+                fix: Some(computed.to_le_bytes().to_vec()),
+            };
+            messages.push((
+                format!(
+                    "group {} descriptor checksum is 0x{:04x}, should be 0x{:04x}",
+                    number, group.checksum, computed
+                ),
+                region,
+            ));
+        }
+    }
+
+    for (what, block, len) in &[
+        ("block bitmap", group.block_bitmap, 1),
+        ("inode bitmap", group.inode_bitmap, 1),
+        ("inode table", group.inode_table, group.inode_table_blocks),
+    ] {
+        if !inside(*block, *len) {
+            messages.push((
+                format!(
+                    "group {} {} at {} is outside the filesystem",
+                    number, what, block
+                ),
+                descriptor.clone(),
+            ));
+        }
+    }
+
+    Ok(messages
+        .into_iter()
+        .map(|(message, region)| Finding {
+            phase: Phase::Groups,
+            severity: Severity::Error,
+            inode: None,
+            message,
+            region: Some(region),
+        })
+        .collect())
+}
+
+/// Where an inode is, for a finding about it; if only its checksum is wrong, just the
+/// checksum, with what it should be. `None` if it isn't in the image at all.
+pub(crate) fn inode_region<R>(fs: &SuperBlock<R>, number: u32) -> Option<Region>
+where
+    R: ReadAt,
+{
+    let offset = fs.groups.index_of(&fs.inner, number).ok()?;
+    let data = fs.load_inode_bytes(number).ok()?;
+    let whole = Region {
+        offset,
+        len: data.len() as u64,
+        fix: None,
+    };
+
+    // only Linux's inodes have somewhere to put a checksum
+    let uuid_checksum = match (fs.uuid_checksum, CreatorOs::from_raw(fs.raw.s_creator_os)) {
+        (Some(_), CreatorOs::Hurd | CreatorOs::Masix) | (None, _) => return Some(whole),
+        (Some(uuid_checksum), _) => uuid_checksum,
+    };
+    // i_extra_isize has room for l_i_checksum_hi
+    let has_high = data.len() >= 0x84 && read_le16(&data[0x80..0x82]) >= 4;
+    let prefix = inode_checksum_prefix(uuid_checksum, number, read_le32(&data[0x64..0x68]));
+    let computed = inode_checksum(prefix, &mut data.clone(), has_high);
+
+    // l_i_checksum_lo, up to l_i_checksum_hi, if it's there
+    let stored = &data[0x7C..if has_high { 0x84 } else { 0x7E }];
+    let mut fix = stored.to_vec();
+    fix[..2].copy_from_slice(&computed.to_le_bytes()[..2]);
+    if has_high {
+        fix[6..].copy_from_slice(&computed.to_le_bytes()[2..]);
+    }
+    if fix == stored {
+        return Some(whole);
+    }
+    Some(Region {
+        offset: offset + 0x7C,
+        len: fix.len() as u64,
+        fix: Some(fix),
+    })
+}
+
+/// The inodes a group's bitmap says are in use, in order; none if the group's inodes
+/// are uninitialised, or its bitmap is past the end of the image.
+pub(crate) fn allocated_inodes<R>(fs: &SuperBlock<R>, number: u32) -> Result<Vec<u32>, Error>
+where
+    R: ReadAt,
+{
+    let groups = &fs.groups;
+    let group = groups.get(&fs.inner, number)?;
+    let missing = fs.first_missing_block().unwrap_or(u64::MAX);
+    if group.flags.contains(BlockGroupFlags::INODE_UNINIT) || group.inode_bitmap >= missing {
+        return Ok(Vec::new());
+    }
+    let mut bitmap = vec![0u8; usize::try_from(groups.block_size)?];
+    fs.inner.read_exact_at(
+        group.inode_bitmap * u64::from(groups.block_size),
+        &mut bitmap,
+    )?;
+
+    let inodes_per_group = groups.inodes_per_group();
+    let mut allocated = Vec::new();
+    for index in 0..inodes_per_group {
+        let byte = usize::try_from(index / 8)?;
+        if byte < bitmap.len() && 0 != bitmap[byte] & (1 << (index % 8)) {
+            allocated.push(number * inodes_per_group + index + 1);
+        }
+    }
+    Ok(allocated)
+}
+
+/// Load the inodes a group's bitmap says are in use, while there's `budget` for them: an
+/// inode costs one, and one more for each of its extents. Those there isn't room for, and
+/// those in a bitmap which can't be read, are left to be loaded as they're found, with any
+/// problem reported as `check` would.
+#[cfg(feature = "rayon")]
+fn prefetch<R>(
+    fs: &SuperBlock<R>,
+    number: u32,
+    budget: &AtomicUsize,
+) -> Vec<(u32, Result<Loaded, Error>)>
+where
+    R: ReadAt,
+{
+    let mut prefetched = Vec::new();
+    for inode in allocated_inodes(fs, number).unwrap_or_default() {
+        if 0 == budget.load(Ordering::Relaxed) {
+            break;
+        }
+        let loaded = fs.load_inode(inode).map(|loaded| Loaded::new(fs, &loaded));
+        let cost = 1 + match &loaded {
+            Ok(Loaded {
+                extents: Some(Ok(extents)),
+                ..
+            }) => extents.len(),
+            _ => 0,
+        };
+        let spent = budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            left.checked_sub(cost)
+        });
+        if spent.is_err() {
+            break;
+        }
+        prefetched.push((inode, loaded));
+    }
+    prefetched
+}
+
+impl Loaded {
+    fn new<R>(fs: &SuperBlock<R>, inode: &Inode) -> Loaded
+    where
+        R: ReadAt,
+    {
+        Loaded {
+            link_count: inode.stat.link_count,
+            file_type: inode.stat.extracted_type,
+            extents: if has_data_blocks(inode) {
+                Some(fs.data_extents(inode))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl<'a, R> Checker<'a, R>
+where
+    R: ReadAt,
+{
+    fn new(fs: &'a SuperBlock<R>) -> Checker<'a, R> {
+        Checker {
+            fs,
+            findings: Vec::new(),
+            prefetched: HashMap::new(),
+        }
+    }
+
+    /// Everything after the groups.
+    fn finish(mut self) -> Result<Vec<Finding>, Error> {
+        let seen = self.tree()?;
+        let orphans = self.orphans();
+        self.references(&seen, &orphans)?;
+        self.lost_and_found();
+
+        self.findings.sort_by_key(|finding| finding.phase);
+        Ok(self.findings)
+    }
+
+    /// What `tree` needs of an inode: what `check_parallel` loaded, or else loaded now.
+    fn load(&mut self, number: u32) -> Result<Loaded, Error> {
+        match self.prefetched.remove(&number) {
+            Some(loaded) => loaded,
+            None => Ok(Loaded::new(self.fs, &self.fs.load_inode(number)?)),
+        }
+    }
+
+    fn report(&mut self, phase: Phase, severity: Severity, inode: Option<u32>, message: String) {
+        self.findings.push(Finding {
+            phase,
+            severity,
+            inode,
+            message,
+            region: None,
+        });
+    }
+
+    /// An inode in the tree which couldn't be loaded, with where it is.
+    fn unreadable(&mut self, inode: u32, message: String) {
+        self.findings.push(Finding {
+            phase: Phase::Tree,
+            severity: Severity::Error,
+            inode: Some(inode),
+            message,
+            region: inode_region(self.fs, inode),
+        });
+    }
+
+    fn superblock(&mut self) {
+        if let Some(missing) = self.fs.first_missing_block() {
+            let blocks_count = self.fs.groups.blocks_count;
+            self.report(
+                Phase::Superblock,
+                Severity::Error,
+                None,
+                format!(
+                    "the image is truncated: blocks {}-{}, {} of {}, are missing",
+                    missing,
+                    blocks_count - 1,
+                    blocks_count - missing,
+                    blocks_count
+                ),
+            );
+        }
+
+        let inode = match self.fs.journal_inode {
+            None => return,
+            Some(0) => {
+                self.report(
+                    Phase::Superblock,
+                    Severity::Warning,
+                    None,
+                    "the journal is on an external device, so wasn't checked".to_string(),
+                );
+                return;
+            }
+            Some(inode) => inode,
+        };
+
+        match self.fs.journal() {
+            Ok(journal) => {
+                let pending = journal
+                    .transactions
+                    .iter()
+                    .filter(|transaction| transaction.needs_replay)
+                    .count();
+                if 0 != pending {
+                    self.report(
+                        Phase::Superblock,
+                        Severity::Warning,
+                        Some(inode),
+                        format!("the journal has {} transactions to replay", pending),
+                    );
+                }
+            }
+            Err(e) => self.report(
+                Phase::Superblock,
+                Severity::Error,
+                Some(inode),
+                format!("the journal is unreadable: {:#}", e),
+            ),
+        }
+    }
+
+    fn tree(&mut self) -> Result<HashMap<u32, Seen>, Error> {
+        let blocks_count = self.fs.groups.blocks_count;
+        let first_block = self.fs.groups.get(&self.fs.inner, 0)?.first_block;
+
+        let mut seen: HashMap<u32, Seen> = HashMap::new();
+        // (first block, length, owner), for finding blocks claimed twice
+        let mut claimed: Vec<(u64, u64, u32)> = Vec::new();
+        // (directory, its parent, its path)
+        let mut pending = vec![(2u32, 2u32, String::new())];
+
+        while let Some((number, parent, path)) = pending.pop() {
+            let inode = match self.fs.load_inode(number) {
+                Ok(inode) => inode,
+                Err(e) => {
+                    self.unreadable(number, format!("{}/ is unreadable: {:#}", path, e));
+                    continue;
+                }
+            };
+
+            let entries = match self.fs.enhance(&inode) {
+                Ok(Enhanced::Directory(entries)) => entries,
+                Ok(_) => {
+                    self.tree_error(number, format!("{}/ is not a directory", path));
+                    continue;
+                }
+                Err(e) => {
+                    self.tree_error(number, format!("{}/ can't be listed: {:#}", path, e));
+                    continue;
+                }
+            };
+
+            let mut subdirectories = 0;
+            for entry in entries {
+                let child_path = format!("{}/{}", path, entry.name);
+                let expected_dot = match entry.name.as_str() {
+                    "." => Some(number),
+                    ".." => Some(parent),
+                    _ => None,
+                };
+
+                if let Some(expected) = expected_dot {
+                    if expected != entry.inode {
+                        self.tree_error(
+                            number,
+                            format!(
+                                "'{}' points to <{}>, not <{}>",
+                                child_path, entry.inode, expected
+                            ),
+                        );
+                    }
+                    continue;
+                }
+
+                let first_visit = !seen.contains_key(&entry.inode);
+                seen.entry(entry.inode).or_default().references += 1;
+
+                if FileType::Directory == entry.file_type {
+                    subdirectories += 1;
+                }
+
+                if !first_visit {
+                    if FileType::Directory == entry.file_type {
+                        self.tree_error(
+                            entry.inode,
+                            format!("directory {} is linked more than once", child_path),
+                        );
+                    }
+                    continue;
+                }
+
+                let child = match self.load(entry.inode) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        self.unreadable(
+                            entry.inode,
+                            format!("{} is unreadable: {:#}", child_path, e),
+                        );
+                        continue;
+                    }
+                };
+
+                seen.entry(entry.inode).or_default().link_count = child.link_count;
+
+                if child.file_type != entry.file_type {
+                    self.tree_error(
+                        entry.inode,
+                        format!(
+                            "{} is a {:?} in its directory, but a {:?} in its inode",
+                            child_path, entry.file_type, child.file_type
+                        ),
+                    );
+                }
+
+                if FileType::Directory == child.file_type {
+                    pending.push((entry.inode, number, child_path.clone()));
+                }
+
+                let extents = match child.extents {
+                    Some(extents) => extents,
+                    None => continue,
+                };
+
+                match extents {
+                    Ok(extents) => {
+                        for extent in extents {
+                            let len = u64::from(extent.len);
+                            if extent.physical < first_block || extent.physical + len > blocks_count
+                            {
+                                self.tree_error(
+                                    entry.inode,
+                                    format!(
+                                        "{} has blocks {}-{}, outside the filesystem",
+                                        child_path,
+                                        extent.physical,
+                                        extent.physical + len - 1
+                                    ),
+                                );
+                            }
+                            if let Some(missing) = self.fs.first_missing_block() {
+                                if extent.physical + len > missing {
+                                    self.tree_error(
+                                        entry.inode,
+                                        format!(
+                                            "{} has blocks past the end of the image, from {}",
+                                            child_path,
+                                            extent.physical.max(missing)
+                                        ),
+                                    );
+                                }
+                            }
+                            claimed.push((extent.physical, len, entry.inode));
+                        }
+                    }
+                    Err(e) => self.tree_error(
+                        entry.inode,
+                        format!("{} has an unreadable extent tree: {:#}", child_path, e),
+                    ),
+                }
+            }
+
+            let root = seen.entry(number).or_default();
+            root.subdirectories = Some(subdirectories);
+            if 2 == number {
+                root.link_count = inode.stat.link_count;
+            }
+        }
+
+        // deduplicated on purpose; see `SuperBlock::shared_extents`
+        if !self.fs.has_shared_blocks() {
+            for shared in overlaps(&claimed) {
+                let owners = shared
+                    .inodes
+                    .iter()
+                    .map(|inode| format!("<{}>", inode))
+                    .collect::<Vec<_>>();
+                self.report(
+                    Phase::Tree,
+                    Severity::Error,
+                    shared.inodes.last().copied(),
+                    match shared.len {
+                        1 => format!("{} claim block {}", owners.join(", "), shared.physical),
+                        len => format!(
+                            "{} claim blocks {}-{}",
+                            owners.join(", "),
+                            shared.physical,
+                            shared.physical + len - 1
+                        ),
+                    },
+                );
+            }
+        }
+
+        Ok(seen)
+    }
+
+    fn tree_error(&mut self, inode: u32, message: String) {
+        self.report(Phase::Tree, Severity::Error, Some(inode), message);
+    }
+
+    /// Follow the orphan list, which is chained through the inodes' deletion times.
+    fn orphans(&mut self) -> HashSet<u32> {
+        let mut orphans = HashSet::new();
+        let mut next = self.fs.last_orphan;
+
+        while 0 != next {
+            if !orphans.insert(next) {
+                self.report(
+                    Phase::Orphans,
+                    Severity::Error,
+                    Some(next),
+                    "the orphan list loops".to_string(),
+                );
+                break;
+            }
+
+            let data = match self.fs.load_inode_bytes(next) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.report(
+                        Phase::Orphans,
+                        Severity::Error,
+                        Some(next),
+                        format!("orphan <{}> is unreadable: {:#}", next, e),
+                    );
+                    break;
+                }
+            };
+
+            self.report(
+                Phase::Orphans,
+                Severity::Warning,
+                Some(next),
+                format!(
+                    "<{}> is an orphan, and will be cleaned up on the next mount",
+                    next
+                ),
+            );
+
+            // i_dtime
+            next = read_le32(&data[0x14..0x18]);
+        }
+
+        orphans
+    }
+
+    fn references(
+        &mut self,
+        seen: &HashMap<u32, Seen>,
+        orphans: &HashSet<u32>,
+    ) -> Result<(), Error> {
+        let mut numbers = seen.keys().copied().collect::<Vec<_>>();
+        numbers.sort_unstable();
+
+        for number in numbers {
+            let inode = &seen[&number];
+            let expected = match inode.subdirectories {
+                // with dir_nlink, a count of one means "too many to count"
+                Some(_) if 1 == inode.link_count => continue,
+                Some(subdirectories) => 2 + subdirectories,
+                None => inode.references,
+            };
+
+            if expected != u32::from(inode.link_count) {
+                self.report(
+                    Phase::References,
+                    Severity::Error,
+                    Some(number),
+                    format!(
+                        "<{}> has a link count of {}, but should be {}",
+                        number, inode.link_count, expected
+                    ),
+                );
+            }
+        }
+
+        let groups = &self.fs.groups;
+        let inodes_per_group = groups.inodes_per_group();
+        let mut bitmap = vec![0u8; usize::try_from(groups.block_size)?];
+
+        for group_number in 0..groups.count() {
+            let group = groups.get(&self.fs.inner, group_number)?;
+            let uninit = group.flags.contains(BlockGroupFlags::INODE_UNINIT);
+            let missing = self.fs.first_missing_block().unwrap_or(u64::MAX);
+            if !uninit && group.inode_bitmap >= missing {
+                // covered by the report that the image is truncated
+                continue;
+            }
+            if !uninit {
+                self.fs.inner.read_exact_at(
+                    group.inode_bitmap * u64::from(groups.block_size),
+                    &mut bitmap,
+                )?;
+            }
+
+            for index in 0..inodes_per_group {
+                let number = group_number * inodes_per_group + index + 1;
+                let byte = usize::try_from(index / 8)?;
+                let allocated =
+                    !uninit && byte < bitmap.len() && 0 != bitmap[byte] & (1 << (index % 8));
+                let reachable = seen.contains_key(&number);
+
+                if reachable && !allocated {
+                    self.report(
+                        Phase::References,
+                        Severity::Error,
+                        Some(number),
+                        format!("<{}> is in use, but marked free in the bitmap", number),
+                    );
+                } else if allocated
+                    && !reachable
+                    && number >= self.fs.first_inode
+                    && !orphans.contains(&number)
+                {
+                    self.report(
+                        Phase::References,
+                        Severity::Warning,
+                        Some(number),
+                        format!("<{}> is allocated, but not in any directory", number),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lost_and_found(&mut self) {
+        // the filesystem's own, in the real root, even if `with_root` has moved `/`
+        let entry = match self
+            .fs
+            .load_inode(2)
+            .and_then(|root| self.fs.dir_entry_named(&root, "lost+found"))
+        {
+            Ok(entry) => entry,
+            Err(e) => {
+                let severity = match e.downcast_ref::<ParseError>() {
+                    Some(ParseError::NotFound { .. }) => Severity::Warning,
+                    _ => Severity::Error,
+                };
+                self.report(
+                    Phase::LostAndFound,
+                    severity,
+                    None,
+                    format!("/lost+found can't be found: {:#}", e),
+                );
+                return;
+            }
+        };
+
+        let entries = match self
+            .fs
+            .load_inode(entry.inode)
+            .and_then(|inode| self.fs.enhance(&inode))
+        {
+            Ok(Enhanced::Directory(entries)) => entries,
+            Ok(_) => {
+                self.report(
+                    Phase::LostAndFound,
+                    Severity::Error,
+                    Some(entry.inode),
+                    "/lost+found is not a directory".to_string(),
+                );
+                return;
+            }
+            // already reported while walking the tree
+            Err(_) => return,
+        };
+
+        for found in entries {
+            if "." == found.name || ".." == found.name {
+                continue;
+            }
+            self.report(
+                Phase::LostAndFound,
+                Severity::Warning,
+                Some(found.inode),
+                format!("/lost+found contains {}", found.name),
+            );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::Read;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::sha256::Sha256;
+use crate::Inode;
+use crate::SuperBlock;
+
+/// How `SuperBlock::chunk_file` splits a file's content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunker {
+    /// Every this many bytes; only the last chunk can be shorter.
+    Fixed(u32),
+    /// Where the content says, with FastCDC (Xia et al., 2016), so an insertion or
+    /// deletion only changes the chunks around it. Chunks are between `min` and `max`
+    /// bytes, except the last, which can be shorter, and usually around `avg`.
+    ///
+    /// The gear hash's table is our own, so the boundaries won't match other FastCDC
+    /// implementations', but they won't change between releases.
+    Fastcdc { min: u32, avg: u32, max: u32 },
+}
+
+impl Chunker {
+    /// FastCDC, with chunks of 2KiB to 64KiB, and 8KiB on average.
+    pub const FASTCDC: Chunker = Chunker::Fastcdc {
+        min: 2 * 1024,
+        avg: 8 * 1024,
+        max: 64 * 1024,
+    };
+}
+
+/// A piece of a file's content, from `SuperBlock::chunk_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Chunk {
+    /// Where it starts, in the file.
+    pub offset: u64,
+    pub len: u32,
+    /// The SHA-256 of its content.
+    pub digest: [u8; 32],
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Split a file's content into chunks, as `chunker` says, with the digest of each, so
+    /// content can be deduplicated without extracting it first. The chunks cover the
+    /// whole file, in order; an empty file has none. A chunk's content can be read with
+    /// `open`, and a seek to its `offset`.
+    pub fn chunk_file(&self, inode: &Inode, chunker: Chunker) -> Result<Vec<Chunk>, Error> {
+        let (min, max) = match chunker {
+            Chunker::Fixed(len) => {
+                ensure!(0 != len, "fixed chunks can't be empty");
+                (len, len)
+            }
+            Chunker::Fastcdc { min, avg, max } => {
+                ensure!(
+                    0 < min && min <= avg && avg <= max,
+                    "FastCDC needs 0 < min <= avg <= max, not {:?}",
+                    chunker
+                );
+                (min, max)
+            }
+        };
+        let max = usize::try_from(max)?;
+
+        let mut reader = self.open(inode)?;
+        let mut chunks = Vec::new();
+        // read, but not yet in a chunk
+        let mut pending = Vec::with_capacity(max);
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut offset = 0u64;
+        let mut ended = false;
+        loop {
+            while !ended && pending.len() < max {
+                let want = buf.len().min(max - pending.len());
+                let read = reader.read(&mut buf[..want])?;
+                if 0 == read {
+                    ended = true;
+                }
+                pending.extend_from_slice(&buf[..read]);
+            }
+            if pending.is_empty() {
+                break;
+            }
+
+            let len = match chunker {
+                Chunker::Fixed(_) => pending.len().min(max),
+                Chunker::Fastcdc { avg, .. } => {
+                    fastcdc_cut(&pending, usize::try_from(min)?, usize::try_from(avg)?, max)
+                }
+            };
+            let mut digest = Sha256::new();
+            digest.update(&pending[..len]);
+            chunks.push(Chunk {
+                offset,
+                len: u32::try_from(len)?,
+                digest: digest.finish(),
+            });
+            offset += len as u64;
+            pending.drain(..len);
+        }
+
+        Ok(chunks)
+    }
+}
+
+/// How long FastCDC makes the chunk at the start of `data`: past `min`, it ends after the
+/// first byte whose gear hash is zero under a mask, which is harder to hit before `avg`,
+/// and easier after, so lengths cluster around `avg` ("normalised chunking", level one).
+fn fastcdc_cut(data: &[u8], min: usize, avg: usize, max: usize) -> usize {
+    if data.len() <= min {
+        return data.len();
+    }
+    let end = data.len().min(max);
+    let normal = end.min(avg);
+
+    // the top bits, which depend on the last 64 bytes, rather than the last few
+    let mask = |bits: u32| !0u64 << (64 - bits);
+    let bits = (63 - (avg as u64).leading_zeros()).clamp(2, 62);
+    let (hard, easy) = (mask(bits + 1), mask(bits - 1));
+
+    let mut hash = 0u64;
+    for (i, &b) in data.iter().enumerate().take(end).skip(min) {
+        hash = (hash << 1).wrapping_add(GEAR[usize::from(b)]);
+        let wanted = if i < normal { hard } else { easy };
+        if 0 == hash & wanted {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// The gear hash's random numbers: splitmix64's, from zero.
+const GEAR: [u64; 256] = gear();
+
+const fn gear() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
@@ -0,0 +1,77 @@
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::Inode;
+use crate::SuperBlock;
+
+/// The most copied at once: a multiple of every block size, so reads stay block aligned.
+const CHUNK: usize = 1024 * 1024;
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Write a file's content to `sink`, returning how much was written. The same bytes
+    /// as reading `open`, but each extent is copied straight out of the image in large,
+    /// block aligned reads, which is much faster for big, contiguous files.
+    ///
+    /// Holes, and data past the end of a truncated image, are written as zeros. As with
+    /// `open`, an encrypted file's content is its ciphertext.
+    pub fn copy_raw(&self, inode: &Inode, sink: &mut dyn Write) -> Result<u64, Error> {
+        let block_size = u64::from(self.groups.block_size);
+        let size = inode.stat.size;
+        let available = self.image_len.unwrap_or(u64::MAX);
+        let mut buf = vec![0u8; CHUNK];
+
+        let mut pos = 0;
+        for extent in self.data_extents(inode)? {
+            let start = u64::from(extent.logical) * block_size;
+            if start >= size {
+                break;
+            }
+            ensure!(
+                start >= pos,
+                assumption_failed(format!("<{}>'s extents overlap", inode.number))
+            );
+            write_zeros(sink, &mut buf, start - pos)?;
+
+            let len = (u64::from(extent.len) * block_size).min(size - start);
+            let physical = extent.physical * block_size;
+            let mut done = 0;
+            while done < len {
+                let chunk = (len - done).min(CHUNK as u64) as usize;
+                let offset = physical + done;
+                let stored = available.saturating_sub(offset).min(chunk as u64) as usize;
+                self.inner
+                    .read_exact_at(offset, &mut buf[..stored])
+                    .with_context(|| anyhow!("reading <{}> at {}", inode.number, offset))?;
+                buf[stored..chunk].iter_mut().for_each(|b| *b = 0);
+                sink.write_all(&buf[..chunk])?;
+                done += chunk as u64;
+            }
+            pos = start + len;
+        }
+        write_zeros(sink, &mut buf, size - pos)?;
+
+        Ok(size)
+    }
+}
+
+fn write_zeros(sink: &mut dyn Write, buf: &mut [u8], mut len: u64) -> Result<(), Error> {
+    if 0 == len {
+        return Ok(());
+    }
+    buf.iter_mut().for_each(|b| *b = 0);
+    while len > 0 {
+        let chunk = len.min(buf.len() as u64) as usize;
+        sink.write_all(&buf[..chunk])?;
+        len -= chunk as u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,839 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::dumpe2fs::ctime;
+use crate::dumpe2fs::MONTHS;
+use crate::not_found;
+use crate::ondisk::RawExtent;
+use crate::ondisk::RawExtentHeader;
+use crate::ondisk::RawExtentIdx;
+use crate::ondisk::RawInode;
+use crate::read_le32;
+use crate::sharing::has_data_blocks;
+use crate::unsupported_feature;
+use crate::zip::civil_from_days;
+use crate::BlockUse;
+use crate::DataExtent;
+use crate::FileType;
+use crate::Inode;
+use crate::InodeFlags;
+use crate::ResolveOptions;
+use crate::SuperBlock;
+use crate::INODE_CORE_SIZE;
+
+/// Runs `debugfs` command lines, such as `stat <12>`, or `ls -l /etc`, printing what
+/// `debugfs` (1.47) prints, so scripts written for it can be pointed at an image this
+/// crate has opened.
+///
+/// Only commands which read are understood: `cd`, `pwd`, `ls` (with `-l`, `-p` and
+/// `-r`), `stat`, `cat`, `dump` (with `-p`), `blocks`, `icheck` and `ncheck` (with `-c`),
+/// and their long names, like `show_inode_info`. Paths are relative to the current
+/// directory, or can be an inode number, like `<12>`. Times are in UTC.
+///
+/// Where `debugfs` scans every inode, this walks from the root: `icheck` only finds
+/// blocks `block_map` does, and `ncheck` only reachable names. `stat` lists extended
+/// attributes by name, rather than in the order they're stored.
+pub struct Debugfs<'a, R> {
+    fs: &'a SuperBlock<R>,
+    /// The current directory, which `cd` changes.
+    cwd: u32,
+    /// Its path, which relative paths are joined to.
+    cwd_path: String,
+}
+
+/// An entry of an extent tree, as `stat` lists it.
+enum TreeEntry {
+    /// An index entry, in a node `level`s below the root, and the block it points to.
+    Index { level: u16, block: u64 },
+    Leaf {
+        logical: u32,
+        physical: u64,
+        len: u16,
+        unwritten: bool,
+    },
+}
+
+impl<'a, R> Debugfs<'a, R>
+where
+    R: ReadAt,
+{
+    /// Start in the root directory.
+    pub fn new(fs: &'a SuperBlock<R>) -> Debugfs<'a, R> {
+        Debugfs {
+            fs,
+            cwd: fs.root_inode,
+            cwd_path: "/".to_string(),
+        }
+    }
+
+    /// Run a command line, like `debugfs -R`, writing what it prints to `out`.
+    pub fn run(&mut self, line: &str, out: &mut dyn io::Write) -> Result<(), Error> {
+        let words = words(line)?;
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (command.as_str(), args),
+            None => return Ok(()),
+        };
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match command {
+            "cd" | "change_working_directory" => self.cd(&args),
+            "pwd" | "print_working_directory" => self.pwd(&args, out),
+            "ls" | "list_directory" => self.ls(&args, out),
+            "stat" | "show_inode_info" => self.stat(&args, out),
+            "cat" => self.cat(&args, out),
+            "dump" | "dump_inode" => self.dump(&args),
+            "blocks" => self.blocks(&args, out),
+            "icheck" => self.icheck(&args, out),
+            "ncheck" => self.ncheck(&args, out),
+            _ => Err(not_found(format!("Command not found {}", command)).into()),
+        }
+    }
+
+    /// Run each line of `script`, like `debugfs -f`: each is written to `out` after
+    /// `debugfs: `, then what it prints; lines starting with `#` are only written. A
+    /// command which fails has its error written to `errors`, and the rest are still
+    /// run. Returns how many failed.
+    pub fn run_script(
+        &mut self,
+        script: &str,
+        out: &mut dyn io::Write,
+        errors: &mut dyn io::Write,
+    ) -> Result<u32, Error> {
+        let mut failed = 0;
+        for line in script.lines() {
+            if line.starts_with('#') {
+                writeln!(out, "{}", line)?;
+                continue;
+            }
+            writeln!(out, "debugfs: {}", line)?;
+            if let Err(e) = self.run(line, out) {
+                let command = line.split_whitespace().next().unwrap_or_default();
+                writeln!(errors, "{}: {:#}", command, e)?;
+                failed += 1;
+            }
+        }
+        Ok(failed)
+    }
+
+    /// An inode, by number, like `<12>`, or by path, from the current directory. A link
+    /// at the end of the path isn't followed.
+    fn inode(&self, spec: &str) -> Result<Inode, Error> {
+        if let Some(number) = spec.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            let number = number
+                .parse::<u32>()
+                .map_err(|_| not_found(format!("Bad inode - {}", spec)))?;
+            return self.fs.load_inode(number);
+        }
+
+        let path = if spec.starts_with('/') {
+            spec.to_string()
+        } else {
+            format!("{}/{}", self.cwd_path.trim_end_matches('/'), spec)
+        };
+        let options = ResolveOptions {
+            follow_final: false,
+            ..Default::default()
+        };
+        let (_, entry) = self
+            .fs
+            .resolve_with_options(&path, &options)
+            .with_context(|| anyhow!("{}: File not found by ext2_lookup", spec))?;
+        self.fs.load_inode(entry.inode)
+    }
+
+    /// An inode named by the only argument, or the usage.
+    fn only_inode(&self, args: &[&str], usage: &str) -> Result<Inode, Error> {
+        match args {
+            [spec] => self.inode(spec),
+            _ => Err(anyhow!("Usage: {}", usage)),
+        }
+    }
+
+    /// A directory's path, found from its `..`s, like `ext2fs_get_pathname`.
+    fn path_of(&self, dir: &Inode) -> Result<String, Error> {
+        let mut path = String::new();
+        let mut child = dir.number;
+        for parent in self.fs.ancestry(dir)? {
+            let record = self
+                .fs
+                .dir_records(&self.fs.load_inode(parent)?, false)?
+                .into_iter()
+                .find(|record| child == record.inode && !is_dots(&record.name))
+                .ok_or_else(|| not_found(format!("<{}> isn't in <{}>", child, parent)))?;
+            path.insert_str(0, &String::from_utf8_lossy(&record.name));
+            path.insert(0, '/');
+            child = parent;
+        }
+        Ok(if path.is_empty() {
+            "/".to_string()
+        } else {
+            path
+        })
+    }
+
+    fn cd(&mut self, args: &[&str]) -> Result<(), Error> {
+        let dir = self.only_inode(args, "cd <file>")?;
+        ensure!(
+            FileType::Directory == dir.stat.extracted_type,
+            not_found(format!("<{}>: Ext2 inode is not a directory", dir.number))
+        );
+        self.cwd_path = self.path_of(&dir)?;
+        self.cwd = dir.number;
+        Ok(())
+    }
+
+    fn pwd(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        ensure!(args.is_empty(), "Usage: pwd");
+        writeln!(
+            out,
+            "[pwd]   INODE: {:>6}  PATH: {}",
+            self.cwd, self.cwd_path
+        )?;
+        writeln!(out, "[root]  INODE: {:>6}  PATH: /", self.fs.root_inode)?;
+        Ok(())
+    }
+
+    fn ls(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        let (mut long, mut parse, mut raw) = (false, false, false);
+        let mut paths = Vec::new();
+        for arg in args {
+            match arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+                Some(flags) => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'l' => long = true,
+                            'p' => parse = true,
+                            'r' => raw = true,
+                            _ => return Err(unsupported_feature(format!("ls -{}", flag)).into()),
+                        }
+                    }
+                }
+                None => paths.push(*arg),
+            }
+        }
+        let dir = match paths[..] {
+            [] => self.fs.load_inode(self.cwd)?,
+            [path] => self.inode(path)?,
+            _ => return Err(anyhow!("Usage: ls [-l] [-p] [-r] [file]")),
+        };
+        ensure!(
+            FileType::Directory == dir.stat.extracted_type,
+            not_found(format!("<{}>: Ext2 inode is not a directory", dir.number))
+        );
+
+        let records = self.fs.dir_records(&dir, false)?;
+        let inodes = if long || parse {
+            self.fs
+                .load_inodes(&records.iter().map(|r| r.inode).collect::<Vec<_>>())
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let mut column = 0;
+        for (index, record) in records.iter().enumerate() {
+            let name = if dir.is_encrypted() && !raw {
+                format!("<encrypted ({})>", record.name.len()).into_bytes()
+            } else if raw {
+                record.name.clone()
+            } else {
+                escape(&record.name)
+            };
+
+            if parse {
+                let stat = &inodes[index].stat;
+                write!(
+                    out,
+                    "/{}/{:06o}/{}/{}/",
+                    record.inode,
+                    stat.extracted_type.mode_bits() | stat.file_mode,
+                    stat.uid,
+                    stat.gid
+                )?;
+                out.write_all(&record.name)?;
+                if FileType::Directory == stat.extracted_type {
+                    writeln!(out, "//")?;
+                } else {
+                    writeln!(out, "/{}/", stat.size)?;
+                }
+            } else if long {
+                let stat = &inodes[index].stat;
+                write!(
+                    out,
+                    " {:>6}  {:>6o} ({})  {:>5}  {:>5}   {:>5} {} ",
+                    record.inode,
+                    stat.extracted_type.mode_bits() | stat.file_mode,
+                    record.file_type.map_or(0, FileType::dir_hint),
+                    stat.uid,
+                    stat.gid,
+                    stat.size,
+                    date(stat.mtime.epoch_secs)
+                )?;
+                out.write_all(&name)?;
+                writeln!(out)?;
+            } else {
+                // wrapped to 80 columns
+                let head = format!(" {}  ({}) ", record.inode, record.rec_len);
+                let len = head.len() + name.len() + 3;
+                if column + len > 80 {
+                    writeln!(out)?;
+                    column = 0;
+                }
+                out.write_all(head.as_bytes())?;
+                out.write_all(&name)?;
+                out.write_all(b"   ")?;
+                column += len;
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn stat(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        let inode = self.only_inode(args, "stat <file>")?;
+        let raw = RawInode::from_slice(&self.fs.load_inode_bytes(inode.number)?)?;
+        let large = usize::from(self.fs.groups.inode_size) > RawInode::SMALL_SIZE;
+        let extra = if large { raw.i_extra_isize } else { 0 };
+
+        let kind = match raw.i_mode & 0xF000 {
+            0x4000 => "directory",
+            0x8000 => "regular",
+            0xA000 => "symlink",
+            0x6000 => "block special",
+            0x2000 => "character special",
+            0x1000 => "FIFO",
+            0xC000 => "socket",
+            _ => "bad type",
+        };
+        writeln!(
+            out,
+            "Inode: {}   Type: {}    Mode:  0{:03o}   Flags: 0x{:x}",
+            inode.number,
+            kind,
+            raw.i_mode & 0o7777,
+            raw.i_flags
+        )?;
+        if extra >= 24 {
+            writeln!(
+                out,
+                "Generation: {}    Version: 0x{:08x}:{:08x}",
+                raw.i_generation, raw.i_version_hi, raw.l_i_version
+            )?;
+        } else {
+            writeln!(
+                out,
+                "Generation: {}    Version: 0x{:08x}",
+                raw.i_generation, raw.l_i_version
+            )?;
+        }
+        let uid = u32::from(raw.i_uid) | (u32::from(raw.l_i_uid_high) << 16);
+        let gid = u32::from(raw.i_gid) | (u32::from(raw.l_i_gid_high) << 16);
+        write!(out, "User: {:>5}   Group: {:>5}", uid as i32, gid as i32)?;
+        if extra >= 32 {
+            write!(out, "   Project: {:>5}", raw.i_projid as i32)?;
+        }
+        writeln!(
+            out,
+            "   Size: {}",
+            u64::from(raw.i_size_lo) | (u64::from(raw.i_size_high) << 32)
+        )?;
+        writeln!(
+            out,
+            "File ACL: {}",
+            u64::from(raw.i_file_acl_lo) | (u64::from(raw.l_i_file_acl_high) << 32)
+        )?;
+        writeln!(
+            out,
+            "Links: {}   Blockcount: {}",
+            raw.i_links_count,
+            u64::from(raw.i_blocks_lo) | (u64::from(raw.l_i_blocks_high) << 32)
+        )?;
+        writeln!(
+            out,
+            "Fragment:  Address: {}    Number: 0    Size: 0",
+            raw.i_obso_faddr
+        )?;
+
+        if extra >= 24 {
+            for (label, secs, high) in [
+                (" ctime", raw.i_ctime, raw.i_ctime_extra),
+                (" atime", raw.i_atime, raw.i_atime_extra),
+                (" mtime", raw.i_mtime, raw.i_mtime_extra),
+                ("crtime", raw.i_crtime, raw.i_crtime_extra),
+            ] {
+                writeln!(
+                    out,
+                    "{}: 0x{:08x}:{:08x} -- {}",
+                    label,
+                    secs,
+                    high,
+                    ctime(extended_time(secs, high))
+                )?;
+            }
+            if 0 != raw.i_dtime {
+                writeln!(
+                    out,
+                    " dtime: 0x{:08x}:({:08x}) -- {}",
+                    raw.i_dtime,
+                    raw.i_ctime_extra,
+                    ctime(extended_time(raw.i_dtime, raw.i_ctime_extra))
+                )?;
+            }
+        } else {
+            for (label, secs) in [
+                ("ctime", raw.i_ctime),
+                ("atime", raw.i_atime),
+                ("mtime", raw.i_mtime),
+                ("dtime", raw.i_dtime),
+            ] {
+                if "dtime" == label && 0 == secs {
+                    continue;
+                }
+                writeln!(
+                    out,
+                    "{}: 0x{:08x} -- {}",
+                    label,
+                    secs,
+                    ctime(extended_time(secs, 0))
+                )?;
+            }
+        }
+        if large {
+            writeln!(out, "Size of extra inode fields: {}", raw.i_extra_isize)?;
+        }
+
+        let xattrs = &inode.stat.xattrs;
+        if !xattrs.is_empty() {
+            writeln!(out, "Extended attributes:")?;
+            let mut names = xattrs.keys().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                let value = &xattrs[name];
+                out.write_all(b"  ")?;
+                out.write_all(&xattr_string(name.as_bytes(), false))?;
+                write!(out, " ({})", value.len())?;
+                if "system.data" != name.as_str() && !value.is_empty() && value.len() < 40 {
+                    out.write_all(b" = ")?;
+                    out.write_all(&xattr_string(value, true))?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        if 0 != self.fs.raw.s_feature_ro_compat & 0x0400 {
+            let mut checksum = u32::from(raw.l_i_checksum_lo);
+            if extra >= 4 {
+                checksum |= u32::from(raw.i_checksum_hi) << 16;
+            }
+            writeln!(out, "Inode checksum: 0x{:08x}", checksum)?;
+        }
+
+        match inode.stat.extracted_type {
+            FileType::SymbolicLink
+                if inode.is_fast_symlink() && !inode.flags.contains(InodeFlags::INLINE_DATA) =>
+            {
+                out.write_all(b"Fast link dest: \"")?;
+                out.write_all(&raw.i_block[..size as usize])?;
+                writeln!(out, "\"")?;
+            }
+            FileType::SymbolicLink if inode.flags.contains(InodeFlags::INLINE_DATA) => {
+                let target = self.fs.read_link(&inode)?;
+                writeln!(out, "Fast link dest: \"{}\"", target.to_string_lossy())?;
+            }
+            FileType::CharacterDevice | FileType::BlockDevice => {
+                let (old, new) = (read_le32(&raw.i_block[0..]), read_le32(&raw.i_block[4..]));
+                let (major, minor, style) = if 0 != old {
+                    ((old >> 8) & 0xff, old & 0xff, "")
+                } else {
+                    (
+                        (new & 0xfff00) >> 8,
+                        (new & 0xff) | ((new >> 12) & 0xfff00),
+                        "(New-style) ",
+                    )
+                };
+                writeln!(
+                    out,
+                    "{}Device major/minor number: {:02}:{:02} (hex {:02x}:{:02x})",
+                    style, major, minor, major, minor
+                )?;
+            }
+            _ if inode.flags.contains(InodeFlags::EXTENTS) => {
+                writeln!(out, "EXTENTS:")?;
+                let entries = self.tree_entries(&inode)?;
+                for (index, entry) in entries.iter().enumerate() {
+                    write!(out, "{}{}", if 0 == index { "" } else { ", " }, entry)?;
+                }
+                if !entries.is_empty() {
+                    writeln!(out)?;
+                }
+            }
+            _ if inode.flags.contains(InodeFlags::INLINE_DATA) => {
+                let stored = xattrs.get("system.data").map_or(0, Vec::len);
+                writeln!(out, "Size of inline data: {}", INODE_CORE_SIZE + stored)?;
+            }
+            _ => {
+                writeln!(out, "BLOCKS:")?;
+                let mut total = 0;
+                for (index, extent) in self.mapped_extents(&inode)?.iter().enumerate() {
+                    let entry = TreeEntry::Leaf {
+                        logical: extent.logical,
+                        physical: extent.physical,
+                        len: extent.len,
+                        unwritten: false,
+                    };
+                    write!(out, "{}{}", if 0 == index { "" } else { ", " }, entry)?;
+                    total += u64::from(extent.len);
+                }
+                if 0 != total {
+                    write!(out, "\nTOTAL: {}\n", total)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cat(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        let inode = self.only_inode(args, "cat <file>")?;
+        io::copy(&mut self.fs.open(&inode)?, out)?;
+        Ok(())
+    }
+
+    fn dump(&self, args: &[&str]) -> Result<(), Error> {
+        let (preserve, args) = match args {
+            ["-p", rest @ ..] => (true, rest),
+            _ => (false, args),
+        };
+        let (spec, dest) = match args {
+            [spec, dest] => (*spec, *dest),
+            _ => return Err(anyhow!("Usage: dump_inode [-p] <file> <output_file>")),
+        };
+        let inode = self.inode(spec)?;
+        let mut file = fs::File::create(dest).with_context(|| anyhow!("creating {:?}", dest))?;
+        io::copy(&mut self.fs.open(&inode)?, &mut file)?;
+        if preserve {
+            set_mode(&file, inode.stat.file_mode)?;
+        }
+        Ok(())
+    }
+
+    fn blocks(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        let inode = self.only_inode(args, "blocks <file>")?;
+        if inode.flags.contains(InodeFlags::EXTENTS) {
+            for entry in self.tree_entries(&inode)? {
+                match entry {
+                    TreeEntry::Index { block, .. } => write!(out, "{} ", block)?,
+                    TreeEntry::Leaf { physical, len, .. } => {
+                        for block in physical..physical + u64::from(len) {
+                            write!(out, "{} ", block)?;
+                        }
+                    }
+                }
+            }
+        } else if !inode.flags.contains(InodeFlags::INLINE_DATA) {
+            for extent in self.mapped_extents(&inode)? {
+                for block in extent.physical..extent.physical + u64::from(extent.len) {
+                    write!(out, "{} ", block)?;
+                }
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn icheck(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        ensure!(!args.is_empty(), "Usage: icheck <block number> ...");
+        let blocks = args
+            .iter()
+            .map(|arg| number(arg).ok_or_else(|| anyhow!("Bad block number - {}", arg)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let map = self.fs.block_map()?;
+        writeln!(out, "Block\tInode number")?;
+        for block in blocks {
+            let owner = map
+                .get(map.partition_point(|run| run.start + run.len <= block))
+                .filter(|run| run.start <= block)
+                .and_then(|run| match run.usage {
+                    BlockUse::Data(inode) | BlockUse::ExtentTree(inode) => Some(inode),
+                    BlockUse::Journal => self.fs.journal_inode,
+                    _ => None,
+                });
+            match owner {
+                Some(inode) => writeln!(out, "{}\t{}", block, inode)?,
+                None => writeln!(out, "{}\t<block not found>", block)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn ncheck(&self, args: &[&str], out: &mut dyn io::Write) -> Result<(), Error> {
+        let (check, args) = match args {
+            ["-c", rest @ ..] => (true, rest),
+            _ => (false, args),
+        };
+        ensure!(!args.is_empty(), "Usage: ncheck [-c] <inode number> ...");
+        let wanted = args
+            .iter()
+            .map(|arg| {
+                number(arg)
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| anyhow!("Bad inode - {}", arg))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // (the directory, the line), as `debugfs` looks through directories by number
+        let mut found = Vec::new();
+        self.fs
+            .walk(&self.fs.root()?, "", &mut |fs, path, dir, _| {
+                if FileType::Directory != dir.stat.extracted_type {
+                    return Ok(true);
+                }
+                for record in fs.dir_records(dir, false)? {
+                    if !wanted.contains(&record.inode) || is_dots(&record.name) {
+                        continue;
+                    }
+                    let parent = if path.is_empty() { "/" } else { path };
+                    let mut line = format!("{}\t{}/", record.inode, parent).into_bytes();
+                    line.extend_from_slice(&record.name);
+                    if let (true, Some(hint)) = (check, record.file_type) {
+                        if hint != fs.load_inode(record.inode)?.stat.extracted_type {
+                            line.extend_from_slice(b"  <--- BAD FILETYPE");
+                        }
+                    }
+                    found.push((dir.number, line));
+                }
+                Ok(true)
+            })?;
+        found.sort_by_key(|&(dir, _)| dir);
+
+        writeln!(out, "Inode\tPathname")?;
+        for (_, line) in found {
+            out.write_all(&line)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Every entry of an inode's extent tree, depth first, each index entry before what
+    /// it points to, as `debugfs` visits them.
+    fn tree_entries(&self, inode: &Inode) -> Result<Vec<TreeEntry>, Error> {
+        let root = RawExtentHeader::from_slice(&inode.core)?;
+        inode.limits.check(
+            "extent tree depth",
+            u64::from(root.eh_depth),
+            u64::from(inode.limits.max_extent_depth),
+        )?;
+        let mut entries = Vec::new();
+        self.add_tree_entries(&inode.core, 0, None, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn add_tree_entries(
+        &self,
+        node: &[u8],
+        level: u16,
+        parent_depth: Option<u16>,
+        entries: &mut Vec<TreeEntry>,
+    ) -> Result<(), Error> {
+        let header = RawExtentHeader::from_slice(node)?;
+        ensure!(
+            RawExtentHeader::MAGIC == header.eh_magic,
+            assumption_failed("invalid extent magic")
+        );
+        // so a damaged tree can't loop
+        if let Some(parent_depth) = parent_depth {
+            ensure!(
+                header.eh_depth < parent_depth,
+                assumption_failed(format!(
+                    "extent node at depth {} below one at {}",
+                    header.eh_depth, parent_depth
+                ))
+            );
+        }
+
+        for index in 0..usize::from(header.eh_entries) {
+            let entry = node
+                .get(RawExtentHeader::SIZE + index * RawExtent::SIZE..)
+                .unwrap_or(&[]);
+            if 0 == header.eh_depth {
+                let extent = RawExtent::from_slice(entry)?;
+                // c.f. EXT_INIT_MAX_LEN
+                let (len, unwritten) = if extent.ee_len > 32768 {
+                    (extent.ee_len - 32768, true)
+                } else {
+                    (extent.ee_len, false)
+                };
+                if 0 != len {
+                    entries.push(TreeEntry::Leaf {
+                        logical: extent.ee_block,
+                        physical: extent.start(),
+                        len,
+                        unwritten,
+                    });
+                }
+                continue;
+            }
+
+            let block = RawExtentIdx::from_slice(entry)?.leaf();
+            entries.push(TreeEntry::Index { level, block });
+            let child = self.fs.load_disc_bytes(block)?;
+            self.add_tree_entries(&child, level + 1, Some(header.eh_depth), entries)?;
+        }
+        Ok(())
+    }
+
+    /// The data of an inode without an extent tree.
+    fn mapped_extents(&self, inode: &Inode) -> Result<Vec<DataExtent>, Error> {
+        if !has_data_blocks(inode) || [0u8; INODE_CORE_SIZE] == inode.core {
+            return Ok(Vec::new());
+        }
+        self.fs.data_extents(inode)
+    }
+}
+
+impl fmt::Display for TreeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TreeEntry::Index { level, block } => write!(f, "(ETB{}):{}", level, block),
+            TreeEntry::Leaf {
+                logical,
+                physical,
+                len,
+                unwritten,
+            } => {
+                let flag = if unwritten { "[u]" } else { "" };
+                if 1 == len {
+                    return write!(f, "({}{}):{}", logical, flag, physical);
+                }
+                let last = u64::from(len) - 1;
+                write!(
+                    f,
+                    "({}-{}{}):{}-{}",
+                    logical,
+                    u64::from(logical) + last,
+                    flag,
+                    physical,
+                    physical + last
+                )
+            }
+        }
+    }
+}
+
+/// Split a command line at whitespace, except inside double quotes.
+fn words(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    ensure!(!quoted, "Unbalanced quotes in command line");
+    words.extend(word);
+    Ok(words)
+}
+
+/// `strtoull(text, 0)`: decimal, hex after `0x`, or octal after `0`.
+fn number(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if text.len() > 1 && text.starts_with('0') {
+        u64::from_str_radix(&text[1..], 8).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn is_dots(name: &[u8]) -> bool {
+    b"." == name || b".." == name
+}
+
+/// A name as `ls` shows it: unprintable bytes, and `\`, as `\x` and the byte in hex.
+fn escape(name: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(name.len());
+    for &b in name {
+        if (32..127).contains(&b) && b'\\' != b {
+            escaped.push(b);
+        } else {
+            escaped.extend(format!("\\x{:02x}", b).bytes());
+        }
+    }
+    escaped
+}
+
+/// `print_xattr_string`: as hex, if much of it isn't printable, or else with octal
+/// escapes, in quotes, if asked for.
+fn xattr_string(bytes: &[u8], quoted: bool) -> Vec<u8> {
+    let printable = |b: u8| (32..127).contains(&b);
+    let mut text = Vec::new();
+    if bytes.iter().filter(|&&b| printable(b)).count() <= bytes.len() * 7 / 8 {
+        for b in bytes {
+            text.extend(format!("{:02x} ", b).bytes());
+        }
+        return text;
+    }
+    if quoted {
+        text.push(b'"');
+    }
+    for &b in bytes {
+        if b'\\' == b {
+            text.extend_from_slice(b"\\\\");
+        } else if printable(b) {
+            text.push(b);
+        } else {
+            text.extend(format!("\\{:03o}", b).bytes());
+        }
+    }
+    if quoted {
+        text.push(b'"');
+    }
+    text
+}
+
+/// Seconds, with the epoch bits from an `_extra` field.
+fn extended_time(secs: u32, extra: u32) -> i64 {
+    i64::from(secs as i32) + (i64::from(extra & 3) << 32)
+}
+
+/// `%2d-%b-%4d %02d:%02d`, as `ls -l` shows a modification time.
+fn date(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:2}-{}-{:4} {:02}:{:02}",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        of_day / 3600,
+        of_day / 60 % 60
+    )
+}
+
+#[cfg(unix)]
+fn set_mode(file: &fs::File, mode: u16) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(u32::from(mode & 0o7777)))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &fs::File, _: u16) -> io::Result<()> {
+    Ok(())
+}
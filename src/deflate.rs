@@ -0,0 +1,437 @@
+//! A small, streaming, raw DEFLATE (RFC 1951) encoder.
+//!
+//! Only the fixed Huffman codes are used, with a greedy LZ77 match search. This is
+//! much worse than zlib, but good enough to make text and sparse files small, and
+//! blocks which don't compress are stored, so the output is never much bigger than
+//! the input.
+
+/// The most input which goes in one block; the largest a stored block can be.
+pub(crate) const BLOCK_SIZE: usize = 0xFFFF;
+
+/// How far back a match can refer.
+const WINDOW: usize = 32 * 1024;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// How many earlier positions to try, for each position.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The most a block of `len` input bytes can grow by: a stored block's header and padding.
+pub(crate) fn worst_case(len: u64) -> u64 {
+    len + 5 * (len / BLOCK_SIZE as u64 + 1)
+}
+
+#[derive(Copy, Clone)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, distance: u16 },
+}
+
+pub(crate) struct Deflater {
+    /// The end of the previous input, for matches to refer back into.
+    window: Vec<u8>,
+    /// Bytes which are complete, but haven't been handed out yet.
+    pending: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl Deflater {
+    pub(crate) fn new() -> Deflater {
+        Deflater {
+            window: Vec::with_capacity(WINDOW),
+            pending: Vec::new(),
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Compress `chunk`, at most `BLOCK_SIZE` bytes, as one block, appending any
+    /// complete bytes to `out`. The `last` block also flushes the final partial byte.
+    pub(crate) fn block(&mut self, chunk: &[u8], last: bool, out: &mut Vec<u8>) {
+        assert!(chunk.len() <= BLOCK_SIZE);
+
+        let start = self.window.len();
+        let mut data = std::mem::take(&mut self.window);
+        data.extend_from_slice(chunk);
+
+        let tokens = tokens(&data, start);
+        let fixed_bits = 3 + tokens.iter().map(|&token| token_bits(token)).sum::<u64>() + 7;
+        let stored_bits = u64::from((self.bit_count + 3 + 7) / 8 * 8 - self.bit_count)
+            + 32
+            + 8 * chunk.len() as u64;
+
+        if fixed_bits <= stored_bits {
+            self.write_bits(u32::from(last), 1);
+            self.write_bits(1, 2);
+            for token in tokens {
+                self.write_token(token);
+            }
+            self.write_symbol(256);
+        } else {
+            self.write_bits(u32::from(last), 1);
+            self.write_bits(0, 2);
+            self.align();
+            let len = chunk.len() as u32;
+            self.write_bits(len, 16);
+            self.write_bits(!len & 0xFFFF, 16);
+            self.drain(out);
+            out.extend_from_slice(chunk);
+        }
+
+        if last {
+            self.align();
+        }
+        self.drain(out);
+
+        let keep = data.len().saturating_sub(WINDOW);
+        data.drain(..keep);
+        self.window = data;
+    }
+
+    fn write_token(&mut self, token: Token) {
+        match token {
+            Token::Literal(byte) => self.write_symbol(u16::from(byte)),
+            Token::Match { len, distance } => {
+                let code = code_for(&LENGTH_BASE, len);
+                self.write_symbol(257 + code as u16);
+                self.write_bits(
+                    u32::from(len - LENGTH_BASE[code]),
+                    u32::from(LENGTH_EXTRA[code]),
+                );
+
+                let code = code_for(&DISTANCE_BASE, distance);
+                self.write_bits(reverse(code as u32, 5), 5);
+                self.write_bits(
+                    u32::from(distance - DISTANCE_BASE[code]),
+                    u32::from(DISTANCE_EXTRA[code]),
+                );
+            }
+        }
+    }
+
+    /// Write a literal/length symbol, in the fixed code.
+    fn write_symbol(&mut self, symbol: u16) {
+        let (code, len) = fixed_code(symbol);
+        self.write_bits(reverse(code, len), len);
+    }
+
+    /// Huffman codes are packed starting from their most significant bit, everything
+    /// else from the least, so codes are reversed before they get here.
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.pending.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Pad with zeros to the next byte boundary.
+    fn align(&mut self) {
+        if 0 != self.bit_count {
+            self.write_bits(0, 8 - self.bit_count);
+        }
+    }
+
+    fn drain(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.pending);
+    }
+}
+
+/// Greedily find matches for everything in `data` from `start` on.
+fn tokens(data: &[u8], start: usize) -> Vec<Token> {
+    let mut chains = Chains::new(data.len());
+    for pos in 0..start {
+        chains.insert(data, pos);
+    }
+
+    let mut tokens = Vec::new();
+    let mut pos = start;
+    while pos < data.len() {
+        let (len, distance) = chains.longest_match(data, pos);
+        if len >= MIN_MATCH {
+            tokens.push(Token::Match {
+                len: len as u16,
+                distance: distance as u16,
+            });
+            for pos in pos..pos + len {
+                chains.insert(data, pos);
+            }
+            pos += len;
+        } else {
+            tokens.push(Token::Literal(data[pos]));
+            chains.insert(data, pos);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Earlier positions, by the hash of the three bytes there.
+struct Chains {
+    /// The most recent position with each hash.
+    head: Vec<usize>,
+    /// For each position, the previous position with the same hash.
+    prev: Vec<usize>,
+}
+
+impl Chains {
+    const NONE: usize = usize::MAX;
+
+    fn new(len: usize) -> Chains {
+        Chains {
+            head: vec![Chains::NONE; 1 << HASH_BITS],
+            prev: vec![Chains::NONE; len],
+        }
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= data.len() {
+            let hash = hash(&data[pos..]);
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos;
+        }
+    }
+
+    /// The length and distance of the longest earlier match for `pos`.
+    fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        let limit = (data.len() - pos).min(MAX_MATCH);
+        let mut best = (0, 0);
+        if limit < MIN_MATCH {
+            return best;
+        }
+
+        let mut candidate = self.head[hash(&data[pos..])];
+        for _ in 0..MAX_CHAIN {
+            if Chains::NONE == candidate || pos - candidate > WINDOW {
+                break;
+            }
+            let len = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, pos - candidate);
+                if len == limit {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate];
+        }
+        best
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16;
+    (word.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn token_bits(token: Token) -> u64 {
+    match token {
+        Token::Literal(byte) => u64::from(fixed_code(u16::from(byte)).1),
+        Token::Match { len, distance } => {
+            let len_code = code_for(&LENGTH_BASE, len);
+            let distance_code = code_for(&DISTANCE_BASE, distance);
+            u64::from(fixed_code(257 + len_code as u16).1)
+                + u64::from(LENGTH_EXTRA[len_code])
+                + 5
+                + u64::from(DISTANCE_EXTRA[distance_code])
+        }
+    }
+}
+
+/// The code, and its length, for a literal/length symbol in the fixed Huffman code.
+fn fixed_code(symbol: u16) -> (u32, u32) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xC0 + symbol - 280, 8),
+    }
+}
+
+/// The last code whose base is at most `value`.
+fn code_for(bases: &[u16], value: u16) -> usize {
+    bases.partition_point(|&base| base <= value) - 1
+}
+
+fn reverse(code: u32, len: u32) -> u32 {
+    code.reverse_bits() >> (32 - len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(Vec::<u8>::new(), inflate(&deflate(b"")));
+    }
+
+    #[test]
+    fn incompressible() {
+        // xorshift, so more than a block of noise, which has to be stored
+        let mut state = 0x2545_F491u32;
+        let data = (0..BLOCK_SIZE * 2 + 100)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let compressed = deflate(&data);
+        assert!(compressed.len() as u64 <= worst_case(data.len() as u64));
+        assert_eq!(data, inflate(&compressed));
+    }
+
+    #[test]
+    fn repetitive() {
+        // several blocks, with matches reaching back into the previous one
+        let data = b"the quick brown fox jumps over the lazy dog\n"
+            .iter()
+            .cycle()
+            .take(200_000)
+            .copied()
+            .collect::<Vec<_>>();
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len() / 50, "{}", compressed.len());
+        assert_eq!(data, inflate(&compressed));
+
+        let zeros = vec![0u8; 100_000];
+        assert_eq!(zeros, inflate(&deflate(&zeros)));
+    }
+
+    #[test]
+    fn furthest_match() {
+        // text, then the same text a whole window later
+        let text = (0..WINDOW as u32)
+            .map(|i| b"abcdefghijklmnopqrstuvwxyz"[(i * i % 26) as usize])
+            .collect::<Vec<_>>();
+        let data = [&text[..], &text[..]].concat();
+        assert_eq!(data, inflate(&deflate(&data)));
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut deflater = Deflater::new();
+        let mut out = Vec::new();
+        let chunks = data.chunks(BLOCK_SIZE).collect::<Vec<_>>();
+        if chunks.is_empty() {
+            deflater.block(&[], true, &mut out);
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            deflater.block(chunk, i + 1 == chunks.len(), &mut out);
+        }
+        out
+    }
+
+    /// Just enough of an inflater for what `Deflater` writes: stored blocks, and blocks
+    /// in the fixed code.
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut bits = Bits { data, pos: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = 1 == bits.take(1);
+            match bits.take(2) {
+                0 => {
+                    bits.pos = (bits.pos + 7) / 8 * 8;
+                    let len = bits.take(16) as usize;
+                    assert_eq!(!len & 0xFFFF, bits.take(16) as usize);
+                    let start = bits.pos / 8;
+                    out.extend_from_slice(&data[start..start + len]);
+                    bits.pos += len * 8;
+                }
+                1 => loop {
+                    let symbol = bits.fixed_symbol();
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if 256 == symbol {
+                        break;
+                    }
+                    let code = usize::from(symbol - 257);
+                    let len = usize::from(LENGTH_BASE[code])
+                        + bits.take(u32::from(LENGTH_EXTRA[code])) as usize;
+                    let code = bits.code(5) as usize;
+                    let distance = usize::from(DISTANCE_BASE[code])
+                        + bits.take(u32::from(DISTANCE_EXTRA[code])) as usize;
+                    for _ in 0..len {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+                other => panic!("block type {}", other),
+            }
+            if last {
+                break;
+            }
+        }
+        assert_eq!(data.len(), (bits.pos + 7) / 8, "trailing data");
+        out
+    }
+
+    struct Bits<'d> {
+        data: &'d [u8],
+        pos: usize,
+    }
+
+    impl Bits<'_> {
+        /// `count` bits, least significant first.
+        fn take(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = self.data[self.pos / 8] >> (self.pos % 8) & 1;
+                value |= u32::from(bit) << i;
+                self.pos += 1;
+            }
+            value
+        }
+
+        /// A Huffman code of `len` bits, most significant first.
+        fn code(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |code, _| code << 1 | self.take(1))
+        }
+
+        /// A literal/length symbol, from the table in RFC 1951 3.2.6.
+        fn fixed_symbol(&mut self) -> u16 {
+            let code = self.code(7);
+            if code <= 0b001_0111 {
+                return 256 + code as u16;
+            }
+            let code = code << 1 | self.take(1);
+            match code {
+                0x30..=0xBF => return (code - 0x30) as u16,
+                0xC0..=0xC7 => return (280 + code - 0xC0) as u16,
+                _ => (),
+            }
+            let code = code << 1 | self.take(1);
+            assert!((0x190..=0x1FF).contains(&code), "{:#x}", code);
+            (144 + code - 0x190) as u16
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::Read;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use bitflags::bitflags;
+use positioned_io2::ReadAt;
+
+use crate::progress::check_cancelled;
+use crate::DirEntry;
+use crate::Enhanced;
+use crate::FileType;
+use crate::Inode;
+use crate::Progress;
+use crate::ProgressReader;
+use crate::SuperBlock;
+
+bitflags! {
+    /// Which aspects of an entry differ.
+    pub struct Changes: u32 {
+        const FILE_TYPE = 0x0001;
+        const MODE      = 0x0002;
+        const OWNER     = 0x0004;
+        const SIZE      = 0x0008;
+        const MTIME     = 0x0010;
+        const XATTRS    = 0x0020;
+        /// The destination of a symlink, or the numbers of a device.
+        const TARGET    = 0x0040;
+        /// Only checked if requested in the `DiffOptions`.
+        const CONTENT   = 0x0080;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Changes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+/// How an entry differs between two filesystems.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Change {
+    /// Only present in the second filesystem.
+    Added,
+    /// Only present in the first filesystem.
+    Removed,
+    Changed(Changes),
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Difference {
+    pub path: String,
+    pub change: Change,
+}
+
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    /// Read regular files to compare their contents, not just their metadata.
+    pub content: bool,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Compare this filesystem, entry by entry, with another. Entries are matched by path;
+    /// the inode numbers are irrelevant. Directories are compared in name order.
+    pub fn diff<S>(
+        &self,
+        other: &SuperBlock<S>,
+        options: &DiffOptions,
+    ) -> Result<Vec<Difference>, Error>
+    where
+        S: ReadAt,
+    {
+        self.diff_with_progress(other, options, &())
+    }
+
+    /// `diff`, telling `progress` about each entry compared, and any content read.
+    pub fn diff_with_progress<S>(
+        &self,
+        other: &SuperBlock<S>,
+        options: &DiffOptions,
+        progress: &dyn Progress,
+    ) -> Result<Vec<Difference>, Error>
+    where
+        S: ReadAt,
+    {
+        let mut differences = Vec::new();
+        diff_inodes(
+            self,
+            &self.root()?,
+            other,
+            &other.root()?,
+            "",
+            options,
+            progress,
+            &mut differences,
+        )?;
+        Ok(differences)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn diff_inodes<R, S>(
+    a_fs: &SuperBlock<R>,
+    a: &Inode,
+    b_fs: &SuperBlock<S>,
+    b: &Inode,
+    path: &str,
+    options: &DiffOptions,
+    progress: &dyn Progress,
+    differences: &mut Vec<Difference>,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+    S: ReadAt,
+{
+    progress.entry(path);
+    check_cancelled(progress)?;
+    let a_enhanced = a_fs.enhance(a)?;
+    let b_enhanced = b_fs.enhance(b)?;
+
+    let mut changes = Changes::empty();
+
+    if a.stat.extracted_type != b.stat.extracted_type {
+        changes |= Changes::FILE_TYPE;
+    }
+    if a.stat.file_mode != b.stat.file_mode {
+        changes |= Changes::MODE;
+    }
+    if a.stat.uid != b.stat.uid || a.stat.gid != b.stat.gid {
+        changes |= Changes::OWNER;
+    }
+    if a.stat.mtime != b.stat.mtime {
+        changes |= Changes::MTIME;
+    }
+    if a.stat.xattrs != b.stat.xattrs {
+        changes |= Changes::XATTRS;
+    }
+
+    let both_directories = match (&a_enhanced, &b_enhanced) {
+        (Enhanced::Directory(_), Enhanced::Directory(_)) => true,
+        (Enhanced::SymbolicLink(a), Enhanced::SymbolicLink(b)) => {
+            if a != b {
+                changes |= Changes::TARGET;
+            }
+            false
+        }
+        (
+            Enhanced::CharacterDevice(a_major, a_minor),
+            Enhanced::CharacterDevice(b_major, b_minor),
+        )
+        | (Enhanced::BlockDevice(a_major, a_minor), Enhanced::BlockDevice(b_major, b_minor)) => {
+            if (a_major, a_minor) != (b_major, b_minor) {
+                changes |= Changes::TARGET;
+            }
+            false
+        }
+        _ => false,
+    };
+
+    // directory sizes are an allocation detail, not something anyone changed
+    if !both_directories && a.stat.size != b.stat.size {
+        changes |= Changes::SIZE;
+    }
+
+    if options.content
+        && FileType::RegularFile == a.stat.extracted_type
+        && FileType::RegularFile == b.stat.extracted_type
+        && (changes.contains(Changes::SIZE)
+            || !same_content(
+                ProgressReader::new(a_fs.open(a)?, progress),
+                ProgressReader::new(b_fs.open(b)?, progress),
+            )
+            // the reader's io::Error would hide why it stopped
+            .map_err(|e| match check_cancelled(progress) {
+                Ok(()) => e,
+                Err(cancelled) => cancelled,
+            })
+            .with_context(|| anyhow!("comparing content of {}", path))?)
+    {
+        changes |= Changes::CONTENT;
+    }
+
+    if !changes.is_empty() {
+        differences.push(Difference {
+            path: display_path(path),
+            change: Change::Changed(changes),
+        });
+    }
+
+    let (a_entries, b_entries) = match (a_enhanced, b_enhanced) {
+        (Enhanced::Directory(a_entries), Enhanced::Directory(b_entries)) => {
+            (sorted_children(a_entries), sorted_children(b_entries))
+        }
+        _ => return Ok(()),
+    };
+
+    let mut a_entries = a_entries.into_iter().peekable();
+    let mut b_entries = b_entries.into_iter().peekable();
+
+    loop {
+        let order = match (a_entries.peek(), b_entries.peek()) {
+            (None, None) => break,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(a_entry), Some(b_entry)) => a_entry.name.cmp(&b_entry.name),
+        };
+
+        match order {
+            std::cmp::Ordering::Less => {
+                let entry = a_entries.next().expect("peeked");
+                let child = format!("{}/{}", path, entry.name);
+                every_path(a_fs, &entry, &child, &mut |path| {
+                    differences.push(Difference {
+                        path,
+                        change: Change::Removed,
+                    })
+                })?;
+            }
+            std::cmp::Ordering::Greater => {
+                let entry = b_entries.next().expect("peeked");
+                let child = format!("{}/{}", path, entry.name);
+                every_path(b_fs, &entry, &child, &mut |path| {
+                    differences.push(Difference {
+                        path,
+                        change: Change::Added,
+                    })
+                })?;
+            }
+            std::cmp::Ordering::Equal => {
+                let a_entry = a_entries.next().expect("peeked");
+                let b_entry = b_entries.next().expect("peeked");
+                let child = format!("{}/{}", path, a_entry.name);
+                diff_inodes(
+                    a_fs,
+                    &a_fs.load_inode(a_entry.inode)?,
+                    b_fs,
+                    &b_fs.load_inode(b_entry.inode)?,
+                    &child,
+                    options,
+                    progress,
+                    differences,
+                )
+                .with_context(|| anyhow!("comparing '{}'", child))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn sorted_children(mut entries: Vec<DirEntry>) -> Vec<DirEntry> {
+    entries.retain(|entry| "." != entry.name && ".." != entry.name);
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Visit an entry, and everything inside it if it's a directory.
+fn every_path<R, F>(
+    fs: &SuperBlock<R>,
+    entry: &DirEntry,
+    path: &str,
+    visit: &mut F,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+    F: FnMut(String),
+{
+    let inode = fs.load_inode(entry.inode)?;
+    fs.walk(&inode, path, &mut |_, path, _, _| {
+        visit(path.to_string());
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+fn display_path(path: &str) -> String {
+    if path.is_empty() {
+        "/".to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+fn same_content<A, B>(mut a: A, mut b: B) -> Result<bool, Error>
+where
+    A: Read,
+    B: Read,
+{
+    let mut a_buf = vec![0u8; 64 * 1024];
+    let mut b_buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = a.read(&mut a_buf)?;
+        if 0 == read {
+            return Ok(0 == b.read(&mut b_buf[..1])?);
+        }
+        b.read_exact(&mut b_buf[..read])?;
+        if a_buf[..read] != b_buf[..read] {
+            return Ok(false);
+        }
+    }
+}
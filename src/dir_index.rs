@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::dirhash::dirhash;
+use crate::not_found;
+use crate::ondisk::RawDirEntry;
+use crate::ondisk::RawDirEntryTail;
+use crate::read_le16;
+use crate::read_le32;
+use crate::FileType;
+use crate::HashVersion;
+use crate::Inode;
+use crate::InodeFlags;
+use crate::SuperBlock;
+use crate::SuperBlockFlags;
+
+/// An indexed directory's hash tree, as stored; see `SuperBlock::dir_index`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DxTree {
+    /// The hash the names were placed with, adjusted for the filesystem's signedness.
+    /// `None` if it's not one we recognise.
+    pub hash_version: Option<HashVersion>,
+    /// How many levels of index there are, counting the root.
+    pub levels: u8,
+    /// The index's blocks: the root, then each node before those it points to.
+    pub nodes: Vec<DxNode>,
+    /// The blocks of entries the index points to, in hash order.
+    pub leaves: Vec<DxLeaf>,
+}
+
+/// A block of the index: its root, or an interior node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DxNode {
+    /// Where the node is in the directory, in blocks; the root is always `0`.
+    pub block: u32,
+    /// How far below the root the node is.
+    pub depth: u8,
+    /// How many entries fit in the node.
+    pub limit: u16,
+    pub entries: Vec<DxEntry>,
+}
+
+/// A pointer from an index node to the next level down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DxEntry {
+    /// The lowest hash under `block`; always `0` for a node's first entry. The bottom bit
+    /// is set if the hash continues from the previous block, as a collision can.
+    pub hash: u32,
+    /// Where the next node, or the leaf, is in the directory, in blocks.
+    pub block: u32,
+}
+
+/// A block of directory entries, and how well it fits the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DxLeaf {
+    /// Where the leaf is in the directory, in blocks.
+    pub block: u32,
+    /// The lowest hash the index sends here.
+    pub first_hash: u32,
+    /// The highest hash the index sends here, inclusive.
+    pub last_hash: u32,
+    /// Entries, not counting unused records.
+    pub entries: u32,
+    /// Bytes taken by the entries, each rounded up to four, as a record needs.
+    pub used: u32,
+    /// Bytes available for entries: the block, less any checksum tail.
+    pub capacity: u32,
+    /// Entries whose names hash outside `first_hash..=last_hash`, which lookups can't
+    /// find. `None` if the hash can't be computed.
+    pub misplaced: Option<u32>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// The hash tree of an indexed directory, or `None` if the directory isn't indexed,
+    /// for looking at how full, or how healthy, the index is. Every block of the index,
+    /// and every leaf, is read, and each name is hashed. Checksums aren't checked.
+    pub fn dir_index(&self, inode: &Inode) -> Result<Option<DxTree>, Error> {
+        ensure!(
+            FileType::Directory == inode.stat.extracted_type,
+            not_found(format!("<{}> isn't a directory", inode.number))
+        );
+        if !inode.flags.contains(InodeFlags::INDEX) {
+            return Ok(None);
+        }
+
+        let reader = self.open(inode)?;
+        let block_size = self.groups.block_size;
+        let load = |number: u32| -> Result<Vec<u8>, Error> {
+            let mut block = vec![0u8; usize::try_from(block_size)?];
+            reader.read_exact_at(u64::from(number) * u64::from(block_size), &mut block)?;
+            Ok(block)
+        };
+
+        // struct dx_root_info, after the `.` and `..` entries
+        let root = load(0)?;
+        ensure!(
+            8 == root[0x1D],
+            assumption_failed(format!(
+                "<{}>'s index root has length {}, not 8",
+                inode.number, root[0x1D]
+            ))
+        );
+        // three, with `largedir`
+        ensure!(
+            root[0x1E] < 3,
+            assumption_failed(format!(
+                "<{}>'s index has {} levels below the root",
+                inode.number, root[0x1E]
+            ))
+        );
+        let flags = SuperBlockFlags::from_bits_truncate(self.raw.s_flags);
+        let hash_version = HashVersion::from_raw(root[0x1C]).map(|v| v.with_flags(flags));
+
+        let mut tree = DxTree {
+            hash_version,
+            levels: root[0x1E] + 1,
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+        };
+
+        // (block, depth, first hash, last hash), in hash order
+        let mut pending = vec![(0, 0, 0, u32::MAX)];
+        let mut seen = HashSet::new();
+        while let Some((block, depth, first_hash, last_hash)) = pending.pop() {
+            ensure!(
+                seen.insert(block),
+                assumption_failed(format!(
+                    "<{}>'s index points at block {} twice",
+                    inode.number, block
+                ))
+            );
+
+            let data = if 0 == block {
+                root.clone()
+            } else {
+                load(block)?
+            };
+            if depth == tree.levels {
+                tree.leaves.push(self.dx_leaf(
+                    inode,
+                    &data,
+                    block,
+                    first_hash,
+                    last_hash,
+                    hash_version,
+                )?);
+                continue;
+            }
+
+            // struct dx_countlimit, in place of the first entry's hash
+            let start = if 0 == block { 0x20 } else { 0x08 };
+            let limit = read_le16(&data[start..]);
+            let count = read_le16(&data[start + 2..]);
+            ensure!(
+                0 < count && count <= limit && start + 8 * usize::from(limit) <= data.len(),
+                assumption_failed(format!(
+                    "<{}>'s index block {} has {} of {} entries",
+                    inode.number, block, count, limit
+                ))
+            );
+
+            let entries = (0..usize::from(count))
+                .map(|i| {
+                    let entry = &data[start + 8 * i..];
+                    DxEntry {
+                        hash: if 0 == i { 0 } else { read_le32(entry) },
+                        block: read_le32(&entry[4..]),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            // pushed last first, so the first is visited first
+            for (i, entry) in entries.iter().enumerate().rev() {
+                let first = if 0 == i { first_hash } else { entry.hash & !1 };
+                let last = match entries.get(i + 1) {
+                    // a collision carried on into the next block is in both
+                    Some(next) if 0 != next.hash & 1 => next.hash & !1,
+                    Some(next) => (next.hash & !1).saturating_sub(1),
+                    None => last_hash,
+                };
+                pending.push((entry.block, depth + 1, first, last));
+            }
+
+            tree.nodes.push(DxNode {
+                block,
+                depth,
+                limit,
+                entries,
+            });
+        }
+
+        Ok(Some(tree))
+    }
+
+    fn dx_leaf(
+        &self,
+        inode: &Inode,
+        data: &[u8],
+        block: u32,
+        first_hash: u32,
+        last_hash: u32,
+        hash_version: Option<HashVersion>,
+    ) -> Result<DxLeaf, Error> {
+        let capacity = if inode.checksum_prefix.is_some() {
+            data.len() - RawDirEntryTail::SIZE
+        } else {
+            data.len()
+        };
+        let mut leaf = DxLeaf {
+            block,
+            first_hash,
+            last_hash,
+            entries: 0,
+            used: 0,
+            capacity: u32::try_from(capacity)?,
+            misplaced: hash_version.map(|_| 0),
+        };
+
+        let mut read = 0;
+        while read < capacity {
+            let entry = RawDirEntry::from_slice(&data[read..])?;
+            let rec_len = entry.record_len(data.len());
+            ensure!(
+                rec_len >= RawDirEntry::HEADER_SIZE + entry.name.len()
+                    && read + rec_len <= data.len(),
+                assumption_failed(format!(
+                    "<{}> has a directory record of length {} at {} in block {}",
+                    inode.number, rec_len, read, block
+                ))
+            );
+            read += rec_len;
+
+            if 0 == entry.inode {
+                continue;
+            }
+            leaf.entries += 1;
+            leaf.used += u32::try_from((RawDirEntry::HEADER_SIZE + entry.name.len() + 3) & !3)?;
+
+            let version = match hash_version {
+                Some(version) => version,
+                None => continue,
+            };
+            match dirhash(version, &self.raw.s_hash_seed, &entry.name) {
+                Ok(hash) if hash.hash < first_hash || hash.hash > last_hash => {
+                    if let Some(misplaced) = leaf.misplaced.as_mut() {
+                        *misplaced += 1;
+                    }
+                }
+                Ok(_) => (),
+                // e.g. siphash, which needs the casefolded name
+                Err(_) => leaf.misplaced = None,
+            }
+        }
+
+        Ok(leaf)
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::ondisk::rec_len_from_disk;
+use crate::read_le16;
+use crate::read_le32;
+use crate::FileType;
+use crate::Inode;
+use crate::InodeFlags;
+use crate::SuperBlock;
+
+/// How big a directory is; see `SuperBlock::dir_summary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DirSummary {
+    /// Entries, not counting `.` and `..`.
+    pub entries: u64,
+    /// Entries which are directories, by the type in the entry.
+    pub subdirs: u64,
+    /// The total length of the entries' names.
+    pub bytes_of_names: u64,
+    /// The directory has a hash tree index.
+    pub htree: bool,
+    /// How many levels the index has, counting its root; `0` without one.
+    pub levels: u8,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Count a directory's entries, e.g. to decide whether it's worth listing. Each block is
+    /// read in turn, but the entries' names aren't decoded, or collected, so this is much
+    /// cheaper than `enhance` on a huge directory. For encrypted directories, the names'
+    /// lengths are of their ciphertext.
+    pub fn dir_summary(&self, inode: &Inode) -> Result<DirSummary, Error> {
+        ensure!(
+            FileType::Directory == inode.stat.extracted_type,
+            not_found(format!("<{}> isn't a directory", inode.number))
+        );
+
+        let htree = inode.flags.contains(InodeFlags::INDEX);
+        let mut summary = DirSummary {
+            entries: 0,
+            subdirs: 0,
+            bytes_of_names: 0,
+            htree,
+            levels: 0,
+        };
+
+        let reader = self.open(inode)?;
+        let block_size = u64::from(self.groups.block_size);
+        let mut block = vec![0u8; usize::try_from(block_size)?];
+        let mut pos = 0;
+        while pos < inode.stat.size {
+            reader.read_exact_at(pos, &mut block)?;
+            if htree && 0 == pos {
+                // struct dx_root_info, after the `.` and `..` entries
+                ensure!(
+                    8 == block[0x1D],
+                    assumption_failed(format!(
+                        "<{}>'s index root has length {}, not 8",
+                        inode.number, block[0x1D]
+                    ))
+                );
+                // three, with `largedir`
+                ensure!(
+                    block[0x1E] < 3,
+                    assumption_failed(format!(
+                        "<{}>'s index has {} levels below the root",
+                        inode.number, block[0x1E]
+                    ))
+                );
+                summary.levels = block[0x1E] + 1;
+            }
+            pos += block_size;
+
+            let mut read = 0;
+            while read + 8 <= block.len() {
+                let entry = &block[read..];
+                let rec_len = rec_len_from_disk(read_le16(&entry[4..]), block.len());
+                let name_len = entry[6];
+                ensure!(
+                    rec_len >= 8 + usize::from(name_len) && read + rec_len <= block.len(),
+                    assumption_failed(format!(
+                        "<{}> has a directory record of length {} at {}",
+                        inode.number,
+                        rec_len,
+                        pos - block_size + read as u64
+                    ))
+                );
+                read += rec_len;
+
+                // unused, including index nodes and checksum tails
+                if 0 == read_le32(entry) {
+                    continue;
+                }
+                let name = &entry[8..8 + usize::from(name_len)];
+                if b"." == name || b".." == name {
+                    continue;
+                }
+                summary.entries += 1;
+                summary.bytes_of_names += u64::from(name_len);
+                if Some(FileType::Directory) == FileType::from_dir_hint(entry[7]) {
+                    summary.subdirs += 1;
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+}
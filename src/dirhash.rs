@@ -0,0 +1,269 @@
+use anyhow::ensure;
+use anyhow::Error;
+
+use crate::not_found;
+use crate::unsupported_feature;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+use crate::SuperBlockFlags;
+
+/// The hash used to place names in an indexed (`dir_index`) directory.
+/// The unsigned variants treat the bytes of the name as unsigned chars; the others
+/// as signed chars, which is what x86 kernels did. Only the three signed variants are
+/// stored on disk: the `s_flags` of the filesystem say which to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum HashVersion {
+    Legacy,
+    HalfMd4,
+    Tea,
+    LegacyUnsigned,
+    HalfMd4Unsigned,
+    TeaUnsigned,
+    /// Used for directories which are both casefolded and encrypted.
+    SipHash,
+}
+
+impl HashVersion {
+    /// `DX_HASH_*`, as stored in `s_def_hash_version` or a directory's index root.
+    pub fn from_raw(raw: u8) -> Option<HashVersion> {
+        Some(match raw {
+            0 => HashVersion::Legacy,
+            1 => HashVersion::HalfMd4,
+            2 => HashVersion::Tea,
+            3 => HashVersion::LegacyUnsigned,
+            4 => HashVersion::HalfMd4Unsigned,
+            5 => HashVersion::TeaUnsigned,
+            6 => HashVersion::SipHash,
+            _ => return None,
+        })
+    }
+
+    /// The variant the kernel actually uses for a stored version, given the `s_flags`.
+    pub fn with_flags(self, flags: SuperBlockFlags) -> HashVersion {
+        if !flags.contains(SuperBlockFlags::UNSIGNED_DIRECTORY_HASH) {
+            return self;
+        }
+        match self {
+            HashVersion::Legacy => HashVersion::LegacyUnsigned,
+            HashVersion::HalfMd4 => HashVersion::HalfMd4Unsigned,
+            HashVersion::Tea => HashVersion::TeaUnsigned,
+            other => other,
+        }
+    }
+
+    fn unsigned(self) -> bool {
+        matches!(
+            self,
+            HashVersion::LegacyUnsigned | HashVersion::HalfMd4Unsigned | HashVersion::TeaUnsigned
+        )
+    }
+}
+
+/// The hash of a name, as used to order an indexed directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DirHash {
+    /// The bottom bit is always clear.
+    pub hash: u32,
+    /// Zero for the legacy hash.
+    pub minor_hash: u32,
+}
+
+/// Hash `name` like the kernel's `ext4fs_dirhash`. An all-zero `seed` means the default.
+pub fn dirhash(version: HashVersion, seed: &[u32; 4], name: &[u8]) -> Result<DirHash, Error> {
+    let mut buf = if seed.iter().all(|&word| 0 == word) {
+        [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476]
+    } else {
+        *seed
+    };
+
+    let unsigned = version.unsigned();
+
+    let (hash, minor_hash) = match version {
+        HashVersion::Legacy | HashVersion::LegacyUnsigned => (legacy(name, unsigned), 0),
+        HashVersion::HalfMd4 | HashVersion::HalfMd4Unsigned => {
+            let mut input = [0u32; 8];
+            for start in (0..name.len()).step_by(32) {
+                str_to_hash_buf(&name[start..], &mut input, unsigned);
+                half_md4_transform(&mut buf, &input);
+            }
+            (buf[1], buf[2])
+        }
+        HashVersion::Tea | HashVersion::TeaUnsigned => {
+            let mut input = [0u32; 4];
+            for start in (0..name.len()).step_by(16) {
+                str_to_hash_buf(&name[start..], &mut input, unsigned);
+                tea_transform(&mut buf, &input);
+            }
+            (buf[0], buf[1])
+        }
+        HashVersion::SipHash => {
+            return Err(unsupported_feature("siphash directory hashes").into());
+        }
+    };
+
+    // the end-of-directory marker is reserved
+    const EOF: u32 = 0x7fff_ffff;
+    let mut hash = hash & !1;
+    if hash == EOF << 1 {
+        hash = (EOF - 1) << 1;
+    }
+
+    Ok(DirHash { hash, minor_hash })
+}
+
+impl<R> SuperBlock<R> {
+    /// Hash `name` with the filesystem's default hash and seed, as used for new
+    /// indexed directories. Existing directories record the hash they were built with.
+    pub fn dirhash(&self, name: &[u8]) -> Result<DirHash, Error> {
+        let version = HashVersion::from_raw(self.raw.s_def_hash_version).ok_or_else(|| {
+            unsupported_feature(format!(
+                "unrecognised default hash version: {}",
+                self.raw.s_def_hash_version
+            ))
+        })?;
+        let flags = SuperBlockFlags::from_bits_truncate(self.raw.s_flags);
+        dirhash(version.with_flags(flags), &self.raw.s_hash_seed, name)
+    }
+
+    /// Whether `a` and `b` name the same entry in `dir`, as a mount would decide. That's
+    /// byte for byte: only casefolded directories differ, and filesystems with the
+    /// `casefold` feature can't be opened, so neither can any such directory.
+    pub fn names_equal(&self, dir: &Inode, a: &[u8], b: &[u8]) -> Result<bool, Error> {
+        ensure!(
+            FileType::Directory == dir.stat.extracted_type,
+            not_found(format!("<{}> is not a directory", dir.number))
+        );
+        Ok(a == b)
+    }
+}
+
+fn char_value(byte: u8, unsigned: bool) -> u32 {
+    if unsigned {
+        u32::from(byte)
+    } else {
+        i32::from(byte as i8) as u32
+    }
+}
+
+fn legacy(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12a3_fe2du32, 0x37ab_e8f9u32);
+    for &byte in name {
+        let mut hash =
+            hash1.wrapping_add(hash0 ^ char_value(byte, unsigned).wrapping_mul(7_152_373));
+        if 0 != hash & 0x8000_0000 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// Pack the start of `rest` into `out`, big-endian-ish, padding with a value derived
+/// from the length of what's left of the name.
+fn str_to_hash_buf(rest: &[u8], out: &mut [u32], unsigned: bool) {
+    let len = rest.len() as u32;
+    let mut pad = len | (len << 8);
+    pad |= pad << 16;
+
+    let mut val = pad;
+    let mut filled = 0;
+    for (i, &byte) in rest.iter().take(out.len() * 4).enumerate() {
+        val = char_value(byte, unsigned).wrapping_add(val << 8);
+        if 3 == i % 4 {
+            out[filled] = val;
+            filled += 1;
+            val = pad;
+        }
+    }
+
+    if filled < out.len() {
+        out[filled] = val;
+        filled += 1;
+    }
+    for word in &mut out[filled..] {
+        *word = pad;
+    }
+}
+
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K2: u32 = 0o13_240_474_631;
+    const K3: u32 = 0o15_666_365_641;
+
+    fn f(x: u32, y: u32, z: u32) -> u32 {
+        z ^ (x & (y ^ z))
+    }
+    fn g(x: u32, y: u32, z: u32) -> u32 {
+        (x & y).wrapping_add((x ^ y) & z)
+    }
+    fn h(x: u32, y: u32, z: u32) -> u32 {
+        x ^ y ^ z
+    }
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+
+    macro_rules! round {
+        ($f:ident, $a:ident, $b:ident, $c:ident, $d:ident, $x:expr, $s:expr) => {
+            $a = $a
+                .wrapping_add($f($b, $c, $d))
+                .wrapping_add($x)
+                .rotate_left($s);
+        };
+    }
+
+    round!(f, a, b, c, d, input[0], 3);
+    round!(f, d, a, b, c, input[1], 7);
+    round!(f, c, d, a, b, input[2], 11);
+    round!(f, b, c, d, a, input[3], 19);
+    round!(f, a, b, c, d, input[4], 3);
+    round!(f, d, a, b, c, input[5], 7);
+    round!(f, c, d, a, b, input[6], 11);
+    round!(f, b, c, d, a, input[7], 19);
+
+    round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9E37_79B9;
+
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum = 0u32;
+
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            ((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+        );
+        b1 = b1.wrapping_add(
+            ((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+        );
+    }
+
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
@@ -0,0 +1,478 @@
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use positioned_io2::ReadAt;
+use positioned_io2::Size;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::DirEntry;
+use crate::Enhanced;
+use crate::Inode;
+use crate::Options;
+use crate::Progress;
+use crate::SuperBlock;
+use crate::WalkOptions;
+
+const SECTOR: u64 = 512;
+
+/// Partition types which hold a chain of logical partitions, rather than a filesystem.
+const EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// The type of the single partition covering a GPT disc, so MBR-only tools leave it alone.
+const PROTECTIVE: u8 = 0xEE;
+
+/// More than any tool makes; a chain this long probably loops.
+const MAX_LOGICAL: u32 = 256;
+
+/// Entries in a GPT, which is usually 128.
+const MAX_GPT_ENTRIES: u32 = 4096;
+
+/// Every ext4 filesystem on a disc, found through its partition table, and any LVM2
+/// physical volumes in it, with the `lvm` feature. See `Disk::open`.
+pub struct Disk<R> {
+    volumes: Vec<Volume<R>>,
+    unopened: Vec<(String, Error)>,
+}
+
+/// A filesystem on a `Disk`.
+pub struct Volume<R> {
+    /// What addresses on it start with: `p2` for the second partition, counting logical
+    /// partitions from `p5`, as Linux does; `vg/lv` for a logical volume; or `disk` if the
+    /// disc has no partition table.
+    pub name: String,
+    /// Where it starts on the disc; for a logical volume, where its physical volume does.
+    pub offset: u64,
+    pub fs: SuperBlock<VolumeReader<R>>,
+}
+
+/// Reads a volume out of its disc.
+pub struct VolumeReader<R> {
+    source: Source<R>,
+}
+
+enum Source<R> {
+    Region(Region<R>),
+    #[cfg(feature = "lvm")]
+    Logical(crate::lvm::LogicalVolumeReader<Region<R>>),
+}
+
+/// A range of the disc, which everything found on it shares.
+struct Region<R> {
+    inner: Arc<R>,
+    start: u64,
+    len: Option<u64>,
+}
+
+/// A partition table entry; the numbering is Linux's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Partition {
+    number: u32,
+    start: u64,
+    len: u64,
+}
+
+impl<R> Disk<R>
+where
+    R: ReadAt,
+{
+    /// Read the disc's partition table, MBR or GPT, and open every filesystem on it,
+    /// and in any logical volumes. A disc without a partition table is opened whole.
+    pub fn open(inner: R) -> Result<Disk<R>, Error> {
+        Disk::open_with_options(inner, &Options::default())
+    }
+
+    /// `open`, with `options` for each filesystem. `options.len` is only used for a disc
+    /// without a partition table; otherwise, each volume's own length is.
+    pub fn open_with_options(inner: R, options: &Options) -> Result<Disk<R>, Error> {
+        let inner = Arc::new(inner);
+        let regions: Vec<(String, Region<R>)> =
+            match partition_table(&*inner).with_context(|| anyhow!("reading partition table"))? {
+                Some(partitions) => partitions
+                    .into_iter()
+                    .map(|part| {
+                        (
+                            format!("p{}", part.number),
+                            Region {
+                                inner: Arc::clone(&inner),
+                                start: part.start,
+                                len: Some(part.len),
+                            },
+                        )
+                    })
+                    .collect(),
+                None => vec![(
+                    "disk".to_string(),
+                    Region {
+                        inner: Arc::clone(&inner),
+                        start: 0,
+                        len: options.len,
+                    },
+                )],
+            };
+
+        let mut disk = Disk {
+            volumes: Vec::new(),
+            unopened: Vec::new(),
+        };
+
+        for (name, region) in regions {
+            #[cfg(feature = "lvm")]
+            {
+                if let Ok(pv) = crate::lvm::physical_volume(region.clone()) {
+                    for lv in pv.logical_volumes {
+                        let name = format!("{}/{}", pv.vg_name, lv.name);
+                        match lv.open(region.clone()) {
+                            Ok(reader) => disk.add(
+                                name,
+                                region.start,
+                                Some(lv.size),
+                                Source::Logical(reader),
+                                options,
+                            ),
+                            Err(e) => disk.unopened.push((name, e)),
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            let (offset, len) = (region.start, region.len);
+            disk.add(name, offset, len, Source::Region(region), options);
+        }
+
+        Ok(disk)
+    }
+
+    fn add(
+        &mut self,
+        name: String,
+        offset: u64,
+        len: Option<u64>,
+        source: Source<R>,
+        options: &Options,
+    ) {
+        let options = Options {
+            len,
+            ..options.clone()
+        };
+        match SuperBlock::new_with_options(VolumeReader { source }, &options) {
+            Ok(fs) => self.volumes.push(Volume { name, offset, fs }),
+            Err(e) => self.unopened.push((name, e)),
+        }
+    }
+
+    /// In the order they are on the disc.
+    pub fn volumes(&self) -> &[Volume<R>] {
+        &self.volumes
+    }
+
+    pub fn volume(&self, name: &str) -> Option<&Volume<R>> {
+        self.volumes.iter().find(|volume| volume.name == name)
+    }
+
+    /// The partitions, and logical volumes, which didn't open as ext4, and why, e.g. swap,
+    /// or another filesystem.
+    pub fn unopened(&self) -> &[(String, Error)] {
+        &self.unopened
+    }
+
+    /// Find the entry at an address: a volume's name, a colon, then a path on it, e.g.
+    /// `p2:/etc/fstab`, or `vg/root:/etc/fstab`.
+    pub fn resolve(&self, address: &str) -> Result<(&Volume<R>, DirEntry), Error> {
+        let (name, path) = address.split_once(':').ok_or_else(|| {
+            not_found(format!(
+                "{:?} doesn't start with a volume, like p1:/etc/fstab",
+                address
+            ))
+        })?;
+        let volume = self
+            .volume(name)
+            .ok_or_else(|| not_found(format!("there's no ext4 volume called {:?}", name)))?;
+        let entry = volume
+            .fs
+            .resolve_path(path)
+            .with_context(|| anyhow!("resolving {:?} on {}", path, name))?;
+        Ok((volume, entry))
+    }
+
+    /// `SuperBlock::walk` every volume in turn. Paths are addresses, as `resolve` takes;
+    /// each volume's root is just its name and a colon, e.g. `p2:`.
+    pub fn walk<F>(&self, visit: &mut F) -> Result<bool, Error>
+    where
+        F: FnMut(&SuperBlock<VolumeReader<R>>, &str, &Inode, &Enhanced) -> Result<bool, Error>,
+    {
+        self.walk_with_options(&WalkOptions::default(), &(), visit)
+    }
+
+    /// `walk`, as `SuperBlock::walk_with_options` does.
+    pub fn walk_with_options<F>(
+        &self,
+        options: &WalkOptions,
+        progress: &dyn Progress,
+        visit: &mut F,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(&SuperBlock<VolumeReader<R>>, &str, &Inode, &Enhanced) -> Result<bool, Error>,
+    {
+        for volume in &self.volumes {
+            let root = volume.fs.root()?;
+            let path = format!("{}:", volume.name);
+            if !volume
+                .fs
+                .walk_with_options(&root, &path, options, progress, visit)
+                .with_context(|| anyhow!("walking {}", volume.name))?
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The address of every entry, on every volume, which `matches`, in walk order.
+    pub fn find<P>(&self, mut matches: P) -> Result<Vec<String>, Error>
+    where
+        P: FnMut(&str, &Inode) -> bool,
+    {
+        let mut found = Vec::new();
+        self.walk(&mut |_, path, inode, _| {
+            if matches(path, inode) {
+                found.push(path.to_string());
+            }
+            Ok(true)
+        })?;
+        Ok(found)
+    }
+}
+
+impl<R: ReadAt> ReadAt for VolumeReader<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.source {
+            Source::Region(region) => region.read_at(pos, buf),
+            #[cfg(feature = "lvm")]
+            Source::Logical(reader) => reader.read_at(pos, buf),
+        }
+    }
+}
+
+impl<R> Size for VolumeReader<R> {
+    fn size(&self) -> io::Result<Option<u64>> {
+        match &self.source {
+            Source::Region(region) => Ok(region.len),
+            #[cfg(feature = "lvm")]
+            Source::Logical(reader) => reader.size(),
+        }
+    }
+}
+
+impl<R> Clone for Region<R> {
+    fn clone(&self) -> Self {
+        Region {
+            inner: Arc::clone(&self.inner),
+            start: self.start,
+            len: self.len,
+        }
+    }
+}
+
+impl<R: ReadAt> ReadAt for Region<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = match self.len {
+            Some(len) if pos >= len => return Ok(0),
+            Some(len) => {
+                let left = usize::try_from(len - pos).unwrap_or(usize::MAX);
+                let end = std::cmp::min(left, buf.len());
+                &mut buf[..end]
+            }
+            None => buf,
+        };
+        self.inner.read_at(self.start + pos, buf)
+    }
+}
+
+/// The partitions, if sector zero is an MBR; following it to a GPT if it's protective.
+fn partition_table<R: ReadAt>(reader: &R) -> Result<Option<Vec<Partition>>, Error> {
+    let mut mbr = [0u8; SECTOR as usize];
+    reader.read_exact_at(0, &mut mbr)?;
+    let entries = match mbr_entries(&mbr) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+    if entries.iter().all(|entry| 0 == entry.kind) {
+        return Ok(None);
+    }
+    if entries.iter().any(|entry| PROTECTIVE == entry.kind) {
+        return gpt(reader).map(Some);
+    }
+
+    let mut partitions = Vec::new();
+    for (number, entry) in (1..).zip(entries.iter()) {
+        if 0 == entry.kind || 0 == entry.sectors {
+            continue;
+        }
+        if EXTENDED.contains(&entry.kind) {
+            logical_partitions(reader, entry.start, &mut partitions)?;
+            continue;
+        }
+        partitions.push(Partition {
+            number,
+            start: entry.start * SECTOR,
+            len: entry.sectors * SECTOR,
+        });
+    }
+    partitions.sort_by_key(|part| part.number);
+    Ok(Some(partitions))
+}
+
+/// An MBR, or extended boot record, entry; all in sectors.
+struct MbrEntry {
+    kind: u8,
+    start: u64,
+    sectors: u64,
+}
+
+/// The four entries, if `sector` looks like a partition table: it's signed, and every
+/// entry is bootable, or not. A bare filesystem's boot sector is usually empty.
+fn mbr_entries(sector: &[u8; SECTOR as usize]) -> Option<[MbrEntry; 4]> {
+    if [0x55, 0xAA] != sector[510..512] {
+        return None;
+    }
+    let entry = |i: usize| {
+        let raw = &sector[446 + 16 * i..446 + 16 * (i + 1)];
+        (
+            raw[0],
+            MbrEntry {
+                kind: raw[4],
+                start: u64::from(LittleEndian::read_u32(&raw[8..])),
+                sectors: u64::from(LittleEndian::read_u32(&raw[12..])),
+            },
+        )
+    };
+    let entries = [entry(0), entry(1), entry(2), entry(3)];
+    if entries
+        .iter()
+        .any(|(status, _)| 0 != *status && 0x80 != *status)
+    {
+        return None;
+    }
+    let [a, b, c, d] = entries;
+    Some([a.1, b.1, c.1, d.1])
+}
+
+/// Follow the chain of extended boot records from the extended partition at `extended`,
+/// a sector, numbering the logical partitions in it from five.
+fn logical_partitions<R: ReadAt>(
+    reader: &R,
+    extended: u64,
+    partitions: &mut Vec<Partition>,
+) -> Result<(), Error> {
+    let mut ebr = extended;
+    for number in 5..5 + MAX_LOGICAL {
+        let mut sector = [0u8; SECTOR as usize];
+        reader.read_exact_at(ebr * SECTOR, &mut sector)?;
+        let [this, next, _, _] = mbr_entries(&sector).ok_or_else(|| {
+            assumption_failed(format!("no extended boot record at sector {}", ebr))
+        })?;
+        // positions are relative to this record, but the next record's, to the partition's
+        if 0 != this.kind && 0 != this.sectors {
+            partitions.push(Partition {
+                number,
+                start: (ebr + this.start) * SECTOR,
+                len: this.sectors * SECTOR,
+            });
+        }
+        if 0 == next.kind || 0 == next.start {
+            return Ok(());
+        }
+        ebr = extended + next.start;
+    }
+    Err(assumption_failed(format!(
+        "more than {} logical partitions; the chain probably loops",
+        MAX_LOGICAL
+    ))
+    .into())
+}
+
+/// The used entries of a GUID partition table, with 512 byte sectors, or 4k.
+fn gpt<R: ReadAt>(reader: &R) -> Result<Vec<Partition>, Error> {
+    for sector in [SECTOR, 4096] {
+        let mut header = vec![0u8; 92];
+        reader.read_exact_at(sector, &mut header)?;
+        if b"EFI PART" != &header[..8] {
+            continue;
+        }
+
+        let header_size = LittleEndian::read_u32(&header[12..]);
+        ensure!(
+            (92..=sector).contains(&u64::from(header_size)),
+            assumption_failed(format!("gpt header size {}", header_size))
+        );
+        header.resize(usize::try_from(header_size)?, 0);
+        reader.read_exact_at(sector, &mut header)?;
+        let checksum = LittleEndian::read_u32(&header[16..]);
+        header[16..20].copy_from_slice(&[0; 4]);
+        ensure!(
+            checksum == crc::crc32::checksum_ieee(&header),
+            assumption_failed("gpt header checksum mismatch")
+        );
+
+        let entries_at = LittleEndian::read_u64(&header[72..]);
+        let count = LittleEndian::read_u32(&header[80..]);
+        let entry_size = LittleEndian::read_u32(&header[84..]);
+        ensure!(
+            count <= MAX_GPT_ENTRIES && (128..=4096).contains(&entry_size),
+            assumption_failed(format!("gpt has {} entries of {} bytes", count, entry_size))
+        );
+        // the header's checksum covers these, but anyone can make a checksum
+        let bytes = |sectors: u64| {
+            sectors.checked_mul(sector).ok_or_else(|| {
+                assumption_failed(format!(
+                    "gpt sector {} is past the end of any disc",
+                    sectors
+                ))
+            })
+        };
+        let entries_len = count.checked_mul(entry_size).ok_or_else(|| {
+            assumption_failed(format!("gpt has {} entries of {} bytes", count, entry_size))
+        })?;
+        let mut entries = vec![0u8; usize::try_from(entries_len)?];
+        reader.read_exact_at(bytes(entries_at)?, &mut entries)?;
+        ensure!(
+            LittleEndian::read_u32(&header[88..]) == crc::crc32::checksum_ieee(&entries),
+            assumption_failed("gpt entries checksum mismatch")
+        );
+
+        let mut partitions = Vec::new();
+        for (number, entry) in (1..).zip(entries.chunks(usize::try_from(entry_size)?)) {
+            // the type; all zeros is unused
+            if entry[..16].iter().all(|&b| 0 == b) {
+                continue;
+            }
+            let first = LittleEndian::read_u64(&entry[32..]);
+            let last = LittleEndian::read_u64(&entry[40..]);
+            ensure!(
+                first <= last,
+                assumption_failed(format!(
+                    "gpt partition {} ends at {}, before it starts at {}",
+                    number, last, first
+                ))
+            );
+            partitions.push(Partition {
+                number,
+                start: bytes(first)?,
+                len: bytes(last - first)?.checked_add(sector).ok_or_else(|| {
+                    assumption_failed(format!("gpt partition {} is too long", number))
+                })?,
+            });
+        }
+        return Ok(partitions);
+    }
+    Err(not_found("protective MBR, but no GPT header").into())
+}
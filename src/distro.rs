@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::DirEntry;
+use crate::Enhanced;
+use crate::FileType;
+use crate::ParseError;
+use crate::SuperBlock;
+
+/// Where each file is looked for, in order. Links are followed, so e.g. an
+/// `/etc/os-release` pointing at `../usr/lib/os-release` is fine.
+const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+const PASSWD: [&str; 1] = ["/etc/passwd"];
+const GROUP: [&str; 1] = ["/etc/group"];
+const DPKG_STATUS: [&str; 1] = ["/var/lib/dpkg/status"];
+const RPM_DATABASE: [&str; 2] = ["/usr/lib/sysimage/rpm", "/var/lib/rpm"];
+
+/// The identity of the installed operating system, as described in `os-release(5)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OsRelease {
+    /// Where it was found, with any links resolved.
+    pub path: String,
+    /// e.g. `debian`.
+    pub id: Option<String>,
+    /// Distributions this one is derived from, most closely related first.
+    pub id_like: Vec<String>,
+    pub name: Option<String>,
+    pub pretty_name: Option<String>,
+    /// e.g. `12`; missing on rolling releases.
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+    /// Every variable in the file, including those above, with quoting removed.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A line of `/etc/passwd`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// The comment field; usually the user's full name.
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+/// A line of `/etc/group`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    /// Users with this as a supplementary group; not those with it as their primary group.
+    pub members: Vec<String>,
+}
+
+/// A package in dpkg's status database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DpkgPackage {
+    pub name: String,
+    pub version: String,
+    pub architecture: Option<String>,
+    /// The source package, if it's named differently, without any version.
+    pub source: Option<String>,
+    /// e.g. `install ok installed`; removed packages can still be listed.
+    pub status: String,
+}
+
+impl DpkgPackage {
+    /// Whether the package's files are on disc, i.e. it isn't removed, or half-installed.
+    pub fn is_installed(&self) -> bool {
+        self.status.ends_with(" installed")
+    }
+}
+
+/// The directory holding rpm's database. The database itself isn't parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RpmDatabase {
+    /// Where it was found, with any links resolved.
+    pub path: String,
+    /// The regular files in the directory, with their sizes, sorted by name.
+    pub files: Vec<(String, u64)>,
+}
+
+/// The storage formats rpm has used over the years.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RpmBackend {
+    /// `rpmdb.sqlite`, the default since rpm 4.16.
+    Sqlite,
+    /// `Packages.db`, used by SUSE.
+    Ndb,
+    /// `Packages`, the original Berkeley DB format.
+    BerkeleyDb,
+}
+
+impl RpmDatabase {
+    /// Guess the format from the names of the files, preferring the newest.
+    pub fn backend(&self) -> Option<RpmBackend> {
+        let has = |wanted: &str| self.files.iter().any(|(name, _)| name == wanted);
+        if has("rpmdb.sqlite") {
+            Some(RpmBackend::Sqlite)
+        } else if has("Packages.db") {
+            Some(RpmBackend::Ndb)
+        } else if has("Packages") {
+            Some(RpmBackend::BerkeleyDb)
+        } else {
+            None
+        }
+    }
+}
+
+/// Everything `SuperBlock::distro` could find. Each part is `None` if its file isn't there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Distro {
+    pub os_release: Option<OsRelease>,
+    pub passwd: Option<Vec<PasswdEntry>>,
+    pub group: Option<Vec<GroupEntry>>,
+    pub dpkg: Option<Vec<DpkgPackage>>,
+    pub rpm: Option<RpmDatabase>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// All of the below, in one go.
+    pub fn distro(&self) -> Result<Distro, Error> {
+        Ok(Distro {
+            os_release: self.os_release()?,
+            passwd: self.passwd()?,
+            group: self.group()?,
+            dpkg: self.dpkg_status()?,
+            rpm: self.rpm_database()?,
+        })
+    }
+
+    /// Parse `/etc/os-release`, falling back to `/usr/lib/os-release`.
+    pub fn os_release(&self) -> Result<Option<OsRelease>, Error> {
+        let (path, reader) = match self.find_file(&OS_RELEASE)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let mut fields = BTreeMap::new();
+        for_each_line(reader, |line| {
+            let line = line.trim();
+            if line.starts_with('#') {
+                return Ok(());
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                fields.insert(key.trim().to_string(), unquote(value.trim()));
+            }
+            Ok(())
+        })
+        .with_context(|| anyhow!("reading {}", path))?;
+
+        let field = |key: &str| fields.get(key).cloned();
+        Ok(Some(OsRelease {
+            id: field("ID"),
+            id_like: field("ID_LIKE")
+                .map(|like| like.split_whitespace().map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            name: field("NAME"),
+            pretty_name: field("PRETTY_NAME"),
+            version_id: field("VERSION_ID"),
+            version_codename: field("VERSION_CODENAME"),
+            path,
+            fields,
+        }))
+    }
+
+    /// Parse `/etc/passwd`. Lines which don't make sense are skipped.
+    pub fn passwd(&self) -> Result<Option<Vec<PasswdEntry>>, Error> {
+        let (path, reader) = match self.find_file(&PASSWD)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let mut entries = Vec::new();
+        for_each_line(reader, |line| {
+            let parts: Vec<&str> = line.split(':').collect();
+            if let [name, _password, uid, gid, gecos, home, shell] = parts[..] {
+                if let (Ok(uid), Ok(gid)) = (uid.parse(), gid.parse()) {
+                    entries.push(PasswdEntry {
+                        name: name.to_string(),
+                        uid,
+                        gid,
+                        gecos: gecos.to_string(),
+                        home: home.to_string(),
+                        shell: shell.to_string(),
+                    });
+                }
+            }
+            Ok(())
+        })
+        .with_context(|| anyhow!("reading {}", path))?;
+        Ok(Some(entries))
+    }
+
+    /// Parse `/etc/group`. Lines which don't make sense are skipped.
+    pub fn group(&self) -> Result<Option<Vec<GroupEntry>>, Error> {
+        let (path, reader) = match self.find_file(&GROUP)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let mut entries = Vec::new();
+        for_each_line(reader, |line| {
+            let parts: Vec<&str> = line.split(':').collect();
+            if let [name, _password, gid, members] = parts[..] {
+                if let Ok(gid) = gid.parse() {
+                    entries.push(GroupEntry {
+                        name: name.to_string(),
+                        gid,
+                        members: members
+                            .split(',')
+                            .filter(|member| !member.is_empty())
+                            .map(|member| member.to_string())
+                            .collect(),
+                    });
+                }
+            }
+            Ok(())
+        })
+        .with_context(|| anyhow!("reading {}", path))?;
+        Ok(Some(entries))
+    }
+
+    /// Parse dpkg's `/var/lib/dpkg/status`, in file order. Entries without a name,
+    /// version or status are skipped.
+    pub fn dpkg_status(&self) -> Result<Option<Vec<DpkgPackage>>, Error> {
+        let (path, reader) = match self.find_file(&DPKG_STATUS)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let mut packages = Vec::new();
+        let mut fields = BTreeMap::new();
+        for_each_line(reader, |line| {
+            if line.trim().is_empty() {
+                packages.extend(dpkg_package(&mut fields));
+            } else if !line.starts_with(' ') && !line.starts_with('\t') {
+                // continuation lines are only used by fields we don't read
+                if let Some((key, value)) = line.split_once(':') {
+                    fields.insert(key.to_string(), value.trim().to_string());
+                }
+            }
+            Ok(())
+        })
+        .with_context(|| anyhow!("reading {}", path))?;
+        packages.extend(dpkg_package(&mut fields));
+
+        Ok(Some(packages))
+    }
+
+    /// List rpm's database directory, `/usr/lib/sysimage/rpm` or `/var/lib/rpm`.
+    /// An empty directory isn't counted.
+    pub fn rpm_database(&self) -> Result<Option<RpmDatabase>, Error> {
+        for candidate in &RPM_DATABASE {
+            let (path, entry) = match self.canonicalize_existing(candidate)? {
+                Some(found) => found,
+                None => continue,
+            };
+            let entries = match self.enhance(&self.load_inode(entry.inode)?)? {
+                Enhanced::Directory(entries) => entries,
+                _ => continue,
+            };
+
+            let mut files = Vec::new();
+            for entry in entries {
+                if FileType::RegularFile == entry.file_type {
+                    let size = self.load_inode(entry.inode)?.stat.size;
+                    files.push((entry.name, size));
+                }
+            }
+            if files.is_empty() {
+                continue;
+            }
+            files.sort();
+            return Ok(Some(RpmDatabase { path, files }));
+        }
+        Ok(None)
+    }
+
+    /// The first of `candidates` which is a regular file, and its canonical path.
+    fn find_file(&self, candidates: &[&str]) -> Result<Option<(String, impl Read + '_)>, Error> {
+        for candidate in candidates {
+            if let Some((path, entry)) = self.canonicalize_existing(candidate)? {
+                if FileType::RegularFile == entry.file_type {
+                    return Ok(Some((path, self.open(&self.load_inode(entry.inode)?)?)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// `canonicalize`, but `None` if any part of the path isn't there.
+    fn canonicalize_existing(&self, path: &str) -> Result<Option<(String, DirEntry)>, Error> {
+        match self.canonicalize(path) {
+            Ok(found) => Ok(Some(found)),
+            Err(e) => match e.downcast_ref::<ParseError>() {
+                Some(ParseError::NotFound { .. }) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// Call `visit` with each line, without its terminator. Invalid UTF-8 is replaced,
+/// rather than failing the whole file.
+fn for_each_line<F>(reader: impl Read, mut visit: F) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<(), Error>,
+{
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if 0 == reader.read_until(b'\n', &mut line)? {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&line);
+        visit(text.trim_end_matches(&['\n', '\r'][..]))?;
+    }
+}
+
+/// Undo the shell-style quoting `os-release(5)` allows.
+fn unquote(value: &str) -> String {
+    let quote = match value.chars().next() {
+        Some(c @ '"') | Some(c @ '\'') if value.len() >= 2 && value.ends_with(c) => c,
+        _ => return value.to_string(),
+    };
+    let inner = &value[1..value.len() - 1];
+    if '\'' == quote {
+        return inner.to_string();
+    }
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if '\\' == c {
+            match chars.next() {
+                Some(next @ ('\\' | '"' | '$' | '`')) => out.push(next),
+                Some(next) => {
+                    out.push('\\');
+                    out.push(next);
+                }
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Take the fields collected for one stanza, and build a package from them, if they're enough.
+fn dpkg_package(fields: &mut BTreeMap<String, String>) -> Option<DpkgPackage> {
+    let mut fields = std::mem::take(fields);
+    Some(DpkgPackage {
+        name: fields.remove("Package")?,
+        version: fields.remove("Version")?,
+        status: fields.remove("Status")?,
+        architecture: fields.remove("Architecture"),
+        source: fields
+            .remove("Source")
+            .and_then(|source| source.split_whitespace().next().map(|s| s.to_string())),
+    })
+}
@@ -0,0 +1,1001 @@
+use std::io;
+
+use anyhow::Error;
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use positioned_io2::ReadAt;
+
+use crate::info::c_string;
+use crate::zip::civil_from_days;
+use crate::BlockGroup;
+use crate::BlockGroupFlags;
+use crate::SuperBlock;
+
+const COMPAT_FEATURES: &[(u32, &str)] = &[
+    (0x0001, "dir_prealloc"),
+    (0x0002, "imagic_inodes"),
+    (0x0004, "has_journal"),
+    (0x0008, "ext_attr"),
+    (0x0010, "resize_inode"),
+    (0x0020, "dir_index"),
+    (0x0040, "lazy_bg"),
+    (0x0100, "snapshot_bitmap"),
+    (0x0200, "sparse_super2"),
+    (0x0400, "fast_commit"),
+    (0x0800, "stable_inodes"),
+    (0x1000, "orphan_file"),
+];
+
+const INCOMPAT_FEATURES: &[(u32, &str)] = &[
+    (0x0001, "compression"),
+    (0x0002, "filetype"),
+    (0x0004, "needs_recovery"),
+    (0x0008, "journal_dev"),
+    (0x0010, "meta_bg"),
+    (0x0040, "extent"),
+    (0x0080, "64bit"),
+    (0x0100, "mmp"),
+    (0x0200, "flex_bg"),
+    (0x0400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x10000, "encrypt"),
+    (0x20000, "casefold"),
+];
+
+const RO_COMPAT_FEATURES: &[(u32, &str)] = &[
+    (0x0001, "sparse_super"),
+    (0x0002, "large_file"),
+    (0x0008, "huge_file"),
+    (0x0010, "uninit_bg"),
+    (0x0020, "dir_nlink"),
+    (0x0040, "extra_isize"),
+    (0x0080, "quota"),
+    (0x0100, "bigalloc"),
+    (0x0200, "snapshot"),
+    (0x0400, "metadata_csum"),
+    (0x0800, "replica"),
+    (0x1000, "read-only"),
+    (0x2000, "project"),
+    (0x4000, "shared_blocks"),
+    (0x8000, "verity"),
+    (0x10000, "orphan_present"),
+];
+
+const MOUNT_OPTIONS: &[(u32, &str)] = &[
+    (0x0001, "debug"),
+    (0x0002, "bsdgroups"),
+    (0x0004, "user_xattr"),
+    (0x0008, "acl"),
+    (0x0010, "uid16"),
+    (0x0100, "nobarrier"),
+    (0x0200, "block_validity"),
+    (0x0400, "discard"),
+    (0x0800, "nodelalloc"),
+];
+
+const JOURNAL_MODE: u32 = 0x0060;
+
+const JOURNAL_COMPAT_FEATURES: &[(u32, &str)] = &[(0x0001, "journal_checksum")];
+
+const JOURNAL_INCOMPAT_FEATURES: &[(u32, &str)] = &[
+    (0x0001, "journal_incompat_revoke"),
+    (0x0002, "journal_64bit"),
+    (0x0004, "journal_async_commit"),
+    (0x0008, "journal_checksum_v2"),
+    (0x0010, "journal_checksum_v3"),
+    (0x0020, "journal_fast_commit"),
+];
+
+const HASHES: &[&str] = &[
+    "legacy",
+    "half_md4",
+    "tea",
+    "legacy_unsigned",
+    "half_md4_unsigned",
+    "tea_unsigned",
+    "siphash",
+];
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const ERROR_CODES: &[&str] = &[
+    "",
+    "UNKNOWN",
+    "EIO",
+    "ENOMEM",
+    "EFSBADCRC",
+    "EFSCORRUPTED",
+    "ENOSPC",
+    "ENOKEY",
+    "EROFS",
+    "EFBIG",
+    "EEXIST",
+    "ERANGE",
+    "EOVERFLOW",
+    "EBUSY",
+    "ENOTDIR",
+    "ENOTEMPTY",
+    "ESHUTDOWN",
+    "EFAULT",
+];
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Describe the filesystem exactly as `dumpe2fs` (1.47) does, so scripts which read its
+    /// output can be pointed at an image this crate has opened: the superblock, an internal
+    /// journal's superblock, then each group, its metadata, and its free blocks and inodes.
+    ///
+    /// Times are in UTC, as `dumpe2fs` shows them with `TZ=UTC`. The reserved blocks' owners
+    /// are named only if they're `root`, as `dumpe2fs` looks names up on the host, not in
+    /// the image. Bad blocks, and `mmp`'s block, aren't shown.
+    pub fn dumpe2fs(&self, out: &mut dyn io::Write) -> Result<(), Error> {
+        self.dumpe2fs_superblock(out)?;
+        if let Some(journal) = self.journal_inode.filter(|&inode| 0 != inode) {
+            self.dumpe2fs_journal(out, journal)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out)?;
+        let free_blocks = self.unallocated_ranges()?;
+        for number in 0..self.groups.count() {
+            let group = self.groups.get(&self.inner, number)?;
+            self.dumpe2fs_group(out, &group, &free_blocks)?;
+        }
+        Ok(())
+    }
+
+    fn dumpe2fs_superblock(&self, out: &mut dyn io::Write) -> Result<(), Error> {
+        let sb = &self.raw;
+        let incompat = sb.s_feature_incompat;
+        let ro_compat = sb.s_feature_ro_compat;
+        let bigalloc = 0 != ro_compat & 0x0100;
+        let block_size = self.groups.block_size;
+
+        let volume_name = c_string(&sb.s_volume_name);
+        let volume_name = if volume_name.is_empty() {
+            "<none>"
+        } else {
+            &volume_name
+        };
+        writeln!(out, "Filesystem volume name:   {}", volume_name)?;
+        let last_mounted = c_string(&sb.s_last_mounted);
+        let last_mounted = if last_mounted.is_empty() {
+            "<not available>"
+        } else {
+            &last_mounted
+        };
+        writeln!(out, "Last mounted on:          {}", last_mounted)?;
+        writeln!(out, "Filesystem UUID:          {}", uuid(&sb.s_uuid))?;
+        writeln!(out, "Filesystem magic number:  0x{:04X}", sb.s_magic)?;
+        writeln!(
+            out,
+            "Filesystem revision #:    {} ({})",
+            sb.s_rev_level,
+            match sb.s_rev_level {
+                0 => "original",
+                1 => "dynamic",
+                _ => "unknown",
+            }
+        )?;
+
+        write!(out, "Filesystem features:     ")?;
+        let mut printed = 0;
+        for (features, names, letter) in &[
+            (sb.s_feature_compat, COMPAT_FEATURES, 'C'),
+            (incompat, INCOMPAT_FEATURES, 'I'),
+            (ro_compat, RO_COMPAT_FEATURES, 'R'),
+        ] {
+            for bit in 0..32 {
+                let mask = 1u32 << bit;
+                if 0 != features & mask {
+                    write!(out, " {}", name(names, mask, *letter, bit))?;
+                    printed += 1;
+                }
+            }
+        }
+        if 0 == printed {
+            write!(out, " (none)")?;
+        }
+        writeln!(out)?;
+
+        if 0 != sb.s_flags {
+            write!(out, "Filesystem flags:         ")?;
+            let mut found = 0;
+            for (mask, name) in &[
+                (0x1, "signed_directory_hash"),
+                (0x2, "unsigned_directory_hash"),
+                (0x4, "test_filesystem"),
+            ] {
+                if 0 != sb.s_flags & mask {
+                    write!(out, "{} ", name)?;
+                    found += 1;
+                }
+            }
+            writeln!(out, "{}", if 0 == found { "(none)" } else { "" })?;
+        }
+
+        write!(out, "Default mount options:   ")?;
+        let options = sb.s_default_mount_opts;
+        let mut printed = 0;
+        if 0 != options & JOURNAL_MODE {
+            let mode = match options & JOURNAL_MODE {
+                0x20 => "journal_data",
+                0x40 => "journal_data_ordered",
+                _ => "journal_data_writeback",
+            };
+            write!(out, " {}", mode)?;
+            printed += 1;
+        }
+        for bit in 0..32 {
+            let mask = 1u32 << bit;
+            if 0 != mask & JOURNAL_MODE || 0 == options & mask {
+                continue;
+            }
+            match MOUNT_OPTIONS.iter().find(|(known, _)| *known == mask) {
+                Some((_, name)) => write!(out, " {}", name)?,
+                None => write!(out, " MNTOPT_{}", bit)?,
+            }
+            printed += 1;
+        }
+        if 0 == printed {
+            write!(out, " (none)")?;
+        }
+        writeln!(out)?;
+
+        let mount_options = c_string(&sb.s_mount_opts);
+        if !mount_options.is_empty() {
+            writeln!(out, "Mount options:            {}", mount_options)?;
+        }
+        writeln!(
+            out,
+            "Filesystem state:         {}{}",
+            if 0 != sb.s_state & 0x1 {
+                "clean"
+            } else {
+                "not clean"
+            },
+            if 0 != sb.s_state & 0x2 {
+                " with errors"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(
+            out,
+            "Errors behavior:          {}",
+            match sb.s_errors {
+                1 => "Continue",
+                2 => "Remount read-only",
+                3 => "Panic",
+                _ => "Unknown (continue)",
+            }
+        )?;
+        writeln!(
+            out,
+            "Filesystem OS type:       {}",
+            match sb.s_creator_os {
+                0 => "Linux",
+                1 => "Hurd",
+                2 => "Masix",
+                3 => "FreeBSD",
+                4 => "Lites",
+                _ => "(unknown os)",
+            }
+        )?;
+
+        let wide = 0 != incompat & 0x0080;
+        let hi = |lo: u32, hi: u32| u64::from(lo) | if wide { u64::from(hi) << 32 } else { 0 };
+        writeln!(out, "Inode count:              {}", sb.s_inodes_count)?;
+        writeln!(
+            out,
+            "Block count:              {}",
+            hi(sb.s_blocks_count_lo, sb.s_blocks_count_hi)
+        )?;
+        writeln!(
+            out,
+            "Reserved block count:     {}",
+            hi(sb.s_r_blocks_count_lo, sb.s_r_blocks_count_hi)
+        )?;
+        if 0 != sb.s_overhead_clusters {
+            writeln!(out, "Overhead clusters:        {}", sb.s_overhead_clusters)?;
+        }
+        writeln!(
+            out,
+            "Free blocks:              {}",
+            hi(sb.s_free_blocks_count_lo, sb.s_free_blocks_count_hi)
+        )?;
+        writeln!(out, "Free inodes:              {}", sb.s_free_inodes_count)?;
+        writeln!(out, "First block:              {}", sb.s_first_data_block)?;
+        writeln!(out, "Block size:               {}", block_size)?;
+        let cluster_size = 1024u64 << sb.s_log_cluster_size.min(32);
+        if bigalloc {
+            writeln!(out, "Cluster size:             {}", cluster_size)?;
+        } else {
+            writeln!(out, "Fragment size:            {}", cluster_size)?;
+        }
+        if wide {
+            writeln!(out, "Group descriptor size:    {}", sb.s_desc_size)?;
+        }
+        if 0 != sb.s_reserved_gdt_blocks {
+            writeln!(
+                out,
+                "Reserved GDT blocks:      {}",
+                sb.s_reserved_gdt_blocks
+            )?;
+        }
+        writeln!(out, "Blocks per group:         {}", sb.s_blocks_per_group)?;
+        if bigalloc {
+            writeln!(out, "Clusters per group:       {}", sb.s_clusters_per_group)?;
+        } else {
+            writeln!(out, "Fragments per group:      {}", sb.s_clusters_per_group)?;
+        }
+        writeln!(out, "Inodes per group:         {}", sb.s_inodes_per_group)?;
+        let inode_size = u64::from(self.groups.inode_size);
+        writeln!(
+            out,
+            "Inode blocks per group:   {}",
+            (u64::from(sb.s_inodes_per_group) * inode_size + u64::from(block_size) - 1)
+                / u64::from(block_size)
+        )?;
+        if 0 != sb.s_raid_stride {
+            writeln!(out, "RAID stride:              {}", sb.s_raid_stride)?;
+        }
+        if 0 != sb.s_raid_stripe_width {
+            writeln!(out, "RAID stripe width:        {}", sb.s_raid_stripe_width)?;
+        }
+        if 0 != sb.s_first_meta_bg {
+            writeln!(out, "First meta block group:   {}", sb.s_first_meta_bg)?;
+        }
+        if 0 != sb.s_log_groups_per_flex {
+            writeln!(
+                out,
+                "Flex block group size:    {}",
+                1u64 << sb.s_log_groups_per_flex.min(32)
+            )?;
+        }
+
+        let created = time(sb.s_mkfs_time, sb.s_mkfs_time_hi);
+        if 0 != created {
+            writeln!(out, "Filesystem created:       {}", ctime(created))?;
+        }
+        let mounted = time(sb.s_mtime, sb.s_mtime_hi);
+        if 0 != mounted {
+            writeln!(out, "Last mount time:          {}", ctime(mounted))?;
+        } else {
+            writeln!(out, "Last mount time:          n/a")?;
+        }
+        writeln!(
+            out,
+            "Last write time:          {}",
+            ctime(time(sb.s_wtime, sb.s_wtime_hi))
+        )?;
+        writeln!(out, "Mount count:              {}", sb.s_mnt_count)?;
+        writeln!(
+            out,
+            "Maximum mount count:      {}",
+            sb.s_max_mnt_count as i16
+        )?;
+        let checked = time(sb.s_lastcheck, sb.s_lastcheck_hi);
+        writeln!(out, "Last checked:             {}", ctime(checked))?;
+        writeln!(
+            out,
+            "Check interval:           {} ({})",
+            sb.s_checkinterval,
+            interval(sb.s_checkinterval)
+        )?;
+        if 0 != sb.s_checkinterval {
+            writeln!(
+                out,
+                "Next check after:         {}",
+                ctime(checked + i64::from(sb.s_checkinterval))
+            )?;
+        }
+
+        let written = sb.s_kbytes_written;
+        if 0 != written {
+            write!(out, "Lifetime writes:          ")?;
+            if written < 1 << 13 {
+                writeln!(out, "{} kB", written)?;
+            } else if written < 1 << 23 {
+                writeln!(out, "{} MB", (written + (1 << 9)) >> 10)?;
+            } else if written < 1 << 33 {
+                writeln!(out, "{} GB", (written + (1 << 19)) >> 20)?;
+            } else if written < 1 << 43 {
+                writeln!(out, "{} TB", (written + (1 << 29)) >> 30)?;
+            } else {
+                writeln!(out, "{} PB", (written + (1 << 39)) >> 40)?;
+            }
+        }
+        writeln!(
+            out,
+            "Reserved blocks uid:      {} (user {})",
+            sb.s_def_resuid,
+            if 0 == sb.s_def_resuid {
+                "root"
+            } else {
+                "unknown"
+            }
+        )?;
+        writeln!(
+            out,
+            "Reserved blocks gid:      {} (group {})",
+            sb.s_def_resgid,
+            if 0 == sb.s_def_resgid {
+                "root"
+            } else {
+                "unknown"
+            }
+        )?;
+        if sb.s_rev_level >= 1 {
+            writeln!(out, "First inode:              {}", sb.s_first_ino)?;
+            writeln!(out, "Inode size:\t          {}", sb.s_inode_size)?;
+            if 0 != sb.s_min_extra_isize {
+                writeln!(out, "Required extra isize:     {}", sb.s_min_extra_isize)?;
+            }
+            if 0 != sb.s_want_extra_isize {
+                writeln!(out, "Desired extra isize:      {}", sb.s_want_extra_isize)?;
+            }
+        }
+
+        if [0u8; 16] != sb.s_journal_uuid {
+            writeln!(
+                out,
+                "Journal UUID:             {}",
+                uuid(&sb.s_journal_uuid)
+            )?;
+        }
+        if 0 != sb.s_journal_inum {
+            writeln!(out, "Journal inode:            {}", sb.s_journal_inum)?;
+        }
+        if 0 != sb.s_journal_dev {
+            writeln!(out, "Journal device:\t          0x{:04x}", sb.s_journal_dev)?;
+        }
+        if 0 != sb.s_last_orphan {
+            writeln!(out, "First orphan inode:       {}", sb.s_last_orphan)?;
+        }
+        if 0 != sb.s_feature_compat & 0x0020 || 0 != sb.s_def_hash_version {
+            let version = usize::from(sb.s_def_hash_version);
+            match HASHES.get(version) {
+                Some(hash) => writeln!(out, "Default directory hash:   {}", hash)?,
+                None => writeln!(out, "Default directory hash:   HASHALG_{}", version)?,
+            }
+        }
+        if [0u32; 4] != sb.s_hash_seed {
+            let mut seed = [0u8; 16];
+            for (bytes, word) in seed.chunks_mut(4).zip(&sb.s_hash_seed) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+            writeln!(out, "Directory Hash Seed:      {}", uuid(&seed))?;
+        }
+        match sb.s_jnl_backup_type {
+            0 => (),
+            1 => writeln!(out, "Journal backup:           inode blocks")?,
+            other => writeln!(out, "Journal backup:           type {}", other)?,
+        }
+        if [0u32; 2] != sb.s_backup_bgs {
+            write!(out, "Backup block groups:      ")?;
+            if 0 != sb.s_backup_bgs[0] {
+                write!(out, "{} ", sb.s_backup_bgs[0])?;
+            }
+            if 0 != sb.s_backup_bgs[1] {
+                write!(out, "{}", sb.s_backup_bgs[1])?;
+            }
+            writeln!(out)?;
+        }
+        if 0 != sb.s_snapshot_inum {
+            writeln!(out, "Snapshot inode:           {}", sb.s_snapshot_inum)?;
+            writeln!(out, "Snapshot ID:              {}", sb.s_snapshot_id)?;
+            writeln!(
+                out,
+                "Snapshot reserved blocks: {}",
+                sb.s_snapshot_r_blocks_count
+            )?;
+        }
+        if 0 != sb.s_snapshot_list {
+            writeln!(out, "Snapshot list head:       {}", sb.s_snapshot_list)?;
+        }
+
+        if 0 != sb.s_error_count {
+            writeln!(out, "FS Error count:           {}", sb.s_error_count)?;
+        }
+        for (which, (at, at_hi, function, line, inode, block, code)) in [
+            (
+                "First",
+                (
+                    sb.s_first_error_time,
+                    sb.s_first_error_time_hi,
+                    &sb.s_first_error_func,
+                    sb.s_first_error_line,
+                    sb.s_first_error_ino,
+                    sb.s_first_error_block,
+                    sb.s_first_error_errcode,
+                ),
+            ),
+            (
+                "Last",
+                (
+                    sb.s_last_error_time,
+                    sb.s_last_error_time_hi,
+                    &sb.s_last_error_func,
+                    sb.s_last_error_line,
+                    sb.s_last_error_ino,
+                    sb.s_last_error_block,
+                    sb.s_last_error_errcode,
+                ),
+            ),
+        ] {
+            if 0 == at {
+                continue;
+            }
+            let label = |what: &str| format!("{} error {}:", which, what);
+            writeln!(out, "{:<26}{}", label("time"), ctime(time(at, at_hi)))?;
+            writeln!(out, "{:<26}{}", label("function"), c_string(function))?;
+            writeln!(out, "{:<26}{}", label("line #"), line)?;
+            if 0 != inode {
+                writeln!(out, "{:<26}{}", label("inode #"), inode)?;
+            }
+            if 0 != block {
+                writeln!(out, "{:<26}{}", label("block #"), block)?;
+            }
+            if 0 != code {
+                match ERROR_CODES.get(usize::from(code)) {
+                    Some(name) => writeln!(out, "{:<26}{}", label("err"), name)?,
+                    None => writeln!(out, "{:<26}UNKNOWN_ERRCODE_{}", label("err"), code)?,
+                }
+            }
+        }
+
+        if 0 != incompat & 0x0100 {
+            writeln!(out, "MMP block number:         {}", sb.s_mmp_block)?;
+            writeln!(
+                out,
+                "MMP update interval:      {}",
+                sb.s_mmp_update_interval
+            )?;
+        }
+        for (label, inode) in [
+            ("User quota inode:", sb.s_usr_quota_inum),
+            ("Group quota inode:", sb.s_grp_quota_inum),
+            ("Project quota inode:", sb.s_prj_quota_inum),
+        ] {
+            if 0 != inode {
+                writeln!(out, "{:<26}{}", label, inode)?;
+            }
+        }
+        if 0 != ro_compat & 0x0400 {
+            writeln!(
+                out,
+                "Checksum type:            {}",
+                if 1 == sb.s_checksum_type {
+                    "crc32c"
+                } else {
+                    "unknown"
+                }
+            )?;
+            writeln!(out, "Checksum:                 0x{:08x}", sb.s_checksum)?;
+        }
+        if [0u8; 16] != sb.s_encrypt_pw_salt {
+            writeln!(
+                out,
+                "Encryption PW Salt:       {}",
+                uuid(&sb.s_encrypt_pw_salt)
+            )?;
+        }
+        if 0 != incompat & 0x2000 {
+            writeln!(
+                out,
+                "Checksum seed:            0x{:08x}",
+                sb.s_checksum_seed
+            )?;
+        }
+        if 0 != incompat & 0x20000 {
+            match sb.s_encoding {
+                1 => writeln!(out, "Character encoding:       utf8-12.1")?,
+                other => writeln!(out, "Character encoding:       ENC_UNKNOWN_{}", other)?,
+            }
+        }
+        if 0 != sb.s_feature_compat & 0x1000 {
+            writeln!(out, "Orphan file inode:        {}", sb.s_orphan_file_inum)?;
+        }
+        Ok(())
+    }
+
+    fn dumpe2fs_journal(&self, out: &mut dyn io::Write, journal: u32) -> Result<(), Error> {
+        // `journal_superblock_t`, which is big endian
+        let mut jsb = [0u8; 1024];
+        self.open(&self.load_inode(journal)?)?
+            .read_exact_at(0, &mut jsb)?;
+        let be = |offset: usize| BigEndian::read_u32(&jsb[offset..]);
+        let block_size = be(0x0C);
+        let max_len = be(0x10);
+        let compat = be(0x24);
+        let incompat = be(0x28);
+        let nr_users = be(0x40);
+
+        let fast_commit = if 0 != incompat & 0x0020 {
+            match be(0x54) {
+                0 => 256,
+                blocks => blocks,
+            }
+        } else {
+            0
+        };
+
+        write!(out, "Journal features:        ")?;
+        let mut printed = 0;
+        for (features, names, letter) in &[
+            (compat, JOURNAL_COMPAT_FEATURES, 'C'),
+            (incompat, JOURNAL_INCOMPAT_FEATURES, 'I'),
+            (be(0x2C), &[][..], 'R'),
+        ] {
+            for bit in 0..32 {
+                let mask = 1u32 << bit;
+                if 0 != features & mask {
+                    write!(out, " {}", name(names, mask, *letter, bit))?;
+                    printed += 1;
+                }
+            }
+        }
+        if 0 == printed {
+            write!(out, " (none)")?;
+        }
+        writeln!(out)?;
+
+        let size = u64::from(block_size / 1024) * u64::from(max_len);
+        if size < 8192 {
+            writeln!(out, "Total journal size:       {}k", size)?;
+        } else {
+            writeln!(out, "Total journal size:       {}M", size >> 10)?;
+        }
+        writeln!(out, "Total journal blocks:     {}", max_len)?;
+        writeln!(
+            out,
+            "Max transaction length:   {}",
+            max_len.wrapping_sub(fast_commit)
+        )?;
+        writeln!(out, "Fast commit length:       {}", fast_commit)?;
+        if 1 != be(0x14) {
+            writeln!(out, "Journal first block:      {}", be(0x14))?;
+        }
+        writeln!(out, "Journal sequence:         0x{:08x}", be(0x18))?;
+        writeln!(out, "Journal start:            {}", be(0x1C))?;
+        if 1 != nr_users {
+            writeln!(out, "Journal number of users:  {}", nr_users)?;
+        }
+        if 0 != compat & 0x0001 {
+            writeln!(out, "Journal checksum type:    crc32")?;
+        }
+        if 0 != incompat & 0x0018 {
+            writeln!(
+                out,
+                "Journal checksum type:    {}",
+                match jsb[0x50] {
+                    1 => "crc32",
+                    2 => "md5",
+                    3 => "sha1",
+                    4 => "crc32c",
+                    _ => "unknown",
+                }
+            )?;
+            writeln!(out, "Journal checksum:         0x{:08x}", be(0xFC))?;
+        }
+        let first_user = <[u8; 16]>::try_from(&jsb[0x100..0x110])?;
+        if nr_users > 1 || [0u8; 16] != first_user {
+            for user in 0..nr_users.min(48) as usize {
+                let start = 0x100 + user * 16;
+                let id = <[u8; 16]>::try_from(&jsb[start..start + 16])?;
+                let label = if 0 == user { "Journal users:" } else { "" };
+                writeln!(out, "{:<26}{}", label, uuid(&id))?;
+            }
+        }
+        if 0 != be(0x20) {
+            writeln!(out, "Journal errno:            {}", be(0x20) as i32)?;
+        }
+        Ok(())
+    }
+
+    fn dumpe2fs_group(
+        &self,
+        out: &mut dyn io::Write,
+        group: &BlockGroup,
+        free_blocks: &[std::ops::Range<u64>],
+    ) -> Result<(), Error> {
+        let sb = &self.raw;
+        let number = group.number;
+
+        write!(
+            out,
+            "Group {}: (Blocks {}-{})",
+            number, group.first_block, group.last_block
+        )?;
+        if group.computed_checksum.is_some() {
+            write!(out, " csum 0x{:04x}", group.checksum)?;
+            if let Some(computed) = group.computed_checksum.filter(|&c| c != group.checksum) {
+                write!(out, " (EXPECTED 0x{:04x})", computed)?;
+            }
+            let mut first = true;
+            for (flag, name) in &[
+                (BlockGroupFlags::INODE_UNINIT, "INODE_UNINIT"),
+                (BlockGroupFlags::BLOCK_UNINIT, "BLOCK_UNINIT"),
+                (BlockGroupFlags::ITABLE_ZEROED, "ITABLE_ZEROED"),
+            ] {
+                if group.flags.contains(*flag) {
+                    write!(out, "{}{}", if first { " [" } else { ", " }, name)?;
+                    first = false;
+                }
+            }
+            if !first {
+                write!(out, "]")?;
+            }
+        }
+        writeln!(out)?;
+
+        // c.f. ext2fs_super_and_bgd_loc2
+        let block_size = u64::from(self.groups.block_size);
+        let desc_size = match sb.s_desc_size {
+            size if 0 != sb.s_feature_incompat & 0x0080 && size > 0 => u64::from(size),
+            _ => 32,
+        };
+        let per_block = block_size / desc_size;
+        let meta_bg = 0 != sb.s_feature_incompat & 0x0010;
+        let desc_blocks = if meta_bg {
+            u64::from(sb.s_first_meta_bg)
+        } else {
+            (u64::from(self.groups.count()) + per_block - 1) / per_block
+        };
+        let group_block = if 0 == group.first_block && 1024 == block_size {
+            1
+        } else {
+            group.first_block
+        };
+        let has_super = self.has_superblock_backup(number);
+        let super_block = if has_super { group_block } else { 0 };
+        let (old_desc, new_desc) =
+            if !meta_bg || u64::from(number) / per_block < u64::from(sb.s_first_meta_bg) {
+                (if has_super { group_block + 1 } else { 0 }, 0)
+            } else if [0, 1, per_block - 1].contains(&(u64::from(number) % per_block)) {
+                (0, group_block + u64::from(has_super))
+            } else {
+                (0, 0)
+            };
+
+        let mut has_super = 0 == number || 0 != super_block;
+        if has_super {
+            write!(
+                out,
+                "  {} superblock at {}",
+                if 0 == number { "Primary" } else { "Backup" },
+                super_block
+            )?;
+        }
+        if 0 != old_desc {
+            write!(
+                out,
+                ", Group descriptors at {}-{}",
+                old_desc,
+                old_desc + desc_blocks - 1
+            )?;
+            let reserved = u64::from(sb.s_reserved_gdt_blocks);
+            if 0 != reserved {
+                write!(
+                    out,
+                    "\n  Reserved GDT blocks at {}-{}",
+                    old_desc + desc_blocks,
+                    old_desc + desc_blocks + reserved - 1
+                )?;
+            }
+        } else if 0 != new_desc {
+            write!(
+                out,
+                "{} Group descriptor at {}",
+                if has_super { ',' } else { ' ' },
+                new_desc
+            )?;
+            has_super = true;
+        }
+        if has_super {
+            writeln!(out)?;
+        }
+
+        let flex_bg = 0 != sb.s_feature_incompat & 0x0200;
+        let relative = |block: u64, table: bool| -> Result<String, Error> {
+            Ok(if block >= group.first_block && block <= group.last_block {
+                if table && block == group.first_block {
+                    String::new()
+                } else {
+                    format!(" (+{})", block - group.first_block)
+                }
+            } else if flex_bg {
+                let owner = self.groups.group_of_block(block)?;
+                let first = self.groups.get(&self.inner, owner)?.first_block;
+                format!(" (bg #{} + {})", owner, block - first)
+            } else {
+                String::new()
+            })
+        };
+        let metadata_csum = 0 != sb.s_feature_ro_compat & 0x0400;
+
+        write!(
+            out,
+            "  Block bitmap at {}{}",
+            group.block_bitmap,
+            relative(group.block_bitmap, false)?
+        )?;
+        if metadata_csum {
+            write!(
+                out,
+                ", csum 0x{:08x}",
+                group.block_bitmap_checksum.unwrap_or(0)
+            )?;
+        }
+        write!(
+            out,
+            "\n  Inode bitmap at {}{}",
+            group.inode_bitmap,
+            relative(group.inode_bitmap, false)?
+        )?;
+        if metadata_csum {
+            write!(
+                out,
+                ", csum 0x{:08x}",
+                group.inode_bitmap_checksum.unwrap_or(0)
+            )?;
+        }
+        let inode_size = u64::from(self.groups.inode_size);
+        let table_blocks =
+            (u64::from(sb.s_inodes_per_group) * inode_size + block_size - 1) / block_size;
+        writeln!(
+            out,
+            "\n  Inode table at {}-{}{}",
+            group.inode_table,
+            group.inode_table + table_blocks - 1,
+            relative(group.inode_table, true)?
+        )?;
+        let units = if 0 != sb.s_feature_ro_compat & 0x0100 {
+            "clusters"
+        } else {
+            "blocks"
+        };
+        write!(
+            out,
+            "  {} free {}, {} free inodes, {} directories",
+            group.free_blocks_count, units, group.free_inodes_count, group.used_dirs_count
+        )?;
+        if 0 != group.itable_unused {
+            write!(out, ", {} unused inodes", group.itable_unused)?;
+        }
+        writeln!(out)?;
+
+        let free = free_blocks
+            .iter()
+            .filter(|range| range.end > group.first_block && range.start <= group.last_block)
+            .map(|range| range.start.max(group.first_block)..range.end.min(group.last_block + 1));
+        writeln!(out, "  Free blocks: {}", ranges(free))?;
+
+        let per_group = u64::from(sb.s_inodes_per_group);
+        let first_inode = u64::from(number) * per_group + 1;
+        let mut free = Vec::new();
+        if group.flags.contains(BlockGroupFlags::INODE_UNINIT) {
+            free.push(first_inode..first_inode + per_group);
+        } else {
+            let mut bitmap = vec![0u8; usize::try_from((per_group + 7) / 8)?];
+            self.inner
+                .read_exact_at(group.inode_bitmap * block_size, &mut bitmap)?;
+            for index in 0..per_group {
+                if 0 != bitmap[(index / 8) as usize] & (1 << (index % 8)) {
+                    continue;
+                }
+                let inode = first_inode + index;
+                match free.last_mut() {
+                    Some(last) if last.end == inode => last.end += 1,
+                    _ => free.push(inode..inode + 1),
+                }
+            }
+        }
+        writeln!(out, "  Free inodes: {}", ranges(free.into_iter()))?;
+
+        Ok(())
+    }
+}
+
+fn name(names: &[(u32, &str)], mask: u32, letter: char, bit: u32) -> String {
+    match names.iter().find(|(known, _)| *known == mask) {
+        Some((_, name)) => name.to_string(),
+        None => format!("FEATURE_{}{}", letter, bit),
+    }
+}
+
+/// `e2p_uuid2str`: `<none>` if it's all zeros.
+fn uuid(bytes: &[u8; 16]) -> String {
+    if [0u8; 16] == *bytes {
+        return "<none>".to_string();
+    }
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn time(lo: u32, hi: u8) -> i64 {
+    i64::from(lo) | (i64::from(hi) << 32)
+}
+
+/// `ctime(3)`, in UTC, without its newline: `Fri Jul 14 02:40:00 2017`.
+pub(crate) fn ctime(secs: i64) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+    let days = secs.div_euclid(86_400);
+    let of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        // the epoch was a Thursday
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        year
+    )
+}
+
+/// `interval_string`, from `lib/e2p/ls.c`: `1 month, 2 weeks, 3:04:05`.
+fn interval(mut secs: u32) -> String {
+    const DAY: u32 = 86_400;
+
+    if 0 == secs {
+        return "<none>".to_string();
+    }
+    let mut parts = Vec::new();
+    for (unit, name) in &[(DAY * 30, "month"), (DAY * 7, "week"), (DAY, "day")] {
+        if secs >= *unit {
+            let count = secs / unit;
+            secs -= count * unit;
+            parts.push(format!(
+                "{} {}{}",
+                count,
+                name,
+                if count > 1 { "s" } else { "" }
+            ));
+        }
+    }
+    if secs > 0 {
+        parts.push(format!(
+            "{}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        ));
+    }
+    parts.join(", ")
+}
+
+/// `1330-1337, 1339-4095`, as `print_free` lists what's free.
+fn ranges(ranges: impl Iterator<Item = std::ops::Range<u64>>) -> String {
+    ranges
+        .map(|range| {
+            if range.end - range.start > 1 {
+                format!("{}-{}", range.start, range.end - 1)
+            } else {
+                range.start.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,200 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::progress::check_cancelled;
+use crate::unsupported_feature;
+use crate::DirEntry;
+use crate::EncryptedPolicy;
+use crate::Enhanced;
+use crate::Inode;
+use crate::Progress;
+use crate::SuperBlock;
+use crate::WalkOptions;
+use crate::WalkOrder;
+
+/// What `Events::next_event` found next.
+pub enum Event {
+    /// A directory, before anything in it. `enhanced` is its listing, as stored.
+    EnterDir {
+        path: String,
+        inode: Inode,
+        enhanced: Enhanced,
+    },
+    /// Anything which isn't a directory.
+    Entry {
+        path: String,
+        inode: Inode,
+        enhanced: Enhanced,
+    },
+    /// After everything in the directory `EnterDir` announced at `path`.
+    LeaveDir { path: String },
+    /// An entry whose inode, or directory listing, couldn't be read; it's skipped, and
+    /// the rest carries on.
+    Corruption { path: String, error: Error },
+    /// There's nothing more; every later call says this, too.
+    End,
+}
+
+/// The entries of a filesystem, one at a time, in the order `walk` visits them, for callers
+/// which would rather pull than be called back. Only the directories on the way to the
+/// current entry are held in memory. See `SuperBlock::events`.
+pub struct Events<'a, R> {
+    fs: &'a SuperBlock<R>,
+    options: WalkOptions,
+    progress: &'a dyn Progress,
+    /// The first entry, until it's been read.
+    start: Option<(String, u32)>,
+    /// The directories being read, outermost first.
+    stack: Vec<Frame>,
+}
+
+struct Frame {
+    path: String,
+    entries: std::vec::IntoIter<DirEntry>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Everything in the filesystem, as `Events`.
+    pub fn events(&self) -> Events<'_, R> {
+        self.events_with_options(self.root_inode, "", &WalkOptions::default(), &())
+    }
+
+    /// The events for everything under `inode`, itself included, as `walk_with_options`
+    /// would visit them, with `path` as `inode`'s path.
+    pub fn events_with_options<'a>(
+        &'a self,
+        inode: u32,
+        path: &str,
+        options: &WalkOptions,
+        progress: &'a dyn Progress,
+    ) -> Events<'a, R> {
+        Events {
+            fs: self,
+            options: options.clone(),
+            progress,
+            start: Some((path.to_string(), inode)),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a, R> Events<'a, R>
+where
+    R: ReadAt,
+{
+    /// The next event. Errors which leave the walk unable to continue, such as being
+    /// cancelled, or an encrypted entry `WalkOptions::encrypted` doesn't allow, are
+    /// returned, after which there's only `End`.
+    pub fn next_event(&mut self) -> Result<Event, Error> {
+        let event = self.next_event_inner();
+        if event.is_err() {
+            self.start = None;
+            self.stack.clear();
+        }
+        event
+    }
+
+    fn next_event_inner(&mut self) -> Result<Event, Error> {
+        loop {
+            let (path, number) = match self.start.take() {
+                Some(start) => start,
+                None => {
+                    let frame = match self.stack.last_mut() {
+                        Some(frame) => frame,
+                        None => return Ok(Event::End),
+                    };
+                    match frame.entries.next() {
+                        Some(entry) => (format!("{}/{}", frame.path, entry.name), entry.inode),
+                        None => {
+                            let frame = self.stack.pop().expect("just looked at it");
+                            return Ok(Event::LeaveDir { path: frame.path });
+                        }
+                    }
+                }
+            };
+
+            self.progress.entry(&path);
+            check_cancelled(self.progress)?;
+
+            let inode = match self.fs.load_inode(number) {
+                Ok(inode) => inode,
+                Err(error) => return Ok(Event::Corruption { path, error }),
+            };
+
+            if inode.is_encrypted() {
+                match self.options.encrypted {
+                    EncryptedPolicy::Fail => {
+                        return Err(unsupported_feature(format!(
+                            "<{}> is encrypted: {:?}",
+                            inode.number, path
+                        ))
+                        .into())
+                    }
+                    EncryptedPolicy::Skip => continue,
+                    EncryptedPolicy::YieldCiphertext => (),
+                }
+            }
+
+            let enhanced = match self
+                .fs
+                .enhance(&inode)
+                .with_context(|| anyhow!("reading <{}>", inode.number))
+            {
+                Ok(enhanced) => enhanced,
+                Err(error) => return Ok(Event::Corruption { path, error }),
+            };
+            self.fs.check_consistency()?;
+
+            let listing = match enhanced {
+                Enhanced::Directory(listing) => listing,
+                enhanced => {
+                    return Ok(Event::Entry {
+                        path,
+                        inode,
+                        enhanced,
+                    })
+                }
+            };
+
+            let mut entries = listing
+                .iter()
+                .filter(|entry| "." != entry.name && ".." != entry.name)
+                // only the real root's, whatever `with_root` says
+                .filter(|entry| {
+                    self.options.include_lost_found
+                        || 2 != inode.number
+                        || "lost+found" != entry.name
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            match self.options.sort {
+                WalkOrder::Disk => (),
+                WalkOrder::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+                WalkOrder::Inode => entries.sort_by_key(|entry| entry.inode),
+            }
+            // each directory on the stack holds its entries; one linked inside itself
+            // would nest forever
+            let limits = &self.fs.options.limits;
+            limits.check(
+                "depth of walk",
+                self.stack.len() as u64,
+                u64::from(limits.max_walk_depth),
+            )?;
+            self.stack.push(Frame {
+                path: path.clone(),
+                entries: entries.into_iter(),
+            });
+
+            return Ok(Event::EnterDir {
+                path,
+                inode,
+                enhanced: Enhanced::Directory(listing),
+            });
+        }
+    }
+}
@@ -1,15 +1,19 @@
 use std::convert::TryFrom;
 use std::io;
+use std::ops::Range;
 
 use anyhow::ensure;
 use anyhow::Error;
 use positioned_io2::ReadAt;
 
 use crate::assumption_failed;
-use crate::read_le16;
+use crate::indirect::load_block_map;
+use crate::ondisk::RawExtent;
+use crate::ondisk::RawExtentHeader;
+use crate::ondisk::RawExtentIdx;
 use crate::read_le32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Extent {
     /// The docs call this 'block' (like everything else). I've invented a different name.
     part: u32,
@@ -17,12 +21,47 @@ struct Extent {
     len: u16,
 }
 
+/// Where a node of an extent tree is, which decides how it's checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    /// In the inode itself, so covered by the inode's checksum, not its own.
+    Root,
+    /// In a block, pointing at further blocks.
+    Index,
+    /// In a block, holding extents.
+    Leaf,
+}
+
+/// A contiguous run of a file's data on disc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DataExtent {
+    /// The first block of the file that this covers, numbered from zero.
+    pub logical: u32,
+    /// Where that block is on disc.
+    pub physical: u64,
+    /// The length of the run, in blocks.
+    pub len: u16,
+}
+
+/// Reads a file's content, through its extent tree. Sparse regions read as zeros.
+///
+/// Reads fill the buffer up to the end of the extent (or hole) they start in, or the
+/// file, even if the underlying reader returns less at a time.
+///
+/// As well as `Read` and `Seek`, this implements `ReadAt`, so one reader can be shared
+/// between threads; or `split` can divide it into independent readers.
+#[derive(Clone)]
 pub struct TreeReader<R> {
     inner: R,
     pos: u64,
     len: u64,
     block_size: u32,
     extents: Vec<Extent>,
+    /// Where the underlying image ends, if it's truncated; data past here reads as zeros.
+    available: u64,
+    /// Every node of the tree had its checksum checked.
+    verified: bool,
 }
 
 impl<R> TreeReader<R>
@@ -35,12 +74,42 @@ where
         size: u64,
         core: [u8; crate::INODE_CORE_SIZE],
         checksum_prefix: Option<u32>,
+        limits: &crate::Limits,
     ) -> Result<TreeReader<R>, Error> {
         let extents = load_extent_tree(
             &mut |block| crate::load_disc_bytes(&inner, block_size, block),
             core,
             checksum_prefix,
+            limits,
         )?;
+        let mut reader = TreeReader::create(inner, block_size, size, extents);
+        reader.verified = checksum_prefix.is_some();
+        Ok(reader)
+    }
+
+    /// A reader over a file without an extent tree, whose blocks are found through the
+    /// block map in `core` instead, as ext2 and ext3 files' are. There are no checksums.
+    pub(crate) fn from_block_map(
+        inner: R,
+        block_size: u32,
+        size: u64,
+        core: [u8; crate::INODE_CORE_SIZE],
+        limits: &crate::Limits,
+    ) -> Result<TreeReader<R>, Error> {
+        let extents = load_block_map(
+            &mut |block| crate::load_disc_bytes(&inner, block_size, block),
+            core,
+            block_size,
+            size,
+            limits,
+        )?
+        .into_iter()
+        .map(|extent| Extent {
+            part: extent.logical,
+            start: extent.physical,
+            len: extent.len,
+        })
+        .collect();
         Ok(TreeReader::create(inner, block_size, size, extents))
     }
 
@@ -51,12 +120,76 @@ where
             inner,
             extents,
             block_size,
+            available: u64::MAX,
+            verified: false,
         }
     }
 
+    /// Read the data past `len` bytes of the underlying image as zeros, as if it were
+    /// sparse, instead of stopping short.
+    pub(crate) fn zeros_after(mut self, len: u64) -> TreeReader<R> {
+        self.available = len;
+        self
+    }
+
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    /// Independent readers over parts of the file, e.g. to hash or upload a huge file from
+    /// many threads. Each starts at the start of its range, and ends at the end of its range,
+    /// or of the file. Seeking still uses offsets from the start of the file.
+    pub fn split(&self, ranges: &[Range<u64>]) -> Vec<TreeReader<R>>
+    where
+        R: Clone,
+    {
+        ranges
+            .iter()
+            .map(|range| {
+                let len = range.end.min(self.len);
+                TreeReader {
+                    pos: range.start.min(len),
+                    len,
+                    inner: self.inner.clone(),
+                    extents: self.extents.clone(),
+                    block_size: self.block_size,
+                    available: self.available,
+                    verified: self.verified,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the extent tree was checked against checksums as it was loaded: the root,
+    /// in the inode, by the inode's checksum, and every index and leaf block by its own.
+    /// `false` on filesystems without `metadata_csum`, and for files without an extent
+    /// tree, whose indirect blocks have no checksums.
+    pub fn extent_tree_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// The physical block holding a logical block of the file, if it isn't sparse.
+    pub(crate) fn physical_block(&self, part: u32) -> Option<u64> {
+        match find_part(part, &self.extents) {
+            FoundPart::Actual(extent) => Some(extent.start + u64::from(part - extent.part)),
+            FoundPart::Sparse(_) => None,
+        }
+    }
+
+    /// The runs of the file's data, in file order.
+    pub(crate) fn data_extents(&self) -> Vec<DataExtent> {
+        self.extents.iter().map(Extent::to_data_extent).collect()
+    }
+}
+
+impl Extent {
+    fn to_data_extent(&self) -> DataExtent {
+        DataExtent {
+            logical: self.part,
+            physical: self.start,
+            len: self.len,
+        }
+    }
 }
 
 enum FoundPart<'a> {
@@ -64,7 +197,7 @@ enum FoundPart<'a> {
     Sparse(u32),
 }
 
-fn find_part(part: u32, extents: &[Extent]) -> FoundPart {
+fn find_part(part: u32, extents: &[Extent]) -> FoundPart<'_> {
     for extent in extents {
         if part < extent.part {
             // we've gone past it
@@ -77,22 +210,22 @@ fn find_part(part: u32, extents: &[Extent]) -> FoundPart {
         }
     }
 
-    FoundPart::Sparse(std::u32::MAX)
+    FoundPart::Sparse(u32::MAX)
 }
 
-impl<R> io::Read for TreeReader<R>
+impl<R> TreeReader<R>
 where
     R: ReadAt,
 {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.is_empty() {
+    fn read_from(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || pos >= self.len {
             return Ok(0);
         }
 
         let block_size = u64::from(self.block_size);
 
-        let wanted_block = u32::try_from(self.pos / block_size).unwrap();
-        let read_of_this_block = self.pos % block_size;
+        let wanted_block = u32::try_from(pos / block_size).unwrap();
+        let read_of_this_block = pos % block_size;
 
         match find_part(wanted_block, &self.extents) {
             FoundPart::Actual(extent) => {
@@ -101,40 +234,77 @@ where
                 let remaining_bytes_in_extent =
                     (u64::from(extent.len) * block_size) - bytes_through_extent;
                 let to_read = std::cmp::min(remaining_bytes_in_extent, buf.len() as u64) as usize;
-                let to_read = std::cmp::min(to_read as u64, self.len - self.pos) as usize;
+                let to_read = std::cmp::min(to_read as u64, self.len - pos) as usize;
                 let offset = extent.start * block_size + bytes_through_extent;
-                let read = self.inner.read_at(offset, &mut buf[0..to_read])?;
-                self.pos += u64::try_from(read).expect("infallible u64 conversion");
-                Ok(read)
+                if offset >= self.available {
+                    zero(&mut buf[0..to_read]);
+                    return Ok(to_read);
+                }
+                let to_read = std::cmp::min(to_read as u64, self.available - offset) as usize;
+                read_full_at(&self.inner, offset, &mut buf[0..to_read])
             }
             FoundPart::Sparse(max) => {
-                let max_bytes = u64::from(max) * block_size;
+                let max_bytes = u64::from(max) * block_size - read_of_this_block;
                 let read = std::cmp::min(max_bytes, buf.len() as u64) as usize;
-                let read = std::cmp::min(read as u64, self.len - self.pos) as usize;
+                let read = std::cmp::min(read as u64, self.len - pos) as usize;
                 zero(&mut buf[0..read]);
-                self.pos += u64::try_from(read).expect("infallible u64 conversion");
                 Ok(read)
             }
         }
     }
 }
 
-impl<R> io::Seek for TreeReader<R>
+/// Like `read_exact_at`, but stopping early, without error, at the end of the backend.
+/// Backends, e.g. over a network, may return less than they could; this hides that, so
+/// callers only see short reads at the ends of extents, or of the image.
+fn read_full_at<R: ReadAt>(inner: &R, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let mut done = 0;
+    while done < buf.len() {
+        match inner.read_at(pos + done as u64, &mut buf[done..]) {
+            Ok(0) => break,
+            Ok(read) => done += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(done)
+}
+
+impl<R> io::Read for TreeReader<R>
 where
     R: ReadAt,
 {
-    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
-        match pos {
-            io::SeekFrom::Start(set) => self.pos = set,
-            io::SeekFrom::Current(diff) => self.pos = (self.pos as i64 + diff) as u64,
-            io::SeekFrom::End(set) => {
-                assert!(set >= 0);
-                self.pos = self.len - u64::try_from(set).unwrap()
-            }
-        }
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_from(self.pos, buf)?;
+        self.pos += u64::try_from(read).expect("infallible u64 conversion");
+        Ok(read)
+    }
+}
 
-        assert!(self.pos <= self.len);
+/// Reads from anywhere in the file, ignoring (and not moving) the current position.
+impl<R> ReadAt for TreeReader<R>
+where
+    R: ReadAt,
+{
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_from(pos, buf)
+    }
+}
 
+/// As `std`'s `File`: seeking past the end is fine, and reads there return nothing, but
+/// seeking before the start is an error, which leaves the position where it was.
+impl<R> io::Seek for TreeReader<R>
+where
+    R: ReadAt,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            io::SeekFrom::Start(set) => Some(set),
+            io::SeekFrom::Current(diff) => crate::add_signed(self.pos, diff),
+            io::SeekFrom::End(diff) => crate::add_signed(self.len, diff),
+        };
+        self.pos = pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
         Ok(self.pos)
     }
 }
@@ -145,31 +315,49 @@ fn add_found_extents<F>(
     expected_depth: u16,
     extents: &mut Vec<Extent>,
     checksum_prefix: Option<u32>,
-    first_level: bool,
+    kind: NodeKind,
+    limits: &crate::Limits,
 ) -> Result<(), Error>
 where
     F: FnMut(u64) -> Result<Vec<u8>, Error>,
 {
+    let header = RawExtentHeader::from_slice(data)?;
     ensure!(
-        0x0a == data[0] && 0xf3 == data[1],
+        RawExtentHeader::MAGIC == header.eh_magic,
         assumption_failed("invalid extent magic")
     );
 
-    let extent_entries = read_le16(&data[2..]);
-    // 4..: max; doesn't seem to be useful during read
-    let depth = read_le16(&data[6..]);
-    // 8..: generation, not used in standard ext4
+    let depth = header.eh_depth;
 
     ensure!(
         expected_depth == depth,
         assumption_failed(format!("depth incorrect: {} != {}", expected_depth, depth))
     );
 
-    if !first_level && checksum_prefix.is_some() {
-        let end_of_entries = data.len() - 4;
+    ensure!(
+        (NodeKind::Leaf == kind) == (0 == depth) || NodeKind::Root == kind,
+        assumption_failed(format!("{:?} extent node at depth {}", kind, depth))
+    );
+
+    // c.f. EXT4_EXTENT_TAIL_OFFSET: the tail follows the room for entries, not the block
+    let end_of_entries = RawExtentHeader::SIZE + usize::from(header.eh_max) * RawExtent::SIZE;
+    ensure!(
+        header.eh_entries <= header.eh_max && end_of_entries <= data.len(),
+        assumption_failed(format!(
+            "extent node claims {} of {} entries, in {} bytes",
+            header.eh_entries,
+            header.eh_max,
+            data.len()
+        ))
+    );
+
+    if let (NodeKind::Index | NodeKind::Leaf, Some(checksum_prefix)) = (kind, checksum_prefix) {
+        ensure!(
+            end_of_entries + 4 <= data.len(),
+            assumption_failed("no room for the extent checksum")
+        );
         let on_disc = read_le32(&data[end_of_entries..(end_of_entries + 4)]);
-        let computed =
-            crate::parse::ext4_style_crc32c_le(checksum_prefix.unwrap(), &data[..end_of_entries]);
+        let computed = crate::parse::ext4_style_crc32c_le(checksum_prefix, &data[..end_of_entries]);
 
         ensure!(
             computed == on_disc,
@@ -182,39 +370,45 @@ where
         );
     }
 
-    if 0 == depth {
-        for en in 0..extent_entries {
-            let raw_extent = &data[12 + usize::from(en) * 12..];
-            let ee_block = read_le32(raw_extent);
-            let ee_len = read_le16(&raw_extent[4..]);
-            let ee_start_hi = read_le16(&raw_extent[6..]);
-            let ee_start_lo = read_le32(&raw_extent[8..]);
-            let ee_start = u64::from(ee_start_lo) + 0x1000 * u64::from(ee_start_hi);
+    let entries = (0..usize::from(header.eh_entries)).map(|en| {
+        data.get(RawExtentHeader::SIZE + en * RawExtent::SIZE..)
+            .unwrap_or(&[])
+    });
 
+    if 0 == depth {
+        for raw_extent in entries {
+            let raw_extent = RawExtent::from_slice(raw_extent)?;
             extents.push(Extent {
-                part: ee_block,
-                start: ee_start,
-                len: ee_len,
+                part: raw_extent.ee_block,
+                start: raw_extent.start(),
+                len: raw_extent.ee_len,
             });
         }
 
+        limits.check(
+            "number of extents",
+            extents.len() as u64,
+            limits.max_extent_entries as u64,
+        )?;
+
         return Ok(());
     }
 
-    for en in 0..extent_entries {
-        let extent_idx = &data[12 + usize::from(en) * 12..];
-        //            let ei_block = as_u32(extent_idx);
-        let ei_leaf_lo = read_le32(&extent_idx[4..]);
-        let ei_leaf_hi = read_le16(&extent_idx[8..]);
-        let ee_leaf: u64 = u64::from(ei_leaf_lo) + (u64::from(ei_leaf_hi) << 32);
-        let data = load_block(ee_leaf)?;
+    for extent_idx in entries {
+        let extent_idx = RawExtentIdx::from_slice(extent_idx)?;
+        let data = load_block(extent_idx.leaf())?;
         add_found_extents(
             load_block,
             &data,
             depth - 1,
             extents,
             checksum_prefix,
-            false,
+            if 1 == depth {
+                NodeKind::Leaf
+            } else {
+                NodeKind::Index
+            },
+            limits,
         )?;
     }
 
@@ -225,23 +419,25 @@ fn load_extent_tree<F>(
     load_block: &mut F,
     core: [u8; crate::INODE_CORE_SIZE],
     checksum_prefix: Option<u32>,
+    limits: &crate::Limits,
 ) -> Result<Vec<Extent>, Error>
 where
     F: FnMut(u64) -> Result<Vec<u8>, Error>,
 {
+    let header = RawExtentHeader::from_slice(&core)?;
     ensure!(
-        0x0a == core[0] && 0xf3 == core[1],
+        RawExtentHeader::MAGIC == header.eh_magic,
         assumption_failed("invalid extent magic")
     );
 
-    let extent_entries = read_le16(&core[2..]);
-    // 4..: max; doesn't seem to be useful during read
-    let depth = read_le16(&core[6..]);
+    let extent_entries = header.eh_entries;
+    let depth = header.eh_depth;
 
-    ensure!(
-        depth <= 5,
-        assumption_failed(format!("initial depth too high: {}", depth))
-    );
+    limits.check(
+        "extent tree depth",
+        u64::from(depth),
+        u64::from(limits.max_extent_depth),
+    )?;
 
     let mut extents = Vec::with_capacity(usize::from(extent_entries) + usize::from(depth) * 200);
 
@@ -251,7 +447,8 @@ where
         depth,
         &mut extents,
         checksum_prefix,
-        true,
+        NodeKind::Root,
+        limits,
     )?;
 
     extents.sort_by_key(|e| e.part);
@@ -259,6 +456,22 @@ where
     Ok(extents)
 }
 
+/// `load_extent_tree`, for use outside of a `TreeReader`.
+pub(crate) fn load_data_extents<F>(
+    load_block: &mut F,
+    core: [u8; crate::INODE_CORE_SIZE],
+    checksum_prefix: Option<u32>,
+    limits: &crate::Limits,
+) -> Result<Vec<DataExtent>, Error>
+where
+    F: FnMut(u64) -> Result<Vec<u8>, Error>,
+{
+    Ok(load_extent_tree(load_block, core, checksum_prefix, limits)?
+        .iter()
+        .map(Extent::to_data_extent)
+        .collect())
+}
+
 fn zero(buf: &mut [u8]) {
     unsafe { std::ptr::write_bytes(buf.as_mut_ptr(), 0u8, buf.len()) }
 }
@@ -267,6 +480,8 @@ fn zero(buf: &mut [u8]) {
 mod tests {
     use std::convert::TryFrom;
     use std::io::Read;
+    use std::io::Seek;
+    use std::io::SeekFrom;
 
     use crate::extents::Extent;
     use crate::extents::TreeReader;
@@ -299,6 +514,90 @@ mod tests {
         assert_eq!(vec![40, 41, 42, 43, 80, 81, 82, 83, 84, 85, 86, 87], res);
     }
 
+    /// Returns at most three bytes per call, like a slow pipe.
+    struct ShortReads(Vec<u8>);
+
+    impl positioned_io2::ReadAt for ShortReads {
+        fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read_at(pos, &mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn short_reads() {
+        let data = ShortReads((0..255u8).collect::<Vec<u8>>());
+        let mut reader = TreeReader::create(
+            data,
+            8,
+            8 * 3 + 5,
+            vec![
+                Extent {
+                    part: 0,
+                    start: 1,
+                    len: 2,
+                },
+                Extent {
+                    part: 3,
+                    start: 20,
+                    len: 1,
+                },
+            ],
+        );
+
+        // the whole of the first extent, despite the backend
+        let mut buf = [0u8; 64];
+        assert_eq!(16, reader.read(&mut buf).unwrap());
+        assert_eq!((8..24).collect::<Vec<u8>>(), buf[..16].to_vec());
+
+        // then the hole, then the rest of the file, which ends part way through the extent
+        assert_eq!(8, reader.read(&mut buf).unwrap());
+        assert_eq!([0u8; 8], buf[..8]);
+        assert_eq!(5, reader.read(&mut buf).unwrap());
+        assert_eq!((160..165).collect::<Vec<u8>>(), buf[..5].to_vec());
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+
+        let mut all = Vec::new();
+        reader.seek(std::io::SeekFrom::Start(2)).unwrap();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(27, all.len());
+    }
+
+    #[test]
+    fn seek() {
+        let data = (0..255u8).collect::<Vec<u8>>();
+        let extents = vec![Extent {
+            part: 0,
+            start: 1,
+            len: 2,
+        }];
+        let mut reader = TreeReader::create(data, 4, 6, extents);
+        let mut buf = [0u8; 8];
+
+        assert_eq!(4, reader.seek(SeekFrom::End(-2)).unwrap());
+        assert_eq!(2, reader.read(&mut buf).unwrap());
+        assert_eq!([8, 9], buf[..2]);
+        assert_eq!(3, reader.seek(SeekFrom::Current(-3)).unwrap());
+        assert_eq!(0, reader.seek(SeekFrom::Start(0)).unwrap());
+
+        // past the end is allowed, and reads nothing
+        assert_eq!(10, reader.seek(SeekFrom::End(4)).unwrap());
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+        assert_eq!(100, reader.seek(SeekFrom::Start(100)).unwrap());
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+
+        // before the start isn't, and doesn't move
+        assert!(reader.seek(SeekFrom::End(-7)).is_err());
+        assert!(reader.seek(SeekFrom::Current(-101)).is_err());
+        assert_eq!(100, reader.seek(SeekFrom::Current(0)).unwrap());
+        assert_eq!(
+            std::io::ErrorKind::InvalidInput,
+            reader.seek(SeekFrom::Current(i64::MIN)).unwrap_err().kind()
+        );
+        reader.seek(SeekFrom::Start(u64::MAX)).unwrap();
+        assert!(reader.seek(SeekFrom::Current(1)).is_err());
+    }
+
     #[test]
     fn zero_buf() {
         let mut buf = [7u8; 5];
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::progress::check_cancelled;
+use crate::sha256::Sha256;
+use crate::Enhanced;
+use crate::Progress;
+use crate::ProgressReader;
+use crate::SuperBlock;
+use crate::WalkOptions;
+use crate::WalkOrder;
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// A digest of what the filesystem holds, e.g. `sha256:e3b0...`, which is the same for
+    /// two images with the same files, however they're laid out: block size, inode size
+    /// and numbers, and the superblock's uuid and counters, make no difference.
+    ///
+    /// Every entry's path, type, mode, owner, mtime, xattrs, and symlink target or device
+    /// numbers are included, as are files' sizes and content. Directory sizes, link counts,
+    /// and the other times are left out, as they're set by whatever wrote the image.
+    pub fn fingerprint(&self) -> Result<String, Error> {
+        self.fingerprint_with_progress(&())
+    }
+
+    /// `fingerprint`, telling `progress` about each entry, and the content read.
+    pub fn fingerprint_with_progress(&self, progress: &dyn Progress) -> Result<String, Error> {
+        let options = WalkOptions {
+            sort: WalkOrder::Name,
+            ..WalkOptions::default()
+        };
+        let mut digest = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        self.walk_with_options(
+            &self.root()?,
+            "",
+            &options,
+            progress,
+            &mut |fs, path, inode, enhanced| {
+                let stat = &inode.stat;
+                // each field is fixed length, or ends in a nul, so they can't run together
+                digest.update(path.as_bytes());
+                digest.update(&[0, stat.extracted_type as u8]);
+                digest.update(&stat.file_mode.to_le_bytes());
+                digest.update(&stat.uid.to_le_bytes());
+                digest.update(&stat.gid.to_le_bytes());
+                digest.update(&stat.mtime.epoch_secs.to_le_bytes());
+                // small inodes have no room for nanoseconds, which is the same as zero
+                digest.update(&stat.mtime.nanos.unwrap_or(0).to_le_bytes());
+
+                let xattrs = stat.xattrs.iter().collect::<BTreeMap<_, _>>();
+                digest.update(&(xattrs.len() as u64).to_le_bytes());
+                for (name, value) in xattrs {
+                    digest.update(name.as_bytes());
+                    digest.update(&[0]);
+                    digest.update(&(value.len() as u64).to_le_bytes());
+                    digest.update(value);
+                }
+
+                match enhanced {
+                    Enhanced::RegularFile => {
+                        digest.update(&stat.size.to_le_bytes());
+                        let mut content = ProgressReader::new(fs.open(inode)?, progress);
+                        loop {
+                            let read = content
+                                .read(&mut buf)
+                                // the reader's io::Error would hide why it stopped
+                                .map_err(|e| match check_cancelled(progress) {
+                                    Ok(()) => Error::from(e),
+                                    Err(cancelled) => cancelled,
+                                })
+                                .with_context(|| anyhow!("reading {}", path))?;
+                            if 0 == read {
+                                break;
+                            }
+                            digest.update(&buf[..read]);
+                        }
+                    }
+                    Enhanced::SymbolicLink(target) => {
+                        digest.update(&crate::path_bytes(target));
+                        digest.update(&[0]);
+                    }
+                    Enhanced::CharacterDevice(major, minor)
+                    | Enhanced::BlockDevice(major, minor) => {
+                        digest.update(&major.to_le_bytes());
+                        digest.update(&minor.to_le_bytes());
+                    }
+                    _ => (),
+                }
+                Ok(true)
+            },
+        )?;
+
+        let hex: String = digest
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(format!("sha256:{}", hex))
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::Error;
+
+use crate::read_le32;
+use crate::DataExtent;
+
+/// Pointers in `i_block` straight to the first blocks of the file, before the single,
+/// double and triple indirect blocks.
+const DIRECT: usize = 12;
+
+/// As long as an extent can be, so a run is one an extent tree could hold.
+const MAX_RUN: u16 = 32768;
+
+/// Where the data of a file without an extent tree is, as ext2 and ext3 map it: the first
+/// twelve blocks are listed in the inode, then a block listing the next ones, a block
+/// listing blocks listing more, and one more level again. A zero pointer, at any level, is
+/// a hole. Only the blocks before `size` are looked at; anything after is junk.
+///
+/// `load_block` is given each indirect block, as it's needed, as for an extent tree's.
+pub(crate) fn load_block_map<F>(
+    load_block: &mut F,
+    core: [u8; crate::INODE_CORE_SIZE],
+    block_size: u32,
+    size: u64,
+    limits: &crate::Limits,
+) -> Result<Vec<DataExtent>, Error>
+where
+    F: FnMut(u64) -> Result<Vec<u8>, Error>,
+{
+    let block_size = u64::from(block_size);
+    let per_block = block_size / 4;
+    // logical block numbers are 32 bits, however big `size` claims the file is
+    let end = (size / block_size + u64::from(0 != size % block_size)).min(1 << 32);
+    let pointer = |index: usize| read_le32(&core[index * 4..]);
+
+    let mut map = Runs {
+        extents: Vec::new(),
+        end,
+        per_block,
+        limits,
+    };
+    for index in 0..DIRECT {
+        map.push(index as u64, pointer(index))?;
+    }
+
+    let mut logical = DIRECT as u64;
+    for depth in 1..=3 {
+        if logical >= end {
+            break;
+        }
+        map.descend(load_block, pointer(DIRECT + depth - 1), depth, logical)?;
+        logical += per_block.pow(depth as u32);
+    }
+
+    Ok(map.extents)
+}
+
+struct Runs<'l> {
+    extents: Vec<DataExtent>,
+    /// The first logical block past the end of the file.
+    end: u64,
+    /// Pointers in each indirect block.
+    per_block: u64,
+    limits: &'l crate::Limits,
+}
+
+impl Runs<'_> {
+    /// Follow an indirect block `depth` levels above the data, which maps the file from
+    /// `first`.
+    fn descend<F>(
+        &mut self,
+        load_block: &mut F,
+        block: u32,
+        depth: usize,
+        first: u64,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(u64) -> Result<Vec<u8>, Error>,
+    {
+        // a hole, all the way down
+        if 0 == block {
+            return Ok(());
+        }
+
+        let data = load_block(u64::from(block))?;
+        let span = self.per_block.pow(depth as u32 - 1);
+        for (index, pointer) in data.chunks_exact(4).enumerate() {
+            let logical = first + index as u64 * span;
+            if logical >= self.end {
+                break;
+            }
+            let pointer = read_le32(pointer);
+            if 1 == depth {
+                self.push(logical, pointer)?;
+            } else {
+                self.descend(load_block, pointer, depth - 1, logical)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a block of data, joining it to the last run if it follows on from it.
+    fn push(&mut self, logical: u64, physical: u32) -> Result<(), Error> {
+        if 0 == physical || logical >= self.end {
+            return Ok(());
+        }
+        let logical = u32::try_from(logical)?;
+        let physical = u64::from(physical);
+
+        if let Some(last) = self.extents.last_mut() {
+            if last.len < MAX_RUN
+                && last.logical + u32::from(last.len) == logical
+                && last.physical + u64::from(last.len) == physical
+            {
+                last.len += 1;
+                return Ok(());
+            }
+        }
+
+        self.extents.push(DataExtent {
+            logical,
+            physical,
+            len: 1,
+        });
+        self.limits.check(
+            "number of extents",
+            self.extents.len() as u64,
+            self.limits.max_extent_entries as u64,
+        )?;
+        Ok(())
+    }
+}
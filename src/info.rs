@@ -0,0 +1,289 @@
+use bitflags::bitflags;
+
+use crate::HashVersion;
+use crate::SuperBlock;
+use crate::Time;
+
+bitflags! {
+    /// The options used when mounting, unless overridden; `s_default_mount_opts`.
+    /// The default journalling mode is in `FsInfo::default_journal_mode`.
+    pub struct DefaultMountOptions: u32 {
+        const DEBUG          = 0x0001;
+        const BSDGROUPS      = 0x0002;
+        const USER_XATTR     = 0x0004;
+        const ACL            = 0x0008;
+        const UID16          = 0x0010;
+        const NOBARRIER      = 0x0100;
+        const BLOCK_VALIDITY = 0x0200;
+        const DISCARD        = 0x0400;
+        const NODELALLOC     = 0x0800;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DefaultMountOptions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+/// The `EXT4_DEFM_JMODE` bits of `s_default_mount_opts`.
+const JOURNAL_MODE_MASK: u32 = 0x0060;
+
+/// How file data is written, relative to the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum JournalMode {
+    /// Data is journalled along with the metadata.
+    Data,
+    /// Data is written before its metadata is committed.
+    Ordered,
+    /// Data may be written after its metadata is committed.
+    Writeback,
+}
+
+bitflags! {
+    /// Miscellaneous flags; `s_flags`.
+    pub struct SuperBlockFlags: u32 {
+        /// `dir_index` hashes treat names as signed chars.
+        const SIGNED_DIRECTORY_HASH   = 0x0001;
+        /// `dir_index` hashes treat names as unsigned chars.
+        const UNSIGNED_DIRECTORY_HASH = 0x0002;
+        /// For testing development code in the kernel.
+        const TEST_FILESYSTEM         = 0x0004;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SuperBlockFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+/// The operating system which made the filesystem; `s_creator_os`. This decides what some
+/// of each inode's fields mean: only Linux's have the high halves of the xattr block
+/// number, or the inode checksum, and Masix's haven't the high halves of the owners, either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum CreatorOs {
+    Linux,
+    Hurd,
+    Masix,
+    FreeBsd,
+    Lites,
+    /// Laid out as Linux's are.
+    Other(u32),
+}
+
+impl CreatorOs {
+    pub fn from_raw(raw: u32) -> CreatorOs {
+        match raw {
+            0 => CreatorOs::Linux,
+            1 => CreatorOs::Hurd,
+            2 => CreatorOs::Masix,
+            3 => CreatorOs::FreeBsd,
+            4 => CreatorOs::Lites,
+            other => CreatorOs::Other(other),
+        }
+    }
+}
+
+/// Descriptive information about the filesystem, from the superblock: what it's called,
+/// and how it has been used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FsInfo {
+    pub uuid: [u8; 16],
+    pub creator_os: CreatorOs,
+    /// The label, if one has been set.
+    pub volume_name: Option<String>,
+    /// When `mkfs` ran.
+    pub created: Option<Time>,
+    pub last_mounted: Option<Time>,
+    pub last_written: Option<Time>,
+    /// Where it was last mounted, if the kernel knew; often not set.
+    pub last_mount_point: Option<String>,
+    /// How many times it has been mounted since the last check.
+    pub mount_count: u16,
+    /// How many mounts are allowed before a check is forced; `None` if unlimited.
+    pub max_mount_count: Option<u16>,
+    pub last_checked: Option<Time>,
+    /// The most time allowed between checks, in seconds; `None` if unlimited.
+    pub check_interval: Option<u32>,
+    /// How much has been written over the filesystem's life, in KiB.
+    pub kbytes_written: u64,
+    pub default_mount_options: DefaultMountOptions,
+    /// `None` if the kernel should pick.
+    pub default_journal_mode: Option<JournalMode>,
+    /// Extra options to mount with, in `mount`'s comma-separated syntax.
+    pub mount_options: Option<String>,
+    pub flags: SuperBlockFlags,
+    /// The hash for new indexed directories, adjusted for the signedness in `flags`.
+    /// `None` if it's not one we recognise.
+    pub default_hash_version: Option<HashVersion>,
+    /// Mixed into the directory hash; all zeros means the hash's default.
+    pub hash_seed: [u32; 4],
+}
+
+/// The errors the kernel has recorded in the superblock, since they were last cleared
+/// (usually by `e2fsck`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorHistory {
+    /// How many errors have been seen in total.
+    pub count: u32,
+    pub first: Option<ErrorRecord>,
+    pub last: Option<ErrorRecord>,
+}
+
+/// Where, and in which bit of the kernel, a problem was noticed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorRecord {
+    pub time: Time,
+    pub inode: Option<u32>,
+    pub block: Option<u64>,
+    /// The kernel function which reported the error, e.g. `ext4_lookup`.
+    pub function: String,
+    /// The line in that function's source file.
+    pub line: u32,
+    /// One of the kernel's `EXT4_ERR_*` codes, e.g. 1 for `EIO`; zero if not recorded.
+    pub code: u8,
+}
+
+impl<R> SuperBlock<R> {
+    /// The label, timestamps and usage counters from the superblock.
+    pub fn info(&self) -> FsInfo {
+        let raw = &self.raw;
+
+        // e2fsprogs treats zero and negative maximums as "no maximum"
+        let max_mount_count = raw.s_max_mnt_count as i16;
+
+        FsInfo {
+            uuid: raw.s_uuid,
+            creator_os: CreatorOs::from_raw(raw.s_creator_os),
+            volume_name: non_empty(c_string(&raw.s_volume_name)),
+            created: optional_time(raw.s_mkfs_time, raw.s_mkfs_time_hi),
+            last_mounted: optional_time(raw.s_mtime, raw.s_mtime_hi),
+            last_written: optional_time(raw.s_wtime, raw.s_wtime_hi),
+            last_mount_point: non_empty(c_string(&raw.s_last_mounted)),
+            mount_count: raw.s_mnt_count,
+            max_mount_count: if max_mount_count > 0 {
+                Some(max_mount_count as u16)
+            } else {
+                None
+            },
+            last_checked: optional_time(raw.s_lastcheck, raw.s_lastcheck_hi),
+            check_interval: Some(raw.s_checkinterval).filter(|&interval| 0 != interval),
+            kbytes_written: raw.s_kbytes_written,
+            default_mount_options: DefaultMountOptions::from_bits_truncate(
+                raw.s_default_mount_opts,
+            ),
+            default_journal_mode: match raw.s_default_mount_opts & JOURNAL_MODE_MASK {
+                0x0020 => Some(JournalMode::Data),
+                0x0040 => Some(JournalMode::Ordered),
+                0x0060 => Some(JournalMode::Writeback),
+                _ => None,
+            },
+            mount_options: non_empty(c_string(&raw.s_mount_opts)),
+            flags: SuperBlockFlags::from_bits_truncate(raw.s_flags),
+            default_hash_version: HashVersion::from_raw(raw.s_def_hash_version).map(|version| {
+                version.with_flags(SuperBlockFlags::from_bits_truncate(raw.s_flags))
+            }),
+            hash_seed: raw.s_hash_seed,
+        }
+    }
+
+    /// The errors the kernel has recorded, or `None` if there aren't any.
+    pub fn error_history(&self) -> Option<ErrorHistory> {
+        let raw = &self.raw;
+
+        let first = error_record(
+            raw.s_first_error_time,
+            raw.s_first_error_time_hi,
+            raw.s_first_error_ino,
+            raw.s_first_error_block,
+            &raw.s_first_error_func,
+            raw.s_first_error_line,
+            raw.s_first_error_errcode,
+        );
+        let last = error_record(
+            raw.s_last_error_time,
+            raw.s_last_error_time_hi,
+            raw.s_last_error_ino,
+            raw.s_last_error_block,
+            &raw.s_last_error_func,
+            raw.s_last_error_line,
+            raw.s_last_error_errcode,
+        );
+
+        if 0 == raw.s_error_count && first.is_none() && last.is_none() {
+            return None;
+        }
+
+        Some(ErrorHistory {
+            count: raw.s_error_count,
+            first,
+            last,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn error_record(
+    time: u32,
+    time_hi: u8,
+    inode: u32,
+    block: u64,
+    function: &[u8],
+    line: u32,
+    code: u8,
+) -> Option<ErrorRecord> {
+    // the kernel always sets the time when it records an error
+    let time = optional_time(time, time_hi)?;
+
+    Some(ErrorRecord {
+        time,
+        inode: Some(inode).filter(|&inode| 0 != inode),
+        block: Some(block).filter(|&block| 0 != block),
+        function: c_string(function),
+        line,
+        code,
+    })
+}
+
+/// Superblock times are unsigned, with eight extra high bits stored elsewhere.
+fn superblock_time(lo: u32, hi: u8) -> Time {
+    Time {
+        epoch_secs: i64::from(lo) | (i64::from(hi) << 32),
+        nanos: None,
+    }
+}
+
+/// Zero is used for "never".
+fn optional_time(lo: u32, hi: u8) -> Option<Time> {
+    if 0 == lo && 0 == hi {
+        None
+    } else {
+        Some(superblock_time(lo, hi))
+    }
+}
+
+pub(crate) fn non_empty(value: String) -> Option<String> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+/// A fixed-length, NUL-padded field; not necessarily valid UTF-8.
+pub(crate) fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| 0 == b).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Whether `c_string` drops anything but the NULs padding the field.
+pub(crate) fn text_after_nul(bytes: &[u8]) -> bool {
+    match bytes.iter().position(|&b| 0 == b) {
+        Some(end) => bytes[end..].iter().any(|&b| 0 != b),
+        None => false,
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use bitflags::bitflags;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::assumption_failed;
+use crate::extents::TreeReader;
+use crate::parse::ext4_style_crc32c_le;
+use crate::unsupported_feature;
+use crate::Time;
+
+const JBD2_MAGIC: u32 = 0xC03B_3998;
+
+const DESCRIPTOR_BLOCK: u32 = 1;
+const COMMIT_BLOCK: u32 = 2;
+const SUPERBLOCK_V1: u32 = 3;
+const SUPERBLOCK_V2: u32 = 4;
+const REVOKE_BLOCK: u32 = 5;
+
+const TAG_FLAG_ESCAPE: u32 = 0x1; /* on-disk block is escaped */
+const TAG_FLAG_SAME_UUID: u32 = 0x2; /* block has same uuid as previous */
+const TAG_FLAG_LAST_TAG: u32 = 0x8; /* last tag in this descriptor block */
+
+/// The size of `journal_superblock_t`, which is what the checksum covers.
+const JOURNAL_SUPERBLOCK_LEN: usize = 1024;
+
+bitflags! {
+    pub struct JournalIncompatibleFeature: u32 {
+        const REVOKE         = 0x0001;
+        const SIXTY_FOUR_BIT = 0x0002;
+        const ASYNC_COMMIT   = 0x0004;
+        const CSUM_V2        = 0x0008;
+        const CSUM_V3        = 0x0010;
+        const FAST_COMMIT    = 0x0020;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for JournalIncompatibleFeature {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+/// The jbd2 journal, and the transactions which can be found in its log.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Journal {
+    pub block_size: u32,
+    /// The total number of blocks in the journal, including the superblock.
+    pub max_len: u32,
+    /// The first block of the log; block zero is the journal's superblock.
+    pub first: u32,
+    /// The sequence number of the first transaction recovery would replay.
+    pub sequence: u32,
+    /// The block recovery would start at, or zero if the journal is clean.
+    pub start: u32,
+    pub incompatible_features: JournalIncompatibleFeature,
+    /// Every committed transaction still present in the log, including ones which
+    /// have already been checkpointed, ordered by sequence number.
+    pub transactions: Vec<Transaction>,
+}
+
+/// A committed transaction found in the journal's log.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Transaction {
+    pub sequence: u32,
+    /// The log block of the transaction's first descriptor.
+    pub log_start: u32,
+    /// The log block of the transaction's commit block.
+    pub log_end: u32,
+    /// Copies of filesystem blocks, in the order they were logged.
+    pub blocks: Vec<JournalBlock>,
+    /// Filesystem blocks which earlier transactions must not overwrite.
+    pub revoked: Vec<u64>,
+    pub commit_time: Option<Time>,
+    /// Whether recovery would replay this transaction.
+    pub needs_replay: bool,
+}
+
+/// A copy of a filesystem block, stored in the journal.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct JournalBlock {
+    /// The filesystem block this is a copy of.
+    pub target: u64,
+    /// Where the copy lives, in journal blocks.
+    pub log_block: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    escaped: bool,
+}
+
+impl Journal {
+    /// The blocks recovery would write, in the order it would write them, with revocations applied.
+    pub fn replay_blocks(&self) -> Vec<&JournalBlock> {
+        let live = || self.transactions.iter().filter(|t| t.needs_replay);
+
+        let mut revoked = HashMap::new();
+        for transaction in live() {
+            for block in &transaction.revoked {
+                revoked.insert(*block, transaction.sequence);
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for transaction in live() {
+            for block in &transaction.blocks {
+                // c.f. jbd2_journal_test_revoke
+                match revoked.get(&block.target) {
+                    Some(revoked_in) if !tid_gt(transaction.sequence, *revoked_in) => continue,
+                    _ => blocks.push(block),
+                }
+            }
+        }
+
+        blocks
+    }
+}
+
+pub struct JournalReader<R> {
+    inner: TreeReader<R>,
+    block_size: u32,
+    /// How many blocks the journal inode actually has.
+    len: u32,
+}
+
+impl<R> JournalReader<R>
+where
+    R: positioned_io2::ReadAt,
+{
+    pub fn new(
+        inner: TreeReader<R>,
+        block_size: u32,
+        size: u64,
+    ) -> Result<JournalReader<R>, Error> {
+        Ok(JournalReader {
+            inner,
+            block_size,
+            len: u32::try_from(size / u64::from(block_size))?,
+        })
+    }
+
+    fn read_into(&mut self, log_block: u32, buf: &mut [u8]) -> Result<(), Error> {
+        ensure!(
+            log_block < self.len,
+            assumption_failed(format!(
+                "journal block {} is beyond the end of the journal inode ({})",
+                log_block, self.len
+            ))
+        );
+        self.inner.seek(io::SeekFrom::Start(
+            u64::from(log_block) * u64::from(self.block_size),
+        ))?;
+        self.inner
+            .read_exact(buf)
+            .with_context(|| anyhow!("reading journal block {}", log_block))?;
+        Ok(())
+    }
+
+    fn read_block(&mut self, log_block: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; usize::try_from(self.block_size)?];
+        self.read_into(log_block, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read a logged copy of a filesystem block, undoing any escaping.
+    pub fn load(&mut self, block: &JournalBlock) -> Result<Vec<u8>, Error> {
+        let mut data = self.read_block(block.log_block)?;
+        if block.escaped {
+            BigEndian::write_u32(&mut data[0..4], JBD2_MAGIC);
+        }
+        Ok(data)
+    }
+
+    pub fn into_inner(self) -> TreeReader<R> {
+        self.inner
+    }
+
+    pub fn scan(&mut self) -> Result<Journal, Error> {
+        let mut sb = [0u8; JOURNAL_SUPERBLOCK_LEN];
+        self.read_into(0, &mut sb)?;
+
+        let h_magic = BigEndian::read_u32(&sb[0x00..0x04]);
+        let h_blocktype = BigEndian::read_u32(&sb[0x04..0x08]);
+        ensure!(
+            JBD2_MAGIC == h_magic,
+            assumption_failed(format!("invalid journal magic: {:08x}", h_magic))
+        );
+        ensure!(
+            SUPERBLOCK_V1 == h_blocktype || SUPERBLOCK_V2 == h_blocktype,
+            assumption_failed(format!(
+                "journal superblock has unexpected type: {}",
+                h_blocktype
+            ))
+        );
+
+        let s_blocksize = BigEndian::read_u32(&sb[0x0C..0x10]); /* journal device blocksize */
+        let s_maxlen = BigEndian::read_u32(&sb[0x10..0x14]); /* total blocks in journal file */
+        let s_first = BigEndian::read_u32(&sb[0x14..0x18]); /* first block of log information */
+        let s_sequence = BigEndian::read_u32(&sb[0x18..0x1C]); /* first commit ID expected in log */
+        let s_start = BigEndian::read_u32(&sb[0x1C..0x20]); /* blocknr of start of log */
+        let s_feature_incompat = if SUPERBLOCK_V2 == h_blocktype {
+            BigEndian::read_u32(&sb[0x28..0x2C]) /* incompatible feature set */
+        } else {
+            0
+        };
+
+        ensure!(
+            s_blocksize == self.block_size,
+            unsupported_feature(format!(
+                "journal block size ({}) differs from the filesystem's ({})",
+                s_blocksize, self.block_size
+            ))
+        );
+
+        ensure!(
+            s_first > 0 && s_first < s_maxlen,
+            assumption_failed(format!(
+                "journal log starts outside the journal: {} not in 1..{}",
+                s_first, s_maxlen
+            ))
+        );
+
+        let incompatible_features =
+            JournalIncompatibleFeature::from_bits_truncate(s_feature_incompat);
+
+        ensure!(
+            s_maxlen <= self.len,
+            assumption_failed(format!(
+                "journal claims to be longer ({}) than its inode ({})",
+                s_maxlen, self.len
+            ))
+        );
+
+        let layout = Layout::new(incompatible_features, s_first, s_maxlen);
+
+        // Find every header in the log. Data blocks which happen to start with the magic
+        // are escaped when they're logged, so this shouldn't find anything else.
+        let mut headers = Vec::new();
+        let mut header = [0u8; 12];
+        for log_block in s_first..s_maxlen {
+            self.read_into(log_block, &mut header)?;
+            if JBD2_MAGIC != BigEndian::read_u32(&header[0x00..0x04]) {
+                continue;
+            }
+            let block_type = BigEndian::read_u32(&header[0x04..0x08]);
+            let sequence = BigEndian::read_u32(&header[0x08..0x0C]);
+            headers.push((log_block, block_type, sequence));
+        }
+
+        // Headers of a transaction are contiguous, but it may wrap around the end of the log,
+        // so start looking from the first header of some transaction, not from the first block.
+        let previous = |i: usize| if 0 == i { headers.len() - 1 } else { i - 1 };
+        let start_index = (0..headers.len())
+            .find(|&i| headers[previous(i)].2 != headers[i].2)
+            .unwrap_or(0);
+
+        let mut covered = vec![false; usize::try_from(s_maxlen)?];
+        let mut transactions = Vec::new();
+
+        for k in 0..headers.len() {
+            let (log_block, block_type, sequence) = headers[(start_index + k) % headers.len()];
+            if DESCRIPTOR_BLOCK != block_type && REVOKE_BLOCK != block_type {
+                continue;
+            }
+
+            if covered[usize::try_from(log_block)?] {
+                continue;
+            }
+
+            if let Some(transaction) = self.transaction_at(&layout, log_block, sequence)? {
+                let mut covering = transaction.log_start;
+                loop {
+                    covered[usize::try_from(covering)?] = true;
+                    if covering == transaction.log_end {
+                        break;
+                    }
+                    covering = layout.wrap(covering + 1);
+                }
+                transactions.push(transaction);
+            }
+        }
+
+        transactions.sort_by_key(|t| t.sequence.wrapping_sub(s_sequence) as i32);
+
+        if 0 != s_start {
+            let mut expected = s_sequence;
+            let mut expected_start = s_start;
+            for transaction in &mut transactions {
+                if transaction.sequence != expected || transaction.log_start != expected_start {
+                    continue;
+                }
+                transaction.needs_replay = true;
+                expected = expected.wrapping_add(1);
+                expected_start = layout.wrap(transaction.log_end + 1);
+            }
+        }
+
+        Ok(Journal {
+            block_size: s_blocksize,
+            max_len: s_maxlen,
+            first: s_first,
+            sequence: s_sequence,
+            start: s_start,
+            incompatible_features,
+            transactions,
+        })
+    }
+
+    /// Follow a transaction from its first header, returning it only if it was committed.
+    fn transaction_at(
+        &mut self,
+        layout: &Layout,
+        log_start: u32,
+        sequence: u32,
+    ) -> Result<Option<Transaction>, Error> {
+        let mut transaction = Transaction {
+            sequence,
+            log_start,
+            log_end: log_start,
+            blocks: Vec::new(),
+            revoked: Vec::new(),
+            commit_time: None,
+            needs_replay: false,
+        };
+
+        let mut log_block = log_start;
+
+        // a transaction can't be longer than the log; this stops us looping forever
+        for _ in layout.first..layout.max_len {
+            let data = self.read_block(log_block)?;
+            if JBD2_MAGIC != BigEndian::read_u32(&data[0x00..0x04])
+                || sequence != BigEndian::read_u32(&data[0x08..0x0C])
+            {
+                return Ok(None);
+            }
+
+            match BigEndian::read_u32(&data[0x04..0x08]) {
+                DESCRIPTOR_BLOCK => {
+                    for (target, flags) in layout.tags(&data)? {
+                        log_block = layout.wrap(log_block + 1);
+                        transaction.blocks.push(JournalBlock {
+                            target,
+                            log_block,
+                            escaped: 0 != flags & TAG_FLAG_ESCAPE,
+                        });
+                    }
+                }
+                REVOKE_BLOCK => transaction.revoked.extend(layout.revoked(&data)?),
+                COMMIT_BLOCK => {
+                    let h_commit_sec = BigEndian::read_u64(&data[0x30..0x38]);
+                    let h_commit_nsec = BigEndian::read_u32(&data[0x38..0x3C]);
+                    if 0 != h_commit_sec {
+                        transaction.commit_time = Some(Time {
+                            epoch_secs: i64::try_from(h_commit_sec)?,
+                            nanos: Some(h_commit_nsec),
+                        });
+                    }
+                    transaction.log_end = log_block;
+                    return Ok(Some(transaction));
+                }
+                _ => return Ok(None),
+            }
+
+            log_block = layout.wrap(log_block + 1);
+        }
+
+        Ok(None)
+    }
+}
+
+struct Layout {
+    first: u32,
+    max_len: u32,
+    tag_bytes: usize,
+    long_block_numbers: bool,
+    csum_v3: bool,
+    has_tail: bool,
+}
+
+impl Layout {
+    fn new(features: JournalIncompatibleFeature, first: u32, max_len: u32) -> Layout {
+        let long_block_numbers = features.contains(JournalIncompatibleFeature::SIXTY_FOUR_BIT);
+        let csum_v3 = features.contains(JournalIncompatibleFeature::CSUM_V3);
+        let csum_v2 = features.contains(JournalIncompatibleFeature::CSUM_V2);
+
+        // c.f. jbd2_journal_tag_bytes
+        let tag_bytes = if csum_v3 {
+            16
+        } else {
+            let mut size = 12;
+            if csum_v2 {
+                size += 2;
+            }
+            if !long_block_numbers {
+                size -= 4;
+            }
+            size
+        };
+
+        Layout {
+            first,
+            max_len,
+            tag_bytes,
+            long_block_numbers,
+            csum_v3,
+            has_tail: csum_v2 || csum_v3,
+        }
+    }
+
+    fn wrap(&self, log_block: u32) -> u32 {
+        if log_block >= self.max_len {
+            log_block - self.max_len + self.first
+        } else {
+            log_block
+        }
+    }
+
+    /// The (target block, flags) of each tag in a descriptor block.
+    fn tags(&self, data: &[u8]) -> Result<Vec<(u64, u32)>, Error> {
+        let end = if self.has_tail {
+            data.len() - 4
+        } else {
+            data.len()
+        };
+
+        let mut tags = Vec::new();
+        let mut offset = 12;
+        while offset + self.tag_bytes <= end {
+            let tag = &data[offset..offset + self.tag_bytes];
+            let t_blocknr = BigEndian::read_u32(&tag[0x00..0x04]);
+            let t_flags = if self.csum_v3 {
+                BigEndian::read_u32(&tag[0x04..0x08])
+            } else {
+                u32::from(BigEndian::read_u16(&tag[0x06..0x08]))
+            };
+            let t_blocknr_high = if self.long_block_numbers {
+                BigEndian::read_u32(&tag[0x08..0x0C])
+            } else {
+                0
+            };
+
+            tags.push((
+                u64::from(t_blocknr) | (u64::from(t_blocknr_high) << 32),
+                t_flags,
+            ));
+
+            offset += self.tag_bytes;
+            if 0 == t_flags & TAG_FLAG_SAME_UUID {
+                offset += 16;
+            }
+
+            if 0 != t_flags & TAG_FLAG_LAST_TAG {
+                return Ok(tags);
+            }
+        }
+
+        ensure!(
+            !tags.is_empty(),
+            assumption_failed("journal descriptor block has no tags")
+        );
+
+        Ok(tags)
+    }
+
+    fn revoked(&self, data: &[u8]) -> Result<Vec<u64>, Error> {
+        let r_count = usize::try_from(BigEndian::read_u32(&data[0x0C..0x10]))?;
+        ensure!(
+            r_count >= 16 && r_count <= data.len(),
+            assumption_failed(format!("invalid revoke block length: {}", r_count))
+        );
+
+        let record_len = if self.long_block_numbers { 8 } else { 4 };
+
+        Ok(data[16..r_count]
+            .chunks_exact(record_len)
+            .map(|record| {
+                if self.long_block_numbers {
+                    BigEndian::read_u64(record)
+                } else {
+                    u64::from(BigEndian::read_u32(record))
+                }
+            })
+            .collect())
+    }
+}
+
+/// Mark a filesystem superblock as not needing recovery.
+pub fn mark_recovered(sb: &mut [u8], has_checksums: bool) {
+    const INCOMPAT_RECOVER: u32 = 0x0004;
+    let incompat = LittleEndian::read_u32(&sb[0x60..0x64]);
+    LittleEndian::write_u32(&mut sb[0x60..0x64], incompat & !INCOMPAT_RECOVER);
+
+    if has_checksums {
+        let computed = ext4_style_crc32c_le(!0, &sb[..0x3FC]);
+        LittleEndian::write_u32(&mut sb[0x3FC..0x400], computed);
+    }
+}
+
+/// Mark a journal superblock as having been recovered, as e2fsck does.
+pub fn mark_clean(
+    sb: &mut [u8],
+    incompatible_features: JournalIncompatibleFeature,
+    next_sequence: u32,
+) {
+    BigEndian::write_u32(&mut sb[0x18..0x1C], next_sequence);
+    BigEndian::write_u32(&mut sb[0x1C..0x20], 0);
+
+    if incompatible_features
+        .intersects(JournalIncompatibleFeature::CSUM_V2 | JournalIncompatibleFeature::CSUM_V3)
+    {
+        BigEndian::write_u32(&mut sb[0xFC..0x100], 0);
+        let computed = ext4_style_crc32c_le(!0, &sb[..JOURNAL_SUPERBLOCK_LEN]);
+        BigEndian::write_u32(&mut sb[0xFC..0x100], computed);
+    }
+}
+
+/// `a > b`, allowing for sequence numbers wrapping around.
+fn tid_gt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
@@ -15,29 +15,173 @@ let passwd_reader = superblock.open(&inode).unwrap();
 Note: normal users can't read `/dev/sda1` by default, as it would allow them to read any
 file on the filesystem. You can grant yourself temporary access with
 `sudo setfacl -m u:${USER}:r /dev/sda1`, if you so fancy. This will be lost at reboot.
+
+Images are read through [`ReadAt`], which is `positioned_io2`'s trait, re-exported, so
+anything implementing it (files, slices, `positioned_io2::Slice`s of a disc, or your own
+types) can be used directly, and the readers from [`SuperBlock::open`] can be
+passed back to anything expecting one.
 */
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io;
-use std::io::Read;
-use std::io::Seek;
+use std::path::Path;
+use std::path::PathBuf;
 
 use anyhow::anyhow;
 use anyhow::ensure;
 use anyhow::Context;
 use anyhow::Error;
 use bitflags::bitflags;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::LittleEndian;
+/// Reads without a position, or `&mut`; see the crate docs.
 pub use positioned_io2::ReadAt;
 
+mod aligned;
+mod ancestry;
+mod archive;
 mod block_groups;
+mod block_map;
+mod check;
+mod chunk;
+mod copy;
+mod debugfs;
+mod deflate;
+mod diff;
+mod dir_index;
+mod dir_summary;
+mod dirhash;
+mod disk;
+mod distro;
+mod dumpe2fs;
+mod events;
 mod extents;
-
+mod fingerprint;
+mod indirect;
+mod info;
+mod journal;
+mod locality;
+pub mod luks;
+#[cfg(feature = "lvm")]
+pub mod lvm;
+mod metadata_image;
+mod oci;
+mod overlay;
+mod owners;
+mod patch;
+mod path_cache;
+mod progress;
+mod recover;
+mod redact;
+mod scan;
+mod sha256;
+mod sharing;
+mod spool;
+mod tar;
+mod timeline;
+mod timeout;
+mod unallocated;
+mod vectored;
+mod verify;
+pub mod verity;
+mod view;
+mod warnings;
+mod wipe;
+mod zip;
+
+pub mod ondisk;
 /// Raw object parsing API. Not versioned / supported.
 pub mod parse;
 
+pub use crate::aligned::AlignedReader;
+pub use crate::archive::ArchiveEntry;
+pub use crate::archive::ArchiveSink;
+pub use crate::block_groups::BlockGroup;
+pub use crate::block_groups::BlockGroupFlags;
+pub use crate::block_map::BlockRun;
+pub use crate::block_map::BlockUse;
+pub use crate::check::Finding;
+pub use crate::check::Phase;
+pub use crate::check::Region;
+pub use crate::check::Severity;
+pub use crate::chunk::Chunk;
+pub use crate::chunk::Chunker;
+pub use crate::debugfs::Debugfs;
+pub use crate::diff::Change;
+pub use crate::diff::Changes;
+pub use crate::diff::DiffOptions;
+pub use crate::diff::Difference;
+pub use crate::dir_index::DxEntry;
+pub use crate::dir_index::DxLeaf;
+pub use crate::dir_index::DxNode;
+pub use crate::dir_index::DxTree;
+pub use crate::dir_summary::DirSummary;
+pub use crate::dirhash::dirhash;
+pub use crate::dirhash::DirHash;
+pub use crate::dirhash::HashVersion;
+pub use crate::disk::Disk;
+pub use crate::disk::Volume;
+pub use crate::disk::VolumeReader;
+pub use crate::distro::Distro;
+pub use crate::distro::DpkgPackage;
+pub use crate::distro::GroupEntry;
+pub use crate::distro::OsRelease;
+pub use crate::distro::PasswdEntry;
+pub use crate::distro::RpmBackend;
+pub use crate::distro::RpmDatabase;
+pub use crate::events::Event;
+pub use crate::events::Events;
+pub use crate::extents::DataExtent;
 use crate::extents::TreeReader;
+pub use crate::info::CreatorOs;
+pub use crate::info::DefaultMountOptions;
+pub use crate::info::ErrorHistory;
+pub use crate::info::ErrorRecord;
+pub use crate::info::FsInfo;
+pub use crate::info::JournalMode;
+pub use crate::info::SuperBlockFlags;
+pub use crate::journal::Journal;
+pub use crate::journal::JournalBlock;
+pub use crate::journal::JournalIncompatibleFeature;
+use crate::journal::JournalReader;
+pub use crate::journal::Transaction;
+pub use crate::locality::Locality;
+pub use crate::metadata_image::MetadataExport;
+pub use crate::oci::OciLayer;
+pub use crate::oci::OciLayerWriter;
+pub use crate::overlay::Overlay;
+pub use crate::overlay::OverlayEntry;
+pub use crate::patch::SuperblockPatch;
+pub use crate::progress::Progress;
+pub use crate::progress::ProgressReader;
+pub use crate::recover::CarvedEntry;
+pub use crate::recover::Confidence;
+pub use crate::recover::DeletedInode;
+pub use crate::recover::DeletedSource;
+pub use crate::recover::DirRecord;
+pub use crate::recover::LostEntry;
+pub use crate::recover::Tombstone;
+pub use crate::redact::Redaction;
+pub use crate::scan::scan_for_superblocks;
+pub use crate::scan::SuperblockCandidate;
+pub use crate::sharing::SharedExtent;
+pub use crate::spool::ingest_stream;
+pub use crate::spool::Spool;
+pub use crate::tar::TarWriter;
+pub use crate::timeline::TimelineEntry;
+pub use crate::timeout::TimeoutReader;
+pub use crate::unallocated::HighWaterMark;
+pub use crate::unallocated::UnallocatedReader;
+pub use crate::vectored::read_vectored_at;
+pub use crate::verify::VerifyCursor;
+pub use crate::view::Classified;
+pub use crate::view::Dir;
+pub use crate::view::File;
+pub use crate::view::Symlink;
+pub use crate::warnings::Warning;
+pub use crate::wipe::Wipe;
+pub use crate::zip::ZipMethod;
+pub use crate::zip::ZipWriter;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ParseError {
@@ -53,6 +197,27 @@ pub enum ParseError {
     /// The request is for something which we are sure is not there.
     #[error("filesystem uses an unsupported feature: {reason:?}")]
     NotFound { reason: String },
+
+    /// The operation was stopped early, as its `Progress` asked.
+    #[error("cancelled")]
+    Cancelled,
+
+    /// The image is shorter than the filesystem says it is, e.g. an interrupted copy.
+    #[error("image is truncated; expected {expected} bytes, found {found}")]
+    Truncated { expected: u64, found: u64 },
+
+    /// The filesystem was written to while it was being read, e.g. it's mounted, so
+    /// what was read may be a mix of before and after.
+    #[error("the filesystem changed while it was being read")]
+    Changed,
+
+    /// The filesystem asks for more than `Options::limits` allows; it may be crafted.
+    #[error("{what} is {found}, over the limit of {limit}")]
+    LimitExceeded {
+        what: &'static str,
+        found: u64,
+        limit: u64,
+    },
 }
 
 fn assumption_failed<S: ToString>(reason: S) -> ParseError {
@@ -104,7 +269,8 @@ bitflags! {
 }
 
 /// Flag indicating the type of file stored in this inode.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum FileType {
     RegularFile,     // S_IFREG (Regular file)
     SymbolicLink,    // S_IFLNK (Symbolic link)
@@ -117,10 +283,11 @@ pub enum FileType {
 
 /// Extended, type-specific information read from an inode.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Enhanced {
     RegularFile,
-    /// A symlink, with its decoded destination.
-    SymbolicLink(String),
+    /// A symlink, with its destination, byte for byte; see `SuperBlock::read_link`.
+    SymbolicLink(PathBuf),
     /// A 'c' device, with its major and minor numbers.
     CharacterDevice(u16, u32),
     /// A 'b' device, with its major and minor numbers.
@@ -145,6 +312,24 @@ impl FileType {
         }
     }
 
+    /// The `S_IFMT` bits of a mode, for this type; the inverse of `from_mode`.
+    pub(crate) fn mode_bits(self) -> u16 {
+        match self {
+            FileType::Fifo => 0x1000,
+            FileType::CharacterDevice => 0x2000,
+            FileType::Directory => 0x4000,
+            FileType::BlockDevice => 0x6000,
+            FileType::RegularFile => 0x8000,
+            FileType::SymbolicLink => 0xA000,
+            FileType::Socket => 0xC000,
+        }
+    }
+
+    /// A character or block device.
+    pub fn is_device(self) -> bool {
+        matches!(self, FileType::CharacterDevice | FileType::BlockDevice)
+    }
+
     fn from_dir_hint(hint: u8) -> Option<FileType> {
         match hint {
             1 => Some(FileType::RegularFile),
@@ -157,18 +342,35 @@ impl FileType {
             _ => None,
         }
     }
+
+    /// The `file_type` a directory entry stores for this type; the inverse of `from_dir_hint`.
+    pub(crate) fn dir_hint(self) -> u8 {
+        match self {
+            FileType::RegularFile => 1,
+            FileType::Directory => 2,
+            FileType::CharacterDevice => 3,
+            FileType::BlockDevice => 4,
+            FileType::Fifo => 5,
+            FileType::Socket => 6,
+            FileType::SymbolicLink => 7,
+        }
+    }
 }
 
 /// An entry in a directory, without its extra metadata.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct DirEntry {
     pub inode: u32,
     pub file_type: FileType,
     pub name: String,
 }
 
-/// Full information about a disc entry.
+/// Full information about a disc entry. More fields may be added, so it can only be made
+/// by reading an inode.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub struct Stat {
     pub extracted_type: FileType,
     pub file_mode: u16,
@@ -179,12 +381,57 @@ pub struct Stat {
     pub ctime: Time,
     pub mtime: Time,
     pub btime: Option<Time>,
+    /// `i_dtime`, which is set when the inode is deleted. Inodes on the orphan list, which
+    /// are deleted but still open, instead use it for the next orphan's number.
+    pub deleted_at: Option<Time>,
     pub link_count: u16,
     pub xattrs: HashMap<String, Vec<u8>>,
+    /// The numbers of a character or block device; `None` for anything else.
+    pub device: Option<DeviceNumbers>,
+}
+
+/// Which device a device node is for, as `mknod` takes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceNumbers {
+    pub major: u16,
+    pub minor: u32,
+    /// Both, combined as Linux's `makedev`, and `stat`'s `st_rdev`, do.
+    pub rdev: u64,
+}
+
+impl DeviceNumbers {
+    /// Decode `i_block`, which has the old 8 bit numbers in its first word, or, if that's
+    /// zero, the new, longer, ones in the second.
+    fn from_core(core: &[u8; INODE_CORE_SIZE]) -> DeviceNumbers {
+        let (major, minor) = if 0 != core[0] || 0 != core[1] {
+            (u16::from(core[1]), u32::from(core[0]))
+        } else {
+            // if you think reading this is bad, I had to write it
+            (
+                u16::from(core[5]) | (u16::from(core[6] & 0b0000_1111) << 8),
+                u32::from(core[4])
+                    | (u32::from(core[7]) << 12)
+                    | (u32::from(core[6] & 0b1111_0000) >> 4) << 8,
+            )
+        };
+        let (wide_major, wide_minor) = (u64::from(major), u64::from(minor));
+        DeviceNumbers {
+            major,
+            minor,
+            rdev: (wide_major & 0xfff) << 8
+                | (wide_major & !0xfff) << 32
+                | (wide_minor & 0xff)
+                | (wide_minor & !0xff) << 12,
+        }
+    }
 }
 
 const INODE_CORE_SIZE: usize = 4 * 15;
 
+/// How many of a directory's children a walk loads at once; see `Limits::untrusted`.
+const WALK_BATCH: usize = 64;
+
 /// An actual disc metadata entry.
 pub struct Inode {
     pub stat: Stat,
@@ -197,20 +444,45 @@ pub struct Inode {
     /// I made up a new name.
     core: [u8; INODE_CORE_SIZE],
     block_size: u32,
+    limits: Limits,
+    names: NameDecoding,
+    /// Whether directory entries say what they point at, with the `filetype` feature.
+    has_filetype: bool,
+    /// The 512-byte sectors `i_blocks` says the inode holds, other than its xattr block's.
+    data_sectors: u64,
 }
 
 /// The critical core of the filesystem.
 #[derive(Debug)]
 pub struct SuperBlock<R> {
     inner: R,
+    #[allow(dead_code)]
     load_xattrs: bool,
+    /// `Some(0)` if the journal is on an external device.
+    journal_inode: Option<u32>,
+    /// Inodes below this are reserved for the filesystem's own use.
+    first_inode: u32,
+    /// What paths are relative to; the real root, 2, unless `with_root` moved it.
+    root_inode: u32,
+    /// Where the image ends, if that's before the end of the filesystem.
+    image_len: Option<u64>,
+    /// The head of the list of inodes which were still open when they were deleted.
+    last_orphan: u32,
     /// All* checksums are computed after concatenation with the UUID, so we keep that.
     uuid_checksum: Option<u32>,
     groups: block_groups::BlockGroups,
+    path_cache: path_cache::PathCache,
+    owner_names: owners::OwnerNames,
+    warnings: warnings::Warnings,
+    /// What it was opened with, to re-open it the same way.
+    options: Options,
+    /// Everything, for the fields only needed for display.
+    raw: ondisk::RawSuperblock,
 }
 
 /// A raw filesystem time.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Time {
     pub epoch_secs: i64,
     pub nanos: Option<u32>,
@@ -249,7 +521,7 @@ impl Time {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Checksums {
     Required,
     Enabled,
@@ -261,9 +533,319 @@ impl Default for Checksums {
     }
 }
 
-#[derive(Debug, Default)]
+/// The order `walk` visits the entries of each directory in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// The order the directory stores them in, which depends on how, and in which order,
+    /// they were created, and, for hashed directories, on the hash seed.
+    Disk,
+    /// By name, comparing the bytes, so output is the same for the same tree.
+    Name,
+    /// By inode number, which can be faster to read, as the inode tables are read in order.
+    Inode,
+}
+
+impl Default for WalkOrder {
+    fn default() -> Self {
+        WalkOrder::Disk
+    }
+}
+
+/// What `walk` does when it finds something encrypted, which it can't decrypt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptedPolicy {
+    /// Stop, with an error.
+    Fail,
+    /// Leave it, and anything inside it, out.
+    Skip,
+    /// Carry on, with names and link targets as `nokey` text, and contents as they're stored.
+    YieldCiphertext,
+}
+
+impl Default for EncryptedPolicy {
+    fn default() -> Self {
+        EncryptedPolicy::Fail
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkOptions {
+    pub sort: WalkOrder,
+    /// For each encrypted inode; an encrypted directory's contents are all encrypted, too.
+    pub encrypted: EncryptedPolicy,
+    /// Visit the filesystem's `/lost+found`, which exports usually don't want, as it's
+    /// `e2fsck`'s, not the user's. On by default; see `SuperBlock::lost_found_entries`.
+    pub include_lost_found: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            sort: WalkOrder::default(),
+            encrypted: EncryptedPolicy::default(),
+            include_lost_found: true,
+        }
+    }
+}
+
+/// What to do about a path which tries to leave the root, with `..` or an absolute link.
+/// Nothing outside the image is ever looked at, as it's not there to be seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confinement {
+    /// As in a `chroot`: absolute links start from the root, and `..` at the root is the root.
+    /// A link to `/etc/alternatives/x` finds the image's `/etc/alternatives/x`.
+    InRoot,
+    /// Fail if the path, or any link on the way, goes above the root, or is absolute,
+    /// like `openat2(2)`'s `RESOLVE_BENEATH`.
+    Beneath,
+}
+
+impl Default for Confinement {
+    fn default() -> Self {
+        Confinement::InRoot
+    }
+}
+
+/// How `SuperBlock::resolve_with_options` should treat a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveOptions {
+    pub confinement: Confinement,
+    /// Follow a link at the end of the path, like `stat`, instead of returning it, like `lstat`.
+    pub follow_final: bool,
+}
+
+impl Default for ResolveOptions {
+    fn default() -> Self {
+        ResolveOptions {
+            confinement: Confinement::default(),
+            follow_final: true,
+        }
+    }
+}
+
+/// Where `SuperBlock::with_root` should move the root to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewRoot<'a> {
+    Path(&'a str),
+    Inode(u32),
+}
+
+impl<'a> From<&'a str> for NewRoot<'a> {
+    fn from(path: &'a str) -> Self {
+        NewRoot::Path(path)
+    }
+}
+
+impl From<u32> for NewRoot<'_> {
+    fn from(inode: u32) -> Self {
+        NewRoot::Inode(inode)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Options {
     pub checksums: Checksums,
+    /// Remember this many resolved paths, so looking them up again doesn't re-read every
+    /// directory on the way. Zero, the default, disables the cache.
+    pub path_cache: usize,
+    /// The length of the image, in bytes, if it's known, e.g. from `image_len`. Opening
+    /// fails with `ParseError::Truncated` if the filesystem doesn't fit, unless
+    /// `truncation` allows it.
+    pub len: Option<u64>,
+    pub truncation: Truncation,
+    pub limits: Limits,
+    pub initialisation: Initialisation,
+    pub consistency: Consistency,
+    pub names: NameDecoding,
+    pub creators: Creators,
+}
+
+/// Which operating systems' filesystems to open, by `FsInfo::creator_os`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Creators {
+    /// Only Linux's; anything else fails with `ParseError::UnsupportedFeature`.
+    Linux,
+    /// Any, such as the ext2 filesystems the Hurd, or FreeBSD, make. Their inodes are read
+    /// as that system lays them out, but the features they use haven't been tested.
+    Any,
+}
+
+impl Default for Creators {
+    fn default() -> Self {
+        Creators::Linux
+    }
+}
+
+/// What to do with an image which is shorter than its filesystem, e.g. a partial download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truncation {
+    /// Refuse to open it.
+    Fail,
+    /// Open it, so what is there can be used. File contents past the end read as
+    /// zeros, as if they were sparse; `check` reports the files affected. Metadata past
+    /// the end, such as directories, still fails to load.
+    Tolerate,
+}
+
+impl Default for Truncation {
+    fn default() -> Self {
+        Truncation::Fail
+    }
+}
+
+/// Whether to watch for the filesystem being written to while it's read, as a mounted
+/// device can be; see `SuperBlock::ensure_unchanged`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Assume it isn't.
+    Unchecked,
+    /// After each directory is read, by `walk`s, and path lookups, check the superblock
+    /// still says what it did at open, and fail with `ParseError::Changed` if not. This is
+    /// best-effort: the kernel only updates the superblock now and then, so a change
+    /// can go unnoticed for a while.
+    Checked,
+}
+
+impl Default for Consistency {
+    fn default() -> Self {
+        Consistency::Unchecked
+    }
+}
+
+/// How names which aren't valid utf-8, as legacy filesystems in other encodings have, are
+/// turned into `String`s: the names in directories, and xattr names. Symlink targets are
+/// kept as bytes, but decoded like this when `resolve_path` follows them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameDecoding {
+    /// Fail to read them, and so the directory, link, or inode, they're in.
+    Strict,
+    /// Replace anything invalid with U+FFFD. Names which differ only in their invalid
+    /// bytes look the same, and can't be told apart, or looked up.
+    Lossy,
+    /// Turn every byte of every name into the char with the same value, as latin-1 does,
+    /// valid utf-8 or not, so `to_bytes` can get the bytes back. Non-ascii names which
+    /// were valid look garbled, but every name is distinct, and can be looked up.
+    Raw,
+}
+
+impl Default for NameDecoding {
+    fn default() -> Self {
+        NameDecoding::Strict
+    }
+}
+
+impl NameDecoding {
+    pub(crate) fn decode(self, bytes: &[u8]) -> Result<String, std::str::Utf8Error> {
+        Ok(match self {
+            NameDecoding::Strict => std::str::from_utf8(bytes)?.to_string(),
+            NameDecoding::Lossy => String::from_utf8_lossy(bytes).into_owned(),
+            NameDecoding::Raw => bytes.iter().map(|&b| char::from(b)).collect(),
+        })
+    }
+
+    /// The bytes a name read this way came from, e.g. to create it elsewhere. `None` if
+    /// it can't have come from `Raw`. For `Lossy`, anything invalid stays U+FFFD.
+    pub fn to_bytes(self, name: &str) -> Option<Vec<u8>> {
+        match self {
+            NameDecoding::Strict | NameDecoding::Lossy => Some(name.as_bytes().to_vec()),
+            NameDecoding::Raw => name.chars().map(|c| u8::try_from(c).ok()).collect(),
+        }
+    }
+}
+
+/// How much of the filesystem to read, and check, when opening it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Initialisation {
+    /// Read every block group descriptor, so a corrupt one fails the open.
+    Full,
+    /// Read only the superblock; descriptors are read as they're needed. Enough to
+    /// probe an image, see its `info`, or look up a few paths, cheaply.
+    Minimal,
+}
+
+impl Default for Initialisation {
+    fn default() -> Self {
+        Initialisation::Full
+    }
+}
+
+/// Caps on what a filesystem can make the crate allocate, or walk, so an untrusted
+/// image fails with `ParseError::LimitExceeded`, instead of exhausting memory. The
+/// defaults are far beyond anything `mke2fs` makes for a real disc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Block groups. Descriptors are read a table block at a time, when one of them is
+    /// first wanted, and kept, so a walk of the whole filesystem ends up holding them all.
+    pub max_groups: u32,
+    /// Bytes in each inode.
+    pub max_inode_size: u16,
+    /// Levels of an extent tree, below the inode itself.
+    pub max_extent_depth: u16,
+    /// Extents in one file. `check_parallel` also holds at most this many, counting one
+    /// more for each inode, from the inodes it loads ahead of its walk of the tree.
+    pub max_extent_entries: usize,
+    /// Bytes in a directory, or symlink, which are read all at once.
+    pub max_directory_size: u64,
+    /// How deep a walk goes, by the `/`s in the paths it visits. Each directory on the way
+    /// holds its entries until it's done, and one linked inside itself nests forever.
+    pub max_walk_depth: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            // 256TiB of 4k blocks
+            max_groups: 1 << 21,
+            max_inode_size: 4096,
+            max_extent_depth: 5,
+            max_extent_entries: 1 << 22,
+            max_directory_size: 1 << 30,
+            max_walk_depth: 1 << 12,
+        }
+    }
+}
+
+impl Limits {
+    /// Much lower limits, for parsing untrusted images where memory is short. These cap
+    /// how much an image can make the crate allocate, but it's not a fixed budget: a
+    /// directory, or symlink, is still read whole, up to `max_directory_size`, and a
+    /// file's extents are all listed when it's opened, up to `max_extent_entries`. A walk
+    /// is inside at most `max_walk_depth` directories at once, and holds each one's
+    /// entries, and the inodes of a batch of its children. On top of that are the caches:
+    /// the group descriptors read so far, at most `max_groups` of them, and
+    /// `Options::path_cache`. Filesystems up to around a terabyte still open, but huge
+    /// directories, very fragmented files, and deep trees, can't be read.
+    pub fn untrusted() -> Limits {
+        Limits {
+            max_groups: 1 << 13,
+            max_inode_size: 1024,
+            max_extent_depth: 5,
+            max_extent_entries: 1 << 14,
+            max_directory_size: 1 << 20,
+            max_walk_depth: 1 << 7,
+        }
+    }
+
+    fn check(&self, what: &'static str, found: u64, limit: u64) -> Result<(), ParseError> {
+        if found > limit {
+            return Err(ParseError::LimitExceeded { what, found, limit });
+        }
+        Ok(())
+    }
+}
+
+/// The length of a file, or of a block device, which reports a length of zero in its
+/// metadata, but can be seeked to its end.
+pub fn image_len(file: &std::fs::File) -> io::Result<u64> {
+    use std::io::Seek;
+
+    let metadata = file.metadata()?;
+    if metadata.is_file() {
+        return Ok(metadata.len());
+    }
+    // the position is irrelevant to ReadAt, so moving it is harmless
+    let mut file = file;
+    file.seek(io::SeekFrom::End(0))
 }
 
 impl<R> SuperBlock<R>
@@ -284,20 +866,78 @@ where
         parse::superblock(inner, options).with_context(|| anyhow!("failed to parse superblock"))
     }
 
+    /// Open a filesystem, reading only its superblock; see `Initialisation::Minimal`.
+    pub fn open_minimal(inner: R) -> Result<SuperBlock<R>, Error> {
+        SuperBlock::new_with_options(
+            inner,
+            &Options {
+                initialisation: Initialisation::Minimal,
+                ..Options::default()
+            },
+        )
+    }
+
     /// Load a filesystem entry by inode number.
     pub fn load_inode(&self, inode: u32) -> Result<Inode, Error> {
         let data = self
             .load_inode_bytes(inode)
             .with_context(|| anyhow!("failed to find inode <{}> on disc", inode))?;
 
+        self.parse_inode(inode, data)
+    }
+
+    /// Load many entries at once, such as the children of a directory. Inodes close
+    /// together in an inode table are fetched with a single read, which is much faster
+    /// than `load_inode` on high-latency storage. The results are in the requested order.
+    pub fn load_inodes(&self, inodes: &[u32]) -> Vec<Result<Inode, Error>> {
+        let inode_size = usize::from(self.groups.inode_size);
+        let mut results: Vec<Option<Result<Inode, Error>>> = inodes.iter().map(|_| None).collect();
+
+        let mut wanted = Vec::with_capacity(inodes.len());
+        for (index, &inode) in inodes.iter().enumerate() {
+            match self.groups.index_of(&self.inner, inode) {
+                Ok(offset) => wanted.push((index, offset, vec![0u8; inode_size])),
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+
+        let mut requests = wanted
+            .iter_mut()
+            .map(|(_, offset, data)| (*offset, &mut data[..]))
+            .collect::<Vec<_>>();
+
+        // if the batch fails, try each inode on its own, so only the broken ones fail
+        let batched = read_vectored_at(&self.inner, &mut requests).is_ok();
+
+        for (index, _, data) in wanted {
+            let inode = inodes[index];
+            results[index] = Some(if batched {
+                self.parse_inode(inode, data)
+            } else {
+                self.load_inode(inode)
+            });
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every inode is either wanted, or failed"))
+            .collect()
+    }
+
+    fn parse_inode(&self, inode: u32, data: Vec<u8>) -> Result<Inode, Error> {
         let uuid_checksum = self.uuid_checksum;
         let parsed = parse::inode(
             data,
             |block| self.load_disc_bytes(block),
             uuid_checksum,
             inode,
+            self.options.names,
+            CreatorOs::from_raw(self.raw.s_creator_os),
         )
         .with_context(|| anyhow!("failed to parse inode <{}>", inode))?;
+        for warning in parsed.warnings {
+            self.warnings.push(warning);
+        }
 
         Ok(Inode {
             number: inode,
@@ -306,12 +946,47 @@ where
             core: parsed.core,
             checksum_prefix: parsed.checksum_prefix,
             block_size: self.groups.block_size,
+            limits: self.options.limits,
+            names: self.options.names,
+            has_filetype: self.has_filetype(),
+            data_sectors: self.data_sectors(&parsed.flags, parsed.blocks, parsed.xattr_block),
         })
     }
 
+    /// `i_blocks` in sectors, as the kernel's `ext4_inode_blocks` reads it, less the
+    /// cluster an extended attribute block takes.
+    fn data_sectors(&self, flags: &InodeFlags, blocks: u64, xattr_block: bool) -> u64 {
+        let ro_compat =
+            parse::CompatibleFeatureReadOnly::from_bits_truncate(self.raw.s_feature_ro_compat);
+        let block_size = u64::from(self.groups.block_size);
+        let sectors = if !ro_compat.contains(parse::CompatibleFeatureReadOnly::HUGE_FILE) {
+            blocks & 0xFFFF_FFFF
+        } else if flags.contains(InodeFlags::HUGE_FILE) {
+            blocks.saturating_mul(block_size / 512)
+        } else {
+            blocks
+        };
+        let cluster_size = if ro_compat.contains(parse::CompatibleFeatureReadOnly::BIGALLOC) {
+            1024u64 << self.raw.s_log_cluster_size.min(32)
+        } else {
+            block_size
+        };
+        if xattr_block {
+            sectors.saturating_sub(cluster_size / 512)
+        } else {
+            sectors
+        }
+    }
+
     fn load_inode_bytes(&self, inode: u32) -> Result<Vec<u8>, Error> {
-        let offset = self.groups.index_of(inode)?;
-        let mut data = vec![0u8; usize::try_from(self.groups.inode_size)?];
+        let offset = self.groups.index_of(&self.inner, inode)?;
+        if let Some(len) = self.image_len {
+            ensure!(
+                offset + u64::from(self.groups.inode_size) <= len,
+                not_found(format!("inode <{}> is past the end of the image", inode))
+            );
+        }
+        let mut data = vec![0u8; usize::from(self.groups.inode_size)];
         self.inner.read_exact_at(offset, &mut data)?;
         Ok(data)
     }
@@ -320,39 +995,290 @@ where
         load_disc_bytes(&self.inner, self.groups.block_size, block)
     }
 
+    /// Whether directory entries say what they point at; without the `filetype` feature,
+    /// as on revision 0 filesystems, only the inodes they point at do.
+    pub(crate) fn has_filetype(&self) -> bool {
+        parse::IncompatibleFeature::from_bits_truncate(self.raw.s_feature_incompat)
+            .contains(parse::IncompatibleFeature::FILETYPE)
+    }
+
+    /// What directory entries which don't say point at, from just the modes of their
+    /// inodes, read together, as `load_inodes` reads whole inodes. `None`, with a warning,
+    /// for those whose mode can't be read, or isn't a type.
+    fn file_types_of(&self, inodes: &[u32]) -> Vec<Option<FileType>> {
+        let offsets = inodes
+            .iter()
+            .map(|&inode| self.groups.index_of(&self.inner, inode).ok())
+            .collect::<Vec<_>>();
+        let mut modes = vec![[0u8; 2]; inodes.len()];
+        let mut requests = offsets
+            .iter()
+            .zip(modes.iter_mut())
+            .filter_map(|(offset, mode)| Some(((*offset)?, &mut mode[..])))
+            .collect::<Vec<_>>();
+
+        // if the batch fails, try each inode on its own, so only the broken ones fail
+        let batched = read_vectored_at(&self.inner, &mut requests).is_ok();
+
+        offsets
+            .into_iter()
+            .zip(modes)
+            .zip(inodes)
+            .map(|((offset, mut mode), &inode)| {
+                let found = offset.and_then(|offset| {
+                    if !batched {
+                        self.inner.read_exact_at(offset, &mut mode).ok()?;
+                    }
+                    FileType::from_mode(read_le16(&mode))
+                });
+                if found.is_none() {
+                    self.warnings.push(Warning::UnknownFileType { inode });
+                }
+                found
+            })
+            .collect()
+    }
+
+    /// The number of block groups in the filesystem.
+    pub fn block_group_count(&self) -> u32 {
+        self.groups.count()
+    }
+
+    /// Load the descriptor for a block group, numbered from zero.
+    pub fn block_group(&self, group: u32) -> Result<BlockGroup, Error> {
+        self.groups.get(&self.inner, group)
+    }
+
+    /// The block group whose part of the inode table holds an inode.
+    pub fn group_of_inode(&self, inode: u32) -> Result<u32, Error> {
+        self.groups.group_of_inode(inode)
+    }
+
+    /// The block group a block is in.
+    pub fn group_of_block(&self, block: u64) -> Result<u32, Error> {
+        self.groups.group_of_block(block)
+    }
+
+    /// The size of a filesystem block, in bytes.
+    pub fn block_size(&self) -> u32 {
+        self.groups.block_size
+    }
+
+    /// The number of blocks in the filesystem.
+    pub fn block_count(&self) -> u64 {
+        self.groups.blocks_count
+    }
+
+    /// If the image was opened with `Truncation::Tolerate`, and stops short of the end of
+    /// the filesystem, the first block it doesn't completely contain.
+    pub fn first_missing_block(&self) -> Option<u64> {
+        self.image_len
+            .map(|len| len / u64::from(self.groups.block_size))
+    }
+
+    /// Read a filesystem block, as it is on disc, numbered from zero.
+    pub fn load_block(&self, block: u64) -> Result<Vec<u8>, Error> {
+        if block >= self.groups.blocks_count {
+            return Err(not_found(format!(
+                "block {} is beyond the end of the filesystem ({} blocks)",
+                block, self.groups.blocks_count
+            ))
+            .into());
+        }
+        self.load_disc_bytes(block)
+    }
+
+    fn journal_reader(&self) -> Result<JournalReader<&R>, Error> {
+        let journal_inode = match self.journal_inode {
+            None => return Err(not_found("filesystem has no journal").into()),
+            Some(0) => return Err(unsupported_feature("journal is on an external device").into()),
+            Some(inode) => inode,
+        };
+
+        let inode = self
+            .load_inode(journal_inode)
+            .with_context(|| anyhow!("loading journal inode"))?;
+        JournalReader::new(self.open(&inode)?, self.groups.block_size, inode.stat.size)
+    }
+
+    /// Read the journal's superblock, and find the transactions in its log.
+    pub fn journal(&self) -> Result<Journal, Error> {
+        self.journal_reader()?
+            .scan()
+            .with_context(|| anyhow!("reading journal"))
+    }
+
+    /// Load the copy of a filesystem block which was logged in the journal.
+    pub fn journal_block(&self, block: &JournalBlock) -> Result<Vec<u8>, Error> {
+        self.journal_reader()?.load(block)
+    }
+
+    /// Write a copy of the filesystem to `out`, then replay the journal onto it,
+    /// as `e2fsck` would.
+    pub fn replay_journal_to<W>(&self, mut out: W) -> Result<(), Error>
+    where
+        W: io::Read + io::Write + io::Seek,
+    {
+        let mut reader = self.journal_reader()?;
+        let journal = reader.scan().with_context(|| anyhow!("reading journal"))?;
+
+        let block_size = u64::from(self.groups.block_size);
+        let mut buf = vec![0u8; usize::try_from(block_size * 256)?];
+        let total = self.groups.blocks_count * block_size;
+        let mut pos = 0;
+        out.seek(io::SeekFrom::Start(0))?;
+        while pos < total {
+            let len = std::cmp::min(u64::try_from(buf.len())?, total - pos);
+            let buf = &mut buf[..usize::try_from(len)?];
+            self.inner.read_exact_at(pos, buf)?;
+            out.write_all(buf)?;
+            pos += len;
+        }
+
+        for block in journal.replay_blocks() {
+            let data = reader.load(block)?;
+            out.seek(io::SeekFrom::Start(block.target * block_size))?;
+            out.write_all(&data)?;
+        }
+
+        if let Some(last) = journal.transactions.iter().rev().find(|t| t.needs_replay) {
+            let journal_superblock = reader
+                .into_inner()
+                .physical_block(0)
+                .ok_or_else(|| assumption_failed("journal superblock is sparse"))?;
+            let mut sb = vec![0u8; usize::try_from(block_size)?];
+            out.seek(io::SeekFrom::Start(journal_superblock * block_size))?;
+            out.read_exact(&mut sb)?;
+            journal::mark_clean(
+                &mut sb,
+                journal.incompatible_features,
+                last.sequence.wrapping_add(1),
+            );
+            out.seek(io::SeekFrom::Start(journal_superblock * block_size))?;
+            out.write_all(&sb)?;
+        }
+
+        let mut sb = [0u8; 1024];
+        out.seek(io::SeekFrom::Start(1024))?;
+        out.read_exact(&mut sb)?;
+        journal::mark_recovered(&mut sb, self.uuid_checksum.is_some());
+        out.seek(io::SeekFrom::Start(1024))?;
+        out.write_all(&sb)?;
+
+        Ok(())
+    }
+
     /// Load the root node of the filesystem (typically `/`).
     pub fn root(&self) -> Result<Inode, Error> {
-        self.load_inode(2)
+        self.load_inode(self.root_inode)
             .with_context(|| anyhow!("failed to load root inode"))
     }
 
-    /// Visit every entry in the filesystem in an arbitrary order.
+    /// Visit every entry under `inode`, depth first, each directory before its contents.
+    /// Entries are in the order their directory stores them; see `walk_with_options`.
     /// The closure should return `true` if it wants walking to continue.
     /// The method returns `true` if the closure always returned true.
     pub fn walk<F>(&self, inode: &Inode, path: &str, visit: &mut F) -> Result<bool, Error>
     where
         F: FnMut(&Self, &str, &Inode, &Enhanced) -> Result<bool, Error>,
     {
-        let enhanced = inode.enhance(&self.inner)?;
+        self.walk_with_progress(inode, path, &(), visit)
+    }
+
+    /// `walk`, telling `progress` about each entry before it is visited.
+    pub fn walk_with_progress<F>(
+        &self,
+        inode: &Inode,
+        path: &str,
+        progress: &dyn Progress,
+        visit: &mut F,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(&Self, &str, &Inode, &Enhanced) -> Result<bool, Error>,
+    {
+        self.walk_with_options(inode, path, &WalkOptions::default(), progress, visit)
+    }
+
+    /// `walk_with_progress`, visiting each directory's entries in the order `options` asks for.
+    /// Directories are always visited before their contents.
+    pub fn walk_with_options<F>(
+        &self,
+        inode: &Inode,
+        path: &str,
+        options: &WalkOptions,
+        progress: &dyn Progress,
+        visit: &mut F,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(&Self, &str, &Inode, &Enhanced) -> Result<bool, Error>,
+    {
+        progress.entry(path);
+        progress::check_cancelled(progress)?;
+        // names can't contain a `/`, so each is a level
+        let limits = &self.options.limits;
+        limits.check(
+            "depth of walk",
+            path.bytes().filter(|&b| b'/' == b).count() as u64,
+            u64::from(limits.max_walk_depth),
+        )?;
+
+        if inode.is_encrypted() {
+            match options.encrypted {
+                EncryptedPolicy::Fail => {
+                    return Err(unsupported_feature(format!(
+                        "<{}> is encrypted: {:?}",
+                        inode.number, path
+                    ))
+                    .into())
+                }
+                EncryptedPolicy::Skip => return Ok(true),
+                EncryptedPolicy::YieldCiphertext => (),
+            }
+        }
+
+        let enhanced = self.enhance(inode)?;
+        self.check_consistency()?;
 
         if !visit(self, path, inode, &enhanced).with_context(|| anyhow!("user closure failed"))? {
             return Ok(false);
         }
 
         if let Enhanced::Directory(entries) = enhanced {
-            for entry in entries {
-                if "." == entry.name || ".." == entry.name {
-                    continue;
-                }
+            let mut entries = entries
+                .into_iter()
+                .filter(|entry| "." != entry.name && ".." != entry.name)
+                // only the real root's, whatever `with_root` says
+                .filter(|entry| {
+                    options.include_lost_found || 2 != inode.number || "lost+found" != entry.name
+                })
+                .collect::<Vec<_>>();
+            match options.sort {
+                WalkOrder::Disk => (),
+                WalkOrder::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+                WalkOrder::Inode => entries.sort_by_key(|entry| entry.inode),
+            }
 
-                let child_node = self
-                    .load_inode(entry.inode)
-                    .with_context(|| anyhow!("loading {} ({:?})", entry.name, entry.file_type))?;
-                if !self
-                    .walk(&child_node, &format!("{}/{}", path, entry.name), visit)
-                    .with_context(|| anyhow!("processing '{}'", entry.name))?
-                {
-                    return Ok(false);
+            // a batch at a time, so a wide directory's inodes aren't all held at once
+            for batch in entries.chunks(WALK_BATCH) {
+                let children =
+                    self.load_inodes(&batch.iter().map(|entry| entry.inode).collect::<Vec<_>>());
+
+                for (entry, child_node) in batch.iter().zip(children) {
+                    let child_node = child_node.with_context(|| {
+                        anyhow!("loading {} ({:?})", entry.name, entry.file_type)
+                    })?;
+                    if !self
+                        .walk_with_options(
+                            &child_node,
+                            &format!("{}/{}", path, entry.name),
+                            options,
+                            progress,
+                            visit,
+                        )
+                        .with_context(|| anyhow!("processing '{}'", entry.name))?
+                    {
+                        return Ok(false);
+                    }
                 }
             }
         }
@@ -370,26 +1296,226 @@ where
         if path.is_empty() {
             // this is a bit of a lie, but it works..?
             return Ok(DirEntry {
-                inode: 2,
+                inode: self.root_inode,
                 file_type: FileType::Directory,
                 name: "/".to_string(),
             });
         }
 
-        let mut curr = self.root()?;
+        if let Some(entry) = self.path_cache.get(path) {
+            return Ok(entry);
+        }
+
+        // resolving the parent this way lets it come from the cache, too
+        let (parent, last) = match path.rfind('/') {
+            Some(slash) => (self.resolve_path(&path[..slash])?.inode, &path[slash + 1..]),
+            None => (self.root_inode, path),
+        };
+
+        let entry = self.dir_entry_named(&self.load_inode(parent)?, last)?;
+        self.check_consistency()?;
+        self.path_cache.insert(path, &entry);
+        Ok(entry)
+    }
+
+    /// Make `root` the root of the filesystem, so `root`, `resolve_path`, `canonicalize`,
+    /// `walk`s started from `root`, and everything built on them, only see what's under
+    /// it, e.g. the `/system` of a factory image. A path is resolved relative to the current
+    /// root, following links; an inode is taken as it is.
+    ///
+    /// `canonicalize` can't climb out with `..`, but `resolve_path` takes names literally,
+    /// so an explicit `..` in the new root itself reaches its real parent; use
+    /// `resolve_with_options` for paths which might contain one.
+    pub fn with_root<'a, T: Into<NewRoot<'a>>>(mut self, root: T) -> Result<SuperBlock<R>, Error> {
+        let inode = match root.into() {
+            NewRoot::Path(path) => self.canonicalize(path)?.1.inode,
+            NewRoot::Inode(inode) => inode,
+        };
+        let file_type = self.load_inode(inode)?.stat.extracted_type;
+        ensure!(
+            FileType::Directory == file_type,
+            not_found(format!("the new root, <{}>, is a {:?}", inode, file_type))
+        );
+
+        self.root_inode = inode;
+        // the cached paths were relative to the old root
+        self.path_cache.clear();
+        Ok(self)
+    }
+
+    /// Re-read the superblock, and forget everything read since it was opened, for when the
+    /// image underneath has changed, e.g. a snapshot which has been replaced. Any new root
+    /// from `with_root` is kept, as is the length from `Options`. Fails, leaving everything as
+    /// it was, if the image now holds a different filesystem.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let fresh = parse::superblock(&self.inner, &self.options)
+            .with_context(|| anyhow!("failed to re-read superblock"))?;
+        ensure!(
+            fresh.raw.s_uuid == self.raw.s_uuid,
+            not_found("the image now holds a different filesystem; its uuid has changed")
+        );
+
+        let SuperBlock {
+            inner: _,
+            load_xattrs,
+            journal_inode,
+            first_inode,
+            root_inode: _,
+            image_len,
+            last_orphan,
+            uuid_checksum,
+            groups,
+            path_cache: _,
+            owner_names: _,
+            warnings,
+            options: _,
+            raw,
+        } = fresh;
+        self.load_xattrs = load_xattrs;
+        self.journal_inode = journal_inode;
+        self.first_inode = first_inode;
+        self.image_len = image_len;
+        self.last_orphan = last_orphan;
+        self.uuid_checksum = uuid_checksum;
+        self.groups = groups;
+        self.path_cache.clear();
+        self.owner_names = owners::OwnerNames::default();
+        for warning in warnings.take() {
+            self.warnings.push(warning);
+        }
+        self.raw = raw;
+        Ok(())
+    }
+
+    /// Fail with `ParseError::Changed` if the superblock's record of writes, its last
+    /// write time, and the amount written, have moved on since it was opened (or refreshed),
+    /// so anything read since may be torn. Only catches what the kernel has written back.
+    pub fn ensure_unchanged(&self) -> Result<(), Error> {
+        let mut data = [0u8; 1024];
+        self.inner.read_exact_at(1024, &mut data)?;
+        let now = ondisk::RawSuperblock::from_slice(&data)?;
+        ensure!(
+            now.s_wtime == self.raw.s_wtime
+                && now.s_wtime_hi == self.raw.s_wtime_hi
+                && now.s_kbytes_written == self.raw.s_kbytes_written,
+            ParseError::Changed
+        );
+        Ok(())
+    }
+
+    fn check_consistency(&self) -> Result<(), Error> {
+        match self.options.consistency {
+            Consistency::Unchecked => Ok(()),
+            Consistency::Checked => self.ensure_unchanged(),
+        }
+    }
+
+    /// Forget every path remembered by the cache enabled in `Options`. Only needed if
+    /// the filesystem underneath has changed, e.g. a live block device.
+    pub fn clear_path_cache(&self) {
+        self.path_cache.clear();
+    }
+
+    /// Find the entry a path refers to, following symbolic links (including a final one)
+    /// and `..` components as the kernel would. Returns the canonical path, which contains
+    /// no links or dots, and the entry it names.
+    ///
+    /// Nothing outside the root is reachable: absolute links start again from the root, and
+    /// `..` at the root stays there, as in a `chroot`. See `resolve_with_options`.
+    pub fn canonicalize(&self, path: &str) -> Result<(String, DirEntry), Error> {
+        self.resolve_with_options(path, &ResolveOptions::default())
+    }
 
-        let mut parts = path.split('/').collect::<Vec<&str>>();
-        let last = parts.pop().unwrap();
-        for part in parts {
-            if part.is_empty() {
+    /// `canonicalize`, with control over the final link, and over what happens to paths
+    /// which try to leave the root. Whatever the options, the answer is inside the root.
+    pub fn resolve_with_options(
+        &self,
+        path: &str,
+        options: &ResolveOptions,
+    ) -> Result<(String, DirEntry), Error> {
+        // c.f. MAXSYMLINKS
+        const MAX_LINKS: usize = 40;
+
+        let mut remaining: Vec<String> = path.rsplit('/').map(|s| s.to_string()).collect();
+        let mut resolved: Vec<DirEntry> = Vec::new();
+        let mut links = 0;
+
+        while let Some(part) = remaining.pop() {
+            match part.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    ensure!(
+                        Confinement::InRoot == options.confinement || !resolved.is_empty(),
+                        "{} climbs above the root",
+                        path
+                    );
+                    resolved.pop();
+                    continue;
+                }
+                _ => (),
+            }
+
+            // the path so far has no links or dots in, so it can go through the cache
+            let mut so_far = String::new();
+            for entry in &resolved {
+                so_far.push('/');
+                so_far.push_str(&entry.name);
+            }
+            let entry = self.resolve_path(&format!("{}/{}", so_far, part))?;
+
+            // like the kernel, a trailing slash means the link is followed anyway
+            let last = remaining.is_empty();
+            if FileType::SymbolicLink != entry.file_type || (last && !options.follow_final) {
+                resolved.push(entry);
                 continue;
             }
 
-            let child_inode = self.dir_entry_named(&curr, part)?.inode;
-            curr = self.load_inode(child_inode)?;
+            links += 1;
+            ensure!(
+                links <= MAX_LINKS,
+                not_found(format!("too many levels of symbolic links in {}", path))
+            );
+
+            let link = self.load_inode(entry.inode)?;
+            ensure!(
+                FileType::SymbolicLink == link.stat.extracted_type,
+                assumption_failed(format!(
+                    "directory entry {} claimed to be a symlink, but isn't",
+                    part
+                ))
+            );
+            let target = link.decoded_link_target(&self.inner)?;
+
+            if target.starts_with('/') {
+                ensure!(
+                    Confinement::InRoot == options.confinement,
+                    "{} leads to an absolute link, {}, at {}",
+                    path,
+                    target,
+                    part
+                );
+                resolved.clear();
+            }
+            remaining.extend(target.rsplit('/').map(|s| s.to_string()));
         }
 
-        self.dir_entry_named(&curr, last)
+        let canonical = format!(
+            "/{}",
+            resolved
+                .iter()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+
+        Ok((
+            canonical,
+            resolved.pop().unwrap_or_else(|| DirEntry {
+                inode: self.root_inode,
+                file_type: FileType::Directory,
+                name: "/".to_string(),
+            }),
+        ))
     }
 
     fn dir_entry_named(&self, inode: &Inode, name: &str) -> Result<DirEntry, Error> {
@@ -404,14 +1530,52 @@ where
         }
     }
 
-    /// Read the data from an inode. You might not want to call this on thigns that aren't regular files.
+    /// Read the data from an inode. You might not want to call this on thigns that aren't regular files;
+    /// `classify` only allows opening those.
     pub fn open(&self, inode: &Inode) -> Result<TreeReader<&R>, Error> {
-        inode.reader(&self.inner)
+        let reader = inode.reader(&self.inner)?;
+        Ok(match self.image_len {
+            Some(len) => reader.zeros_after(len),
+            None => reader,
+        })
+    }
+
+    /// Find where a file's data is on disc. Sparse regions are not included.
+    pub fn data_extents(&self, inode: &Inode) -> Result<Vec<DataExtent>, Error> {
+        Ok(self.open(inode)?.data_extents())
+    }
+
+    /// Where a byte of a file is in the image, e.g. to `mmap` or `dd` part of the file
+    /// straight out of it. The rest of the byte's block follows it, but the next block of
+    /// the file may be anywhere. `None` in a hole, past the end of the file, or past the end
+    /// of a truncated image.
+    pub fn physical_offset(&self, inode: &Inode, file_offset: u64) -> Result<Option<u64>, Error> {
+        if file_offset >= inode.stat.size {
+            return Ok(None);
+        }
+        let block_size = u64::from(self.groups.block_size);
+        let part = u32::try_from(file_offset / block_size)?;
+        let offset = self
+            .open(inode)?
+            .physical_block(part)
+            .map(|block| block * block_size + file_offset % block_size);
+        Ok(offset.filter(|&offset| self.image_len.map_or(true, |len| offset < len)))
+    }
+
+    /// A symlink's target, like `std::fs::read_link`: byte for byte, even if it isn't
+    /// utf-8, as `enhance` has it. Only the target is read, and anything else fails.
+    /// Off unix, where paths aren't bytes, anything invalid is replaced.
+    pub fn read_link(&self, inode: &Inode) -> Result<PathBuf, Error> {
+        ensure!(
+            FileType::SymbolicLink == inode.stat.extracted_type,
+            not_found(format!("<{}> isn't a symlink", inode.number))
+        );
+        Ok(path_from_bytes(inode.link_bytes(&self.inner)?))
     }
 
     /// Load extra metadata about some types of entries.
     pub fn enhance(&self, inode: &Inode) -> Result<Enhanced, Error> {
-        inode.enhance(&self.inner)
+        inode.enhance(&self.inner, &|inodes| self.file_types_of(inodes))
     }
 }
 
@@ -430,17 +1594,35 @@ impl Inode {
     where
         R: ReadAt,
     {
-        TreeReader::new(
-            inner,
-            self.block_size,
-            self.stat.size,
-            self.core,
-            self.checksum_prefix,
-        )
-        .with_context(|| anyhow!("opening inode <{}>", self.number))
+        let reader = if self
+            .flags
+            .intersects(InodeFlags::EXTENTS | InodeFlags::INLINE_DATA)
+        {
+            TreeReader::new(
+                inner,
+                self.block_size,
+                self.stat.size,
+                self.core,
+                self.checksum_prefix,
+                &self.limits,
+            )
+        } else {
+            TreeReader::from_block_map(
+                inner,
+                self.block_size,
+                self.stat.size,
+                self.core,
+                &self.limits,
+            )
+        };
+        reader.with_context(|| anyhow!("opening inode <{}>", self.number))
     }
 
-    fn enhance<R>(&self, inner: R) -> Result<Enhanced, Error>
+    fn enhance<R>(
+        &self,
+        inner: R,
+        file_types_of: &dyn Fn(&[u32]) -> Vec<Option<FileType>>,
+    ) -> Result<Enhanced, Error>
     where
         R: ReadAt,
     {
@@ -449,39 +1631,17 @@ impl Inode {
             FileType::Socket => Enhanced::Socket,
             FileType::Fifo => Enhanced::Fifo,
 
-            FileType::Directory => Enhanced::Directory(self.read_directory(inner)?),
+            FileType::Directory => Enhanced::Directory(self.read_directory(inner, file_types_of)?),
             FileType::SymbolicLink => {
-                Enhanced::SymbolicLink(if self.stat.size < u64::try_from(INODE_CORE_SIZE)? {
-                    ensure!(
-                        self.flags.is_empty(),
-                        unsupported_feature(format!(
-                            "symbolic links may not have flags: {:?}",
-                            self.flags
-                        ))
-                    );
-                    std::str::from_utf8(&self.core[0..usize::try_from(self.stat.size)?])
-                        .with_context(|| anyhow!("short symlink is invalid utf-8"))?
-                        .to_string()
-                } else {
-                    ensure!(
-                        self.only_relevant_flag_is_extents(),
-                        unsupported_feature(format!(
-                            "symbolic links may not have non-extent flags: {:?}",
-                            self.flags
-                        ))
-                    );
-                    std::str::from_utf8(&self.load_all(inner)?)
-                        .with_context(|| anyhow!("long symlink is invalid utf-8"))?
-                        .to_string()
-                })
+                Enhanced::SymbolicLink(path_from_bytes(self.link_bytes(inner)?))
             }
             FileType::CharacterDevice => {
-                let (maj, min) = load_maj_min(self.core);
-                Enhanced::CharacterDevice(maj, min)
+                let device = DeviceNumbers::from_core(&self.core);
+                Enhanced::CharacterDevice(device.major, device.minor)
             }
             FileType::BlockDevice => {
-                let (maj, min) = load_maj_min(self.core);
-                Enhanced::BlockDevice(maj, min)
+                let device = DeviceNumbers::from_core(&self.core);
+                Enhanced::BlockDevice(device.major, device.minor)
             }
         })
     }
@@ -490,112 +1650,265 @@ impl Inode {
     where
         R: ReadAt,
     {
+        self.limits.check(
+            "size of directory, or symlink",
+            self.stat.size,
+            self.limits.max_directory_size,
+        )?;
         let size = usize::try_from(self.stat.size)?;
         let mut ret = vec![0u8; size];
+        let block_size = usize::try_from(self.block_size)?;
+
+        // ask for every extent at once, so a fragmented directory isn't a read per extent
+        let extents = self.reader(&inner)?.data_extents();
+        let mut requests = Vec::with_capacity(extents.len());
+        let mut rest = &mut ret[..];
+        let mut pos = 0;
+        for extent in extents {
+            let start = usize::try_from(extent.logical)? * block_size;
+            if start >= size {
+                break;
+            }
+            ensure!(
+                start >= pos,
+                assumption_failed(format!("extents overlap at block {}", extent.logical))
+            );
+            let end = size.min(start + usize::from(extent.len) * block_size);
+
+            // anything skipped over is sparse, and stays zero
+            let (_, tail) = std::mem::take(&mut rest).split_at_mut(start - pos);
+            let (chunk, tail) = tail.split_at_mut(end - start);
+            requests.push((extent.physical * u64::from(self.block_size), chunk));
+            rest = tail;
+            pos = end;
+        }
 
-        self.reader(inner)?.read_exact(&mut ret)?;
+        read_vectored_at(&inner, &mut requests)?;
 
         Ok(ret)
     }
 
-    fn read_directory<R>(&self, inner: R) -> Result<Vec<DirEntry>, Error>
+    /// A symlink whose target is in the inode's 60 bytes of `i_block`, rather than in a
+    /// block. Like the kernel, this goes by whether `i_blocks` counts any block but the
+    /// xattr block, not by the length: a short target may still be in a block, or be
+    /// inline data.
+    pub fn is_fast_symlink(&self) -> bool {
+        if FileType::SymbolicLink != self.stat.extracted_type {
+            return false;
+        }
+        if self.flags.contains(InodeFlags::EA_INODE) {
+            return 0 != self.stat.size && self.stat.size < INODE_CORE_SIZE as u64;
+        }
+        !self.flags.contains(InodeFlags::INLINE_DATA) && 0 == self.data_sectors
+    }
+
+    /// A fast symlink's target, in `i_block`, which the kernel refuses if it doesn't fit.
+    fn fast_link_target(&self) -> Result<&[u8], Error> {
+        ensure!(
+            self.stat.size < INODE_CORE_SIZE as u64,
+            assumption_failed(format!(
+                "fast symlink is too long for the inode: {}",
+                self.stat.size
+            ))
+        );
+        Ok(&self.core[..usize::try_from(self.stat.size)?])
+    }
+
+    /// A symlink's target, byte for byte; an encrypted one's as `nokey_name` shows it.
+    fn link_bytes<R>(&self, inner: R) -> Result<Vec<u8>, Error>
     where
         R: ReadAt,
     {
-        let mut dirs = Vec::with_capacity(40);
+        if self.is_encrypted() {
+            Ok(self.encrypted_link_target(inner)?.into_bytes())
+        } else {
+            self.link_target(inner)
+        }
+    }
 
-        let data = {
-            // if the flags, minus irrelevant flags, isn't just EXTENTS...
+    /// A symlink's target decoded as the names in directories are, to follow it among them.
+    fn decoded_link_target<R>(&self, inner: R) -> Result<String, Error>
+    where
+        R: ReadAt,
+    {
+        if self.is_encrypted() {
+            return self.encrypted_link_target(inner);
+        }
+        let target = self.link_target(inner)?;
+        self.names
+            .decode(&target)
+            .with_context(|| anyhow!("symlink target is invalid utf-8"))
+    }
+
+    /// An encrypted symlink's target, which we can't decrypt, as `nokey_name` shows it.
+    fn encrypted_link_target<R>(&self, inner: R) -> Result<String, Error>
+    where
+        R: ReadAt,
+    {
+        let data = if self.is_fast_symlink() {
+            self.fast_link_target()?.to_vec()
+        } else {
             ensure!(
-                self.only_relevant_flag_is_extents(),
+                self.only_relevant_flags_are(InodeFlags::ENCRYPT),
                 unsupported_feature(format!(
-                    "inode with unsupported flags: {0:x} {0:b}",
+                    "symbolic links may not have non-extent flags: {:?}",
                     self.flags
                 ))
             );
-
             self.load_all(inner)?
         };
+        // a struct fscrypt_symlink_data: the length of the ciphertext, then it
+        ensure!(
+            data.len() >= 2,
+            assumption_failed("encrypted symlink is too short for its length")
+        );
+        let len = usize::from(read_le16(&data));
+        let ciphertext = data.get(2..2 + len).ok_or_else(|| {
+            assumption_failed(format!("encrypted symlink target is short: {}", len))
+        })?;
+        Ok(nokey_name(ciphertext))
+    }
 
-        let total_len = data.len();
-
-        let mut cursor = io::Cursor::new(data);
-        let mut read = 0usize;
-        loop {
-            let child_inode = cursor.read_u32::<LittleEndian>()?;
-            let rec_len = cursor.read_u16::<LittleEndian>()?;
-
+    /// An unencrypted symlink's target, exactly `i_size` bytes of it.
+    fn link_target<R>(&self, inner: R) -> Result<Vec<u8>, Error>
+    where
+        R: ReadAt,
+    {
+        if self.is_fast_symlink() {
             ensure!(
-                rec_len > 8,
+                self.flags.is_empty(),
                 unsupported_feature(format!(
-                    "directory record length is too short, {} must be > 8",
-                    rec_len
+                    "symbolic links may not have flags: {:?}",
+                    self.flags
                 ))
             );
+            Ok(self.fast_link_target()?.to_vec())
+        } else {
+            ensure!(
+                self.only_relevant_flags_are(InodeFlags::empty()),
+                unsupported_feature(format!(
+                    "symbolic links may not have non-extent flags: {:?}",
+                    self.flags
+                ))
+            );
+            self.load_all(inner)
+        }
+    }
 
-            let name_len = cursor.read_u8()?;
-            let file_type = cursor.read_u8()?;
-            let mut name = vec![0u8; usize::try_from(name_len)?];
-            cursor.read_exact(&mut name)?;
-            if 0 != child_inode {
-                let name = std::str::from_utf8(&name)
-                    .map_err(|e| parse_error(format!("invalid utf-8 in file name: {}", e)))?;
-
-                dirs.push(DirEntry {
-                    inode: child_inode,
-                    name: name.to_string(),
-                    file_type: FileType::from_dir_hint(file_type).ok_or_else(|| {
-                        unsupported_feature(format!(
-                            "unexpected file type in directory: {}",
-                            file_type
-                        ))
-                    })?,
-                });
-            } else if 12 == rec_len && 0 == name_len && 0xDE == file_type {
-                // Magic entry representing the end of the list
-
-                if let Some(checksum_prefix) = self.checksum_prefix {
-                    let expected = cursor.read_u32::<LittleEndian>()?;
-                    let computed =
-                        parse::ext4_style_crc32c_le(checksum_prefix, &cursor.into_inner()[0..read]);
-                    ensure!(
-                        expected == computed,
-                        assumption_failed(format!(
-                            "directory checksum mismatch: on-disk: {:08x}, computed: {:08x}",
-                            expected, computed
-                        ))
-                    );
-                }
+    /// A directory's `..` entry's inode, which is always in its first block.
+    fn parent<R>(&self, inner: R) -> Result<u32, Error>
+    where
+        R: ReadAt,
+    {
+        let mut block = vec![0u8; usize::try_from(self.block_size)?];
+        self.reader(&inner)?.read_exact_at(0, &mut block)?;
+        // a hashed directory's first block is its index's root, which has no tail
+        let checksum_prefix = if self.flags.contains(InodeFlags::INDEX) {
+            None
+        } else {
+            self.checksum_prefix
+        };
+        parse::dirents(&block, self.has_filetype, checksum_prefix)?
+            .into_iter()
+            .find(|entry| b".." == &entry.name[..])
+            .map(|entry| entry.inode)
+            .ok_or_else(|| assumption_failed(format!("<{}> has no '..'", self.number)).into())
+    }
 
-                break;
-            }
+    /// The directory's entries; `file_types_of` finds what those which don't say point at,
+    /// and those it can't are left out.
+    fn read_directory<R>(
+        &self,
+        inner: R,
+        file_types_of: &dyn Fn(&[u32]) -> Vec<Option<FileType>>,
+    ) -> Result<Vec<DirEntry>, Error>
+    where
+        R: ReadAt,
+    {
+        let mut dirs = Vec::with_capacity(40);
 
-            cursor.seek(io::SeekFrom::Current(
-                i64::from(rec_len) - i64::from(name_len) - 4 - 2 - 1 - 1,
-            ))?;
+        let encrypted = self.is_encrypted();
+        let data = {
+            // if the flags, minus irrelevant flags, and EXTENTS, aren't empty (or ENCRYPT)...
+            ensure!(
+                self.only_relevant_flags_are(if encrypted {
+                    InodeFlags::ENCRYPT
+                } else {
+                    InodeFlags::empty()
+                }),
+                unsupported_feature(format!(
+                    "inode with unsupported flags: {0:x} {0:b}",
+                    self.flags
+                ))
+            );
 
-            read += usize::try_from(rec_len)?;
-            if read >= total_len {
-                ensure!(
-                    read == total_len,
-                    assumption_failed(format!("short read, {} != {}", read, total_len))
-                );
+            self.load_all(inner)?
+        };
 
-                ensure!(
-                    self.checksum_prefix.is_none(),
-                    assumption_failed(
-                        "directory checksums are enabled but checksum record not found"
-                    )
-                );
+        let indexed = self.flags.contains(InodeFlags::INDEX);
+        for (number, block) in data.chunks(usize::try_from(self.block_size)?).enumerate() {
+            // the root of a hashed directory's index, or an interior node, which is
+            // an unused entry covering the whole block; neither have a tail
+            let index = indexed
+                && (0 == number
+                    || (0 == read_le32(block)
+                        && block.len()
+                            == ondisk::rec_len_from_disk(read_le16(&block[4..]), block.len())));
+            let checksum_prefix = if index { None } else { self.checksum_prefix };
+
+            for entry in parse::dirents(block, self.has_filetype, checksum_prefix)? {
+                // . and .. are left alone
+                let name = if encrypted && b"." != &entry.name[..] && b".." != &entry.name[..] {
+                    nokey_name(&entry.name)
+                } else {
+                    self.names
+                        .decode(&entry.name)
+                        .map_err(|e| parse_error(format!("invalid utf-8 in file name: {}", e)))?
+                };
 
-                break;
+                dirs.push((entry.inode, name, entry.file_type));
             }
         }
 
-        Ok(dirs)
+        let untyped = dirs
+            .iter()
+            .filter(|(_, _, file_type)| file_type.is_none())
+            .map(|&(inode, _, _)| inode)
+            .collect::<Vec<_>>();
+        let mut found = if untyped.is_empty() {
+            Vec::new()
+        } else {
+            file_types_of(&untyped)
+        }
+        .into_iter();
+
+        Ok(dirs
+            .into_iter()
+            .filter_map(|(inode, name, file_type)| {
+                Some(DirEntry {
+                    inode,
+                    name,
+                    file_type: file_type.or_else(|| found.next().flatten())?,
+                })
+            })
+            .collect())
     }
 
-    fn only_relevant_flag_is_extents(&self) -> bool {
+    /// The seed for the inode's checksums, and its directory blocks', if the filesystem
+    /// has `metadata_csum`; see `parse::dirents`.
+    pub fn checksum_prefix(&self) -> Option<u32> {
+        self.checksum_prefix
+    }
+
+    /// Whether the inode's name (if it's a directory, its children's names), content, or
+    /// link target, are encrypted with `fscrypt`. There's no support for decrypting them.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags.contains(InodeFlags::ENCRYPT)
+    }
+
+    /// Whether the flags which change how the inode is read are just `expected`, ignoring
+    /// `EXTENTS`: its blocks can be found with or without an extent tree.
+    fn only_relevant_flags_are(&self, expected: InodeFlags) -> bool {
         self.flags
             & (InodeFlags::COMPR
                 | InodeFlags::DIRTY
@@ -605,25 +1918,61 @@ impl Inode {
                 | InodeFlags::NOTAIL
                 | InodeFlags::TOPDIR
                 | InodeFlags::HUGE_FILE
-                | InodeFlags::EXTENTS
                 | InodeFlags::EA_INODE
                 | InodeFlags::EOFBLOCKS
                 | InodeFlags::INLINE_DATA)
-            == InodeFlags::EXTENTS
+            == expected
+    }
+}
+
+/// An encrypted name, or link target, which we can't decrypt, as something printable:
+/// the ciphertext in unpadded url-safe base64, as the kernel shows names without the key
+/// (although it hashes the long ones, and older kernels used another alphabet).
+fn nokey_name(ciphertext: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut out = String::with_capacity((ciphertext.len() * 4 + 2) / 3);
+    for chunk in ciphertext.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(char::from(ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3f]));
+        }
     }
+    out
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
 }
 
-fn load_maj_min(core: [u8; INODE_CORE_SIZE]) -> (u16, u32) {
-    if 0 != core[0] || 0 != core[1] {
-        (u16::from(core[1]), u32::from(core[0]))
+/// The bytes of a path, as `path_from_bytes` made it.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+/// A position moved by a `SeekFrom`'s offset; `None` before the start, or past `u64::MAX`.
+fn add_signed(base: u64, diff: i64) -> Option<u64> {
+    if diff >= 0 {
+        base.checked_add(diff as u64)
     } else {
-        // if you think reading this is bad, I had to write it
-        (
-            u16::from(core[5]) | (u16::from(core[6] & 0b0000_1111) << 8),
-            u32::from(core[4])
-                | (u32::from(core[7]) << 12)
-                | (u32::from(core[6] & 0b1111_0000) >> 4) << 8,
-        )
+        base.checked_sub(diff.unsigned_abs())
     }
 }
 
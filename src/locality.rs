@@ -0,0 +1,95 @@
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::parse::IncompatibleFeature;
+use crate::Inode;
+use crate::SuperBlock;
+
+/// How far a file's data is from its inode, in block groups; see `SuperBlock::locality`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Locality {
+    pub inode: u32,
+    /// The block group holding the inode.
+    pub inode_group: u32,
+    /// How many groups `flex_bg` packs together, and the kernel treats as one when placing
+    /// data; 1 without it. Distances are in these.
+    pub groups_per_flex: u32,
+    /// Data blocks; the extent tree's own blocks aren't counted.
+    pub blocks: u64,
+    /// Data blocks in the same flex group as the inode.
+    pub local_blocks: u64,
+    /// How far the furthest data block is.
+    pub max_distance: u32,
+    /// Every data block's distance, added up.
+    pub total_distance: u64,
+}
+
+impl Locality {
+    /// The average distance of a data block; zero for a file without any.
+    pub fn mean_distance(&self) -> f64 {
+        if 0 == self.blocks {
+            return 0.;
+        }
+        self.total_distance as f64 / self.blocks as f64
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// How near a file's data is to its inode, as `flex_bg` intends it to be: the kernel
+    /// allocates in the inode's flex group first, so data elsewhere is a sign of a full,
+    /// or fragmented, filesystem.
+    pub fn locality(&self, inode: &Inode) -> Result<Locality, Error> {
+        let groups_per_flex = self.groups_per_flex();
+        let inode_group = self.group_of_inode(inode.number)?;
+        let home = inode_group / groups_per_flex;
+
+        let mut locality = Locality {
+            inode: inode.number,
+            inode_group,
+            groups_per_flex,
+            blocks: 0,
+            local_blocks: 0,
+            max_distance: 0,
+            total_distance: 0,
+        };
+
+        for extent in self.data_extents(inode)? {
+            let end = extent.physical + u64::from(extent.len);
+            let mut block = extent.physical;
+            // an extent can cross into the next group
+            while block < end {
+                let group = self.group_of_block(block)?;
+                let run = std::cmp::min(end, self.groups.end_of_group(group)) - block;
+                let flex = group / groups_per_flex;
+                let distance = if flex > home {
+                    flex - home
+                } else {
+                    home - flex
+                };
+
+                locality.blocks += run;
+                if 0 == distance {
+                    locality.local_blocks += run;
+                }
+                locality.max_distance = locality.max_distance.max(distance);
+                locality.total_distance += u64::from(distance) * run;
+                block += run;
+            }
+        }
+
+        Ok(locality)
+    }
+
+    fn groups_per_flex(&self) -> u32 {
+        let incompat = IncompatibleFeature::from_bits_truncate(self.raw.s_feature_incompat);
+        if incompat.contains(IncompatibleFeature::FLEX_BG) && self.raw.s_log_groups_per_flex < 32 {
+            1 << self.raw.s_log_groups_per_flex
+        } else {
+            1
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::convert::TryFrom;
+use std::io;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use positioned_io2::ReadAt;
+use positioned_io2::Size;
+
+use crate::assumption_failed;
+use crate::info::c_string as c_str;
+use crate::not_found;
+use crate::sha256::Sha256;
+use crate::unsupported_feature;
+
+const MAGIC: &[u8; 6] = b"LUKS\xba\xbe";
+
+/// LUKS1 counts in these; LUKS2 can use larger sectors.
+const SECTOR: u64 = 512;
+
+/// The unencrypted header of a LUKS volume: enough to say what it is, and where the
+/// encrypted data is, but not to decrypt it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LuksHeader {
+    /// 1 or 2.
+    pub version: u16,
+    pub uuid: String,
+    /// As `cryptsetup` names it, e.g. `aes-xts-plain64`.
+    pub cipher: String,
+    /// Where the encrypted data starts, in bytes.
+    pub payload_offset: u64,
+    /// The size of the units the data is encrypted in, in bytes.
+    pub sector_size: u32,
+}
+
+/// Decrypts a LUKS volume's data, with a key found elsewhere, e.g. by the caller
+/// unlocking a key slot; this crate doesn't do any cryptography itself.
+pub trait SectorDecryptor {
+    /// Decrypt one sector, of the header's `sector_size`, in place. `sector` counts
+    /// sectors of that size from the start of the payload, as dm-crypt's IVs do.
+    fn decrypt(&self, sector: u64, data: &mut [u8]) -> io::Result<()>;
+}
+
+impl<F> SectorDecryptor for F
+where
+    F: Fn(u64, &mut [u8]) -> io::Result<()>,
+{
+    fn decrypt(&self, sector: u64, data: &mut [u8]) -> io::Result<()> {
+        self(sector, data)
+    }
+}
+
+/// Reads the plaintext of a LUKS volume. See `LuksHeader::open`.
+pub struct DecryptingReader<R, D> {
+    inner: R,
+    decryptor: D,
+    payload_offset: u64,
+    sector_size: u64,
+}
+
+impl LuksHeader {
+    /// Read the header, if this is a LUKS volume, or `None` if it isn't.
+    pub fn detect<R: ReadAt>(reader: R) -> Result<Option<LuksHeader>, Error> {
+        let mut header = vec![0u8; 4096];
+        reader.read_exact_at(0, &mut header)?;
+        if MAGIC != &header[..6] {
+            return Ok(None);
+        }
+
+        let version = BigEndian::read_u16(&header[6..]);
+        Ok(Some(match version {
+            1 => luks1(&header)?,
+            2 => luks2(&reader, &header)?,
+            _ => return Err(unsupported_feature(format!("LUKS version {}", version)).into()),
+        }))
+    }
+
+    /// Read the volume's plaintext, decrypting it with `decryptor`.
+    pub fn open<R: ReadAt, D: SectorDecryptor>(
+        &self,
+        inner: R,
+        decryptor: D,
+    ) -> DecryptingReader<R, D> {
+        DecryptingReader {
+            inner,
+            decryptor,
+            payload_offset: self.payload_offset,
+            sector_size: u64::from(self.sector_size),
+        }
+    }
+}
+
+fn luks1(header: &[u8]) -> Result<LuksHeader, Error> {
+    let cipher_name = c_str(&header[8..40]);
+    let cipher_mode = c_str(&header[40..72]);
+    let payload_offset = u64::from(BigEndian::read_u32(&header[104..])) * SECTOR;
+    Ok(LuksHeader {
+        version: 1,
+        uuid: c_str(&header[168..208]),
+        cipher: format!("{}-{}", cipher_name, cipher_mode),
+        payload_offset,
+        sector_size: 512,
+    })
+}
+
+/// Most of a LUKS2 header is JSON, following the binary header.
+fn luks2<R: ReadAt>(reader: R, header: &[u8]) -> Result<LuksHeader, Error> {
+    let header_size = BigEndian::read_u64(&header[8..]);
+    ensure!(
+        (4096..=4 * 1024 * 1024).contains(&header_size),
+        assumption_failed(format!("LUKS2 header size {} is implausible", header_size))
+    );
+    let mut json = vec![0u8; usize::try_from(header_size - 4096)?];
+    reader.read_exact_at(4096, &mut json)?;
+    verify_checksum(header, &json)?;
+    if let Some(end) = json.iter().position(|&b| 0 == b) {
+        json.truncate(end);
+    }
+    let json = std::str::from_utf8(&json).with_context(|| anyhow!("LUKS2 JSON isn't utf-8"))?;
+    let json = Json::parse(json).with_context(|| anyhow!("parsing LUKS2 JSON"))?;
+
+    // cryptsetup only makes one segment; the data is in the lowest numbered
+    let segment = match json.get("segments") {
+        Some(Json::Object(segments)) => segments
+            .iter()
+            .filter_map(|(key, segment)| Some((key.parse::<u32>().ok()?, segment)))
+            .min_by_key(|(key, _)| *key)
+            .map(|(_, segment)| segment),
+        _ => None,
+    }
+    .ok_or_else(|| not_found("LUKS2 header has no segments"))?;
+
+    ensure!(
+        Some("crypt") == segment.get("type").and_then(Json::as_str),
+        unsupported_feature("LUKS2 segment isn't of type crypt")
+    );
+    let field = |name: &str| {
+        segment
+            .get(name)
+            .ok_or_else(|| assumption_failed(format!("LUKS2 segment has no {}", name)))
+    };
+    // offsets are strings, as they may be too big for a JavaScript number
+    let payload_offset = field("offset")?
+        .as_str()
+        .and_then(|offset| offset.parse::<u64>().ok())
+        .ok_or_else(|| assumption_failed("LUKS2 segment offset isn't a number"))?;
+    let sector_size = match field("sector_size")? {
+        Json::Number(size) => size
+            .parse::<u32>()
+            .map_err(|_| assumption_failed(format!("invalid sector size: {}", size)))?,
+        _ => return Err(assumption_failed("LUKS2 sector size isn't a number").into()),
+    };
+    ensure!(
+        sector_size.is_power_of_two() && (512..=4096).contains(&sector_size),
+        assumption_failed(format!("invalid sector size: {}", sector_size))
+    );
+
+    Ok(LuksHeader {
+        version: 2,
+        uuid: c_str(&header[168..208]),
+        cipher: field("encryption")?
+            .as_str()
+            .ok_or_else(|| assumption_failed("LUKS2 encryption isn't a string"))?
+            .to_string(),
+        payload_offset,
+        sector_size,
+    })
+}
+
+impl<R, D> DecryptingReader<R, D> {
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: ReadAt, D: SectorDecryptor> ReadAt for DecryptingReader<R, D> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let sector_size = self.sector_size;
+        let first = pos / sector_size;
+        let last = (pos + buf.len() as u64 - 1) / sector_size;
+        let mut data = vec![0u8; ((last - first + 1) * sector_size) as usize];
+
+        let mut filled = 0;
+        while filled < data.len() {
+            let at = self.payload_offset + first * sector_size + filled as u64;
+            match self.inner.read_at(at, &mut data[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(ref e) if io::ErrorKind::Interrupted == e.kind() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        // only whole sectors can be decrypted
+        let whole = filled - filled % sector_size as usize;
+        for (index, sector) in data[..whole].chunks_mut(sector_size as usize).enumerate() {
+            self.decryptor.decrypt(first + index as u64, sector)?;
+        }
+
+        let skip = (pos - first * sector_size) as usize;
+        if skip >= whole {
+            return Ok(0);
+        }
+        let len = buf.len().min(whole - skip);
+        buf[..len].copy_from_slice(&data[skip..skip + len]);
+        Ok(len)
+    }
+}
+
+impl<R: Size, D> Size for DecryptingReader<R, D> {
+    fn size(&self) -> io::Result<Option<u64>> {
+        Ok(self
+            .inner
+            .size()?
+            .map(|len| len.saturating_sub(self.payload_offset)))
+    }
+}
+
+/// Just enough JSON for a LUKS2 header.
+#[derive(Debug)]
+/// The checksum of the whole header, binary and JSON, with the checksum itself zeroed,
+/// which is only ever SHA-256; as `cryptsetup` does, anything else isn't trusted.
+fn verify_checksum(header: &[u8], json: &[u8]) -> Result<(), Error> {
+    let algorithm = c_str(&header[72..104]);
+    ensure!(
+        "sha256" == algorithm,
+        unsupported_feature(format!("LUKS2 header checksum {:?}", algorithm))
+    );
+    let mut digest = Sha256::new();
+    digest.update(&header[..448]);
+    digest.update(&[0u8; 64]);
+    digest.update(&header[512..]);
+    digest.update(json);
+    ensure!(
+        digest.finish()[..] == header[448..480],
+        assumption_failed("LUKS2 header checksum doesn't match")
+    );
+    Ok(())
+}
+
+enum Json {
+    /// As written, as it might not fit any particular type.
+    Number(String),
+    Str(String),
+    Object(Vec<(String, Json)>),
+    /// Arrays, booleans and nulls, which are parsed, but never needed.
+    Other,
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, Error> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars, 0)?;
+        skip_whitespace(&mut chars);
+        ensure!(
+            chars.peek().is_none(),
+            assumption_failed("trailing data after JSON")
+        );
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// How deeply arrays and objects can nest; `cryptsetup` makes a few levels, and each is
+/// a level of recursion.
+const MAX_DEPTH: u32 = 64;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Chars, wanted: char) -> Result<(), Error> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == wanted => Ok(()),
+        other => Err(assumption_failed(format!("expected {:?}, not {:?}", wanted, other)).into()),
+    }
+}
+
+fn parse_value(chars: &mut Chars, depth: u32) -> Result<Json, Error> {
+    skip_whitespace(chars);
+    if let Some('{' | '[') = chars.peek() {
+        ensure!(
+            depth < MAX_DEPTH,
+            assumption_failed(format!("JSON nests more than {} deep", MAX_DEPTH))
+        );
+    }
+    Ok(match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut entries = Vec::new();
+            skip_whitespace(chars);
+            if Some(&'}') == chars.peek() {
+                chars.next();
+                return Ok(Json::Object(entries));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                expect(chars, ':')?;
+                entries.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    other => {
+                        return Err(assumption_failed(format!(
+                            "expected ',' or '}}', not {:?}",
+                            other
+                        ))
+                        .into())
+                    }
+                }
+            }
+            Json::Object(entries)
+        }
+        Some('[') => {
+            chars.next();
+            skip_whitespace(chars);
+            if Some(&']') == chars.peek() {
+                chars.next();
+                return Ok(Json::Other);
+            }
+            loop {
+                parse_value(chars, depth + 1)?;
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => break,
+                    other => {
+                        return Err(assumption_failed(format!(
+                            "expected ',' or ']', not {:?}",
+                            other
+                        ))
+                        .into())
+                    }
+                }
+            }
+            Json::Other
+        }
+        Some('"') => Json::Str(parse_string(chars)?),
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "null" | "true" | "false" => Json::Other,
+                _ if word.parse::<f64>().is_ok() => Json::Number(word),
+                _ => {
+                    return Err(assumption_failed(format!("invalid JSON value: {:?}", word)).into())
+                }
+            }
+        }
+        None => return Err(assumption_failed("JSON ends with a missing value").into()),
+    })
+}
+
+fn parse_string(chars: &mut Chars) -> Result<String, Error> {
+    expect(chars, '"')?;
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .map_err(|_| assumption_failed(format!("invalid escape: \\u{}", hex)))?;
+                    // surrogate pairs don't appear in LUKS headers
+                    value.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                }
+                Some(c) => value.push(c),
+                None => break,
+            },
+            Some(c) => value.push(c),
+            None => break,
+        }
+    }
+    Err(assumption_failed("unterminated JSON string").into())
+}
@@ -0,0 +1,540 @@
+use std::convert::TryFrom;
+use std::io;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use positioned_io2::ReadAt;
+use positioned_io2::Size;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::unsupported_feature;
+
+const SECTOR: u64 = 512;
+
+/// The label can be in any of the first four sectors.
+const LABEL_SECTORS: u64 = 4;
+
+/// The seed of LVM's checksums, which are a CRC32 without the usual inversions.
+const INITIAL_CRC: u32 = 0xf597_a6cf;
+
+const MDA_MAGIC: &[u8; 16] = b" LVM2 x[5A%r0N*>";
+
+/// An LVM2 physical volume, and the logical volumes its volume group keeps on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhysicalVolume {
+    /// As LVM prints it, e.g. `x2Hdf4-...`.
+    pub uuid: String,
+    /// The volume group this belongs to.
+    pub vg_name: String,
+    pub logical_volumes: Vec<LogicalVolume>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LogicalVolume {
+    pub name: String,
+    pub uuid: String,
+    /// In bytes.
+    pub size: u64,
+    /// In the order they appear in the logical volume.
+    pub segments: Vec<Segment>,
+}
+
+/// A run of a logical volume's extents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Segment {
+    /// Where this starts in the logical volume, in bytes.
+    pub start: u64,
+    /// In bytes.
+    pub len: u64,
+    /// What LVM calls it; linear segments are `striped`, with a single stripe.
+    pub kind: String,
+    /// Where a linear segment starts on this physical volume, in bytes; `None` for every
+    /// other kind, and for segments on other physical volumes.
+    pub physical: Option<u64>,
+}
+
+/// Reads a logical volume out of its physical volume. See `LogicalVolume::open`.
+pub struct LogicalVolumeReader<R> {
+    inner: R,
+    size: u64,
+    segments: Vec<Segment>,
+}
+
+/// Find the logical volumes on an LVM2 physical volume, e.g. a partition of type `8e`,
+/// by reading its label and the latest copy of its volume group's metadata.
+pub fn physical_volume<R: ReadAt>(reader: R) -> Result<PhysicalVolume, Error> {
+    let (label_pos, label) = find_label(&reader)?;
+
+    // struct pv_header: the uuid, the device size, then lists of data and metadata areas
+    let header = usize::try_from(read_le32(&label[20..]))?;
+    ensure!(
+        header + 40 <= label.len(),
+        assumption_failed(format!("pv header at {} is outside its label", header))
+    );
+    let uuid = format_uuid(&label[header..header + 32])?;
+
+    let mut pos = label_pos + u64::try_from(header)? + 40;
+    let mut metadata_areas = Vec::new();
+    let mut lists = 0;
+    while lists < 2 {
+        let mut locn = [0u8; 16];
+        reader.read_exact_at(pos, &mut locn)?;
+        pos += 16;
+        let offset = LittleEndian::read_u64(&locn);
+        if 0 == offset {
+            lists += 1;
+        } else if 1 == lists {
+            metadata_areas.push(offset);
+        }
+    }
+
+    let mda = *metadata_areas
+        .first()
+        .ok_or_else(|| not_found("physical volume has no metadata area"))?;
+    let text = read_metadata(&reader, mda)?;
+    let config = Config::parse(&text).with_context(|| anyhow!("parsing volume group metadata"))?;
+    volume_group(&config, uuid)
+}
+
+impl LogicalVolume {
+    /// Read the volume from the physical volume it was found on. Only linear volumes,
+    /// entirely on that physical volume, can be read.
+    pub fn open<R: ReadAt>(&self, inner: R) -> Result<LogicalVolumeReader<R>, Error> {
+        for segment in &self.segments {
+            ensure!(
+                segment.physical.is_some(),
+                unsupported_feature(format!(
+                    "logical volume {} has a {} segment at {}, which isn't linear, on this physical volume",
+                    self.name, segment.kind, segment.start
+                ))
+            );
+        }
+        Ok(LogicalVolumeReader {
+            inner,
+            size: self.size,
+            segments: self.segments.clone(),
+        })
+    }
+}
+
+impl<R> LogicalVolumeReader<R> {
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: ReadAt> ReadAt for LogicalVolumeReader<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let segment = match self
+            .segments
+            .iter()
+            .find(|segment| pos >= segment.start && pos < segment.start + segment.len)
+        {
+            Some(segment) => segment,
+            None => return Ok(0),
+        };
+        let into = pos - segment.start;
+        let len = std::cmp::min(buf.len() as u64, segment.len - into) as usize;
+        let physical = segment.physical.expect("checked by open");
+        self.inner.read_at(physical + into, &mut buf[..len])
+    }
+}
+
+impl<R> Size for LogicalVolumeReader<R> {
+    fn size(&self) -> io::Result<Option<u64>> {
+        Ok(Some(self.size))
+    }
+}
+
+/// The label sector, and where it is.
+fn find_label<R: ReadAt>(reader: &R) -> Result<(u64, [u8; 512]), Error> {
+    let mut sector = [0u8; 512];
+    for number in 0..LABEL_SECTORS {
+        let pos = number * SECTOR;
+        reader.read_exact_at(pos, &mut sector)?;
+        if b"LABELONE" != &sector[..8] {
+            continue;
+        }
+        ensure!(
+            number == LittleEndian::read_u64(&sector[8..]),
+            assumption_failed(format!("label in sector {} says it's elsewhere", number))
+        );
+        ensure!(
+            read_le32(&sector[16..]) == lvm_crc(&sector[20..]),
+            assumption_failed("label checksum mismatch")
+        );
+        ensure!(
+            b"LVM2 001" == &sector[24..32],
+            unsupported_feature(format!(
+                "label type {:?}",
+                String::from_utf8_lossy(&sector[24..32])
+            ))
+        );
+        return Ok((pos, sector));
+    }
+    Err(not_found("no LVM2 label in the first four sectors").into())
+}
+
+/// The newest metadata in the circular buffer at `mda`.
+fn read_metadata<R: ReadAt>(reader: &R, mda: u64) -> Result<String, Error> {
+    let mut header = [0u8; 512];
+    reader.read_exact_at(mda, &mut header)?;
+    ensure!(
+        read_le32(&header) == lvm_crc(&header[4..]),
+        assumption_failed("metadata area header checksum mismatch")
+    );
+    ensure!(
+        MDA_MAGIC == &header[4..20],
+        assumption_failed("metadata area header has the wrong magic")
+    );
+    ensure!(
+        1 == read_le32(&header[20..]),
+        unsupported_feature(format!(
+            "metadata area version {}",
+            read_le32(&header[20..])
+        ))
+    );
+    let size = LittleEndian::read_u64(&header[32..]);
+
+    // the first raw_locn is the committed metadata
+    let offset = LittleEndian::read_u64(&header[40..]);
+    let len = LittleEndian::read_u64(&header[48..]);
+    let checksum = read_le32(&header[56..]);
+    ensure!(
+        0 != offset && 0 != len,
+        not_found("physical volume isn't in a volume group")
+    );
+    ensure!(
+        offset < size && len < size,
+        assumption_failed(format!(
+            "metadata at {}+{} is outside its {} byte area",
+            offset, len, size
+        ))
+    );
+
+    let mut text = vec![0u8; usize::try_from(len)?];
+    // it wraps around to just after the header
+    let first = std::cmp::min(len, size - offset);
+    let (before, after) = text.split_at_mut(usize::try_from(first)?);
+    reader.read_exact_at(mda + offset, before)?;
+    reader.read_exact_at(mda + SECTOR, after)?;
+
+    ensure!(
+        checksum == lvm_crc(&text),
+        assumption_failed("metadata checksum mismatch")
+    );
+    if let Some(end) = text.iter().position(|&b| 0 == b) {
+        text.truncate(end);
+    }
+    String::from_utf8(text).with_context(|| anyhow!("metadata is invalid utf-8"))
+}
+
+fn volume_group(config: &Config, pv_uuid: String) -> Result<PhysicalVolume, Error> {
+    let (vg_name, vg) = config
+        .0
+        .iter()
+        .find_map(|(name, value)| match value {
+            Value::Section(section) => Some((name, section)),
+            _ => None,
+        })
+        .ok_or_else(|| assumption_failed("metadata has no volume group"))?;
+
+    // in units of `unit` bytes, from untrusted text, so any of it can overflow
+    let bytes = |value: i64, unit: u64| -> Result<u64, Error> {
+        u64::try_from(value)
+            .ok()
+            .and_then(|value| value.checked_mul(unit))
+            .ok_or_else(|| assumption_failed(format!("invalid size: {}", value)).into())
+    };
+    let sectors = |value: i64| bytes(value, SECTOR);
+    let extent_size = sectors(vg.int("extent_size")?)?;
+
+    let (pv_key, pe_start) = vg
+        .section("physical_volumes")?
+        .0
+        .iter()
+        .find_map(|(key, value)| match value {
+            Value::Section(pv) if pv.string("id").ok() == Some(pv_uuid.as_str()) => {
+                Some((key, pv.int("pe_start")))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| not_found(format!("physical volume {} isn't in {}", pv_uuid, vg_name)))?;
+    let pe_start = sectors(pe_start?)?;
+
+    let mut logical_volumes = Vec::new();
+    let lvs = match vg.get("logical_volumes") {
+        Some(Value::Section(lvs)) => &lvs.0[..],
+        _ => &[],
+    };
+    for (name, lv) in lvs {
+        let lv = match lv {
+            Value::Section(lv) => lv,
+            _ => continue,
+        };
+        let mut segments = Vec::new();
+        for (_, segment) in &lv.0 {
+            let segment = match segment {
+                Value::Section(segment) => segment,
+                _ => continue,
+            };
+            let start = bytes(segment.int("start_extent")?, extent_size)?;
+            let len = bytes(segment.int("extent_count")?, extent_size)?;
+            let kind = segment.string("type")?.to_string();
+
+            let linear = "striped" == kind && Some(1) == segment.int("stripe_count").ok();
+            let physical = match segment.get("stripes") {
+                Some(Value::List(stripes)) if linear => match &stripes[..] {
+                    [Value::Str(pv), Value::Int(extent)] if pv == pv_key => Some(
+                        pe_start
+                            .checked_add(bytes(*extent, extent_size)?)
+                            .ok_or_else(|| assumption_failed("segment is past the end"))?,
+                    ),
+                    _ => None,
+                },
+                _ => None,
+            };
+            segments.push(Segment {
+                start,
+                len,
+                kind,
+                physical,
+            });
+        }
+        segments.sort_by_key(|segment| segment.start);
+
+        logical_volumes.push(LogicalVolume {
+            name: name.to_string(),
+            uuid: lv.string("id")?.to_string(),
+            size: segments
+                .iter()
+                .try_fold(0u64, |size, segment| size.checked_add(segment.len))
+                .ok_or_else(|| assumption_failed(format!("{} is too big", name)))?,
+            segments,
+        });
+    }
+
+    Ok(PhysicalVolume {
+        uuid: pv_uuid,
+        vg_name: vg_name.to_string(),
+        logical_volumes,
+    })
+}
+
+/// LVM prints its 32 character ids in groups of 6-4-4-4-4-4-6.
+fn format_uuid(raw: &[u8]) -> Result<String, Error> {
+    ensure!(
+        32 == raw.len() && raw.is_ascii(),
+        assumption_failed(format!("invalid uuid: {:?}", String::from_utf8_lossy(raw)))
+    );
+    let raw = std::str::from_utf8(raw)?;
+    let mut formatted = String::with_capacity(38);
+    let mut rest = raw;
+    for len in &[6, 4, 4, 4, 4, 4, 6] {
+        if !formatted.is_empty() {
+            formatted.push('-');
+        }
+        let (group, tail) = rest.split_at(*len);
+        formatted.push_str(group);
+        rest = tail;
+    }
+    Ok(formatted)
+}
+
+fn lvm_crc(buf: &[u8]) -> u32 {
+    !crc::crc32::update(!INITIAL_CRC, &crc::crc32::IEEE_TABLE, buf)
+}
+
+fn read_le32(from: &[u8]) -> u32 {
+    LittleEndian::read_u32(from)
+}
+
+/// How deeply sections and lists can nest; LVM writes a few levels, and each is a level
+/// of recursion.
+const MAX_DEPTH: u32 = 64;
+
+/// LVM's metadata: `key = value` pairs, and named sections in braces. Values are
+/// strings, integers, or lists of them.
+#[derive(Debug)]
+struct Config(Vec<(String, Value)>);
+
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Int(i64),
+    List(Vec<Value>),
+    Section(Config),
+}
+
+impl Config {
+    fn parse(text: &str) -> Result<Config, Error> {
+        let tokens = tokenise(text)?;
+        let mut pos = 0;
+        let config = Config::parse_section(&tokens, &mut pos, 0)?;
+        ensure!(
+            pos == tokens.len(),
+            assumption_failed(format!("unexpected {:?}", tokens[pos]))
+        );
+        Ok(config)
+    }
+
+    /// `depth` counts the sections and lists this is inside.
+    fn parse_section(tokens: &[Token], pos: &mut usize, depth: u32) -> Result<Config, Error> {
+        ensure!(
+            depth < MAX_DEPTH,
+            assumption_failed(format!("metadata nests more than {} deep", MAX_DEPTH))
+        );
+        let mut entries = Vec::new();
+        while let Some(Token::Word(key)) = tokens.get(*pos) {
+            *pos += 1;
+            match tokens.get(*pos) {
+                Some(Token::Punct('=')) => {
+                    *pos += 1;
+                    entries.push((key.clone(), parse_value(tokens, pos, depth)?));
+                }
+                Some(Token::Punct('{')) => {
+                    *pos += 1;
+                    let section = Config::parse_section(tokens, pos, depth + 1)?;
+                    ensure!(
+                        Some(&Token::Punct('}')) == tokens.get(*pos),
+                        assumption_failed(format!("section {} isn't closed", key))
+                    );
+                    *pos += 1;
+                    entries.push((key.clone(), Value::Section(section)));
+                }
+                other => {
+                    return Err(assumption_failed(format!(
+                        "expected '=' or '{{' after {}, not {:?}",
+                        key, other
+                    ))
+                    .into())
+                }
+            }
+        }
+        Ok(Config(entries))
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    fn int(&self, key: &str) -> Result<i64, Error> {
+        match self.get(key) {
+            Some(Value::Int(value)) => Ok(*value),
+            _ => Err(assumption_failed(format!("{} should be a number", key)).into()),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<&str, Error> {
+        match self.get(key) {
+            Some(Value::Str(value)) => Ok(value),
+            _ => Err(assumption_failed(format!("{} should be a string", key)).into()),
+        }
+    }
+
+    fn section(&self, key: &str) -> Result<&Config, Error> {
+        match self.get(key) {
+            Some(Value::Section(value)) => Ok(value),
+            _ => Err(assumption_failed(format!("{} should be a section", key)).into()),
+        }
+    }
+}
+
+fn parse_value(tokens: &[Token], pos: &mut usize, depth: u32) -> Result<Value, Error> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| assumption_failed("metadata ends with a missing value"))?;
+    *pos += 1;
+    Ok(match token {
+        Token::Str(value) => Value::Str(value.clone()),
+        Token::Word(word) => Value::Int(
+            word.parse()
+                .map_err(|_| assumption_failed(format!("{} isn't a value", word)))?,
+        ),
+        Token::Punct('[') => {
+            ensure!(
+                depth < MAX_DEPTH,
+                assumption_failed(format!("metadata nests more than {} deep", MAX_DEPTH))
+            );
+            let mut items = Vec::new();
+            loop {
+                if Some(&Token::Punct(']')) == tokens.get(*pos) {
+                    *pos += 1;
+                    break;
+                }
+                items.push(parse_value(tokens, pos, depth + 1)?);
+                match tokens.get(*pos) {
+                    Some(Token::Punct(',')) => *pos += 1,
+                    Some(Token::Punct(']')) => (),
+                    other => {
+                        return Err(assumption_failed(format!(
+                            "expected ',' or ']', not {:?}",
+                            other
+                        ))
+                        .into())
+                    }
+                }
+            }
+            Value::List(items)
+        }
+        Token::Punct(c) => return Err(assumption_failed(format!("unexpected '{}'", c)).into()),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+fn tokenise(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                while chars.peek().map_or(false, |&c| '\n' != c) {
+                    chars.next();
+                }
+            }
+            '=' | '{' | '}' | '[' | ']' | ',' => tokens.push(Token::Punct(c)),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(assumption_failed("unterminated string").into()),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "={}[],\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::BlockUse;
+use crate::FileType;
+use crate::SuperBlock;
+
+/// The most copied at once: a multiple of every block size, so reads stay block aligned.
+const CHUNK: usize = 1024 * 1024;
+
+/// What `SuperBlock::export_metadata` copies, beyond the superblocks, the group
+/// descriptors, the bitmaps, the inode tables and the extent trees, which it always does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataExport {
+    /// Copy the journal, which, with `data=journal`, can hold file data, too.
+    pub journal: bool,
+    /// Leave out directories' entries, and long symlinks' targets, which hold the names of
+    /// things, leaving only the structure.
+    pub omit_names: bool,
+    /// Write zeros over every block which isn't copied, rather than seeking past it, so
+    /// `out` can be a copy of the whole image, which this scrubs.
+    pub scrub: bool,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Copy the filesystem's metadata to `out`, at the same places as in the image, but
+    /// none of the files' content, like `e2image -r`, so an image can be shared to debug
+    /// it without sharing what's in it. Returns how many blocks were copied.
+    ///
+    /// The blocks which aren't copied are skipped, so a new file is sparse, and reads
+    /// them as zeros; `out` is grown to the length of the filesystem. What's copied is
+    /// found with `block_map`, so xattr blocks, and anything else it doesn't recognise,
+    /// aren't.
+    pub fn export_metadata<W>(&self, mut out: W, options: &MetadataExport) -> Result<u64, Error>
+    where
+        W: io::Write + io::Seek,
+    {
+        let block_size = u64::from(self.groups.block_size);
+        let available = self.image_len.unwrap_or(u64::MAX);
+        let mut names = HashMap::new();
+        let mut buf = vec![0u8; CHUNK];
+
+        let mut copied = 0;
+        let mut end = 0;
+        for run in self.block_map()? {
+            let wanted = match run.usage {
+                BlockUse::Superblock
+                | BlockUse::GroupDescriptors
+                | BlockUse::BlockBitmap
+                | BlockUse::InodeBitmap
+                | BlockUse::InodeTable
+                | BlockUse::ExtentTree(_) => true,
+                BlockUse::Journal => options.journal,
+                BlockUse::Data(inode) if !options.omit_names => match names.get(&inode) {
+                    Some(&named) => named,
+                    None => {
+                        let file_type = self.load_inode(inode)?.stat.extracted_type;
+                        let named =
+                            FileType::Directory == file_type || FileType::SymbolicLink == file_type;
+                        names.insert(inode, named);
+                        named
+                    }
+                },
+                BlockUse::Boot | BlockUse::Data(_) | BlockUse::Free | BlockUse::Unknown => false,
+            };
+
+            let start = run.start * block_size;
+            let len = run.len * block_size;
+            end = start + len;
+            if !wanted && !options.scrub {
+                continue;
+            }
+
+            out.seek(io::SeekFrom::Start(start))?;
+            let mut done = 0;
+            while done < len {
+                let chunk = (len - done).min(CHUNK as u64) as usize;
+                let offset = start + done;
+                let stored = if wanted {
+                    available.saturating_sub(offset).min(chunk as u64) as usize
+                } else {
+                    0
+                };
+                self.inner
+                    .read_exact_at(offset, &mut buf[..stored])
+                    .with_context(|| anyhow!("reading {:?} at {}", run.usage, offset))?;
+                buf[stored..chunk].iter_mut().for_each(|b| *b = 0);
+                out.write_all(&buf[..chunk])?;
+                done += chunk as u64;
+            }
+            if wanted {
+                copied += run.len;
+            }
+        }
+
+        // if the last runs were skipped, nothing has been written that far
+        if end > 0 && out.seek(io::SeekFrom::End(0))? < end {
+            out.seek(io::SeekFrom::Start(end - 1))?;
+            out.write_all(&[0])?;
+        }
+        out.flush()?;
+
+        Ok(copied)
+    }
+}
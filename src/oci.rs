@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::sha256::Sha256;
+use crate::tar::Header;
+use crate::ArchiveEntry;
+use crate::ArchiveSink;
+use crate::Enhanced;
+use crate::SuperBlock;
+use crate::TarWriter;
+
+/// Writes an OCI image layer: a tar, with overlayfs' whiteouts and opaque directories
+/// turned into the `.wh.` files the image spec uses, so the upper directory of an
+/// overlay can be exported as a layer.
+pub struct OciLayerWriter<W> {
+    tar: TarWriter<Digesting<W>>,
+}
+
+/// What an image manifest and config need to know about a layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OciLayer {
+    /// The digest of the uncompressed tar, e.g. `sha256:e3b0...`, for the config's `diff_ids`.
+    pub diff_id: String,
+    /// The length of the uncompressed tar.
+    pub size: u64,
+}
+
+/// overlayfs marks a deleted file with a character device with this number.
+pub(crate) const WHITEOUT_DEVICE: (u16, u32) = (0, 0);
+
+pub(crate) const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+const OVERLAY_XATTR_PREFIXES: [&str; 2] = ["trusted.overlay.", "user.overlay."];
+
+impl<W: Write> OciLayerWriter<W> {
+    pub fn new(inner: W) -> OciLayerWriter<W> {
+        OciLayerWriter {
+            tar: TarWriter::new(Digesting {
+                inner,
+                digest: Sha256::new(),
+                len: 0,
+            }),
+        }
+    }
+
+    /// Finish the tar, and return the writer, and the layer's digest.
+    pub fn finish(self) -> Result<(W, OciLayer), Error> {
+        let digesting = self.tar.finish()?;
+        let digest = digesting.digest.finish();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        Ok((
+            digesting.inner,
+            OciLayer {
+                diff_id: format!("sha256:{}", hex),
+                size: digesting.len,
+            },
+        ))
+    }
+
+    /// An empty file, to stand for something which isn't really there.
+    fn marker(&mut self, like: &Header, path: String) -> Result<(), Error> {
+        let header = Header {
+            path,
+            mode: 0,
+            uid: like.uid,
+            gid: like.gid,
+            size: 0,
+            mtime: like.mtime,
+            kind: b'0',
+            link: Vec::new(),
+            device: (0, 0),
+            xattrs: BTreeMap::new(),
+        };
+        self.tar.write_entry(&header, &mut io::empty())
+    }
+}
+
+/// Overlay's own extended attributes aren't included; sockets are left out, as for tar.
+impl<W: Write> ArchiveSink for OciLayerWriter<W> {
+    fn add(&mut self, entry: &ArchiveEntry, content: &mut dyn Read) -> Result<(), Error> {
+        let mut header = match Header::for_entry(entry) {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+
+        // whiteouts are often hard links to one shared device, so check this first
+        if let Enhanced::CharacterDevice(major, minor) = entry.enhanced {
+            if WHITEOUT_DEVICE == (*major, *minor) {
+                let (parent, name) = match entry.path.rfind('/') {
+                    Some(slash) => entry.path.split_at(slash + 1),
+                    None => ("", entry.path),
+                };
+                return self.marker(&header, format!("{}.wh.{}", parent, name));
+            }
+        }
+
+        let opaque = OPAQUE_XATTRS
+            .iter()
+            .any(|name| header.xattrs.get(*name).map(|v| &v[..]) == Some(b"y"));
+        header.xattrs.retain(|name, _| {
+            !OVERLAY_XATTR_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        });
+        self.tar.write_entry(&header, content)?;
+
+        if opaque && b'5' == header.kind {
+            self.marker(&header, format!("{}.wh..wh..opq", header.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Write everything under `path` as an OCI image layer. See `OciLayerWriter`.
+    pub fn export_oci_layer<W: Write>(&self, path: &str, out: W) -> Result<(W, OciLayer), Error> {
+        let mut layer = OciLayerWriter::new(out);
+        self.export(path, &mut layer)?;
+        layer.finish()
+    }
+}
+
+/// Hashes everything on its way through.
+struct Digesting<W> {
+    inner: W,
+    digest: Sha256,
+    len: u64,
+}
+
+impl<W: Write> Write for Digesting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,650 @@
+/*!
+The on-disk structures, field for field, as laid out in the kernel's `fs/ext4/ext4.h`.
+
+Unlike `parse`, this module is part of the crate's stable API: the structs and their
+field names follow the kernel's, and only change if the format does. Nothing here
+checks that values make sense; that's left to the caller, or the rest of the crate.
+
+All integers are little-endian on disk, and native in these structs.
+*/
+
+use std::convert::TryInto;
+
+use anyhow::ensure;
+use anyhow::Error;
+
+use crate::assumption_failed;
+
+/// A value which is stored in a fixed number of bytes.
+trait Field: Sized {
+    const SIZE: usize;
+    fn read(data: &[u8]) -> Self;
+    fn write(&self, data: &mut [u8]);
+}
+
+impl Field for u8 {
+    const SIZE: usize = 1;
+    fn read(data: &[u8]) -> Self {
+        data[0]
+    }
+    fn write(&self, data: &mut [u8]) {
+        data[0] = *self;
+    }
+}
+
+impl Field for u16 {
+    const SIZE: usize = 2;
+    fn read(data: &[u8]) -> Self {
+        u16::from_le_bytes(data[..2].try_into().expect("sliced"))
+    }
+    fn write(&self, data: &mut [u8]) {
+        data[..2].copy_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Field for u32 {
+    const SIZE: usize = 4;
+    fn read(data: &[u8]) -> Self {
+        u32::from_le_bytes(data[..4].try_into().expect("sliced"))
+    }
+    fn write(&self, data: &mut [u8]) {
+        data[..4].copy_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Field for u64 {
+    const SIZE: usize = 8;
+    fn read(data: &[u8]) -> Self {
+        u64::from_le_bytes(data[..8].try_into().expect("sliced"))
+    }
+    fn write(&self, data: &mut [u8]) {
+        data[..8].copy_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl<const N: usize> Field for [u8; N] {
+    const SIZE: usize = N;
+    fn read(data: &[u8]) -> Self {
+        data[..N].try_into().expect("sliced")
+    }
+    fn write(&self, data: &mut [u8]) {
+        data[..N].copy_from_slice(self);
+    }
+}
+
+impl<const N: usize> Field for [u32; N] {
+    const SIZE: usize = 4 * N;
+    fn read(data: &[u8]) -> Self {
+        let mut values = [0u32; N];
+        for (value, bytes) in values.iter_mut().zip(data.chunks_exact(4)) {
+            *value = u32::read(bytes);
+        }
+        values
+    }
+    fn write(&self, data: &mut [u8]) {
+        for (value, bytes) in self.iter().zip(data.chunks_exact_mut(4)) {
+            value.write(bytes);
+        }
+    }
+}
+
+/// Declare a struct whose fields are stored one after another, with no padding.
+macro_rules! on_disk {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($(#[$field_meta:meta])* pub $field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: $ty,)*
+        }
+
+        impl $name {
+            /// The size of the full structure, in bytes.
+            pub const SIZE: usize = 0 $(+ <$ty as Field>::SIZE)*;
+
+            /// `data` must be exactly `SIZE` long.
+            fn read_fields(data: &[u8]) -> $name {
+                assert_eq!(Self::SIZE, data.len());
+                let mut pos = 0;
+                $(
+                    let $field = <$ty as Field>::read(&data[pos..]);
+                    pos += <$ty as Field>::SIZE;
+                )*
+                debug_assert_eq!(Self::SIZE, pos);
+                $name { $($field,)* }
+            }
+
+            /// The full structure, `SIZE` bytes long, as it would be on disk.
+            pub fn to_bytes(&self) -> Vec<u8> {
+                let mut data = vec![0u8; Self::SIZE];
+                let mut pos = 0;
+                $(
+                    self.$field.write(&mut data[pos..]);
+                    pos += <$ty as Field>::SIZE;
+                )*
+                debug_assert_eq!(Self::SIZE, pos);
+                data
+            }
+        }
+    };
+}
+
+/// Copy `data` into a buffer of `size`, filling any missing tail with zeros.
+fn padded(data: &[u8], size: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; size];
+    write_truncated(data, &mut buf);
+    buf
+}
+
+/// Copy as much of `bytes` into `data` as will fit.
+fn write_truncated(bytes: &[u8], data: &mut [u8]) {
+    let len = data.len().min(bytes.len());
+    data[..len].copy_from_slice(&bytes[..len]);
+}
+
+on_disk! {
+    /// `struct ext4_super_block`, found 1024 bytes into the filesystem.
+    pub struct RawSuperblock {
+        pub s_inodes_count: u32,
+        pub s_blocks_count_lo: u32,
+        pub s_r_blocks_count_lo: u32,
+        pub s_free_blocks_count_lo: u32,
+        pub s_free_inodes_count: u32,
+        pub s_first_data_block: u32,
+        pub s_log_block_size: u32,
+        pub s_log_cluster_size: u32,
+        pub s_blocks_per_group: u32,
+        pub s_clusters_per_group: u32,
+        pub s_inodes_per_group: u32,
+        pub s_mtime: u32,
+        pub s_wtime: u32,
+        pub s_mnt_count: u16,
+        pub s_max_mnt_count: u16,
+        /// `0xEF53`.
+        pub s_magic: u16,
+        pub s_state: u16,
+        pub s_errors: u16,
+        pub s_minor_rev_level: u16,
+        pub s_lastcheck: u32,
+        pub s_checkinterval: u32,
+        pub s_creator_os: u32,
+        pub s_rev_level: u32,
+        pub s_def_resuid: u16,
+        pub s_def_resgid: u16,
+        pub s_first_ino: u32,
+        pub s_inode_size: u16,
+        pub s_block_group_nr: u16,
+        pub s_feature_compat: u32,
+        pub s_feature_incompat: u32,
+        pub s_feature_ro_compat: u32,
+        pub s_uuid: [u8; 16],
+        pub s_volume_name: [u8; 16],
+        pub s_last_mounted: [u8; 64],
+        pub s_algorithm_usage_bitmap: u32,
+        pub s_prealloc_blocks: u8,
+        pub s_prealloc_dir_blocks: u8,
+        pub s_reserved_gdt_blocks: u16,
+        pub s_journal_uuid: [u8; 16],
+        pub s_journal_inum: u32,
+        pub s_journal_dev: u32,
+        pub s_last_orphan: u32,
+        pub s_hash_seed: [u32; 4],
+        pub s_def_hash_version: u8,
+        pub s_jnl_backup_type: u8,
+        pub s_desc_size: u16,
+        pub s_default_mount_opts: u32,
+        pub s_first_meta_bg: u32,
+        pub s_mkfs_time: u32,
+        pub s_jnl_blocks: [u32; 17],
+        pub s_blocks_count_hi: u32,
+        pub s_r_blocks_count_hi: u32,
+        pub s_free_blocks_count_hi: u32,
+        pub s_min_extra_isize: u16,
+        pub s_want_extra_isize: u16,
+        pub s_flags: u32,
+        pub s_raid_stride: u16,
+        pub s_mmp_update_interval: u16,
+        pub s_mmp_block: u64,
+        pub s_raid_stripe_width: u32,
+        pub s_log_groups_per_flex: u8,
+        pub s_checksum_type: u8,
+        pub s_encryption_level: u8,
+        pub s_reserved_pad: u8,
+        pub s_kbytes_written: u64,
+        pub s_snapshot_inum: u32,
+        pub s_snapshot_id: u32,
+        pub s_snapshot_r_blocks_count: u64,
+        pub s_snapshot_list: u32,
+        pub s_error_count: u32,
+        pub s_first_error_time: u32,
+        pub s_first_error_ino: u32,
+        pub s_first_error_block: u64,
+        pub s_first_error_func: [u8; 32],
+        pub s_first_error_line: u32,
+        pub s_last_error_time: u32,
+        pub s_last_error_ino: u32,
+        pub s_last_error_line: u32,
+        pub s_last_error_block: u64,
+        pub s_last_error_func: [u8; 32],
+        pub s_mount_opts: [u8; 64],
+        pub s_usr_quota_inum: u32,
+        pub s_grp_quota_inum: u32,
+        pub s_overhead_clusters: u32,
+        pub s_backup_bgs: [u32; 2],
+        pub s_encrypt_algos: [u8; 4],
+        pub s_encrypt_pw_salt: [u8; 16],
+        pub s_lpf_ino: u32,
+        pub s_prj_quota_inum: u32,
+        pub s_checksum_seed: u32,
+        pub s_wtime_hi: u8,
+        pub s_mtime_hi: u8,
+        pub s_mkfs_time_hi: u8,
+        pub s_lastcheck_hi: u8,
+        pub s_first_error_time_hi: u8,
+        pub s_last_error_time_hi: u8,
+        pub s_first_error_errcode: u8,
+        pub s_last_error_errcode: u8,
+        pub s_encoding: u16,
+        pub s_encoding_flags: u16,
+        pub s_orphan_file_inum: u32,
+        pub s_reserved: [u32; 94],
+        /// crc32c of the preceding bytes, if `metadata_csum` is enabled.
+        pub s_checksum: u32,
+    }
+}
+
+impl RawSuperblock {
+    /// The superblock's location, from the start of the filesystem.
+    pub const OFFSET: u64 = 1024;
+
+    /// Parse the first `SIZE` bytes of `data`. The magic number isn't checked.
+    pub fn from_slice(data: &[u8]) -> Result<RawSuperblock, Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "superblock needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&data[..Self::SIZE]))
+    }
+
+    /// Overwrite the first `SIZE` bytes of `data`. The checksum isn't updated.
+    pub fn write_into(&self, data: &mut [u8]) -> Result<(), Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "superblock needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        data[..Self::SIZE].copy_from_slice(&self.to_bytes());
+        Ok(())
+    }
+}
+
+on_disk! {
+    /// `struct ext4_group_desc`. On filesystems without the `64bit` feature, descriptors
+    /// are only 32 bytes, and the `_hi` fields don't exist.
+    pub struct RawBlockGroup {
+        pub bg_block_bitmap_lo: u32,
+        pub bg_inode_bitmap_lo: u32,
+        pub bg_inode_table_lo: u32,
+        pub bg_free_blocks_count_lo: u16,
+        pub bg_free_inodes_count_lo: u16,
+        pub bg_used_dirs_count_lo: u16,
+        pub bg_flags: u16,
+        pub bg_exclude_bitmap_lo: u32,
+        pub bg_block_bitmap_csum_lo: u16,
+        pub bg_inode_bitmap_csum_lo: u16,
+        pub bg_itable_unused_lo: u16,
+        pub bg_checksum: u16,
+        pub bg_block_bitmap_hi: u32,
+        pub bg_inode_bitmap_hi: u32,
+        pub bg_inode_table_hi: u32,
+        pub bg_free_blocks_count_hi: u16,
+        pub bg_free_inodes_count_hi: u16,
+        pub bg_used_dirs_count_hi: u16,
+        pub bg_itable_unused_hi: u16,
+        pub bg_exclude_bitmap_hi: u32,
+        pub bg_block_bitmap_csum_hi: u16,
+        pub bg_inode_bitmap_csum_hi: u16,
+        pub bg_reserved: u32,
+    }
+}
+
+impl RawBlockGroup {
+    /// The size of a descriptor without the `64bit` feature.
+    pub const SMALL_SIZE: usize = 32;
+
+    /// Parse a descriptor of `s_desc_size` bytes (or 32, without `64bit`).
+    /// Fields beyond the end of `data` are zero.
+    pub fn from_slice(data: &[u8]) -> Result<RawBlockGroup, Error> {
+        ensure!(
+            data.len() >= Self::SMALL_SIZE,
+            assumption_failed(format!(
+                "group descriptor needs {} bytes, not {}",
+                Self::SMALL_SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&padded(data, Self::SIZE)))
+    }
+
+    /// Overwrite a descriptor of `s_desc_size` bytes (or 32, without `64bit`);
+    /// fields which don't fit are dropped. The checksum isn't updated.
+    pub fn write_into(&self, data: &mut [u8]) -> Result<(), Error> {
+        ensure!(
+            data.len() >= Self::SMALL_SIZE,
+            assumption_failed(format!(
+                "group descriptor needs {} bytes, not {}",
+                Self::SMALL_SIZE,
+                data.len()
+            ))
+        );
+        write_truncated(&self.to_bytes(), data);
+        Ok(())
+    }
+}
+
+on_disk! {
+    /// `struct ext4_inode`, with the Linux variants of the OS-dependent fields.
+    /// Only the first 128 bytes are always present: the rest, starting at
+    /// `i_checksum_hi`, exist if `i_extra_isize` covers them.
+    pub struct RawInode {
+        pub i_mode: u16,
+        pub i_uid: u16,
+        pub i_size_lo: u32,
+        pub i_atime: u32,
+        pub i_ctime: u32,
+        pub i_mtime: u32,
+        pub i_dtime: u32,
+        pub i_gid: u16,
+        pub i_links_count: u16,
+        pub i_blocks_lo: u32,
+        pub i_flags: u32,
+        pub l_i_version: u32,
+        /// The extent tree, block map, inline data, or fast symlink target.
+        pub i_block: [u8; 60],
+        pub i_generation: u32,
+        pub i_file_acl_lo: u32,
+        pub i_size_high: u32,
+        pub i_obso_faddr: u32,
+        pub l_i_blocks_high: u16,
+        pub l_i_file_acl_high: u16,
+        pub l_i_uid_high: u16,
+        pub l_i_gid_high: u16,
+        pub l_i_checksum_lo: u16,
+        pub l_i_reserved: u16,
+        pub i_extra_isize: u16,
+        pub i_checksum_hi: u16,
+        pub i_ctime_extra: u32,
+        pub i_mtime_extra: u32,
+        pub i_atime_extra: u32,
+        pub i_crtime: u32,
+        pub i_crtime_extra: u32,
+        pub i_version_hi: u32,
+        pub i_projid: u32,
+    }
+}
+
+impl RawInode {
+    /// The size of an inode on a filesystem with `s_inode_size` of 128.
+    pub const SMALL_SIZE: usize = 128;
+
+    /// Parse an inode of `s_inode_size` bytes. Fields past the end of `data` are zero;
+    /// fields past `128 + i_extra_isize` are parsed anyway, and are probably junk.
+    pub fn from_slice(data: &[u8]) -> Result<RawInode, Error> {
+        ensure!(
+            data.len() >= Self::SMALL_SIZE,
+            assumption_failed(format!(
+                "inode needs {} bytes, not {}",
+                Self::SMALL_SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&padded(data, Self::SIZE)))
+    }
+
+    /// Overwrite an inode of `s_inode_size` bytes; fields which don't fit are dropped,
+    /// and anything after `SIZE`, such as in-inode xattrs, is left alone.
+    /// The checksum isn't updated.
+    pub fn write_into(&self, data: &mut [u8]) -> Result<(), Error> {
+        ensure!(
+            data.len() >= Self::SMALL_SIZE,
+            assumption_failed(format!(
+                "inode needs {} bytes, not {}",
+                Self::SMALL_SIZE,
+                data.len()
+            ))
+        );
+        write_truncated(&self.to_bytes(), data);
+        Ok(())
+    }
+}
+
+/// `struct ext4_dir_entry_2`: a single record in a linear directory block.
+/// The record is `rec_len` long on disk; the unused space after the name may
+/// contain the remains of deleted entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawDirEntry {
+    /// Zero for an unused record.
+    pub inode: u32,
+    pub rec_len: u16,
+    pub name_len: u8,
+    /// Only meaningful with the `filetype` feature.
+    pub file_type: u8,
+    /// Exactly `name_len` bytes.
+    pub name: Vec<u8>,
+}
+
+impl RawDirEntry {
+    /// The size of the fields before the name.
+    pub const HEADER_SIZE: usize = 8;
+
+    /// Parse the record at the start of `data`, which must contain all of the name,
+    /// but needn't be `rec_len` long.
+    pub fn from_slice(data: &[u8]) -> Result<RawDirEntry, Error> {
+        ensure!(
+            data.len() >= Self::HEADER_SIZE,
+            assumption_failed(format!(
+                "directory entry needs {} bytes, not {}",
+                Self::HEADER_SIZE,
+                data.len()
+            ))
+        );
+
+        let name_len = data[6];
+        let end = Self::HEADER_SIZE + usize::from(name_len);
+        ensure!(
+            data.len() >= end,
+            assumption_failed(format!(
+                "directory entry name runs off the end: {} > {}",
+                end,
+                data.len()
+            ))
+        );
+
+        Ok(RawDirEntry {
+            inode: u32::read(data),
+            rec_len: u16::read(&data[4..]),
+            name_len,
+            file_type: data[7],
+            name: data[Self::HEADER_SIZE..end].to_vec(),
+        })
+    }
+
+    /// The record as it would be on disk: `rec_len` long, or just long enough for the
+    /// name if `rec_len` is shorter than that. Any space after the name is zero.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let end = Self::HEADER_SIZE + self.name.len();
+        let mut data = vec![0u8; end.max(usize::from(self.rec_len))];
+        self.inode.write(&mut data);
+        self.rec_len.write(&mut data[4..]);
+        data[6] = self.name_len;
+        data[7] = self.file_type;
+        data[Self::HEADER_SIZE..end].copy_from_slice(&self.name);
+        data
+    }
+
+    /// How much of a block of `block_size` bytes the record claims: `rec_len`, decoded.
+    pub fn record_len(&self, block_size: usize) -> usize {
+        rec_len_from_disk(self.rec_len, block_size)
+    }
+
+    /// Is this the fake entry which holds the block's checksum, a `RawDirEntryTail`?
+    pub fn is_tail(&self) -> bool {
+        0 == self.inode
+            && RawDirEntryTail::SIZE == usize::from(self.rec_len)
+            && 0 == self.name_len
+            && RawDirEntryTail::FILE_TYPE == self.file_type
+    }
+}
+
+/// A directory record's length, from its `rec_len`, in a block of `block_size` bytes. In
+/// 64KiB blocks, a record can be too long for 16 bits: a whole block is stored as `0`, or
+/// `0xFFFF`, and otherwise the top bits are in the bottom two, which are always clear.
+pub fn rec_len_from_disk(rec_len: u16, block_size: usize) -> usize {
+    let len = usize::from(rec_len);
+    if block_size < 65536 {
+        len
+    } else if 0 == len || 0xFFFF == len {
+        block_size
+    } else {
+        (len & 0xFFFC) | ((len & 3) << 16)
+    }
+}
+
+on_disk! {
+    /// `struct ext4_dir_entry_tail`: the last record in each linear directory block,
+    /// with `metadata_csum`. It looks like an unused `RawDirEntry` to older readers.
+    pub struct RawDirEntryTail {
+        pub det_reserved_zero1: u32,
+        /// `12`.
+        pub det_rec_len: u16,
+        pub det_reserved_zero2: u8,
+        /// `0xDE`.
+        pub det_reserved_ft: u8,
+        /// crc32c of the rest of the block, seeded with the inode's checksum prefix.
+        pub det_checksum: u32,
+    }
+}
+
+impl RawDirEntryTail {
+    /// The `file_type` which marks a tail.
+    pub const FILE_TYPE: u8 = 0xDE;
+
+    pub fn from_slice(data: &[u8]) -> Result<RawDirEntryTail, Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "directory tail needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&data[..Self::SIZE]))
+    }
+}
+
+on_disk! {
+    /// `struct ext4_extent_header`, at the start of `i_block` and of each extent
+    /// tree block. Followed by `eh_entries` of `RawExtent` if `eh_depth` is zero,
+    /// or of `RawExtentIdx` otherwise.
+    pub struct RawExtentHeader {
+        /// `0xF30A`.
+        pub eh_magic: u16,
+        pub eh_entries: u16,
+        /// How many entries there's room for.
+        pub eh_max: u16,
+        /// Zero for a leaf.
+        pub eh_depth: u16,
+        pub eh_generation: u32,
+    }
+}
+
+impl RawExtentHeader {
+    pub const MAGIC: u16 = 0xF30A;
+
+    /// Parse the header at the start of `data`. The magic number isn't checked.
+    pub fn from_slice(data: &[u8]) -> Result<RawExtentHeader, Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "extent header needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&data[..Self::SIZE]))
+    }
+}
+
+on_disk! {
+    /// `struct ext4_extent`: a leaf of the extent tree, mapping a run of the file's
+    /// blocks to a run of the disk's.
+    pub struct RawExtent {
+        /// The first logical block covered.
+        pub ee_block: u32,
+        /// Over 32768 for an unwritten extent, which covers `ee_len - 32768` blocks.
+        pub ee_len: u16,
+        pub ee_start_hi: u16,
+        pub ee_start_lo: u32,
+    }
+}
+
+impl RawExtent {
+    pub fn from_slice(data: &[u8]) -> Result<RawExtent, Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "extent needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&data[..Self::SIZE]))
+    }
+
+    /// The physical block the extent starts at.
+    pub fn start(&self) -> u64 {
+        u64::from(self.ee_start_lo) | (u64::from(self.ee_start_hi) << 32)
+    }
+}
+
+on_disk! {
+    /// `struct ext4_extent_idx`: an interior node of the extent tree, pointing at
+    /// the block holding the next level down.
+    pub struct RawExtentIdx {
+        /// The first logical block covered by the node.
+        pub ei_block: u32,
+        pub ei_leaf_lo: u32,
+        pub ei_leaf_hi: u16,
+        pub ei_unused: u16,
+    }
+}
+
+impl RawExtentIdx {
+    pub fn from_slice(data: &[u8]) -> Result<RawExtentIdx, Error> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            assumption_failed(format!(
+                "extent index needs {} bytes, not {}",
+                Self::SIZE,
+                data.len()
+            ))
+        );
+        Ok(Self::read_fields(&data[..Self::SIZE]))
+    }
+
+    /// The physical block holding the next level of the tree.
+    pub fn leaf(&self) -> u64 {
+        u64::from(self.ei_leaf_lo) | (u64::from(self.ei_leaf_hi) << 32)
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::oci::OPAQUE_XATTRS;
+use crate::oci::WHITEOUT_DEVICE;
+use crate::Enhanced;
+use crate::FileType;
+use crate::Inode;
+use crate::ParseError;
+use crate::SuperBlock;
+
+/// The prefix of an OCI layer's whiteout files; `.wh..wh..opq` makes its directory opaque.
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// Filesystems, and directories of loose files, layered over each other, as overlayfs
+/// would mount them, so the result of applying an update can be looked at without
+/// applying it. Nothing is ever written.
+///
+/// A path is looked for in the top layer first, then in each layer below. A directory
+/// in more than one layer is merged: listing it lists what's in any of them. An upper
+/// layer can hide what's below with a whiteout: overlayfs' `0:0` character device, or
+/// an OCI layer's empty `.wh.name` file; and can hide everything below a directory by
+/// making it opaque, with overlayfs' `trusted.overlay.opaque` (or `user.`) xattr of `y`,
+/// or an OCI layer's `.wh..wh..opq` file. A file in an upper layer hides a directory of
+/// the same name below. Symbolic links are never followed.
+pub struct Overlay<'a, R> {
+    /// The base first.
+    layers: Vec<Layer<'a, R>>,
+}
+
+enum Layer<'a, R> {
+    Image(&'a SuperBlock<R>),
+    Dir(PathBuf),
+}
+
+/// Something found in an `Overlay`: an inode, from a layer which is a filesystem, or a
+/// loose file, from a layer which is a directory.
+pub enum OverlayEntry<'a, R> {
+    Image {
+        /// Counting up from the base, which is zero.
+        layer: usize,
+        fs: &'a SuperBlock<R>,
+        inode: Inode,
+    },
+    Host {
+        layer: usize,
+        path: PathBuf,
+        metadata: fs::Metadata,
+    },
+}
+
+/// What a single layer has at a path.
+enum Hit<'a, R> {
+    Entry(OverlayEntry<'a, R>),
+    /// Nothing, so look in the layers below.
+    Absent,
+    /// Nothing, and the layers below mustn't be looked in: it's whited out, or below a
+    /// file, or an opaque directory.
+    Hidden,
+}
+
+impl<'a, R> Overlay<'a, R>
+where
+    R: ReadAt,
+{
+    pub fn new(base: &'a SuperBlock<R>) -> Overlay<'a, R> {
+        Overlay {
+            layers: vec![Layer::Image(base)],
+        }
+    }
+
+    /// Add a filesystem on top.
+    pub fn with_image(mut self, upper: &'a SuperBlock<R>) -> Overlay<'a, R> {
+        self.layers.push(Layer::Image(upper));
+        self
+    }
+
+    /// Add a directory of loose files on top, like overlayfs' `upperdir`.
+    pub fn with_dir<P: Into<PathBuf>>(mut self, upper: P) -> Overlay<'a, R> {
+        self.layers.push(Layer::Dir(upper.into()));
+        self
+    }
+
+    /// What is at `path`, from the highest layer with anything there; `None` if nothing
+    /// is, or it's been hidden. `..` is dropped with the name before it.
+    pub fn lookup(&self, path: &str) -> Result<Option<OverlayEntry<'a, R>>, Error> {
+        let components = components(path);
+        for (number, layer) in self.layers.iter().enumerate().rev() {
+            match layer.hit(number, &components)? {
+                Hit::Entry(entry) => return Ok(Some(entry)),
+                Hit::Hidden => return Ok(None),
+                Hit::Absent => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    /// The names in a directory, merged from every layer that has it, which aren't
+    /// hidden, with what each is, from the highest layer it's in, in name order.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<(String, OverlayEntry<'a, R>)>, Error> {
+        let components = components(path);
+        match self.lookup(path)? {
+            Some(entry) if FileType::Directory == entry.file_type() => (),
+            Some(_) => return Err(anyhow!("{} isn't a directory", path)),
+            None => return Err(anyhow!("{} isn't in the overlay", path)),
+        }
+
+        // `None` for the names whited out above
+        let mut found = BTreeMap::new();
+        for (number, layer) in self.layers.iter().enumerate().rev() {
+            let dir = match layer.hit(number, &components)? {
+                Hit::Entry(dir) => dir,
+                Hit::Absent => continue,
+                Hit::Hidden => break,
+            };
+            // a file above hides a directory below
+            if FileType::Directory != dir.file_type() {
+                break;
+            }
+
+            for name in layer.names(&dir)? {
+                if OPAQUE_MARKER == name {
+                    continue;
+                }
+                if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+                    found.entry(hidden.to_string()).or_insert(None);
+                    continue;
+                }
+                if found.contains_key(&name) {
+                    continue;
+                }
+                let mut child = components.clone();
+                child.push(name.as_str());
+                let entry = layer.get(number, &child)?;
+                found.insert(name, entry.filter(|entry| !entry.is_whiteout()));
+            }
+
+            if layer.opaque(number, &components, &dir)? {
+                break;
+            }
+        }
+
+        Ok(found
+            .into_iter()
+            .filter_map(|(name, entry)| Some((name, entry?)))
+            .collect())
+    }
+}
+
+impl<'a, R> Layer<'a, R>
+where
+    R: ReadAt,
+{
+    /// Look for a path in just this layer, a component at a time, so a whiteout, file,
+    /// or opaque directory on the way can hide it.
+    fn hit(&self, number: usize, components: &[&str]) -> Result<Hit<'a, R>, Error> {
+        let mut opaque = false;
+        let mut entry = self
+            .get(number, &[])?
+            .ok_or_else(|| anyhow!("layer {} has no root", number))?;
+        for i in 0..components.len() {
+            if FileType::Directory != entry.file_type() {
+                return Ok(Hit::Hidden);
+            }
+            opaque |= self.opaque(number, &components[..i], &entry)?;
+
+            let (parent, name) = (&components[..i], components[i]);
+            let marker = format!("{}{}", WHITEOUT_PREFIX, name);
+            let mut marker_path = parent.to_vec();
+            marker_path.push(&marker);
+            if self.get(number, &marker_path)?.is_some() {
+                return Ok(Hit::Hidden);
+            }
+
+            entry = match self.get(number, &components[..=i])? {
+                Some(entry) => entry,
+                None if opaque => return Ok(Hit::Hidden),
+                None => return Ok(Hit::Absent),
+            };
+            if entry.is_whiteout() {
+                return Ok(Hit::Hidden);
+            }
+        }
+        Ok(Hit::Entry(entry))
+    }
+
+    /// What's at exactly this path in this layer, if anything.
+    fn get(
+        &self,
+        number: usize,
+        components: &[&str],
+    ) -> Result<Option<OverlayEntry<'a, R>>, Error> {
+        match *self {
+            Layer::Image(fs) => {
+                let path = format!("/{}", components.join("/"));
+                let entry = match fs.resolve_path(&path) {
+                    Ok(entry) => entry,
+                    Err(e) => match e.downcast_ref::<ParseError>() {
+                        Some(ParseError::NotFound { .. }) => return Ok(None),
+                        _ => return Err(e),
+                    },
+                };
+                Ok(Some(OverlayEntry::Image {
+                    layer: number,
+                    fs,
+                    inode: fs.load_inode(entry.inode)?,
+                }))
+            }
+            Layer::Dir(ref root) => {
+                // a name at a time, so a symlink on the way isn't followed out of the layer
+                let mut path = root.clone();
+                for (i, name) in components.iter().enumerate() {
+                    if i > 0 {
+                        match fs::symlink_metadata(&path) {
+                            Ok(metadata) if metadata.file_type().is_dir() => (),
+                            Ok(_) => return Ok(None),
+                            Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(None),
+                            Err(e) => {
+                                return Err(Error::new(e).context(anyhow!("reading {:?}", path)))
+                            }
+                        }
+                    }
+                    path.push(name);
+                }
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) => Ok(Some(OverlayEntry::Host {
+                        layer: number,
+                        path,
+                        metadata,
+                    })),
+                    Err(e) if io::ErrorKind::NotFound == e.kind() => Ok(None),
+                    Err(e) => Err(Error::new(e).context(anyhow!("reading {:?}", path))),
+                }
+            }
+        }
+    }
+
+    /// Whether a directory in this layer hides what's below it, by its xattr, or by
+    /// having the OCI marker file in it. A loose directory's xattrs aren't looked at.
+    fn opaque(
+        &self,
+        number: usize,
+        components: &[&str],
+        dir: &OverlayEntry<'a, R>,
+    ) -> Result<bool, Error> {
+        if let OverlayEntry::Image { inode, .. } = dir {
+            let marked = OPAQUE_XATTRS
+                .iter()
+                .any(|name| inode.stat.xattrs.get(*name).map(|v| &v[..]) == Some(&b"y"[..]));
+            if marked {
+                return Ok(true);
+            }
+        }
+        let mut marker = components.to_vec();
+        marker.push(OPAQUE_MARKER);
+        Ok(self.get(number, &marker)?.is_some())
+    }
+
+    /// The names in a directory in this layer, without `.` and `..`.
+    fn names(&self, dir: &OverlayEntry<'a, R>) -> Result<Vec<String>, Error> {
+        match dir {
+            OverlayEntry::Image { fs, inode, .. } => match fs.enhance(inode)? {
+                Enhanced::Directory(entries) => Ok(entries
+                    .into_iter()
+                    .map(|entry| entry.name)
+                    .filter(|name| "." != name.as_str() && ".." != name.as_str())
+                    .collect()),
+                _ => Ok(Vec::new()),
+            },
+            // `read_dir` would follow a symlink
+            OverlayEntry::Host { metadata, .. } if !metadata.file_type().is_dir() => Ok(Vec::new()),
+            OverlayEntry::Host { path, .. } => {
+                let mut names = Vec::new();
+                for entry in fs::read_dir(path).with_context(|| anyhow!("listing {:?}", path))? {
+                    let name = entry?.file_name();
+                    names.push(
+                        name.into_string()
+                            .map_err(|name| anyhow!("{:?} in {:?} isn't UTF-8", name, path))?,
+                    );
+                }
+                Ok(names)
+            }
+        }
+    }
+}
+
+impl<'a, R> OverlayEntry<'a, R>
+where
+    R: ReadAt,
+{
+    /// Which layer it's from, counting up from the base, which is zero.
+    pub fn layer(&self) -> usize {
+        match self {
+            OverlayEntry::Image { layer, .. } | OverlayEntry::Host { layer, .. } => *layer,
+        }
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self {
+            OverlayEntry::Image { inode, .. } => inode.stat.extracted_type,
+            OverlayEntry::Host { metadata, .. } => host_type(metadata),
+        }
+    }
+
+    /// Read a regular file's content.
+    pub fn open(&self) -> Result<Box<dyn io::Read + 'a>, Error> {
+        match self {
+            OverlayEntry::Image { fs, inode, .. } => Ok(Box::new(fs.open(inode)?)),
+            OverlayEntry::Host { path, .. } => Ok(Box::new(
+                fs::File::open(path).with_context(|| anyhow!("opening {:?}", path))?,
+            )),
+        }
+    }
+
+    /// overlayfs' whiteout: a character device, numbered `0:0`.
+    fn is_whiteout(&self) -> bool {
+        match self {
+            OverlayEntry::Image { inode, .. } => {
+                FileType::CharacterDevice == inode.stat.extracted_type
+                    && inode.stat.device.map_or(false, |device| {
+                        WHITEOUT_DEVICE == (device.major, device.minor)
+                    })
+            }
+            OverlayEntry::Host { metadata, .. } => {
+                FileType::CharacterDevice == host_type(metadata) && 0 == host_rdev(metadata)
+            }
+        }
+    }
+}
+
+/// Split a path into names, dropping empty ones, and `.`; `..` drops the name before it.
+fn components(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    components
+}
+
+#[cfg(unix)]
+fn host_type(metadata: &fs::Metadata) -> FileType {
+    use std::os::unix::fs::FileTypeExt;
+    let kind = metadata.file_type();
+    if kind.is_dir() {
+        FileType::Directory
+    } else if kind.is_symlink() {
+        FileType::SymbolicLink
+    } else if kind.is_char_device() {
+        FileType::CharacterDevice
+    } else if kind.is_block_device() {
+        FileType::BlockDevice
+    } else if kind.is_fifo() {
+        FileType::Fifo
+    } else if kind.is_socket() {
+        FileType::Socket
+    } else {
+        FileType::RegularFile
+    }
+}
+
+#[cfg(not(unix))]
+fn host_type(metadata: &fs::Metadata) -> FileType {
+    let kind = metadata.file_type();
+    if kind.is_dir() {
+        FileType::Directory
+    } else if kind.is_symlink() {
+        FileType::SymbolicLink
+    } else {
+        FileType::RegularFile
+    }
+}
+
+#[cfg(unix)]
+fn host_rdev(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.rdev()
+}
+
+#[cfg(not(unix))]
+fn host_rdev(_: &fs::Metadata) -> u64 {
+    0
+}
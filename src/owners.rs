@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::SuperBlock;
+
+/// The image's own user and group names, read from its `/etc/passwd` and `/etc/group`
+/// the first time they're wanted.
+#[derive(Debug, Default)]
+pub(crate) struct OwnerNames {
+    users: Mutex<Option<HashMap<u32, String>>>,
+    groups: Mutex<Option<HashMap<u32, String>>>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// The name of a user, according to the image's `/etc/passwd`, not the host's.
+    /// `None` if there's no such user, or no `/etc/passwd`.
+    pub fn name_for_uid(&self, uid: u32) -> Result<Option<String>, Error> {
+        let mut users = self.owner_names.users.lock().expect("poisoned");
+        if users.is_none() {
+            let mut names = HashMap::new();
+            for user in self.passwd()?.unwrap_or_default() {
+                // like getpwuid, the first line for an id wins
+                names.entry(user.uid).or_insert(user.name);
+            }
+            *users = Some(names);
+        }
+        Ok(users.as_ref().expect("just loaded").get(&uid).cloned())
+    }
+
+    /// The name of a group, according to the image's `/etc/group`, not the host's.
+    /// `None` if there's no such group, or no `/etc/group`.
+    pub fn name_for_gid(&self, gid: u32) -> Result<Option<String>, Error> {
+        let mut groups = self.owner_names.groups.lock().expect("poisoned");
+        if groups.is_none() {
+            let mut names = HashMap::new();
+            for group in self.group()?.unwrap_or_default() {
+                names.entry(group.gid).or_insert(group.name);
+            }
+            *groups = Some(names);
+        }
+        Ok(groups.as_ref().expect("just loaded").get(&gid).cloned())
+    }
+
+    /// The user's name, or, like `ls`, the number if it hasn't got one.
+    pub fn display_uid(&self, uid: u32) -> Result<String, Error> {
+        Ok(self.name_for_uid(uid)?.unwrap_or_else(|| uid.to_string()))
+    }
+
+    /// The group's name, or, like `ls`, the number if it hasn't got one.
+    pub fn display_gid(&self, gid: u32) -> Result<String, Error> {
+        Ok(self.name_for_gid(gid)?.unwrap_or_else(|| gid.to_string()))
+    }
+}
@@ -11,14 +11,16 @@ use anyhow::Context;
 use anyhow::Error;
 use bitflags::bitflags;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use positioned_io2::{Cursor, ReadAt};
+use positioned_io2::ReadAt;
 
+use crate::info::text_after_nul;
 use crate::not_found;
 use crate::parse_error;
 use crate::read_le16;
 use crate::read_le32;
 use crate::unsupported_feature;
 use crate::Time;
+use crate::Warning;
 use crate::{assumption_failed, read_lei32};
 
 const EXT4_SUPER_MAGIC: u16 = 0xEF53;
@@ -34,11 +36,14 @@ bitflags! {
         const RESIZE_INODE  = 0x0010;
         const DIR_INDEX     = 0x0020;
         const SPARSE_SUPER2 = 0x0200;
+        const FAST_COMMIT   = 0x0400;
+        const STABLE_INODES = 0x0800;
+        const ORPHAN_FILE   = 0x1000;
     }
 }
 
 bitflags! {
-    struct CompatibleFeatureReadOnly: u32 {
+    pub(crate) struct CompatibleFeatureReadOnly: u32 {
         const SPARSE_SUPER  = 0x0001;
         const LARGE_FILE    = 0x0002;
         const BTREE_DIR     = 0x0004;
@@ -51,12 +56,15 @@ bitflags! {
         const METADATA_CSUM = 0x0400;
         const READONLY      = 0x1000;
         const PROJECT       = 0x2000;
-
+        /// Blocks may belong to more than one file, as deduplication.
+        const SHARED_BLOCKS = 0x4000;
+        const VERITY        = 0x8000;
+        const ORPHAN_PRESENT = 0x10000;
     }
 }
 
 bitflags! {
-    struct IncompatibleFeature: u32 {
+    pub(crate) struct IncompatibleFeature: u32 {
        const COMPRESSION    = 0x0001;
        const FILETYPE       = 0x0002;
        const RECOVER        = 0x0004; /* Needs recovery */
@@ -75,12 +83,13 @@ bitflags! {
     }
 }
 
-pub fn superblock<R>(mut reader: R, options: &crate::Options) -> Result<crate::SuperBlock<R>, Error>
+pub fn superblock<R>(reader: R, options: &crate::Options) -> Result<crate::SuperBlock<R>, Error>
 where
     R: ReadAt,
 {
     let mut entire_superblock = [0u8; 1024];
     reader.read_exact_at(1024, &mut entire_superblock)?;
+    let raw = crate::ondisk::RawSuperblock::from_slice(&entire_superblock)?;
 
     let mut inner = io::Cursor::new(&mut entire_superblock[..]);
 
@@ -112,10 +121,13 @@ where
     inner.read_u16::<LittleEndian>()?; /* Maximal mount count */
     let s_magic = inner.read_u16::<LittleEndian>()?; /* Magic signature */
 
-    ensure!(
-        EXT4_SUPER_MAGIC == s_magic,
-        not_found(format!("invalid magic number: {:x}", s_magic))
-    );
+    if EXT4_SUPER_MAGIC != s_magic {
+        return Err(not_found(match identify_other(&reader) {
+            Some(other) => format!("invalid magic number: {:x}; {}", s_magic, other),
+            None => format!("invalid magic number: {:x}", s_magic),
+        })
+        .into());
+    }
 
     let s_state = inner.read_u16::<LittleEndian>()?; /* File system state */
     //    let s_errors =
@@ -129,10 +141,10 @@ where
     let s_creator_os = inner.read_u32::<LittleEndian>()?; /* OS */
 
     ensure!(
-        0 == s_creator_os,
+        0 == s_creator_os || crate::Creators::Any == options.creators,
         unsupported_feature(format!(
-            "only support filesystems created on linux, not '{}'",
-            s_creator_os
+            "only support filesystems created on linux, not {:?}",
+            crate::CreatorOs::from_raw(s_creator_os)
         ))
     );
 
@@ -141,15 +153,28 @@ where
     inner.read_u16::<LittleEndian>()?; /* Default uid for reserved blocks */
     //    let s_def_resgid =
     inner.read_u16::<LittleEndian>()?; /* Default gid for reserved blocks */
-    //    let s_first_ino =
-    inner.read_u32::<LittleEndian>()?; /* First non-reserved inode */
+    let s_first_ino = inner.read_u32::<LittleEndian>()?; /* First non-reserved inode */
     let s_inode_size = inner.read_u16::<LittleEndian>()?; /* size of inode structure */
+
+    // EXT2_GOOD_OLD_REV predates these fields, and the features after them: the first
+    // eleven inodes are reserved, and inodes are 128 bytes
+    let (s_first_ino, s_inode_size) = match s_rev_level {
+        0 => (11, 128),
+        1 => (s_first_ino, s_inode_size),
+        _ => return Err(unsupported_feature(format!("rev level {}", s_rev_level)).into()),
+    };
     //    let s_block_group_nr =
     inner.read_u16::<LittleEndian>()?; /* block group # of this superblock */
     let s_feature_compat = inner.read_u32::<LittleEndian>()?; /* compatible feature set */
 
     let compatible_features = CompatibleFeature::from_bits_truncate(s_feature_compat);
 
+    let warnings = crate::warnings::Warnings::default();
+    let unknown = s_feature_compat & !CompatibleFeature::all().bits();
+    if 0 != unknown {
+        warnings.push(Warning::UnknownCompatibleFeatures(unknown));
+    }
+
     let load_xattrs = compatible_features.contains(CompatibleFeature::EXT_ATTR);
 
     let s_feature_incompat = inner.read_u32::<LittleEndian>()?; /* incompatible feature set */
@@ -166,7 +191,9 @@ where
         | IncompatibleFeature::EXTENTS
         | IncompatibleFeature::FLEX_BG
         | IncompatibleFeature::RECOVER
-        | IncompatibleFeature::SIXTY_FOUR_BIT;
+        | IncompatibleFeature::SIXTY_FOUR_BIT
+        // only the encrypted inodes are unreadable; see `EncryptedPolicy`
+        | IncompatibleFeature::ENCRYPT;
 
     if incompatible_features.intersects(!supported_incompatible_features) {
         return Err(parse_error(format!(
@@ -181,6 +208,10 @@ where
 
     let compatible_features_read_only =
         CompatibleFeatureReadOnly::from_bits_truncate(s_feature_ro_compat);
+    let unknown = s_feature_ro_compat & !CompatibleFeatureReadOnly::all().bits();
+    if 0 != unknown {
+        warnings.push(Warning::UnknownReadOnlyFeatures(unknown));
+    }
 
     let has_checksums =
         compatible_features_read_only.contains(CompatibleFeatureReadOnly::METADATA_CSUM);
@@ -212,12 +243,10 @@ where
     inner.read_u16::<LittleEndian>()?; /* Per group desc for online growth */
     let mut s_journal_uuid = [0u8; 16];
     inner.read_exact(&mut s_journal_uuid)?; /* uuid of journal superblock */
-    //    let s_journal_inum =
-    inner.read_u32::<LittleEndian>()?; /* inode number of journal file */
+    let s_journal_inum = inner.read_u32::<LittleEndian>()?; /* inode number of journal file */
     //    let s_journal_dev =
     inner.read_u32::<LittleEndian>()?; /* device number of journal file */
-    //    let s_last_orphan =
-    inner.read_u32::<LittleEndian>()?; /* start of list of inodes to delete */
+    let s_last_orphan = inner.read_u32::<LittleEndian>()?; /* start of list of inodes to delete */
     let mut s_hash_seed = [0u8; 4 * 4];
     inner.read_exact(&mut s_hash_seed)?; /* HTREE hash seed */
     //    let s_def_hash_version =
@@ -291,11 +320,12 @@ where
         return Err(parse_error("inodes per group cannot be zero".to_string()));
     }
 
+    if 0 == s_blocks_per_group {
+        return Err(parse_error("blocks per group cannot be zero".to_string()));
+    }
+
     let block_size: u32 = match s_log_block_size {
-        0 => 1024,
-        1 => 2048,
-        2 => 4096,
-        6 => 65536,
+        0..=6 => 1024 << s_log_block_size,
         _ => {
             return Err(parse_error(format!(
                 "unexpected block size: 2^{}",
@@ -304,6 +334,20 @@ where
         }
     };
 
+    ensure!(
+        u64::from(s_inodes_per_group) <= 8 * u64::from(block_size),
+        assumption_failed(format!(
+            "more inodes per group ({}) than fit in the group's bitmap",
+            s_inodes_per_group
+        ))
+    );
+
+    options.limits.check(
+        "inode size",
+        u64::from(s_inode_size),
+        u64::from(options.limits.max_inode_size),
+    )?;
+
     if !long_structs {
         ensure!(
             0 == s_desc_size,
@@ -314,11 +358,6 @@ where
         );
     }
 
-    ensure!(
-        1 == s_rev_level,
-        unsupported_feature(format!("rev level {}", s_rev_level))
-    );
-
     let group_table_pos = if 1024 == block_size {
         // for 1k blocks, the table is in the third block, after:
         1024   // boot sector
@@ -329,44 +368,280 @@ where
         block_size
     };
 
-    let mut grouper = Cursor::new(&mut reader);
-    grouper.seek(io::SeekFrom::Start(u64::from(group_table_pos)))?;
-    let blocks_count = (u64::from(s_blocks_count_lo)
-        + (u64::from(s_blocks_count_hi.unwrap_or(0)) << 32)
-        - u64::from(s_first_data_block)
-        + u64::from(s_blocks_per_group)
-        - 1)
-        / u64::from(s_blocks_per_group);
+    let uuid_checksum = if has_checksums {
+        // TODO: check s_checksum_seed
+        Some(ext4_style_crc32c_le(!0, &s_uuid))
+    } else {
+        None
+    };
+
+    let group_checksum = if let Some(uuid_checksum) = uuid_checksum {
+        crate::block_groups::GroupChecksum::Crc32c(uuid_checksum)
+    } else if compatible_features_read_only.contains(CompatibleFeatureReadOnly::GDT_CSUM) {
+        crate::block_groups::GroupChecksum::Crc16(s_uuid)
+    } else {
+        crate::block_groups::GroupChecksum::None
+    };
+
+    let blocks_count =
+        u64::from(s_blocks_count_lo) + (u64::from(s_blocks_count_hi.unwrap_or(0)) << 32);
+
+    ensure!(
+        blocks_count > u64::from(s_first_data_block),
+        assumption_failed(format!(
+            "first data block ({}) is beyond the end of the filesystem ({})",
+            s_first_data_block, blocks_count
+        ))
+    );
+
+    let mut image_len = None;
+    if let Some(found) = options.len {
+        let expected = blocks_count
+            .checked_mul(u64::from(block_size))
+            .ok_or_else(|| assumption_failed(format!("{} blocks is too many", blocks_count)))?;
+        if found < expected {
+            ensure!(
+                crate::Truncation::Tolerate == options.truncation,
+                crate::ParseError::Truncated { expected, found }
+            );
+            image_len = Some(found);
+        }
+    }
 
     let groups = crate::block_groups::BlockGroups::new(
-        &mut grouper,
+        u64::from(group_table_pos),
         blocks_count,
+        s_first_data_block,
+        s_blocks_per_group,
         s_desc_size,
         s_inodes_per_group,
         block_size,
         s_inode_size,
+        group_checksum,
+        &options.limits,
     )?;
 
-    let uuid_checksum = if has_checksums {
-        // TODO: check s_checksum_seed
-        Some(ext4_style_crc32c_le(!0, &s_uuid))
+    if crate::Initialisation::Full == options.initialisation {
+        for group in 0..groups.count() {
+            groups.get(&reader, group)?;
+        }
+    }
+
+    let journal_inode = if compatible_features.contains(CompatibleFeature::HAS_JOURNAL) {
+        Some(s_journal_inum)
     } else {
         None
     };
 
+    let texts = [
+        ("s_volume_name", &raw.s_volume_name[..]),
+        ("s_last_mounted", &raw.s_last_mounted[..]),
+        ("s_mount_opts", &raw.s_mount_opts[..]),
+    ];
+    for (field, text) in texts {
+        if text_after_nul(text) {
+            warnings.push(Warning::TextAfterNul { field });
+        }
+    }
+
     Ok(crate::SuperBlock {
         inner: reader,
         load_xattrs,
+        journal_inode,
+        first_inode: s_first_ino,
+        root_inode: 2,
+        image_len,
+        last_orphan: s_last_orphan,
         uuid_checksum,
         groups,
+        path_cache: crate::path_cache::PathCache::new(options.path_cache),
+        owner_names: crate::owners::OwnerNames::default(),
+        warnings,
+        options: options.clone(),
+        raw,
     })
 }
 
+/// A hint about what a non-ext4 image actually is, for things which often contain ext4.
+fn identify_other<R: ReadAt>(reader: R) -> Option<&'static str> {
+    let mut sectors = [0u8; 4 * 512];
+    reader.read_exact_at(0, &mut sectors).ok()?;
+
+    let lvm = sectors
+        .chunks(512)
+        .any(|sector| b"LABELONE" == &sector[..8] && b"LVM2 001" == &sector[24..32]);
+    if lvm {
+        return Some(
+            "this is an LVM2 physical volume; open one of its logical volumes, with ext4::lvm",
+        );
+    }
+    if b"LUKS\xba\xbe" == &sectors[..6] {
+        return Some("this is a LUKS encrypted volume; decrypt it, with ext4::luks");
+    }
+    None
+}
+
+/// A used entry in a directory block, as `dirents` found it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    pub inode: u32,
+    /// As stored; not necessarily UTF-8, and, for encrypted directories, ciphertext.
+    pub name: Vec<u8>,
+    /// `None` if the filesystem hasn't got the `filetype` feature.
+    pub file_type: Option<crate::FileType>,
+}
+
+/// Parse one block of a (linear, or leaf) directory, without needing its inode.
+///
+/// With `checksum_prefix`, the inode's seed (`Inode::checksum_prefix`), the block must
+/// end with a `RawDirEntryTail` whose checksum matches; without, any tail is skipped.
+/// Index blocks of hashed directories have no tail, so need `None`; their `.` and `..`
+/// are returned, and the rest skipped.
+pub fn dirents(
+    block: &[u8],
+    has_filetype: bool,
+    checksum_prefix: Option<u32>,
+) -> Result<Vec<Dirent>, Error> {
+    let mut entries = Vec::new();
+    let mut read = 0usize;
+    while read < block.len() {
+        let entry = crate::ondisk::RawDirEntry::from_slice(&block[read..])?;
+        let rec_len = entry.record_len(block.len());
+
+        ensure!(
+            rec_len > 8,
+            unsupported_feature(format!(
+                "directory record length is too short, {} must be > 8",
+                rec_len
+            ))
+        );
+
+        // each block has its own tail, which must be its last record; anything like one
+        // elsewhere is just an unused entry
+        if entry.is_tail() && read + crate::ondisk::RawDirEntryTail::SIZE == block.len() {
+            if let Some(checksum_prefix) = checksum_prefix {
+                let expected =
+                    crate::ondisk::RawDirEntryTail::from_slice(&block[read..])?.det_checksum;
+                let computed = ext4_style_crc32c_le(checksum_prefix, &block[0..read]);
+                ensure!(
+                    expected == computed,
+                    assumption_failed(format!(
+                        "directory checksum mismatch: on-disk: {:08x}, computed: {:08x}",
+                        expected, computed
+                    ))
+                );
+            }
+            return Ok(entries);
+        }
+
+        if 0 != entry.inode {
+            let file_type = if has_filetype {
+                Some(
+                    crate::FileType::from_dir_hint(entry.file_type).ok_or_else(|| {
+                        unsupported_feature(format!(
+                            "unexpected file type in directory: {}",
+                            entry.file_type
+                        ))
+                    })?,
+                )
+            } else {
+                None
+            };
+            entries.push(Dirent {
+                inode: entry.inode,
+                name: entry.name,
+                file_type,
+            });
+        }
+
+        read += rec_len;
+    }
+
+    ensure!(
+        read == block.len(),
+        assumption_failed(format!("short read, {} != {}", read, block.len()))
+    );
+    ensure!(
+        checksum_prefix.is_none(),
+        assumption_failed("directory checksums are enabled but checksum record not found")
+    );
+    Ok(entries)
+}
+
+/// Walk an inode's extent tree, starting from its `core` (`i_block`), loading any
+/// further levels with `load_block`, and return the runs of data, in file order.
+///
+/// With `checksum_prefix` (`ParsedInode::checksum_prefix`), the checksums of the
+/// loaded levels are checked. The default `Limits` apply.
+pub fn extent_tree<F>(
+    core: [u8; crate::INODE_CORE_SIZE],
+    mut load_block: F,
+    checksum_prefix: Option<u32>,
+) -> Result<Vec<crate::DataExtent>, Error>
+where
+    F: FnMut(u64) -> Result<Vec<u8>, Error>,
+{
+    crate::extents::load_data_extents(
+        &mut load_block,
+        core,
+        checksum_prefix,
+        &crate::Limits::default(),
+    )
+}
+
+/// As `extent_tree`, for an inode without the `EXTENTS` flag, whose `core` is a block
+/// map: `load_block` is asked for its indirect blocks. Only the blocks before `size`,
+/// the inode's `i_size`, are followed. The default `Limits` apply.
+pub fn block_map<F>(
+    core: [u8; crate::INODE_CORE_SIZE],
+    mut load_block: F,
+    block_size: u32,
+    size: u64,
+) -> Result<Vec<crate::DataExtent>, Error>
+where
+    F: FnMut(u64) -> Result<Vec<u8>, Error>,
+{
+    crate::indirect::load_block_map(
+        &mut load_block,
+        core,
+        block_size,
+        size,
+        &crate::Limits::default(),
+    )
+}
+
 pub struct ParsedInode {
     pub stat: crate::Stat,
     pub flags: crate::InodeFlags,
     pub core: [u8; crate::INODE_CORE_SIZE],
     pub checksum_prefix: Option<u32>,
+    /// `i_blocks`, in whatever unit the superblock and the `HUGE_FILE` flag say.
+    pub blocks: u64,
+    /// Whether the inode has an extended attribute block, which `blocks` counts.
+    pub xattr_block: bool,
+    pub warnings: Vec<crate::Warning>,
+}
+
+/// The seed for an inode's checksum, and for the checksums of the blocks it owns.
+pub(crate) fn inode_checksum_prefix(uuid_checksum: u32, number: u32, generation: u32) -> u32 {
+    let mut bytes = [0u8; 8];
+    LittleEndian::write_u32(&mut bytes[0..4], number);
+    LittleEndian::write_u32(&mut bytes[4..8], generation);
+    ext4_style_crc32c_le(uuid_checksum, &bytes)
+}
+
+/// What the checksum of an inode, the whole of it as on disc, should be. Its checksum
+/// fields are zeroed first: the low half's, and the high half's, if it `has_high`.
+pub(crate) fn inode_checksum(checksum_prefix: u32, data: &mut [u8], has_high: bool) -> u32 {
+    data[0x7C] = 0;
+    data[0x7D] = 0;
+
+    if has_high {
+        data[0x82] = 0;
+        data[0x83] = 0;
+    }
+
+    ext4_style_crc32c_le(checksum_prefix, data)
 }
 
 pub fn inode<F>(
@@ -374,6 +649,8 @@ pub fn inode<F>(
     load_block: F,
     uuid_checksum: Option<u32>,
     number: u32,
+    names: crate::NameDecoding,
+    creator_os: crate::CreatorOs,
 ) -> Result<ParsedInode, Error>
 where
     F: FnOnce(u64) -> Result<Vec<u8>, Error>,
@@ -390,10 +667,10 @@ where
     let i_atime = read_lei32(&data[0x08..0x0C]); /* Access time */
     let i_ctime = read_lei32(&data[0x0C..0x10]); /* Inode Change time */
     let i_mtime = read_lei32(&data[0x10..0x14]); /* Modification time */
-    //    let i_dtime           = read_le32(&data[0x14..0x18]); /* Deletion Time */
+    let i_dtime = read_lei32(&data[0x14..0x18]); /* Deletion Time */
     let i_gid = read_le16(&data[0x18..0x1A]); /* Low 16 bits of Group Id */
     let i_links_count = read_le16(&data[0x1A..0x1C]); /* Links count */
-    //    let i_blocks_lo       = read_le32(&data[0x1C..0x20]); /* Blocks count */
+    let i_blocks_lo = read_le32(&data[0x1C..0x20]); /* Blocks count */
     let i_flags = read_le32(&data[0x20..0x24]); /* File flags */
     //    let l_i_version       = read_le32(&data[0x24..0x28]);
 
@@ -404,19 +681,34 @@ where
     let i_file_acl_lo = read_le32(&data[0x68..0x6C]); /* File ACL */
     let i_size_high = read_le32(&data[0x6C..0x70]);
     //    let i_obso_faddr      = read_le32(&data[0x70..0x74]); /* Obsoleted fragment address */
-    //    let l_i_blocks_high   = read_le16(&data[0x74..0x76]); /* were l_i_reserved1 */
-    let l_i_file_acl_high = read_le16(&data[0x76..0x78]);
-    let l_i_uid_high = read_le16(&data[0x78..0x7A]); /* these 2 fields */
-    let l_i_gid_high = read_le16(&data[0x7A..0x7C]); /* were reserved2[0] */
-    let l_i_checksum_lo = read_le16(&data[0x7C..0x7E]); /* crc32c(uuid+inum+inode) LE */
-    //    let l_i_reserved      = read_le16(&data[0x7E..0x80]);
+    // osd2, whose fields depend on the os; only Linux's layout has all of these
+    let (l_i_blocks_high, l_i_file_acl_high, l_i_uid_high, l_i_gid_high, l_i_checksum_lo) =
+        match creator_os {
+            // h_i_frag, h_i_fsize, h_i_mode_high, then the owners, then h_i_author
+            crate::CreatorOs::Hurd => (
+                0,
+                0,
+                read_le16(&data[0x78..0x7A]),
+                read_le16(&data[0x7A..0x7C]),
+                None,
+            ),
+            // m_i_frag, m_i_fsize, m_pad1, m_i_reserved2
+            crate::CreatorOs::Masix => (0, 0, 0, 0, None),
+            _ => (
+                read_le16(&data[0x74..0x76]), /* l_i_blocks_high, were l_i_reserved1 */
+                read_le16(&data[0x76..0x78]), /* l_i_file_acl_high */
+                read_le16(&data[0x78..0x7A]), /* l_i_uid_high */
+                read_le16(&data[0x7A..0x7C]), /* l_i_gid_high */
+                Some(read_le16(&data[0x7C..0x7E])), /* crc32c(uuid+inum+inode) LE */
+            ),
+        };
 
     let i_extra_isize = if data.len() < 0x82 {
         0
     } else {
         read_le16(&data[0x80..0x82])
     };
-    let inode_end = INODE_BASE_LEN + usize::try_from(i_extra_isize)?;
+    let inode_end = INODE_BASE_LEN + usize::from(i_extra_isize);
 
     ensure!(
         inode_end <= data.len(),
@@ -459,23 +751,12 @@ where
     }; /* extra FileCreationtime (nsec << 2 | epoch) */
     //    let i_version_hi      = if i_extra_isize < 26 { None } else { Some(read_le32(&data[0x98..0x9C])) }; /* high 32 bits for 64-bit version */
     //    let i_projid          = if i_extra_isize < 30 { None } else { Some(read_le32(&data[0x9C..0xA0])) }; /* Project ID */
-    let mut checksum_prefix = None;
-
-    if let Some(uuid_checksum) = uuid_checksum {
-        data[0x7C] = 0;
-        data[0x7D] = 0;
-
-        let mut bytes = [0u8; 8];
-        LittleEndian::write_u32(&mut bytes[0..4], number);
-        LittleEndian::write_u32(&mut bytes[4..8], i_generation);
-        checksum_prefix = Some(ext4_style_crc32c_le(uuid_checksum, &bytes));
+    let checksum_prefix = uuid_checksum
+        .map(|uuid_checksum| inode_checksum_prefix(uuid_checksum, number, i_generation));
 
-        if i_checksum_hi.is_some() {
-            data[0x82] = 0;
-            data[0x83] = 0;
-        }
-
-        let computed = ext4_style_crc32c_le(checksum_prefix.unwrap(), &data);
+    // as the kernel, the checksum of an inode without the field isn't checked
+    if let (Some(checksum_prefix), Some(l_i_checksum_lo)) = (checksum_prefix, l_i_checksum_lo) {
+        let computed = inode_checksum(checksum_prefix, &mut data, i_checksum_hi.is_some());
 
         if let Some(high) = i_checksum_hi {
             let expected = u32::from(l_i_checksum_lo) | (u32::from(high) << 16);
@@ -503,20 +784,42 @@ where
 
     if inode_end + 4 <= data.len() && XATTR_MAGIC == read_le32(&data[inode_end..(inode_end + 4)]) {
         let table_start = &data[inode_end + 4..];
-        read_xattrs(&mut xattrs, table_start, table_start)?;
+        read_xattrs(&mut xattrs, table_start, table_start, names)?;
     }
 
     if 0 != i_file_acl_lo || 0 != l_i_file_acl_high {
         let block = u64::from(i_file_acl_lo) | (u64::from(l_i_file_acl_high) << 32);
 
-        xattr_block(&mut xattrs, load_block(block)?, uuid_checksum, block)
+        xattr_block(&mut xattrs, load_block(block)?, uuid_checksum, block, names)
             .with_context(|| anyhow!("loading xattr block {}", block))?
     }
 
+    let extracted_type = crate::FileType::from_mode(i_mode).ok_or_else(|| {
+        unsupported_feature(format!("unexpected file type in mode: {:b}", i_mode))
+    })?;
+
+    let mut warnings = Vec::new();
+    let times = [
+        ("atime", i_atime_extra),
+        ("ctime", i_ctime_extra),
+        ("mtime", i_mtime_extra),
+        ("btime", i_crtime_extra),
+    ];
+    for (field, extra) in times {
+        if let Some(nanos) = extra
+            .map(|extra| extra >> 2)
+            .filter(|&nanos| nanos > 999_999_999)
+        {
+            warnings.push(Warning::NanosecondsClamped {
+                inode: number,
+                field,
+                nanos,
+            });
+        }
+    }
+
     let stat = crate::Stat {
-        extracted_type: crate::FileType::from_mode(i_mode).ok_or_else(|| {
-            unsupported_feature(format!("unexpected file type in mode: {:b}", i_mode))
-        })?,
+        extracted_type,
         file_mode: i_mode & 0b111_111_111_111,
         uid: u32::from(i_uid) | (u32::from(l_i_uid_high) << 16),
         gid: u32::from(i_gid) | (u32::from(l_i_gid_high) << 16),
@@ -525,8 +828,18 @@ where
         ctime: Time::from_extra(i_ctime, i_ctime_extra),
         mtime: Time::from_extra(i_mtime, i_mtime_extra),
         btime: i_crtime.map(|i_crtime| Time::from_extra(i_crtime, i_crtime_extra)),
+        deleted_at: if 0 != i_dtime {
+            Some(Time::from_extra(i_dtime, None))
+        } else {
+            None
+        },
         link_count: i_links_count,
         xattrs,
+        device: if extracted_type.is_device() {
+            Some(crate::DeviceNumbers::from_core(&i_block))
+        } else {
+            None
+        },
     };
 
     Ok(ParsedInode {
@@ -536,6 +849,9 @@ where
         })?,
         core: i_block,
         checksum_prefix,
+        blocks: u64::from(i_blocks_lo) | (u64::from(l_i_blocks_high) << 32),
+        xattr_block: 0 != i_file_acl_lo || 0 != l_i_file_acl_high,
+        warnings,
     })
 }
 
@@ -544,6 +860,7 @@ fn xattr_block(
     mut data: Vec<u8>,
     uuid_checksum: Option<u32>,
     block_number: u64,
+    names: crate::NameDecoding,
 ) -> Result<(), Error> {
     ensure!(
         data.len() > 0x20,
@@ -589,13 +906,14 @@ fn xattr_block(
         ))
     );
 
-    read_xattrs(xattrs, &data[0x20..], &data[..])
+    read_xattrs(xattrs, &data[0x20..], &data[..], names)
 }
 
 fn read_xattrs(
     xattrs: &mut HashMap<String, Vec<u8>>,
     mut reading: &[u8],
     block_offset_start: &[u8],
+    names: crate::NameDecoding,
 ) -> Result<(), Error> {
     loop {
         ensure!(
@@ -615,7 +933,7 @@ fn read_xattrs(
         let e_value_size = read_le32(&reading[0x08..0x0C]);
         //        let e_hash              = read_le32(&reading[0x0C..0x10]);
 
-        let end_of_name = 0x10 + usize::try_from(e_name_len)?;
+        let end_of_name = 0x10 + usize::from(e_name_len);
 
         ensure!(
             reading.len() > end_of_name,
@@ -639,10 +957,12 @@ fn read_xattrs(
                     e_name_prefix_magic
                 ))),
             },
-            std::str::from_utf8(name_suffix).with_context(|| anyhow!("name is invalid utf-8"))?
+            names
+                .decode(name_suffix)
+                .with_context(|| anyhow!("name is invalid utf-8"))?
         );
 
-        let start = usize::try_from(e_value_offset)?;
+        let start = usize::from(e_value_offset);
         let end = start + usize::try_from(e_value_size)?;
 
         ensure!(
@@ -664,6 +984,11 @@ fn read_xattrs(
     Ok(())
 }
 
+/// The crc16 used by `uninit_bg`; no pre- or post-inversion, unlike the crc crate's version.
+pub fn ext4_style_crc16(seed: u16, buf: &[u8]) -> u16 {
+    !crc::crc16::update(!seed, &crc::crc16::USB_TABLE, buf)
+}
+
 /// This is what the function in the ext4 code does, based on its results. I'm so sorry.
 pub fn ext4_style_crc32c_le(seed: u32, buf: &[u8]) -> u32 {
     crc::crc32::update(seed ^ (!0), &crc::crc32::CASTAGNOLI_TABLE, buf) ^ (!0u32)
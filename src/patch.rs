@@ -0,0 +1,144 @@
+use std::io;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::ondisk::RawSuperblock;
+use crate::parse::ext4_style_crc32c_le;
+use crate::SuperBlock;
+
+/// What `SuperBlock::patch_superblock` changes; `None`, or `false`, leaves a field as it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SuperblockPatch {
+    /// The new label, at most 16 bytes; empty to remove it.
+    pub volume_name: Option<String>,
+    /// The new last mount point, at most 64 bytes, e.g. to note where an image came from.
+    pub last_mount_point: Option<String>,
+    /// Forget the errors the kernel has recorded: the count, and the first and last.
+    pub clear_error_history: bool,
+    /// Mark the filesystem as not having errors, by clearing the error bit in `s_state`,
+    /// and leaving the clean bit as it is, so it can be opened, and mounted, again.
+    /// Nothing is repaired; `e2fsck` is for that. The `SuperBlock` to patch with can be
+    /// opened from a copy taken before the errors were recorded.
+    pub clear_errors: bool,
+}
+
+/// `EXT4_ERROR_FS`, in `s_state`; `EXT4_VALID_FS` is `0x1`.
+const STATE_ERRORS: u16 = 0x2;
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Change a few fields in every copy of the superblock, the primary and the backups,
+    /// in `out`, which must be this filesystem's image, e.g. opened again for writing.
+    /// Only those fields, and the checksum, are written; nothing else is touched. Returns
+    /// how many copies were written.
+    ///
+    /// A backup which doesn't look like a copy of this superblock, by its magic number and
+    /// uuid, is left alone. The superblock already read isn't changed: see `refresh`.
+    pub fn patch_superblock<W>(&self, mut out: W, patch: &SuperblockPatch) -> Result<u32, Error>
+    where
+        W: io::Read + io::Write + io::Seek,
+    {
+        let volume_name = patch
+            .volume_name
+            .as_deref()
+            .map(|name| fixed::<16>(name, "volume name"))
+            .transpose()?;
+        let last_mount_point = patch
+            .last_mount_point
+            .as_deref()
+            .map(|path| fixed::<64>(path, "last mount point"))
+            .transpose()?;
+
+        let block_size = u64::from(self.groups.block_size);
+        let mut written = 0;
+        for number in 0..self.groups.count() {
+            let pos = if 0 == number {
+                RawSuperblock::OFFSET
+            } else if self.has_superblock_backup(number) {
+                self.groups.get(&self.inner, number)?.first_block * block_size
+            } else {
+                continue;
+            };
+
+            let mut data = [0u8; RawSuperblock::SIZE];
+            out.seek(io::SeekFrom::Start(pos))?;
+            out.read_exact(&mut data)
+                .with_context(|| anyhow!("reading group {}'s superblock", number))?;
+            let mut raw = RawSuperblock::from_slice(&data)?;
+            if 0xEF53 != raw.s_magic || self.raw.s_uuid != raw.s_uuid {
+                ensure!(
+                    0 != number,
+                    not_found("the image doesn't hold this filesystem; its uuid has changed")
+                );
+                continue;
+            }
+
+            if let Some(volume_name) = volume_name {
+                raw.s_volume_name = volume_name;
+            }
+            if let Some(last_mount_point) = last_mount_point {
+                raw.s_last_mounted = last_mount_point;
+            }
+            if patch.clear_error_history {
+                clear_error_history(&mut raw);
+            }
+            if patch.clear_errors {
+                raw.s_state &= !STATE_ERRORS;
+            }
+            raw.write_into(&mut data)?;
+            if self.uuid_checksum.is_some() {
+                let computed = ext4_style_crc32c_le(!0, &data[..0x3FC]);
+                data[0x3FC..].copy_from_slice(&computed.to_le_bytes());
+            }
+
+            out.seek(io::SeekFrom::Start(pos))?;
+            out.write_all(&data)?;
+            written += 1;
+        }
+        out.flush()?;
+
+        Ok(written)
+    }
+}
+
+/// A string in a nul-padded field, which needn't have a nul if it fills it.
+fn fixed<const N: usize>(value: &str, what: &str) -> Result<[u8; N], Error> {
+    ensure!(
+        value.len() <= N && !value.contains('\0'),
+        assumption_failed(format!(
+            "{} must be at most {} bytes, without nuls: {:?}",
+            what, N, value
+        ))
+    );
+    let mut field = [0u8; N];
+    field[..value.len()].copy_from_slice(value.as_bytes());
+    Ok(field)
+}
+
+fn clear_error_history(raw: &mut RawSuperblock) {
+    raw.s_error_count = 0;
+
+    raw.s_first_error_time = 0;
+    raw.s_first_error_time_hi = 0;
+    raw.s_first_error_ino = 0;
+    raw.s_first_error_block = 0;
+    raw.s_first_error_func = [0; 32];
+    raw.s_first_error_line = 0;
+    raw.s_first_error_errcode = 0;
+
+    raw.s_last_error_time = 0;
+    raw.s_last_error_time_hi = 0;
+    raw.s_last_error_ino = 0;
+    raw.s_last_error_block = 0;
+    raw.s_last_error_func = [0; 32];
+    raw.s_last_error_line = 0;
+    raw.s_last_error_errcode = 0;
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::DirEntry;
+
+/// Remembers the entries that paths resolved to, forgetting the least recently used
+/// once it's full.
+pub(crate) struct PathCache {
+    capacity: usize,
+    inner: Mutex<Lru>,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, (DirEntry, u64)>,
+    /// When each path was last used, oldest first.
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl PathCache {
+    /// A `capacity` of zero disables the cache.
+    pub(crate) fn new(capacity: usize) -> PathCache {
+        PathCache {
+            capacity,
+            inner: Mutex::new(Lru::default()),
+        }
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Option<DirEntry> {
+        if 0 == self.capacity {
+            return None;
+        }
+
+        let mut lru = self.inner.lock().expect("poisoned");
+        let now = lru.tick();
+        let (entry, used) = lru.entries.get_mut(path)?;
+        let entry = entry.clone();
+        let previous = std::mem::replace(used, now);
+        lru.order.remove(&previous);
+        lru.order.insert(now, path.to_string());
+        Some(entry)
+    }
+
+    pub(crate) fn insert(&self, path: &str, entry: &DirEntry) {
+        if 0 == self.capacity {
+            return;
+        }
+
+        let mut lru = self.inner.lock().expect("poisoned");
+        let now = lru.tick();
+        if let Some((_, previous)) = lru.entries.insert(path.to_string(), (entry.clone(), now)) {
+            lru.order.remove(&previous);
+        }
+        lru.order.insert(now, path.to_string());
+
+        while lru.entries.len() > self.capacity {
+            let used = *lru.order.keys().next().expect("entries are in the order");
+            let oldest = lru.order.remove(&used).expect("just found");
+            lru.entries.remove(&oldest);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.inner.lock().expect("poisoned") = Lru::default();
+    }
+}
+
+impl Lru {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl fmt::Debug for PathCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathCache")
+            .field("capacity", &self.capacity)
+            .field(
+                "len",
+                &self.inner.lock().map(|lru| lru.entries.len()).unwrap_or(0),
+            )
+            .finish()
+    }
+}
@@ -0,0 +1,86 @@
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use anyhow::Error;
+
+use crate::ParseError;
+
+/// Told about the work done by long operations, such as walks and diffs, so it can be
+/// shown to a user, and asked whether they should carry on.
+/// Every method does nothing by default.
+///
+/// The methods take `&self`, so one `Progress` can be shared by a walk and the readers
+/// opened inside it; implementations will want a `Cell`, an atomic, or similar.
+pub trait Progress {
+    /// An entry is about to be visited.
+    fn entry(&self, _path: &str) {}
+
+    /// Some file content has been read.
+    fn read(&self, _bytes: u64) {}
+
+    /// Checked between entries, and before each read. If this returns `true`, the
+    /// operation stops, failing with `ParseError::Cancelled`.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Reports nothing, and never cancels.
+impl Progress for () {}
+
+/// Cancels once the flag is set, e.g. from another thread.
+impl Progress for AtomicBool {
+    fn cancelled(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+/// Cancels once this deadline has passed.
+impl Progress for Instant {
+    fn cancelled(&self) -> bool {
+        Instant::now() >= *self
+    }
+}
+
+pub(crate) fn check_cancelled(progress: &dyn Progress) -> Result<(), Error> {
+    if progress.cancelled() {
+        return Err(ParseError::Cancelled.into());
+    }
+    Ok(())
+}
+
+/// Wraps a reader (usually from `SuperBlock::open`), reporting everything read through it.
+/// Once cancelled, reads fail with an `io::Error` wrapping `ParseError::Cancelled`.
+pub struct ProgressReader<'p, Rd> {
+    inner: Rd,
+    progress: &'p dyn Progress,
+}
+
+impl<'p, Rd> ProgressReader<'p, Rd> {
+    pub fn new(inner: Rd, progress: &'p dyn Progress) -> Self {
+        ProgressReader { inner, progress }
+    }
+
+    pub fn into_inner(self) -> Rd {
+        self.inner
+    }
+}
+
+impl<'p, Rd: io::Read> io::Read for ProgressReader<'p, Rd> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.progress.cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Other, ParseError::Cancelled));
+        }
+        let read = self.inner.read(buf)?;
+        self.progress.read(read as u64);
+        Ok(read)
+    }
+}
+
+impl<'p, Rd: io::Seek> io::Seek for ProgressReader<'p, Rd> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,525 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::ondisk::RawDirEntry;
+
+use crate::read_le16;
+use crate::read_le32;
+use crate::read_lei32;
+use crate::BlockGroupFlags;
+use crate::DirEntry;
+use crate::Enhanced;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+use crate::Time;
+
+/// Where a deleted inode was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DeletedSource {
+    /// The inode as it is now, in the inode table. The kernel throws away the extents
+    /// of files it deletes, so these are rarely recoverable, but other tools may not.
+    InodeTable,
+    /// An older copy of the inode, from before it was deleted, logged by this transaction.
+    Journal { sequence: u32 },
+}
+
+pub struct DeletedInode {
+    pub source: DeletedSource,
+    /// When the inode was deleted, according to the inode table.
+    pub deleted_at: Option<Time>,
+    pub inode: Inode,
+    /// This is a regular file with content, and its extent tree can still be read.
+    /// The blocks it points at may have been reused since.
+    pub intact: bool,
+}
+
+/// An inode which has been deleted and wiped, keeping only when it was deleted: unlike
+/// one which has never been used, whose deletion time is zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Tombstone {
+    pub number: u32,
+    pub deleted_at: Time,
+}
+
+/// A file `e2fsck` found no directory for, and put in `/lost+found`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LostEntry {
+    /// As `e2fsck` named it, e.g. `#13`.
+    pub entry: DirEntry,
+    /// The name the latest directory block in the journal gave it, if there is one. The
+    /// inode may have been reused since, so this is a hint, not a certainty.
+    pub original_name: Option<String>,
+}
+
+/// A record in a directory, with where it was found on disc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DirRecord {
+    /// The physical block holding the record.
+    pub block: u64,
+    /// Where the record starts in the block.
+    pub offset: u32,
+    /// How much of the block the record claims, including any slack after the name:
+    /// `rec_len`, decoded, as 64KiB blocks store it differently.
+    pub rec_len: u32,
+    /// Zero if the entry has been deleted.
+    pub inode: u32,
+    /// `None` if the hint isn't recognised, or there isn't one.
+    pub file_type: Option<FileType>,
+    /// The raw name, which needn't be valid UTF-8.
+    pub name: Vec<u8>,
+}
+
+impl DirRecord {
+    /// The entry was deleted, but its record wasn't merged into the previous one,
+    /// usually because it was first in the block.
+    pub fn deleted(&self) -> bool {
+        0 == self.inode
+    }
+}
+
+/// A possible deleted directory entry, found in slack space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CarvedEntry {
+    /// What was found, and where. The inode may have been reused since.
+    pub record: DirRecord,
+    pub confidence: Confidence,
+}
+
+/// How much a carved entry looks like a real one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Confidence {
+    Low,
+    Medium,
+    /// The inode number, file type, record length and name are all sensible.
+    High,
+}
+
+/// The bits of a raw inode needed to decide whether it's interesting.
+struct Slot {
+    mode: u16,
+    links: u16,
+    dtime: i32,
+    generation: u32,
+}
+
+impl Slot {
+    fn new(data: &[u8]) -> Slot {
+        Slot {
+            mode: read_le16(&data[0x00..0x02]),
+            dtime: read_lei32(&data[0x14..0x18]),
+            links: read_le16(&data[0x1A..0x1C]),
+            generation: read_le32(&data[0x64..0x68]),
+        }
+    }
+
+    fn deleted(&self) -> bool {
+        0 != self.mode && 0 == self.links && 0 != self.dtime
+    }
+
+    fn live(&self) -> bool {
+        0 != self.mode && 0 != self.links && 0 == self.dtime
+    }
+
+    fn tombstone(&self) -> bool {
+        0 == self.mode && 0 != self.dtime
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Find inodes which have been deleted: those still in the inode table, and
+    /// older copies in the journal of inodes which have since been deleted or reused.
+    /// Inodes which can't be parsed at all are skipped.
+    pub fn deleted_inodes(&self) -> Result<Vec<DeletedInode>, Error> {
+        let inode_size = usize::from(self.groups.inode_size);
+        let inodes_per_group = self.groups.inodes_per_group();
+        let mut found = Vec::new();
+
+        self.inode_table_slots(|number, data| {
+            if Slot::new(data).deleted() {
+                if let Ok(inode) = self.parse_inode(number, data.to_vec()) {
+                    found.push(self.deleted_inode(
+                        DeletedSource::InodeTable,
+                        Some(Slot::new(data).dtime),
+                        inode,
+                    ));
+                }
+            }
+        })?;
+
+        let journal = match self.journal_inode {
+            Some(0) | None => return Ok(found),
+            Some(_) => self.journal()?,
+        };
+
+        // later transactions replace the copies from earlier ones
+        let mut logged = BTreeMap::new();
+
+        for transaction in &journal.transactions {
+            for block in &transaction.blocks {
+                let (group, first_index) = match self.inode_table_containing(block.target)? {
+                    Some(found) => found,
+                    None => continue,
+                };
+
+                let data = self.journal_block(block)?;
+                for (offset, data) in (0..).zip(data.chunks(inode_size)) {
+                    let index = first_index + offset;
+                    if index >= inodes_per_group || !Slot::new(data).live() {
+                        continue;
+                    }
+
+                    let number = group * inodes_per_group + index + 1;
+                    let current = match self.load_inode_bytes(number) {
+                        Ok(current) => Slot::new(&current),
+                        Err(_) => continue,
+                    };
+
+                    if current.live() && current.generation == Slot::new(data).generation {
+                        continue;
+                    }
+
+                    if let Ok(inode) = self.parse_inode(number, data.to_vec()) {
+                        let deleted_at = if current.deleted() {
+                            Some(current.dtime)
+                        } else {
+                            None
+                        };
+                        logged.insert(
+                            number,
+                            self.deleted_inode(
+                                DeletedSource::Journal {
+                                    sequence: transaction.sequence,
+                                },
+                                deleted_at,
+                                inode,
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        found.extend(logged.into_values());
+        found.sort_by_key(|deleted| {
+            (
+                deleted.inode.number,
+                DeletedSource::InodeTable != deleted.source,
+            )
+        });
+        Ok(found)
+    }
+
+    /// Inodes in the inode tables which have been deleted, and then wiped, so only their
+    /// deletion time is left; `deleted_inodes` has those which are still intact.
+    pub fn tombstones(&self) -> Result<Vec<Tombstone>, Error> {
+        let mut found = Vec::new();
+        self.inode_table_slots(|number, data| {
+            let slot = Slot::new(data);
+            if slot.tombstone() {
+                found.push(Tombstone {
+                    number,
+                    deleted_at: Time::from_extra(slot.dtime, None),
+                });
+            }
+        })?;
+        Ok(found)
+    }
+
+    /// Every inode in the initialised part of the inode tables, raw, with its number.
+    fn inode_table_slots<F>(&self, mut visit: F) -> Result<(), Error>
+    where
+        F: FnMut(u32, &[u8]),
+    {
+        let inode_size = usize::from(self.groups.inode_size);
+        let inodes_per_group = self.groups.inodes_per_group();
+
+        for number in 0..self.groups.count() {
+            let group = self.groups.get(&self.inner, number)?;
+            if group.flags.contains(BlockGroupFlags::INODE_UNINIT) {
+                continue;
+            }
+
+            let used = inodes_per_group.saturating_sub(group.itable_unused);
+            let mut table = vec![0u8; usize::try_from(used)? * inode_size];
+            self.inner.read_exact_at(
+                group.inode_table * u64::from(self.groups.block_size),
+                &mut table,
+            )?;
+
+            for (index, data) in (0..).zip(table.chunks(inode_size)) {
+                visit(number * inodes_per_group + index + 1, data);
+            }
+        }
+        Ok(())
+    }
+
+    /// The entries of the filesystem's `/lost+found`, with their names from before they were
+    /// lost, if the journal still has a directory block listing them.
+    pub fn lost_found_entries(&self) -> Result<Vec<LostEntry>, Error> {
+        let entry = self.dir_entry_named(&self.load_inode(2)?, "lost+found")?;
+        let dir = self.load_inode(entry.inode)?;
+        let entries = match self.enhance(&dir)? {
+            Enhanced::Directory(entries) => entries,
+            _ => return Err(assumption_failed("/lost+found is not a directory").into()),
+        };
+        let mut lost = entries
+            .into_iter()
+            .filter(|entry| "." != entry.name && ".." != entry.name)
+            .map(|entry| LostEntry {
+                entry,
+                original_name: None,
+            })
+            .collect::<Vec<_>>();
+
+        let journal = match self.journal_inode {
+            Some(0) | None => return Ok(lost),
+            Some(_) => self.journal()?,
+        };
+
+        // once e2fsck has moved them in, lost+found's own blocks, logged, only have the
+        // names it gave them
+        let own_blocks = self.data_extents(&dir)?;
+
+        // later transactions replace names from earlier ones
+        for transaction in &journal.transactions {
+            for block in &transaction.blocks {
+                if own_blocks.iter().any(|extent| {
+                    (extent.physical..extent.physical + u64::from(extent.len))
+                        .contains(&block.target)
+                }) {
+                    continue;
+                }
+                let data = self.journal_block(block)?;
+                // most logged blocks aren't directories, and won't parse as one
+                let dirents = match crate::parse::dirents(&data, self.has_filetype(), None) {
+                    Ok(dirents) => dirents,
+                    Err(_) => continue,
+                };
+                for dirent in dirents {
+                    let name = match self.options.names.decode(&dirent.name) {
+                        Ok(name) if "." != name && ".." != name && !is_lost_name(&name) => name,
+                        _ => continue,
+                    };
+                    for found in &mut lost {
+                        if found.entry.inode == dirent.inode {
+                            found.original_name = Some(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(lost)
+    }
+
+    /// Every record in a directory, in on-disc order, with its location. Deleted entries
+    /// whose name is still present are included if `include_deleted` is set; the
+    /// checksum record, and unused records without a name, never are.
+    pub fn dir_records(
+        &self,
+        inode: &Inode,
+        include_deleted: bool,
+    ) -> Result<Vec<DirRecord>, Error> {
+        let mut records = Vec::new();
+
+        for (block, data) in self.dir_blocks(inode)? {
+            for (offset, raw) in raw_records(block, &data)? {
+                if raw.is_tail() || raw.name.is_empty() {
+                    continue;
+                }
+                if 0 == raw.inode && !include_deleted {
+                    continue;
+                }
+
+                records.push(DirRecord {
+                    block,
+                    offset: u32::try_from(offset)?,
+                    rec_len: u32::try_from(raw.record_len(data.len()))?,
+                    inode: raw.inode,
+                    file_type: FileType::from_dir_hint(raw.file_type),
+                    name: raw.name,
+                });
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Look for the remains of deleted entries in a directory's slack space: the gaps
+    /// after each record's name. When an entry is deleted, the kernel usually just
+    /// extends the previous record over it, so its name stays on disc until reused.
+    ///
+    /// Anything which looks enough like a record is returned, so expect false positives.
+    pub fn carve_directory(&self, inode: &Inode) -> Result<Vec<CarvedEntry>, Error> {
+        let mut carved = Vec::new();
+
+        for (block, data) in self.dir_blocks(inode)? {
+            for (offset, raw) in raw_records(block, &data)? {
+                if raw.is_tail() {
+                    continue;
+                }
+                let slack_start = offset + align4(RawDirEntry::HEADER_SIZE + raw.name.len());
+                let slack_end = offset + raw.record_len(data.len());
+                self.carve_slack(block, &data, slack_start, slack_end, &mut carved)?;
+            }
+        }
+
+        Ok(carved)
+    }
+
+    fn carve_slack(
+        &self,
+        block: u64,
+        data: &[u8],
+        mut pos: usize,
+        end: usize,
+        carved: &mut Vec<CarvedEntry>,
+    ) -> Result<(), Error> {
+        while pos + RawDirEntry::HEADER_SIZE < end {
+            let raw = match RawDirEntry::from_slice(&data[pos..end]) {
+                Ok(raw) if plausible(&raw, data.len()) => raw,
+                _ => {
+                    pos += 4;
+                    continue;
+                }
+            };
+
+            let checks = [
+                0 != raw.inode && raw.inode <= self.raw.s_inodes_count,
+                FileType::from_dir_hint(raw.file_type).is_some(),
+                pos + raw.record_len(data.len()) <= data.len(),
+                std::str::from_utf8(&raw.name)
+                    .map_or(false, |name| name.chars().all(|c| !c.is_control())),
+            ];
+            let confidence = match checks.iter().filter(|&&check| check).count() {
+                4 => Confidence::High,
+                3 => Confidence::Medium,
+                _ => Confidence::Low,
+            };
+
+            let next = pos + align4(RawDirEntry::HEADER_SIZE + raw.name.len());
+            carved.push(CarvedEntry {
+                record: DirRecord {
+                    block,
+                    offset: u32::try_from(pos)?,
+                    rec_len: u32::try_from(raw.record_len(data.len()))?,
+                    inode: raw.inode,
+                    file_type: FileType::from_dir_hint(raw.file_type),
+                    name: raw.name,
+                },
+                confidence,
+            });
+            pos = next;
+        }
+
+        Ok(())
+    }
+
+    /// The blocks of a directory, with their physical locations.
+    fn dir_blocks(&self, inode: &Inode) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        ensure!(
+            FileType::Directory == inode.stat.extracted_type,
+            assumption_failed(format!("<{}> is not a directory", inode.number))
+        );
+
+        let block_size = u64::from(self.groups.block_size);
+        let mut blocks = Vec::new();
+
+        for extent in self.data_extents(inode)? {
+            for index in 0..u64::from(extent.len) {
+                if (u64::from(extent.logical) + index) * block_size >= inode.stat.size {
+                    break;
+                }
+
+                let block = extent.physical + index;
+                blocks.push((block, self.load_block(block)?));
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    fn deleted_inode(
+        &self,
+        source: DeletedSource,
+        dtime: Option<i32>,
+        inode: Inode,
+    ) -> DeletedInode {
+        let intact = FileType::RegularFile == inode.stat.extracted_type
+            && 0 != inode.stat.size
+            && self.open(&inode).is_ok();
+
+        DeletedInode {
+            source,
+            deleted_at: dtime.map(|dtime| Time::from_extra(dtime, None)),
+            inode,
+            intact,
+        }
+    }
+
+    /// The group, and the index of the first inode in the block, if this block is in an inode table.
+    fn inode_table_containing(&self, block: u64) -> Result<Option<(u32, u32)>, Error> {
+        let inodes_per_block = self.groups.block_size / u32::from(self.groups.inode_size);
+        for number in 0..self.groups.count() {
+            let group = self.groups.get(&self.inner, number)?;
+            if block >= group.inode_table && block < group.inode_table + group.inode_table_blocks {
+                let index = u32::try_from(block - group.inode_table)? * inodes_per_block;
+                return Ok(Some((number, index)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The records in a directory block, and their offsets.
+pub(crate) fn raw_records(block: u64, data: &[u8]) -> Result<Vec<(usize, RawDirEntry)>, Error> {
+    let mut records = Vec::new();
+    let mut offset = 0usize;
+    while offset < data.len() {
+        let raw = RawDirEntry::from_slice(&data[offset..])?;
+        let rec_len = raw.record_len(data.len());
+        ensure!(
+            rec_len >= RawDirEntry::HEADER_SIZE && offset + rec_len <= data.len(),
+            assumption_failed(format!(
+                "invalid record length {} at {} in block {}",
+                rec_len, offset, block
+            ))
+        );
+        records.push((offset, raw));
+        offset += rec_len;
+    }
+    Ok(records)
+}
+
+/// Could this be a record at all? Names can't be empty, or contain `/` or NUL.
+fn plausible(raw: &RawDirEntry, block_size: usize) -> bool {
+    let rec_len = raw.record_len(block_size);
+    !raw.name.is_empty()
+        && rec_len >= RawDirEntry::HEADER_SIZE + raw.name.len()
+        && 0 == rec_len % 4
+        && !raw.name.iter().any(|&b| 0 == b || b'/' == b)
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A name e2fsck gives what it reconnects, `#` and the inode's number, not a real one.
+fn is_lost_name(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('#') && name[1..].bytes().all(|b| b.is_ascii_digit())
+}
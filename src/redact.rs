@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::ondisk::RawDirEntry;
+use crate::ondisk::RawDirEntryTail;
+use crate::parse::ext4_style_crc32c_le;
+use crate::unsupported_feature;
+use crate::FileType;
+use crate::Inode;
+use crate::InodeFlags;
+use crate::SuperBlock;
+
+/// What `SuperBlock::redact` does to the entries it's asked to, beyond zeroing the files'
+/// content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redaction {
+    /// Also rename them, to the first name of the same length which isn't already in their
+    /// directory, counting up in base 36: `file` becomes `0000`, or `0001` if that's taken.
+    pub rename: bool,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Hide the entries `select` picks, from a walk from the root, in `out`, which must be
+    /// this filesystem's image, e.g. a copy opened for writing: a regular file's blocks
+    /// are overwritten with zeros, and, with `rename`, any entry's name is replaced. Sizes,
+    /// owners, and times are kept, and nothing is allocated, or freed, so the image still
+    /// shows how the filesystem was laid out. Returns how many entries were picked.
+    ///
+    /// A renamed entry in a hashed directory is no longer where its hash says, so the
+    /// kernel can list it, but not find it by name, until `e2fsck -fD` rebuilds the
+    /// index. Names in encrypted directories are already ciphertext, so are left alone.
+    pub fn redact<W, F>(
+        &self,
+        mut out: W,
+        redaction: &Redaction,
+        mut select: F,
+    ) -> Result<u32, Error>
+    where
+        W: io::Write + io::Seek,
+        F: FnMut(&str, &Inode) -> bool,
+    {
+        let block_size = u64::from(self.groups.block_size);
+
+        // (path, inode), in the order they were visited, so parents are before children
+        let mut selected = Vec::new();
+        let mut zero = Vec::new();
+        self.walk(&self.root()?, "", &mut |fs, path, inode, _| {
+            if path.is_empty() || !select(path, inode) {
+                return Ok(true);
+            }
+            if FileType::RegularFile == inode.stat.extracted_type {
+                ensure!(
+                    !inode.flags.contains(InodeFlags::INLINE_DATA),
+                    unsupported_feature(format!("{:?}'s content is inline", path))
+                );
+                zero.extend(fs.data_extents(inode)?);
+            }
+            selected.push((path.to_string(), inode.number));
+            Ok(true)
+        })?;
+
+        let zeros = vec![0u8; usize::try_from(block_size)?];
+        for extent in zero {
+            out.seek(io::SeekFrom::Start(extent.physical * block_size))?;
+            for _ in 0..extent.len {
+                out.write_all(&zeros)?;
+            }
+        }
+
+        if redaction.rename {
+            let mut by_parent = HashMap::<&str, Vec<(&str, u32)>>::new();
+            for (path, inode) in &selected {
+                let (parent, name) = path.rsplit_once('/').expect("paths start with a /");
+                by_parent.entry(parent).or_default().push((name, *inode));
+            }
+            for (parent, entries) in by_parent {
+                let dir = if parent.is_empty() {
+                    self.root()?
+                } else {
+                    self.load_inode(self.resolve_path(parent)?.inode)?
+                };
+                if dir.is_encrypted() {
+                    continue;
+                }
+                self.rename_entries(&mut out, &dir, &entries)?;
+            }
+        }
+        out.flush()?;
+
+        Ok(u32::try_from(selected.len())?)
+    }
+
+    /// Give each of `entries`, `(name, inode)`, a new name in `dir`'s blocks in `out`.
+    fn rename_entries<W>(
+        &self,
+        out: &mut W,
+        dir: &Inode,
+        entries: &[(&str, u32)],
+    ) -> Result<(), Error>
+    where
+        W: io::Write + io::Seek,
+    {
+        let records = self.dir_records(dir, false)?;
+        let mut taken = records
+            .iter()
+            .map(|record| record.name.clone())
+            .collect::<HashSet<_>>();
+
+        // block: (offset of the name, new name)
+        let mut changes = HashMap::<u64, Vec<(usize, Vec<u8>)>>::new();
+        let mut found = 0;
+        for record in &records {
+            let picked = entries
+                .iter()
+                .any(|&(name, inode)| inode == record.inode && name.as_bytes() == &record.name[..]);
+            if !picked {
+                continue;
+            }
+            let name = fresh_name(record.name.len(), &taken).ok_or_else(|| {
+                assumption_failed(format!(
+                    "<{}> has no names of length {} left to rename {:?} to",
+                    dir.number,
+                    record.name.len(),
+                    String::from_utf8_lossy(&record.name)
+                ))
+            })?;
+            taken.insert(name.clone());
+            found += 1;
+            let offset = usize::try_from(record.offset)? + RawDirEntry::HEADER_SIZE;
+            changes
+                .entry(record.block)
+                .or_default()
+                .push((offset, name));
+        }
+
+        // e.g. a name which isn't UTF-8, which the walk's path couldn't hold as it is
+        ensure!(
+            found == entries.len(),
+            assumption_failed(format!(
+                "only {} of {} entries to rename were found in <{}>",
+                found,
+                entries.len(),
+                dir.number
+            ))
+        );
+
+        let block_size = u64::from(self.groups.block_size);
+        for (block, names) in changes {
+            let mut data = self.load_block(block)?;
+            for (offset, name) in names {
+                data[offset..offset + name.len()].copy_from_slice(&name);
+            }
+
+            let tail = data.len() - RawDirEntryTail::SIZE;
+            if let Some(checksum_prefix) = dir.checksum_prefix {
+                if RawDirEntry::from_slice(&data[tail..])?.is_tail() {
+                    let computed = ext4_style_crc32c_le(checksum_prefix, &data[..tail]);
+                    data[data.len() - 4..].copy_from_slice(&computed.to_le_bytes());
+                }
+            }
+
+            out.seek(io::SeekFrom::Start(block * block_size))?;
+            out.write_all(&data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The first name of `len` characters, counting up in base 36, that isn't `taken`.
+fn fresh_name(len: usize, taken: &HashSet<Vec<u8>>) -> Option<Vec<u8>> {
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    let mut name = vec![b'0'; len];
+    loop {
+        if !taken.contains(&name) {
+            return Some(name);
+        }
+        // add one, from the right
+        let mut pos = len;
+        loop {
+            if 0 == pos {
+                return None;
+            }
+            pos -= 1;
+            let digit = DIGITS.iter().position(|&d| d == name[pos]).expect("ours");
+            if digit + 1 < DIGITS.len() {
+                name[pos] = DIGITS[digit + 1];
+                break;
+            }
+            name[pos] = DIGITS[0];
+        }
+    }
+}
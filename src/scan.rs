@@ -0,0 +1,167 @@
+use std::convert::TryFrom;
+use std::io;
+use std::ops::Range;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::info::c_string;
+use crate::info::non_empty;
+use crate::ondisk::RawSuperblock;
+use crate::parse::ext4_style_crc32c_le;
+use crate::parse::CompatibleFeatureReadOnly;
+use crate::parse::IncompatibleFeature;
+
+/// Filesystems start on a sector boundary, and every copy of the superblock is on one.
+const SECTOR: u64 = 512;
+
+/// How much to read at once.
+const CHUNK: u64 = 1024 * 1024;
+
+/// A copy of a superblock, found by `scan_for_superblocks`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SuperblockCandidate {
+    /// Where the filesystem starts, if this is a real superblock; this is what to skip,
+    /// e.g. with a `positioned_io2::Slice`, to open it.
+    pub offset: u64,
+    /// Where this copy of the superblock is.
+    pub found_at: u64,
+    /// The block group this copy is at the start of: 0 for the primary, otherwise a backup.
+    pub group: u32,
+    pub block_size: u32,
+    pub blocks_count: u64,
+    pub uuid: [u8; 16],
+    pub volume_name: Option<String>,
+    /// `None` if the filesystem doesn't have metadata checksums.
+    pub checksum_valid: Option<bool>,
+}
+
+/// Search the bytes in `range` of `reader` for ext4 superblocks, primary or backup, e.g.
+/// on a disc whose partition table is gone. Every sector is checked for the magic number,
+/// and the rest of the superblock for sensible values, so this reads the whole range.
+///
+/// Backups know which block group they're in, so the filesystem's start can be worked
+/// out from any of them. The candidates are in the order they were found.
+pub fn scan_for_superblocks<R>(
+    reader: R,
+    range: Range<u64>,
+) -> Result<Vec<SuperblockCandidate>, Error>
+where
+    R: ReadAt,
+{
+    let size = u64::try_from(RawSuperblock::SIZE)?;
+    let mut candidates = Vec::new();
+    let mut buf = vec![0u8; usize::try_from(CHUNK + size)?];
+
+    let mut pos = (range.start + SECTOR - 1) / SECTOR * SECTOR;
+    while pos < range.end {
+        let read = read_up_to(&reader, pos, &mut buf)?;
+        let read = u64::try_from(read)?;
+        if read < size {
+            break;
+        }
+
+        let mut sector = 0;
+        while sector + size <= read && sector < CHUNK && pos + sector < range.end {
+            let start = usize::try_from(sector)?;
+            let data = &buf[start..start + RawSuperblock::SIZE];
+            if let Some(candidate) = plausible(pos + sector, data)? {
+                candidates.push(candidate);
+            }
+            sector += SECTOR;
+        }
+        pos += CHUNK;
+    }
+
+    Ok(candidates)
+}
+
+/// If `data` looks like a superblock, what it says about its filesystem.
+fn plausible(found_at: u64, data: &[u8]) -> Result<Option<SuperblockCandidate>, Error> {
+    // s_magic; most sectors fail here, so don't parse them
+    if [0x53, 0xEF] != data[0x38..0x3A] {
+        return Ok(None);
+    }
+    let raw = RawSuperblock::from_slice(data)?;
+
+    // block sizes from 1KiB to 64KiB
+    if raw.s_log_block_size > 6 {
+        return Ok(None);
+    }
+    let block_size = 1024u32 << raw.s_log_block_size;
+    let bitmap_bits = 8 * block_size;
+
+    let expected_first_data_block = if 1024 == block_size { 1 } else { 0 };
+    let groups_fit = (1..=bitmap_bits).contains(&raw.s_blocks_per_group)
+        && (1..=bitmap_bits).contains(&raw.s_inodes_per_group);
+    let inode_size_ok = 0 == raw.s_rev_level
+        || (raw.s_inode_size.is_power_of_two()
+            && (128..=block_size).contains(&u32::from(raw.s_inode_size)));
+    if raw.s_first_data_block != expected_first_data_block
+        || !groups_fit
+        || raw.s_rev_level > 1
+        || !inode_size_ok
+    {
+        return Ok(None);
+    }
+
+    let incompat = IncompatibleFeature::from_bits_truncate(raw.s_feature_incompat);
+    let blocks_count = u64::from(raw.s_blocks_count_lo)
+        | if incompat.contains(IncompatibleFeature::SIXTY_FOUR_BIT) {
+            u64::from(raw.s_blocks_count_hi) << 32
+        } else {
+            0
+        };
+    if blocks_count <= u64::from(raw.s_first_data_block) {
+        return Ok(None);
+    }
+
+    let group = u32::from(raw.s_block_group_nr);
+    let from_start = if 0 == group {
+        RawSuperblock::OFFSET
+    } else {
+        let block = u64::from(raw.s_first_data_block)
+            + u64::from(group) * u64::from(raw.s_blocks_per_group);
+        if block >= blocks_count {
+            return Ok(None);
+        }
+        block * u64::from(block_size)
+    };
+    let offset = match found_at.checked_sub(from_start) {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+
+    let checksum_valid = CompatibleFeatureReadOnly::from_bits_truncate(raw.s_feature_ro_compat)
+        .contains(CompatibleFeatureReadOnly::METADATA_CSUM)
+        .then(|| raw.s_checksum == ext4_style_crc32c_le(!0, &data[..0x3FC]));
+
+    Ok(Some(SuperblockCandidate {
+        offset,
+        found_at,
+        group,
+        block_size,
+        blocks_count,
+        uuid: raw.s_uuid,
+        volume_name: non_empty(c_string(&raw.s_volume_name)),
+        checksum_valid,
+    }))
+}
+
+/// Fill as much of `buf` as there is data for, stopping at the end of the reader.
+fn read_up_to<R: ReadAt>(reader: &R, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read_at(
+            pos + u64::try_from(filled).expect("infallible u64 conversion"),
+            &mut buf[filled..],
+        ) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref e) if io::ErrorKind::Interrupted == e.kind() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
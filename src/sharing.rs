@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::parse::CompatibleFeatureReadOnly;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+
+/// A run of blocks which more than one inode's data claims; see `SuperBlock::shared_extents`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SharedExtent {
+    /// The first block.
+    pub physical: u64,
+    /// In blocks.
+    pub len: u64,
+    /// Every inode claiming all of these blocks, in order; an inode appears twice if it
+    /// claims them twice itself.
+    pub inodes: Vec<u32>,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// The blocks claimed by more than one inode, in block order. ext4 has no reflinks, so
+    /// on most filesystems this is damage, which `check` reports, too, but images with the
+    /// `shared_blocks` feature, such as Android's, are deduplicated like this on purpose.
+    ///
+    /// Only the data of inodes reachable from the root is considered; extent tree blocks,
+    /// and inodes which are allocated but unlinked, aren't.
+    pub fn shared_extents(&self) -> Result<Vec<SharedExtent>, Error> {
+        let mut claimed = Vec::new();
+        let mut visited = HashSet::new();
+        self.walk(&self.load_inode(2)?, "", &mut |fs, _, inode, _| {
+            if visited.insert(inode.number) && has_data_blocks(inode) {
+                claimed.extend(
+                    fs.data_extents(inode)?
+                        .into_iter()
+                        .map(|extent| (extent.physical, u64::from(extent.len), inode.number)),
+                );
+            }
+            Ok(true)
+        })?;
+        Ok(overlaps(&claimed))
+    }
+
+    /// Whether the `shared_blocks` feature says blocks are shared on purpose.
+    pub(crate) fn has_shared_blocks(&self) -> bool {
+        CompatibleFeatureReadOnly::from_bits_truncate(self.raw.s_feature_ro_compat)
+            .contains(CompatibleFeatureReadOnly::SHARED_BLOCKS)
+    }
+}
+
+/// Whether an inode's data is in blocks, rather than the inode itself, or nowhere.
+pub(crate) fn has_data_blocks(inode: &Inode) -> bool {
+    match inode.stat.extracted_type {
+        FileType::RegularFile | FileType::Directory => true,
+        FileType::SymbolicLink => !inode.is_fast_symlink(),
+        _ => false,
+    }
+}
+
+/// The runs of blocks in more than one of the `(first block, length, owner)`s.
+pub(crate) fn overlaps(claimed: &[(u64, u64, u32)]) -> Vec<SharedExtent> {
+    // (block, whether a claim starts here, rather than ends, owner)
+    let mut edges = Vec::with_capacity(claimed.len() * 2);
+    for &(start, len, owner) in claimed {
+        if 0 != len {
+            edges.push((start, true, owner));
+            edges.push((start + len, false, owner));
+        }
+    }
+    edges.sort_unstable();
+
+    let mut shared: Vec<SharedExtent> = Vec::new();
+    // owners claiming the blocks since the last edge, and how many times
+    let mut active: BTreeMap<u32, usize> = BTreeMap::new();
+    let mut edges = edges.into_iter().peekable();
+    let mut from = 0;
+    while let Some(&(at, _, _)) = edges.peek() {
+        if active.values().sum::<usize>() > 1 && at > from {
+            let inodes = active
+                .iter()
+                .flat_map(|(&owner, &times)| std::iter::repeat(owner).take(times))
+                .collect::<Vec<_>>();
+            match shared.last_mut() {
+                Some(last) if last.physical + last.len == from && last.inodes == inodes => {
+                    last.len += at - from;
+                }
+                _ => shared.push(SharedExtent {
+                    physical: from,
+                    len: at - from,
+                    inodes,
+                }),
+            }
+        }
+
+        while let Some((_, starts, owner)) = edges.next_if(|&(block, _, _)| block == at) {
+            if starts {
+                *active.entry(owner).or_default() += 1;
+            } else if let Some(times) = active.get_mut(&owner) {
+                *times -= 1;
+                if 0 == *times {
+                    active.remove(&owner);
+                }
+            }
+        }
+        from = at;
+    }
+
+    shared
+}
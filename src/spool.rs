@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+use positioned_io2::WriteAt;
+
+use crate::SuperBlock;
+
+/// How much is taken from the stream at once.
+const CHUNK: usize = 64 * 1024;
+
+/// Open a filesystem which can only be read from start to end, such as a pipe from
+/// `zcat`, by copying the stream to a file in `spool_dir` as it's needed. See `Spool`.
+pub fn ingest_stream<S>(source: S, spool_dir: &Path) -> Result<SuperBlock<Spool<S>>, Error>
+where
+    S: io::Read,
+{
+    let spool = Spool::new(source, spool_dir)
+        .with_context(|| anyhow!("creating a spool file in {:?}", spool_dir))?;
+    SuperBlock::new(spool)
+}
+
+/// A stream, as a `ReadAt`: everything read from the stream is copied to a file, which
+/// later reads come from. A read beyond what has been copied so far waits while the stream
+/// is read up to it, so reading near the end of the image reads the whole stream, and
+/// the file grows to the size of the image.
+///
+/// The file is removed as soon as it's created, so it's gone when the spool is dropped,
+/// however the process ends. On systems which can't remove an open file, it's left behind.
+pub struct Spool<S> {
+    state: Mutex<State<S>>,
+}
+
+struct State<S> {
+    /// `None` once it has ended.
+    source: Option<S>,
+    file: fs::File,
+    /// How much has been copied to `file`.
+    len: u64,
+    chunk: Vec<u8>,
+}
+
+impl<S> Spool<S>
+where
+    S: io::Read,
+{
+    pub fn new(source: S, spool_dir: &Path) -> io::Result<Spool<S>> {
+        Ok(Spool {
+            state: Mutex::new(State {
+                source: Some(source),
+                file: create_spool(spool_dir)?,
+                len: 0,
+                chunk: vec![0u8; CHUNK],
+            }),
+        })
+    }
+
+    /// How much of the stream has been copied so far.
+    pub fn spooled(&self) -> u64 {
+        self.state.lock().expect("not poisoned").len
+    }
+
+    /// Read the rest of the stream, returning its length, e.g. to tell `Options::len`.
+    pub fn finish(&self) -> io::Result<u64> {
+        let mut state = self.state.lock().expect("not poisoned");
+        state.fill_to(u64::MAX)?;
+        Ok(state.len)
+    }
+}
+
+impl<S> State<S>
+where
+    S: io::Read,
+{
+    fn fill_to(&mut self, end: u64) -> io::Result<()> {
+        while self.len < end {
+            let source = match self.source.as_mut() {
+                Some(source) => source,
+                None => break,
+            };
+            let read = match source.read(&mut self.chunk) {
+                Ok(read) => read,
+                Err(e) if io::ErrorKind::Interrupted == e.kind() => continue,
+                Err(e) => return Err(e),
+            };
+            if 0 == read {
+                self.source = None;
+                break;
+            }
+            self.file.write_all_at(self.len, &self.chunk[..read])?;
+            self.len += read as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<S> ReadAt for Spool<S>
+where
+    S: io::Read,
+{
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().expect("not poisoned");
+        state.fill_to(pos.saturating_add(buf.len() as u64))?;
+        if pos >= state.len {
+            return Ok(0);
+        }
+        let available = (state.len - pos).min(buf.len() as u64) as usize;
+        state.file.read_at(pos, &mut buf[..available])
+    }
+}
+
+fn create_spool(dir: &Path) -> io::Result<fs::File> {
+    static CREATED: AtomicU32 = AtomicU32::new(0);
+
+    loop {
+        let path = dir.join(format!(
+            ".ext4-spool-{}-{}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = match fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => file,
+            // left by an earlier process with our id
+            Err(e) if io::ErrorKind::AlreadyExists == e.kind() => continue,
+            Err(e) => return Err(e),
+        };
+        let _ = fs::remove_file(&path);
+        return Ok(file);
+    }
+}
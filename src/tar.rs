@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::ArchiveEntry;
+use crate::ArchiveSink;
+use crate::Enhanced;
+use crate::SuperBlock;
+
+const BLOCK: usize = 512;
+
+/// Writes a POSIX (pax) tar file, as it goes. Plain ustar headers are used unless a
+/// name, size, id or time doesn't fit, then a pax extended header is added first.
+pub struct TarWriter<W> {
+    inner: W,
+}
+
+/// The fields of a header, before they're squeezed into ustar's fixed sizes.
+pub(crate) struct Header {
+    pub(crate) path: String,
+    pub(crate) mode: u16,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) size: u64,
+    pub(crate) mtime: i64,
+    pub(crate) kind: u8,
+    pub(crate) link: Vec<u8>,
+    pub(crate) device: (u32, u32),
+    /// Written as `SCHILY.xattr.*` pax records, as GNU tar and bsdtar do.
+    pub(crate) xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl Header {
+    /// The header for an entry, or `None` if tar can't represent it.
+    pub(crate) fn for_entry(entry: &ArchiveEntry) -> Option<Header> {
+        let stat = &entry.inode.stat;
+        let mut header = Header {
+            path: entry.path.to_string(),
+            mode: stat.file_mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: 0,
+            mtime: stat.mtime.epoch_secs,
+            kind: b'0',
+            link: Vec::new(),
+            device: (0, 0),
+            xattrs: tar_xattrs(&stat.xattrs),
+        };
+
+        if let Some(target) = entry.hard_link_to {
+            header.kind = b'1';
+            header.link = target.to_string().into_bytes();
+            return Some(header);
+        }
+
+        match entry.enhanced {
+            Enhanced::RegularFile => header.size = stat.size,
+            Enhanced::Directory(_) => {
+                header.kind = b'5';
+                header.path.push('/');
+            }
+            Enhanced::SymbolicLink(target) => {
+                header.kind = b'2';
+                header.link = crate::path_bytes(target);
+            }
+            Enhanced::CharacterDevice(major, minor) => {
+                header.kind = b'3';
+                header.device = (u32::from(*major), *minor);
+            }
+            Enhanced::BlockDevice(major, minor) => {
+                header.kind = b'4';
+                header.device = (u32::from(*major), *minor);
+            }
+            Enhanced::Fifo => header.kind = b'6',
+            Enhanced::Socket => return None,
+        }
+        Some(header)
+    }
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> TarWriter<W> {
+        TarWriter { inner }
+    }
+
+    /// Write the end-of-archive marker, and return the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.write_all(&[0; 2 * BLOCK])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Write the header, and, for regular files, `header.size` bytes of `content`.
+    pub(crate) fn write_entry(
+        &mut self,
+        header: &Header,
+        content: &mut dyn Read,
+    ) -> Result<(), Error> {
+        self.write_header(header)?;
+
+        if b'0' == header.kind {
+            let written = io::copy(&mut content.take(header.size), &mut self.inner)?;
+            ensure!(
+                written == header.size,
+                "{:?} ended after {} bytes, not {}",
+                header.path,
+                written,
+                header.size
+            );
+            self.pad(written)?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self, header: &Header) -> Result<(), Error> {
+        let mut block = [0u8; BLOCK];
+        let mut pax = Vec::new();
+
+        if !put_str(&mut block[0..100], &header.path) {
+            pax_record(&mut pax, "path", header.path.as_bytes());
+        }
+        put_octal(&mut block[100..108], u64::from(header.mode & 0o7777));
+        if !put_octal(&mut block[108..116], u64::from(header.uid)) {
+            pax_record(&mut pax, "uid", header.uid.to_string().as_bytes());
+        }
+        if !put_octal(&mut block[116..124], u64::from(header.gid)) {
+            pax_record(&mut pax, "gid", header.gid.to_string().as_bytes());
+        }
+        if !put_octal(&mut block[124..136], header.size) {
+            pax_record(&mut pax, "size", header.size.to_string().as_bytes());
+        }
+        let fits = u64::try_from(header.mtime)
+            .map(|mtime| put_octal(&mut block[136..148], mtime))
+            .unwrap_or(false);
+        if !fits {
+            pax_record(&mut pax, "mtime", header.mtime.to_string().as_bytes());
+        }
+        block[156] = header.kind;
+        if !put_bytes(&mut block[157..257], &header.link) {
+            pax_record(&mut pax, "linkpath", &header.link);
+        }
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        put_octal(&mut block[329..337], u64::from(header.device.0));
+        put_octal(&mut block[337..345], u64::from(header.device.1));
+        for (name, value) in &header.xattrs {
+            pax_record(&mut pax, &format!("SCHILY.xattr.{}", name), value);
+        }
+
+        if !pax.is_empty() {
+            let name = header.path.rsplit('/').next().unwrap_or("");
+            let mut pax_block = [0u8; BLOCK];
+            put_str(&mut pax_block[0..100], &format!("PaxHeaders/{:.80}", name));
+            put_octal(&mut pax_block[100..108], 0o644);
+            put_octal(&mut pax_block[108..116], 0);
+            put_octal(&mut pax_block[116..124], 0);
+            put_octal(&mut pax_block[124..136], u64::try_from(pax.len())?);
+            put_octal(&mut pax_block[136..148], 0);
+            pax_block[156] = b'x';
+            pax_block[257..263].copy_from_slice(b"ustar\0");
+            pax_block[263..265].copy_from_slice(b"00");
+            self.write_block(pax_block)?;
+            self.inner.write_all(&pax)?;
+            self.pad(u64::try_from(pax.len())?)?;
+        }
+
+        self.write_block(block)
+    }
+
+    fn write_block(&mut self, mut block: [u8; BLOCK]) -> Result<(), Error> {
+        // the checksum is of the header with the checksum field set to spaces
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        self.inner.write_all(&block)?;
+        Ok(())
+    }
+
+    /// Fill the rest of the block after `len` bytes of content.
+    fn pad(&mut self, len: u64) -> io::Result<()> {
+        let used = (len % BLOCK as u64) as usize;
+        if 0 != used {
+            self.inner.write_all(&[0; BLOCK][used..])?;
+        }
+        Ok(())
+    }
+}
+
+/// Sockets can't be represented, and are left out. User and group names are left
+/// blank, so extracting uses the numeric ids.
+impl<W: Write> ArchiveSink for TarWriter<W> {
+    fn add(&mut self, entry: &ArchiveEntry, content: &mut dyn Read) -> Result<(), Error> {
+        match Header::for_entry(entry) {
+            Some(header) => self.write_entry(&header, content),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Write everything under `path` to a tar, with names relative to `path`.
+    pub fn export_tar<W: Write>(&self, path: &str, out: W) -> Result<W, Error> {
+        let mut tar = TarWriter::new(out);
+        self.export(path, &mut tar)?;
+        tar.finish()
+    }
+}
+
+/// Extended attributes as Linux presents them. ext4 stores ACLs in its own, more
+/// compact, format; those which can't be understood are left out.
+pub(crate) fn tar_xattrs(xattrs: &HashMap<String, Vec<u8>>) -> BTreeMap<String, Vec<u8>> {
+    xattrs
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = match name.as_str() {
+                "system.posix_acl_access" | "system.posix_acl_default" => vfs_acl(value)?,
+                _ => value.clone(),
+            };
+            Some((name.clone(), value))
+        })
+        .collect()
+}
+
+/// ext4's `ext4_acl_header` and entries, to the kernel's `posix_acl_xattr_header`,
+/// which has a version of 2 and an id in every entry.
+fn vfs_acl(ext4: &[u8]) -> Option<Vec<u8>> {
+    const ACL_USER: u16 = 0x02;
+    const ACL_GROUP: u16 = 0x08;
+
+    if ext4.get(..4)? != 1u32.to_le_bytes() {
+        return None;
+    }
+
+    let mut vfs = 2u32.to_le_bytes().to_vec();
+    let mut rest = &ext4[4..];
+    while !rest.is_empty() {
+        let tag = u16::from_le_bytes([*rest.first()?, *rest.get(1)?]);
+        let perm = rest.get(2..4)?;
+        let id = if ACL_USER == tag || ACL_GROUP == tag {
+            let id = rest.get(4..8)?;
+            rest = &rest[8..];
+            id
+        } else {
+            rest = &rest[4..];
+            &[0xff; 4][..]
+        };
+        vfs.extend_from_slice(&tag.to_le_bytes());
+        vfs.extend_from_slice(perm);
+        vfs.extend_from_slice(id);
+    }
+    Some(vfs)
+}
+
+/// Copy `value` into a field, if it fits. It doesn't need a terminator.
+fn put_str(field: &mut [u8], value: &str) -> bool {
+    put_bytes(field, value.as_bytes())
+}
+
+fn put_bytes(field: &mut [u8], bytes: &[u8]) -> bool {
+    if bytes.len() > field.len() {
+        return false;
+    }
+    field[..bytes.len()].copy_from_slice(bytes);
+    true
+}
+
+/// Zero-padded octal, with a terminating NUL, if it fits.
+fn put_octal(field: &mut [u8], value: u64) -> bool {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    if text.len() > digits {
+        return false;
+    }
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+    true
+}
+
+/// A pax record is `"<len> <key>=<value>\n"`, where `len` includes its own digits.
+fn pax_record(pax: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let rest = 1 + key.len() + 1 + value.len() + 1;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    pax.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    pax.extend_from_slice(value);
+    pax.push(b'\n');
+}
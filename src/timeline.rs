@@ -0,0 +1,132 @@
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::DeletedSource;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+use crate::Time;
+
+/// A file and its times, for building a timeline of activity on the filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TimelineEntry {
+    /// For deleted inodes, which have no name, `$OrphanFiles/OrphanFile-N`, like The
+    /// Sleuth Kit.
+    pub path: String,
+    pub inode: u32,
+    pub file_type: FileType,
+    /// The permission bits, as in `Stat::file_mode`.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: Time,
+    pub mtime: Time,
+    pub ctime: Time,
+    pub btime: Option<Time>,
+    pub deleted: bool,
+}
+
+impl TimelineEntry {
+    fn new(path: String, inode: &Inode, deleted: bool) -> TimelineEntry {
+        let stat = &inode.stat;
+        TimelineEntry {
+            path,
+            inode: inode.number,
+            file_type: stat.extracted_type,
+            mode: stat.file_mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            atime: stat.atime.clone(),
+            mtime: stat.mtime.clone(),
+            ctime: stat.ctime.clone(),
+            btime: stat.btime.clone(),
+            deleted,
+        }
+    }
+
+    /// A line of a TSK 3 "body file", as read by `mactime`, without the newline:
+    /// `MD5|name|inode|mode|UID|GID|size|atime|mtime|ctime|crtime`.
+    /// The MD5 isn't computed, and is always `0`.
+    pub fn body_line(&self) -> String {
+        format!(
+            "0|{}{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+            self.path,
+            if self.deleted { " (deleted)" } else { "" },
+            self.inode,
+            mode_string(self.file_type, self.mode),
+            self.uid,
+            self.gid,
+            self.size,
+            self.atime.epoch_secs,
+            self.mtime.epoch_secs,
+            self.ctime.epoch_secs,
+            self.btime.as_ref().map_or(0, |btime| btime.epoch_secs),
+        )
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Every file reachable from the root, in walk order, then, if `include_deleted`,
+    /// the deleted inodes still in the inode tables.
+    pub fn timeline(&self, include_deleted: bool) -> Result<Vec<TimelineEntry>, Error> {
+        let mut entries = Vec::new();
+        self.walk(&self.root()?, "", &mut |_, path, inode, _| {
+            let path = if path.is_empty() { "/" } else { path };
+            entries.push(TimelineEntry::new(path.to_string(), inode, false));
+            Ok(true)
+        })?;
+
+        if include_deleted {
+            for deleted in self.deleted_inodes()? {
+                if DeletedSource::InodeTable != deleted.source {
+                    continue;
+                }
+                let number = deleted.inode.number;
+                entries.push(TimelineEntry::new(
+                    format!("$OrphanFiles/OrphanFile-{}", number),
+                    &deleted.inode,
+                    true,
+                ));
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Like `ls -l`, but prefixed with the type again, as TSK does: `r/rrw-r--r--`.
+fn mode_string(file_type: FileType, mode: u16) -> String {
+    let kind = match file_type {
+        FileType::RegularFile => 'r',
+        FileType::Directory => 'd',
+        FileType::SymbolicLink => 'l',
+        FileType::CharacterDevice => 'c',
+        FileType::BlockDevice => 'b',
+        FileType::Fifo => 'p',
+        FileType::Socket => 'h',
+    };
+
+    let mut out = format!("{}/{}", kind, kind);
+    for (shift, special, set, unset) in [
+        (6, 0o4000, 's', 'S'),
+        (3, 0o2000, 's', 'S'),
+        (0, 0o1000, 't', 'T'),
+    ] {
+        let bits = (mode >> shift) & 0o7;
+        out.push(if 0 != bits & 0o4 { 'r' } else { '-' });
+        out.push(if 0 != bits & 0o2 { 'w' } else { '-' });
+        out.push(match (0 != bits & 0o1, 0 != mode & special) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    out
+}
@@ -0,0 +1,109 @@
+use std::io;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use positioned_io2::ReadAt;
+
+/// Wraps a reader which might never return, such as a network block device, or a dying
+/// disc, so a single read can't stall a whole walk or scan. Reads which take longer than
+/// the deadline fail with `io::ErrorKind::TimedOut`, which, like any other read error,
+/// fails whatever the crate was doing: the entry being read, for `walk`'s callers, or
+/// the content being read, for `TreeReader`'s.
+///
+/// Each read is made on a worker thread, which the caller waits for. A worker whose read
+/// timed out is abandoned, and exits whenever the read finally returns; the others are
+/// kept for later reads, and there are as many as there are concurrent reads.
+pub struct TimeoutReader<R> {
+    inner: Arc<R>,
+    deadline: Duration,
+    idle: Mutex<Vec<Worker>>,
+}
+
+struct Worker {
+    requests: mpsc::Sender<(u64, usize)>,
+    responses: mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+impl<R> TimeoutReader<R>
+where
+    R: ReadAt + Send + Sync + 'static,
+{
+    pub fn new(inner: R, deadline: Duration) -> TimeoutReader<R> {
+        TimeoutReader {
+            inner: Arc::new(inner),
+            deadline,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    fn worker(&self) -> io::Result<Worker> {
+        if let Some(worker) = self.idle.lock().expect("not poisoned").pop() {
+            return Ok(worker);
+        }
+
+        let (requests, incoming) = mpsc::channel::<(u64, usize)>();
+        let (outgoing, responses) = mpsc::channel();
+        let inner = Arc::clone(&self.inner);
+        thread::Builder::new()
+            .name("ext4-timeout-reader".to_string())
+            .spawn(move || {
+                for (pos, len) in incoming {
+                    let mut buf = vec![0u8; len];
+                    let read = inner.read_at(pos, &mut buf).map(|read| {
+                        buf.truncate(read);
+                        buf
+                    });
+                    // the caller gave up on us
+                    if outgoing.send(read).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Worker {
+            requests,
+            responses,
+        })
+    }
+}
+
+impl<R> ReadAt for TimeoutReader<R>
+where
+    R: ReadAt + Send + Sync + 'static,
+{
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let worker = self.worker()?;
+        let gone = || io::Error::new(io::ErrorKind::Other, "timeout reader's worker died");
+        worker.requests.send((pos, buf.len())).map_err(|_| gone())?;
+        let data = match worker.responses.recv_timeout(self.deadline) {
+            Ok(read) => read,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "read of {} bytes at {} took more than {:?}",
+                        buf.len(),
+                        pos,
+                        self.deadline
+                    ),
+                ))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(gone()),
+        };
+        self.idle.lock().expect("not poisoned").push(worker);
+
+        let data = data?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
@@ -0,0 +1,265 @@
+use std::convert::TryFrom;
+use std::io;
+use std::ops::Range;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::add_signed;
+use crate::BlockGroupFlags;
+use crate::SuperBlock;
+
+/// The free space of a filesystem, read as if it were one file: each run of
+/// unallocated blocks, one after another. Made by `SuperBlock::unallocated`.
+///
+/// Useful for running a carving tool over only the space that might have old
+/// content in it. `physical_offset` maps a position back to where it is on disc.
+pub struct UnallocatedReader<'a, R> {
+    inner: &'a R,
+    block_size: u64,
+    /// The position each run starts at in this reader, and the blocks in it.
+    runs: Vec<(u64, Range<u64>)>,
+    len: u64,
+    pos: u64,
+}
+
+/// How far into a filesystem anything is, from `SuperBlock::high_water_mark`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HighWaterMark {
+    /// One more than the highest block in use: an image only needs this many blocks
+    /// copying, as everything after is free.
+    pub blocks: u64,
+    /// `blocks`, in bytes.
+    pub len: u64,
+    /// The free blocks after the mark, which cutting the filesystem off there would reclaim.
+    pub reclaimable: u64,
+    /// Every free block, wherever it is. Getting back more than `reclaimable` means
+    /// moving things into the free space before the mark, as `resize2fs -M` does.
+    pub free: u64,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// The runs of blocks which aren't allocated, according to the block bitmaps, in order.
+    ///
+    /// Groups which have never had any blocks allocated don't have a bitmap on disc;
+    /// everything in them is free except the group metadata, which is worked out from
+    /// the superblock and descriptors.
+    pub fn unallocated_ranges(&self) -> Result<Vec<Range<u64>>, Error> {
+        let block_size = u64::from(self.groups.block_size);
+        let mut bitmap = vec![0u8; usize::try_from(block_size)?];
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        let mut tables = None;
+
+        for number in 0..self.groups.count() {
+            let group = self.groups.get(&self.inner, number)?;
+            if group.flags.contains(BlockGroupFlags::BLOCK_UNINIT) {
+                let tables = match &mut tables {
+                    Some(tables) => tables,
+                    None => tables.insert(self.group_tables()?),
+                };
+                bitmap.iter_mut().for_each(|byte| *byte = 0);
+                let mut mark = |range: &Range<u64>| {
+                    let start = range.start.max(group.first_block);
+                    let end = range.end.min(group.last_block + 1);
+                    for block in start..end {
+                        let index = (block - group.first_block) as usize;
+                        bitmap[index / 8] |= 1 << (index % 8);
+                    }
+                };
+                mark(&self.superblock_backup(number)?);
+                tables.iter().for_each(mark);
+            } else {
+                self.inner
+                    .read_exact_at(group.block_bitmap * block_size, &mut bitmap)?;
+            }
+
+            for block in group.first_block..=group.last_block {
+                let index = usize::try_from(block - group.first_block)?;
+                if 0 != bitmap[index / 8] & (1 << (index % 8)) {
+                    continue;
+                }
+
+                match ranges.last_mut() {
+                    Some(last) if last.end == block => last.end += 1,
+                    _ => ranges.push(block..block + 1),
+                }
+            }
+        }
+
+        Ok(ranges)
+    }
+
+    /// Where the blocks in use end, according to the block bitmaps, so an imaging tool
+    /// knows how much of the filesystem it has to copy, and how much free space is at the
+    /// end, which could be trimmed off. The group metadata, including backup superblocks,
+    /// counts as in use. An image cut off at the mark still says it's the full size, so
+    /// is only readable with `Truncation::Tolerate` until it's shrunk to match.
+    pub fn high_water_mark(&self) -> Result<HighWaterMark, Error> {
+        let blocks_count = self.groups.blocks_count;
+        let ranges = self.unallocated_ranges()?;
+        let free = ranges.iter().map(|range| range.end - range.start).sum();
+        let blocks = match ranges.last() {
+            Some(last) if blocks_count == last.end => last.start,
+            _ => blocks_count,
+        };
+
+        Ok(HighWaterMark {
+            blocks,
+            len: blocks * u64::from(self.groups.block_size),
+            reclaimable: blocks_count - blocks,
+            free,
+        })
+    }
+
+    /// Every unallocated block, in order. See `unallocated_ranges`.
+    pub fn unallocated_blocks(&self) -> Result<impl Iterator<Item = u64>, Error> {
+        Ok(self.unallocated_ranges()?.into_iter().flatten())
+    }
+
+    /// Read all of the unallocated blocks, one after another.
+    pub fn unallocated(&self) -> Result<UnallocatedReader<'_, R>, Error> {
+        let block_size = u64::from(self.groups.block_size);
+        let mut len = 0;
+        let runs = self
+            .unallocated_ranges()?
+            .into_iter()
+            .map(|range| {
+                let start = len;
+                len += (range.end - range.start) * block_size;
+                (start, range)
+            })
+            .collect();
+
+        Ok(UnallocatedReader {
+            inner: &self.inner,
+            block_size,
+            runs,
+            len,
+            pos: 0,
+        })
+    }
+
+    /// The superblock and descriptor backups at the start of a group, if it has them.
+    pub(crate) fn superblock_backup(&self, number: u32) -> Result<Range<u64>, Error> {
+        let group = self.groups.get(&self.inner, number)?;
+        if !self.has_superblock_backup(number) {
+            return Ok(group.first_block..group.first_block);
+        }
+
+        let block_size = u64::from(self.groups.block_size);
+        let desc_size = match self.raw.s_desc_size {
+            0 => 32,
+            size => u64::from(size),
+        };
+        let descriptors = u64::from(self.groups.count()) * desc_size;
+        let len = 1
+            + (descriptors + block_size - 1) / block_size
+            + u64::from(self.raw.s_reserved_gdt_blocks);
+        Ok(group.first_block..group.first_block + len)
+    }
+
+    /// Every group's bitmaps and inode table: with `flex_bg`, these can be in any group.
+    fn group_tables(&self) -> Result<Vec<Range<u64>>, Error> {
+        let mut tables = Vec::new();
+        for number in 0..self.groups.count() {
+            let group = self.groups.get(&self.inner, number)?;
+            tables.push(group.block_bitmap..group.block_bitmap + 1);
+            tables.push(group.inode_bitmap..group.inode_bitmap + 1);
+            tables.push(group.inode_table..group.inode_table + group.inode_table_blocks);
+        }
+        Ok(tables)
+    }
+
+    pub(crate) fn has_superblock_backup(&self, number: u32) -> bool {
+        const SPARSE_SUPER: u32 = 0x0001;
+        const SPARSE_SUPER2: u32 = 0x0200;
+        if 0 != self.raw.s_feature_compat & SPARSE_SUPER2 {
+            return 0 == number || self.raw.s_backup_bgs.contains(&number);
+        }
+        if 0 == self.raw.s_feature_ro_compat & SPARSE_SUPER || number <= 1 {
+            return true;
+        }
+
+        [3, 5, 7].iter().any(|&base| {
+            let mut power = base;
+            while power < number {
+                power *= base;
+            }
+            power == number
+        })
+    }
+}
+
+impl<'a, R> UnallocatedReader<'a, R> {
+    /// The total size of the free space, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        0 == self.len
+    }
+
+    /// Where a position in this reader is on disc, in bytes from the start of the filesystem.
+    pub fn physical_offset(&self, pos: u64) -> Option<u64> {
+        if pos >= self.len {
+            return None;
+        }
+        let (start, blocks) = &self.runs[self.run_containing(pos)];
+        Some(blocks.start * self.block_size + (pos - start))
+    }
+
+    fn run_containing(&self, pos: u64) -> usize {
+        match self.runs.binary_search_by_key(&pos, |(start, _)| *start) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        }
+    }
+}
+
+impl<'a, R> ReadAt for UnallocatedReader<'a, R>
+where
+    R: ReadAt,
+{
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (start, blocks) = &self.runs[self.run_containing(pos)];
+        let run_end = start + (blocks.end - blocks.start) * self.block_size;
+        let len = usize::try_from(run_end - pos)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        let physical = blocks.start * self.block_size + (pos - start);
+        self.inner.read_at(physical, &mut buf[..len])
+    }
+}
+
+impl<'a, R> io::Read for UnallocatedReader<'a, R>
+where
+    R: ReadAt,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_at(self.pos, buf)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a, R> io::Seek for UnallocatedReader<'a, R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            io::SeekFrom::Start(set) => Some(set),
+            io::SeekFrom::Current(diff) => add_signed(self.pos, diff),
+            io::SeekFrom::End(diff) => add_signed(self.len, diff),
+        };
+        self.pos = pos
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.pos)
+    }
+}
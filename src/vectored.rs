@@ -0,0 +1,60 @@
+use std::io;
+
+use positioned_io2::ReadAt;
+
+/// Don't read more than this many unwanted bytes to join two requests together.
+const MAX_GAP: u64 = 64 * 1024;
+
+/// Fill every buffer from its offset, like `read_exact_at` on each. Requests which are
+/// close together are joined into a single read, which is much faster on storage where
+/// each request has a high latency.
+///
+/// `ReadAt` belongs to `positioned_io2`, so this can't be a method on it.
+pub fn read_vectored_at<R>(inner: &R, requests: &mut [(u64, &mut [u8])]) -> io::Result<()>
+where
+    R: ReadAt + ?Sized,
+{
+    let mut order = (0..requests.len()).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|&index| requests[index].0);
+
+    let mut start = 0;
+    while start < order.len() {
+        let first = requests[order[start]].0;
+        let mut last = end_of(&requests[order[start]])?;
+        let mut end = start + 1;
+        while end < order.len() && requests[order[end]].0 <= last.saturating_add(MAX_GAP) {
+            last = last.max(end_of(&requests[order[end]])?);
+            end += 1;
+        }
+
+        if 1 == end - start {
+            let (offset, ref mut buf) = requests[order[start]];
+            inner.read_exact_at(offset, buf)?;
+        } else {
+            let mut data = vec![0u8; (last - first) as usize];
+            inner.read_exact_at(first, &mut data)?;
+            for &index in &order[start..end] {
+                let (offset, ref mut buf) = requests[index];
+                let from = (offset - first) as usize;
+                let len = buf.len();
+                buf.copy_from_slice(&data[from..from + len]);
+            }
+        }
+
+        start = end;
+    }
+
+    Ok(())
+}
+
+/// Where a request's buffer ends, which must not be past the end of the address space.
+fn end_of(&(offset, ref buf): &(u64, &mut [u8])) -> io::Result<u64> {
+    offset.checked_add(buf.len() as u64).ok_or_else(|| {
+        let message = format!(
+            "{} bytes at {} is past the end of anything",
+            buf.len(),
+            offset
+        );
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    })
+}
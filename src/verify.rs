@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::ensure;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::assumption_failed;
+use crate::check::allocated_inodes;
+use crate::check::group_findings;
+use crate::check::inode_region;
+use crate::parse_error;
+use crate::sharing::has_data_blocks;
+use crate::Finding;
+use crate::Phase;
+use crate::Progress;
+use crate::Region;
+use crate::Severity;
+use crate::SuperBlock;
+
+/// How far `SuperBlock::verify` has got through a filesystem, so a later call, maybe from
+/// another process, can carry on from there. It's shown as, and parsed from, a line like
+/// `0b3c5e2a9d8f4c6e8a1b2c3d4e5f6a7b:4/16:2049`: the filesystem's UUID, the next group,
+/// of how many, and the next inode in that group, counting from zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyCursor {
+    /// So a cursor isn't used to carry on through a different filesystem.
+    uuid: [u8; 16],
+    group: u32,
+    groups: u32,
+    index: u32,
+}
+
+impl VerifyCursor {
+    /// Every group, and every inode, has been verified.
+    pub fn is_finished(&self) -> bool {
+        self.group >= self.groups
+    }
+
+    /// How many groups have been verified, of how many.
+    pub fn groups_done(&self) -> (u32, u32) {
+        (self.group.min(self.groups), self.groups)
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// A cursor at the start of this filesystem, for `verify`.
+    pub fn verify_cursor(&self) -> VerifyCursor {
+        VerifyCursor {
+            uuid: self.raw.s_uuid,
+            group: 0,
+            groups: self.groups.count(),
+            index: 0,
+        }
+    }
+
+    /// Verify the filesystem a piece at a time, from `cursor`, until it has all been
+    /// verified, or `progress` is cancelled, e.g. by an `Instant` which has passed;
+    /// `cursor` is moved on past everything verified. Each group's descriptor is checked,
+    /// and every inode its bitmap says is in use is loaded, verifying its checksum, and
+    /// its extent tree's. Returns what was found in this piece, by phase.
+    ///
+    /// Unlike `check`, this doesn't walk the directories, so doesn't look at how the
+    /// inodes are linked together, but the work is the same for any piece, so can be
+    /// bounded, and nothing needs to be remembered between pieces except the cursor.
+    pub fn verify(
+        &self,
+        cursor: &mut VerifyCursor,
+        progress: &dyn Progress,
+    ) -> Result<Vec<Finding>, Error> {
+        ensure!(
+            self.raw.s_uuid == cursor.uuid && self.groups.count() == cursor.groups,
+            assumption_failed("the cursor is for a different filesystem")
+        );
+
+        let mut findings = Vec::new();
+        while !cursor.is_finished() && !progress.cancelled() {
+            let inodes = match allocated_inodes(self, cursor.group) {
+                Ok(inodes) => inodes,
+                Err(e) => {
+                    findings.push(Finding {
+                        phase: Phase::Groups,
+                        severity: Severity::Error,
+                        inode: None,
+                        message: format!(
+                            "group {} inode bitmap is unreadable: {:#}",
+                            cursor.group, e
+                        ),
+                        region: None,
+                    });
+                    Vec::new()
+                }
+            };
+
+            let first = cursor.group * self.groups.inodes_per_group() + 1;
+            let mut cancelled = false;
+            for inode in inodes {
+                let index = inode - first;
+                if index < cursor.index {
+                    continue;
+                }
+                if progress.cancelled() {
+                    cancelled = true;
+                    break;
+                }
+                self.verify_inode(inode, &mut findings);
+                cursor.index = index + 1;
+            }
+            if cancelled {
+                break;
+            }
+
+            // last, so it's reported once, however the group is split between pieces
+            findings.extend(group_findings(self, cursor.group)?);
+            cursor.group += 1;
+            cursor.index = 0;
+        }
+
+        findings.sort_by_key(|finding| finding.phase);
+        Ok(findings)
+    }
+
+    fn verify_inode(&self, number: u32, findings: &mut Vec<Finding>) {
+        // reserved for the filesystem's own use, like the resize inode, so not files
+        if number < self.first_inode && 2 != number {
+            return;
+        }
+
+        let mut report = |message: String, region: Option<Region>| {
+            findings.push(Finding {
+                phase: Phase::Inodes,
+                severity: Severity::Error,
+                inode: Some(number),
+                message,
+                region,
+            })
+        };
+
+        let inode = match self.load_inode(number) {
+            Ok(inode) => inode,
+            Err(e) => {
+                return report(
+                    format!("<{}> is unreadable: {:#}", number, e),
+                    inode_region(self, number),
+                )
+            }
+        };
+        if !has_data_blocks(&inode) {
+            return;
+        }
+        let extents = match self.data_extents(&inode) {
+            Ok(extents) => extents,
+            Err(e) => {
+                return report(
+                    format!("<{}> has an unreadable extent tree: {:#}", number, e),
+                    None,
+                )
+            }
+        };
+
+        let first_block = u64::from(self.raw.s_first_data_block);
+        let blocks_count = self.groups.blocks_count;
+        for extent in extents {
+            let len = u64::from(extent.len);
+            if extent.physical < first_block || extent.physical + len > blocks_count {
+                report(
+                    format!(
+                        "<{}> has blocks {}-{}, outside the filesystem",
+                        number,
+                        extent.physical,
+                        extent.physical + len - 1
+                    ),
+                    None,
+                );
+            } else if let Some(missing) = self.first_missing_block() {
+                if extent.physical + len > missing {
+                    report(
+                        format!(
+                            "<{}> has blocks past the end of the image, from {}",
+                            number,
+                            extent.physical.max(missing)
+                        ),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for VerifyCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.uuid {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, ":{}/{}:{}", self.group, self.groups, self.index)
+    }
+}
+
+impl FromStr for VerifyCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<VerifyCursor, Error> {
+        let bad = || parse_error(format!("invalid verify cursor: {:?}", s));
+        let mut parts = s.trim().splitn(3, ':');
+        let (uuid, groups, index) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(uuid), Some(groups), Some(index)) => (uuid, groups, index),
+            _ => return Err(bad()),
+        };
+        let (group, groups) = groups.split_once('/').ok_or_else(bad)?;
+
+        ensure!(32 == uuid.len() && uuid.is_ascii(), bad());
+        let mut cursor = VerifyCursor {
+            uuid: [0; 16],
+            group: group.parse().map_err(|_| bad())?,
+            groups: groups.parse().map_err(|_| bad())?,
+            index: index.parse().map_err(|_| bad())?,
+        };
+        for (i, b) in cursor.uuid.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&uuid[i * 2..i * 2 + 2], 16).map_err(|_| bad())?;
+        }
+        Ok(cursor)
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Mutex;
+
+use anyhow::ensure;
+use anyhow::Error;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use positioned_io2::ReadAt;
+use positioned_io2::Size;
+
+use crate::assumption_failed;
+use crate::not_found;
+use crate::sha256::Sha256;
+use crate::unsupported_feature;
+
+const DIGEST_SIZE: usize = 32;
+
+/// How a dm-verity hash tree is laid out, from its superblock, or, for setups without
+/// one, such as Android's, from wherever they keep it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VerityParams {
+    pub data_block_size: u32,
+    pub hash_block_size: u32,
+    /// The number of data blocks covered by the tree.
+    pub data_blocks: u64,
+    pub salt: Vec<u8>,
+    /// Where the tree starts, on the hash device, in bytes.
+    pub tree_offset: u64,
+}
+
+/// Checks everything read from the data device against a dm-verity hash tree, and the
+/// root hash, so a reader can be sure the data is unmodified. Reads of data which
+/// doesn't match fail with `io::ErrorKind::InvalidData`.
+///
+/// The data and hash devices can be the same, with the tree after the filesystem.
+/// Only SHA-256, in the current (version 1) format, is supported.
+pub struct VerityReader<D, H> {
+    data: D,
+    hash: H,
+    params: VerityParams,
+    root_hash: [u8; DIGEST_SIZE],
+    /// The first block of each level, on the hash device; the root's level is last.
+    levels: Vec<u64>,
+    /// Hash blocks which have been checked, by level and index.
+    verified: Mutex<HashMap<(usize, u64), Vec<u8>>>,
+}
+
+impl VerityParams {
+    /// Read a verity superblock, as `veritysetup format` writes, at `offset` on the hash device.
+    pub fn read_superblock<H: ReadAt>(hash: H, offset: u64) -> Result<VerityParams, Error> {
+        let mut sb = [0u8; 512];
+        hash.read_exact_at(offset, &mut sb)?;
+        ensure!(b"verity\0\0" == &sb[..8], not_found("no verity superblock"));
+        let version = LittleEndian::read_u32(&sb[8..]);
+        let hash_type = LittleEndian::read_u32(&sb[12..]);
+        ensure!(
+            1 == version && 1 == hash_type,
+            unsupported_feature(format!(
+                "verity superblock version {}, hash type {}",
+                version, hash_type
+            ))
+        );
+        let algorithm = crate::info::c_string(&sb[32..64]);
+        ensure!(
+            "sha256" == algorithm,
+            unsupported_feature(format!("verity hash algorithm {}", algorithm))
+        );
+        let hash_block_size = LittleEndian::read_u32(&sb[68..]);
+        let salt_size = usize::from(LittleEndian::read_u16(&sb[80..]));
+        ensure!(
+            salt_size <= 256,
+            assumption_failed(format!("verity salt of {} bytes", salt_size))
+        );
+        ensure!(
+            hash_block_size.is_power_of_two() && hash_block_size >= 512,
+            assumption_failed(format!("verity hash block size {}", hash_block_size))
+        );
+
+        Ok(VerityParams {
+            data_block_size: LittleEndian::read_u32(&sb[64..]),
+            hash_block_size,
+            data_blocks: LittleEndian::read_u64(&sb[72..]),
+            salt: sb[88..88 + salt_size].to_vec(),
+            // the tree starts at the first whole hash block after the superblock
+            tree_offset: (offset + 512 + u64::from(hash_block_size) - 1)
+                / u64::from(hash_block_size)
+                * u64::from(hash_block_size),
+        })
+    }
+}
+
+impl<D: ReadAt, H: ReadAt> VerityReader<D, H> {
+    /// `root_hash` is the trusted hash, e.g. from `veritysetup format`, or a signed manifest.
+    pub fn new(
+        data: D,
+        hash: H,
+        params: VerityParams,
+        root_hash: &[u8],
+    ) -> Result<VerityReader<D, H>, Error> {
+        ensure!(
+            DIGEST_SIZE == root_hash.len(),
+            assumption_failed(format!(
+                "a sha256 root hash is {} bytes, not {}",
+                DIGEST_SIZE,
+                root_hash.len()
+            ))
+        );
+        ensure!(
+            params.data_block_size.is_power_of_two() && params.data_block_size >= 512,
+            assumption_failed(format!("verity data block size {}", params.data_block_size))
+        );
+        ensure!(
+            usize::try_from(params.hash_block_size)? >= 2 * DIGEST_SIZE,
+            assumption_failed(format!("verity hash block size {}", params.hash_block_size))
+        );
+
+        // like veritysetup: each level covers the one below, until one block covers it all,
+        // and they're stored from the top down
+        let per_block = u64::from(params.hash_block_size) / DIGEST_SIZE as u64;
+        let mut sizes = Vec::new();
+        let mut covering = params.data_blocks;
+        while covering > 1 {
+            covering = (covering + per_block - 1) / per_block;
+            sizes.push(covering);
+        }
+        let mut levels = vec![0; sizes.len()];
+        let mut position = params.tree_offset / u64::from(params.hash_block_size);
+        for level in (0..sizes.len()).rev() {
+            levels[level] = position;
+            position += sizes[level];
+        }
+
+        let mut root = [0u8; DIGEST_SIZE];
+        root.copy_from_slice(root_hash);
+        Ok(VerityReader {
+            data,
+            hash,
+            params,
+            root_hash: root,
+            levels,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn into_inner(self) -> (D, H) {
+        (self.data, self.hash)
+    }
+
+    fn digest(&self, block: &[u8]) -> [u8; DIGEST_SIZE] {
+        let mut digest = Sha256::new();
+        digest.update(&self.params.salt);
+        digest.update(block);
+        digest.finish()
+    }
+
+    /// The digest the tree expects a block at `level` to have; level 0 is the data.
+    fn expected(&self, level: usize, index: u64) -> io::Result<[u8; DIGEST_SIZE]> {
+        if level == self.levels.len() {
+            debug_assert_eq!(0, index);
+            return Ok(self.root_hash);
+        }
+        let per_block = u64::from(self.params.hash_block_size) / DIGEST_SIZE as u64;
+        let parent = self.hash_block(level, index / per_block)?;
+        let entry = usize::try_from(index % per_block).expect("within a block") * DIGEST_SIZE;
+        let mut expected = [0u8; DIGEST_SIZE];
+        expected.copy_from_slice(&parent[entry..entry + DIGEST_SIZE]);
+        Ok(expected)
+    }
+
+    /// A block of the tree, once it, and everything above it, has been checked.
+    fn hash_block(&self, level: usize, index: u64) -> io::Result<Vec<u8>> {
+        if let Some(block) = self.verified.lock().expect("poisoned").get(&(level, index)) {
+            return Ok(block.clone());
+        }
+
+        let size = u64::from(self.params.hash_block_size);
+        let mut block = vec![0u8; size as usize];
+        self.hash
+            .read_exact_at((self.levels[level] + index) * size, &mut block)?;
+        if self.digest(&block) != self.expected(level + 1, index)? {
+            return Err(mismatch(format!(
+                "verity hash block {} at level {}",
+                index, level
+            )));
+        }
+
+        self.verified
+            .lock()
+            .expect("poisoned")
+            .insert((level, index), block.clone());
+        Ok(block)
+    }
+}
+
+impl<D: ReadAt, H: ReadAt> ReadAt for VerityReader<D, H> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let block_size = u64::from(self.params.data_block_size);
+        let end = self.params.data_blocks * block_size;
+        if buf.is_empty() || pos >= end {
+            return Ok(0);
+        }
+
+        let index = pos / block_size;
+        let mut block = vec![0u8; block_size as usize];
+        self.data.read_exact_at(index * block_size, &mut block)?;
+        if self.digest(&block) != self.expected(0, index)? {
+            return Err(mismatch(format!("data block {}", index)));
+        }
+
+        let skip = (pos - index * block_size) as usize;
+        let len = buf.len().min(block.len() - skip);
+        buf[..len].copy_from_slice(&block[skip..skip + len]);
+        Ok(len)
+    }
+}
+
+impl<D, H> Size for VerityReader<D, H> {
+    fn size(&self) -> io::Result<Option<u64>> {
+        Ok(Some(
+            self.params.data_blocks * u64::from(self.params.data_block_size),
+        ))
+    }
+}
+
+fn mismatch(what: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} doesn't match the verity hash tree", what),
+    )
+}
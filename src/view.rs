@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::extents::TreeReader;
+use crate::DataExtent;
+use crate::DirEntry;
+use crate::FileType;
+use crate::Inode;
+use crate::SuperBlock;
+
+/// An inode, sorted by what it is, so only what makes sense for it can be done to it:
+/// only `File`s can be opened, and only `Dir`s listed. See `SuperBlock::classify`.
+pub enum Classified<'a, R> {
+    File(File<'a, R>),
+    Dir(Dir<'a, R>),
+    Symlink(Symlink<'a, R>),
+    /// Devices, fifos and sockets, which have nothing to read.
+    Other(&'a Inode),
+}
+
+/// A regular file.
+pub struct File<'a, R> {
+    fs: &'a SuperBlock<R>,
+    inode: &'a Inode,
+}
+
+/// A directory.
+pub struct Dir<'a, R> {
+    fs: &'a SuperBlock<R>,
+    inode: &'a Inode,
+}
+
+/// A symbolic link.
+pub struct Symlink<'a, R> {
+    fs: &'a SuperBlock<R>,
+    inode: &'a Inode,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Sort an inode by its type, for the operations which only make sense for that type.
+    pub fn classify<'a>(&'a self, inode: &'a Inode) -> Classified<'a, R> {
+        match inode.stat.extracted_type {
+            FileType::RegularFile => Classified::File(File { fs: self, inode }),
+            FileType::Directory => Classified::Dir(Dir { fs: self, inode }),
+            FileType::SymbolicLink => Classified::Symlink(Symlink { fs: self, inode }),
+            _ => Classified::Other(inode),
+        }
+    }
+}
+
+impl<'a, R> Classified<'a, R> {
+    pub fn inode(&self) -> &'a Inode {
+        match self {
+            Classified::File(file) => file.inode,
+            Classified::Dir(dir) => dir.inode,
+            Classified::Symlink(link) => link.inode,
+            Classified::Other(inode) => inode,
+        }
+    }
+}
+
+impl<'a, R: ReadAt> File<'a, R> {
+    pub fn inode(&self) -> &'a Inode {
+        self.inode
+    }
+
+    /// Read the file's content.
+    pub fn open(&self) -> Result<TreeReader<&'a R>, Error> {
+        self.fs.open(self.inode)
+    }
+
+    /// Where the content is on disc. Sparse regions are not included.
+    pub fn data_extents(&self) -> Result<Vec<DataExtent>, Error> {
+        self.fs.data_extents(self.inode)
+    }
+}
+
+impl<'a, R: ReadAt> Dir<'a, R> {
+    pub fn inode(&self) -> &'a Inode {
+        self.inode
+    }
+
+    /// The directory's entries, including `.` and `..`, in the order they're stored.
+    pub fn entries(&self) -> Result<Vec<DirEntry>, Error> {
+        self.list(true)
+    }
+
+    /// The directory's entries, in the order they're stored, with `.` and `..`, as
+    /// `readdir` returns them, or without, as `walk` and `diff` want them.
+    pub fn list(&self, include_dots: bool) -> Result<Vec<DirEntry>, Error> {
+        let mut entries = self
+            .inode
+            .read_directory(&self.fs.inner, &|inodes| self.fs.file_types_of(inodes))?;
+        if !include_dots {
+            entries.retain(|entry| "." != entry.name && ".." != entry.name);
+        }
+        Ok(entries)
+    }
+
+    /// The directory's parent's inode; see `SuperBlock::parent_of`.
+    pub fn parent(&self) -> Result<u32, Error> {
+        self.fs.parent_of(self.inode)
+    }
+}
+
+impl<'a, R: ReadAt> Symlink<'a, R> {
+    pub fn inode(&self) -> &'a Inode {
+        self.inode
+    }
+
+    /// Where the link points, unresolved; see `SuperBlock::read_link`.
+    pub fn target(&self) -> Result<PathBuf, Error> {
+        self.fs.read_link(self.inode)
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+
+use positioned_io2::ReadAt;
+
+use crate::SuperBlock;
+
+/// Something odd about a filesystem, which didn't stop it being read, but which means
+/// what was read may not be quite what's on disc. See `SuperBlock::warnings`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Warning {
+    /// Compatible features we don't know, from `s_feature_compat`. Nothing needs them to
+    /// read the filesystem, but whatever they add isn't shown.
+    UnknownCompatibleFeatures(u32),
+    /// Read-only compatible features we don't know, from `s_feature_ro_compat`, which
+    /// the filesystem can be read without, but not safely written.
+    UnknownReadOnlyFeatures(u32),
+    /// A fixed-length text field in the superblock, like `s_volume_name`, has more after
+    /// the NUL which ends it, which was dropped.
+    TextAfterNul { field: &'static str },
+    /// One of an inode's times, like `mtime`, had more than a second's worth of
+    /// nanoseconds, which were clamped to 999,999,999.
+    NanosecondsClamped {
+        inode: u32,
+        field: &'static str,
+        nanos: u32,
+    },
+    /// A directory entry, which doesn't say what it points at, points at an inode whose
+    /// mode couldn't be read, or isn't a file type; the entry was left out of the listing.
+    UnknownFileType { inode: u32 },
+}
+
+/// What's been noticed so far, each only once.
+#[derive(Debug, Default)]
+pub(crate) struct Warnings {
+    inner: Mutex<Noticed>,
+}
+
+#[derive(Debug, Default)]
+struct Noticed {
+    /// Not yet taken, in the order they were noticed.
+    pending: Vec<Warning>,
+    seen: HashSet<Warning>,
+}
+
+impl Warnings {
+    pub(crate) fn push(&self, warning: Warning) {
+        let mut noticed = self.inner.lock().expect("poisoned");
+        if noticed.seen.insert(warning.clone()) {
+            noticed.pending.push(warning);
+        }
+    }
+
+    fn list(&self) -> Vec<Warning> {
+        self.inner.lock().expect("poisoned").pending.clone()
+    }
+
+    pub(crate) fn take(&self) -> Vec<Warning> {
+        std::mem::take(&mut self.inner.lock().expect("poisoned").pending)
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Everything odd noticed since it was opened, or `take_warnings` was last called,
+    /// which didn't stop the filesystem being read, in the order it was noticed: some
+    /// when it was opened, the rest as inodes are loaded. Each is only reported once,
+    /// however many times it's seen.
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.warnings.list()
+    }
+
+    /// `warnings`, and forget them, so the next call only returns what's new, e.g.
+    /// after each file of a long walk.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::UnknownCompatibleFeatures(bits) => {
+                write!(f, "unknown compatible features: {:#x}", bits)
+            }
+            Warning::UnknownReadOnlyFeatures(bits) => {
+                write!(f, "unknown read-only compatible features: {:#x}", bits)
+            }
+            Warning::TextAfterNul { field } => {
+                write!(f, "{} has text after its terminating NUL", field)
+            }
+            Warning::NanosecondsClamped {
+                inode,
+                field,
+                nanos,
+            } => write!(
+                f,
+                "<{}>'s {} has {} nanoseconds, clamped to 999999999",
+                inode, field, nanos
+            ),
+            Warning::UnknownFileType { inode } => write!(
+                f,
+                "a directory entry points at <{}>, whose type couldn't be read",
+                inode
+            ),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::io;
+
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::check::allocated_inodes;
+use crate::ondisk::RawDirEntry;
+use crate::ondisk::RawDirEntryTail;
+use crate::parse::ext4_style_crc32c_le;
+use crate::recover::raw_records;
+use crate::FileType;
+use crate::Inode;
+use crate::InodeFlags;
+use crate::SuperBlock;
+
+/// What `SuperBlock::wipe_free_space` does, beyond zeroing the unallocated blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Wipe {
+    /// Also zero the slack in directories: the gap after each entry's name, where the
+    /// names of deleted entries are left, and the whole of any deleted entry which
+    /// wasn't merged into the one before it.
+    pub directory_slack: bool,
+    /// Write nothing, just count what would be written.
+    pub dry_run: bool,
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Overwrite the free space with zeros in `out`, which must be this filesystem's image,
+    /// e.g. a copy opened for writing, so the image compresses better, and nothing deleted
+    /// can be recovered from it. The free blocks are those `unallocated_ranges` finds in
+    /// the block bitmaps. Returns how many bytes were overwritten, or, with `dry_run`,
+    /// would have been, in which case `out` isn't touched.
+    ///
+    /// Nothing is allocated, or freed, and nothing in use changes. Blocks past the end of
+    /// a truncated image are left alone, rather than extending it. The blocks of a hashed
+    /// directory's index are left alone, too: its entries are in what looks like slack.
+    pub fn wipe_free_space<W>(&self, mut out: W, wipe: &Wipe) -> Result<u64, Error>
+    where
+        W: io::Write + io::Seek,
+    {
+        let block_size = u64::from(self.groups.block_size);
+
+        let mut free = self.unallocated_ranges()?;
+        if let Some(missing) = self.first_missing_block() {
+            free.retain(|range| range.start < missing);
+            if let Some(last) = free.last_mut() {
+                last.end = last.end.min(missing);
+            }
+        }
+        let mut wiped = free
+            .iter()
+            .map(|range| (range.end - range.start) * block_size)
+            .sum();
+
+        // (block, its new content), only for the blocks which change
+        let mut rewritten = Vec::new();
+        if wipe.directory_slack {
+            for group in 0..self.groups.count() {
+                for number in allocated_inodes(self, group)? {
+                    // reserved for the filesystem's own use, so never directories
+                    if number < self.first_inode && 2 != number {
+                        continue;
+                    }
+                    let inode = self.load_inode(number)?;
+                    if FileType::Directory != inode.stat.extracted_type
+                        || inode.flags.contains(InodeFlags::INLINE_DATA)
+                    {
+                        continue;
+                    }
+                    wiped += self.wipe_slack(&inode, &mut rewritten)?;
+                }
+            }
+        }
+
+        if wipe.dry_run {
+            return Ok(wiped);
+        }
+
+        let zeros = vec![0u8; usize::try_from(block_size)?];
+        for range in free {
+            out.seek(io::SeekFrom::Start(range.start * block_size))?;
+            for _ in range {
+                out.write_all(&zeros)?;
+            }
+        }
+        for (block, data) in rewritten {
+            out.seek(io::SeekFrom::Start(block * block_size))?;
+            out.write_all(&data)?;
+        }
+        out.flush()?;
+
+        Ok(wiped)
+    }
+
+    /// Zero the slack in a directory's blocks, adding those which changed to `rewritten`,
+    /// with their checksums fixed. Returns how many bytes of slack there were.
+    fn wipe_slack(&self, dir: &Inode, rewritten: &mut Vec<(u64, Vec<u8>)>) -> Result<u64, Error> {
+        let block_size = u64::from(self.groups.block_size);
+        let index = match self.dir_index(dir)? {
+            Some(tree) => tree
+                .nodes
+                .iter()
+                .map(|node| u64::from(node.block))
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        let mut wiped = 0;
+        for extent in self.data_extents(dir)? {
+            for i in 0..u64::from(extent.len) {
+                let logical = u64::from(extent.logical) + i;
+                if logical * block_size >= dir.stat.size {
+                    break;
+                }
+                if index.contains(&logical) {
+                    continue;
+                }
+
+                let block = extent.physical + i;
+                let mut data = self.load_block(block)?;
+                let original = data.clone();
+                for (offset, raw) in raw_records(block, &data)? {
+                    if raw.is_tail() {
+                        continue;
+                    }
+                    // a deleted entry only needs its length, to find the next
+                    let start = if 0 == raw.inode {
+                        offset + 6
+                    } else {
+                        offset + RawDirEntry::HEADER_SIZE + raw.name.len()
+                    };
+                    let end = offset + raw.record_len(data.len());
+                    let start = start.min(end);
+                    data[start..end].iter_mut().for_each(|b| *b = 0);
+                    wiped += (end - start) as u64;
+                }
+                if data == original {
+                    continue;
+                }
+
+                let tail = data.len() - RawDirEntryTail::SIZE;
+                if let Some(checksum_prefix) = dir.checksum_prefix {
+                    if RawDirEntry::from_slice(&data[tail..])?.is_tail() {
+                        let computed = ext4_style_crc32c_le(checksum_prefix, &data[..tail]);
+                        data[data.len() - 4..].copy_from_slice(&computed.to_le_bytes());
+                    }
+                }
+                rewritten.push((block, data));
+            }
+        }
+
+        Ok(wiped)
+    }
+}
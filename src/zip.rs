@@ -0,0 +1,393 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::ensure;
+use anyhow::Context;
+use anyhow::Error;
+use positioned_io2::ReadAt;
+
+use crate::deflate;
+use crate::deflate::Deflater;
+use crate::ArchiveEntry;
+use crate::ArchiveSink;
+use crate::Enhanced;
+use crate::FileType;
+use crate::Stat;
+use crate::SuperBlock;
+use crate::Time;
+
+/// How the content of each file is written into a zip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipMethod {
+    /// Uncompressed.
+    Store,
+    /// Compressed, but not as well as `zip -9` would.
+    Deflate,
+}
+
+/// Writes a zip file as it goes, without seeking: sizes and checksums follow each
+/// file's content. Zip64 records are used only where something is too big without them.
+pub struct ZipWriter<W> {
+    inner: W,
+    method: ZipMethod,
+    /// How much has been written to `inner`.
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+struct CentralEntry {
+    name: Vec<u8>,
+    method: u16,
+    dos_time: (u16, u16),
+    mtime: Option<i32>,
+    crc: u32,
+    compressed: u64,
+    uncompressed: u64,
+    external_attributes: u32,
+    header_offset: u64,
+    zip64: bool,
+}
+
+const VERSION_MADE_BY_UNIX: u16 = 3 << 8;
+const VERSION_DEFLATE: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// General purpose flags: sizes are in a descriptor after the data, and names are UTF-8.
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+
+const METHOD_STORE: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// `FILE_ATTRIBUTE_DIRECTORY`, for readers which ignore the unix mode.
+const DOS_DIRECTORY: u32 = 0x10;
+
+const EXTRA_ZIP64: u16 = 0x0001;
+/// Info-ZIP's "extended timestamp", with a 32-bit unix mtime.
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(inner: W, method: ZipMethod) -> ZipWriter<W> {
+        ZipWriter {
+            inner,
+            method,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add an entry called `name`, with the type, mode, mtime and size from `stat`.
+    /// Directories should have a name ending in `/`. Symlinks are stored as their
+    /// target, as `zip --symlinks` does.
+    pub fn add(&mut self, name: &str, stat: &Stat, content: &mut dyn Read) -> Result<(), Error> {
+        let directory = FileType::Directory == stat.extracted_type;
+        let method = match self.method {
+            ZipMethod::Deflate if !directory => METHOD_DEFLATE,
+            _ => METHOD_STORE,
+        };
+
+        // the sizes aren't known until the end, so commit to zip64 now if they could be large
+        let zip64 = deflate::worst_case(stat.size) >= 0xFFFF_FFFF || self.offset >= 0xFFFF_FFFF;
+
+        let mut entry = CentralEntry {
+            name: name.as_bytes().to_vec(),
+            method,
+            dos_time: dos_time(&stat.mtime),
+            mtime: i32::try_from(stat.mtime.epoch_secs).ok(),
+            crc: 0,
+            compressed: 0,
+            uncompressed: 0,
+            external_attributes: (u32::from(stat.extracted_type.mode_bits() | stat.file_mode)
+                << 16)
+                | if directory { DOS_DIRECTORY } else { 0 },
+            header_offset: self.offset,
+            zip64,
+        };
+
+        let mut extra = Vec::new();
+        if zip64 {
+            extra.extend_from_slice(&EXTRA_ZIP64.to_le_bytes());
+            extra.extend_from_slice(&16u16.to_le_bytes());
+            extra.extend_from_slice(&[0; 16]);
+        }
+        timestamp_extra(&mut extra, entry.mtime);
+
+        let mut header = Vec::with_capacity(30 + entry.name.len() + extra.len());
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&entry.version_needed().to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&entry.dos_time.0.to_le_bytes());
+        header.extend_from_slice(&entry.dos_time.1.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        let size = if zip64 { 0xFFFF_FFFFu32 } else { 0 };
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&u16::try_from(entry.name.len())?.to_le_bytes());
+        header.extend_from_slice(&u16::try_from(extra.len())?.to_le_bytes());
+        header.extend_from_slice(&entry.name);
+        header.extend_from_slice(&extra);
+        self.write(&header)?;
+
+        self.write_content(&mut entry, content)
+            .with_context(|| anyhow!("writing {:?}", name))?;
+        ensure!(
+            zip64 || (entry.compressed < 0xFFFF_FFFF && entry.uncompressed < 0xFFFF_FFFF),
+            "{:?} was much longer than its recorded size",
+            name
+        );
+
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend_from_slice(&0x0807_4b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc.to_le_bytes());
+        if zip64 {
+            descriptor.extend_from_slice(&entry.compressed.to_le_bytes());
+            descriptor.extend_from_slice(&entry.uncompressed.to_le_bytes());
+        } else {
+            descriptor.extend_from_slice(&(entry.compressed as u32).to_le_bytes());
+            descriptor.extend_from_slice(&(entry.uncompressed as u32).to_le_bytes());
+        }
+        self.write(&descriptor)?;
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn write_content(
+        &mut self,
+        entry: &mut CentralEntry,
+        content: &mut dyn Read,
+    ) -> Result<(), Error> {
+        let mut chunk = vec![0u8; deflate::BLOCK_SIZE];
+        let mut len = read_full(content, &mut chunk)?;
+
+        let mut deflater = Deflater::new();
+        let mut next = vec![0u8; deflate::BLOCK_SIZE];
+        let mut compressed = Vec::new();
+        loop {
+            let data = &chunk[..len];
+            entry.crc = crc::crc32::update(entry.crc, &crc::crc32::IEEE_TABLE, data);
+            entry.uncompressed += len as u64;
+
+            let next_len = if len == chunk.len() {
+                read_full(content, &mut next)?
+            } else {
+                0
+            };
+            let last = 0 == next_len;
+
+            if METHOD_DEFLATE == entry.method {
+                compressed.clear();
+                deflater.block(data, last, &mut compressed);
+                self.write(&compressed)?;
+                entry.compressed += compressed.len() as u64;
+            } else {
+                self.write(data)?;
+                entry.compressed += len as u64;
+            }
+
+            if last {
+                return Ok(());
+            }
+            std::mem::swap(&mut chunk, &mut next);
+            len = next_len;
+        }
+    }
+
+    /// Write the central directory, and return the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let entries = std::mem::take(&mut self.entries);
+        let start = self.offset;
+        for entry in &entries {
+            let header = entry.central_header()?;
+            self.write(&header)?;
+        }
+        let end = self.offset;
+        let count = entries.len() as u64;
+        let size = end - start;
+
+        let zip64 = count >= 0xFFFF || size >= 0xFFFF_FFFF || start >= 0xFFFF_FFFF;
+        let mut trailer = Vec::with_capacity(98);
+        if zip64 {
+            trailer.extend_from_slice(&0x0606_4b50u32.to_le_bytes());
+            trailer.extend_from_slice(&44u64.to_le_bytes());
+            trailer.extend_from_slice(&(VERSION_MADE_BY_UNIX | VERSION_ZIP64).to_le_bytes());
+            trailer.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            trailer.extend_from_slice(&0u32.to_le_bytes());
+            trailer.extend_from_slice(&0u32.to_le_bytes());
+            trailer.extend_from_slice(&count.to_le_bytes());
+            trailer.extend_from_slice(&count.to_le_bytes());
+            trailer.extend_from_slice(&size.to_le_bytes());
+            trailer.extend_from_slice(&start.to_le_bytes());
+
+            trailer.extend_from_slice(&0x0706_4b50u32.to_le_bytes());
+            trailer.extend_from_slice(&0u32.to_le_bytes());
+            trailer.extend_from_slice(&end.to_le_bytes());
+            trailer.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        trailer.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes());
+        let short_count = count.min(0xFFFF) as u16;
+        trailer.extend_from_slice(&short_count.to_le_bytes());
+        trailer.extend_from_slice(&short_count.to_le_bytes());
+        trailer.extend_from_slice(&(size.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        trailer.extend_from_slice(&(start.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        trailer.extend_from_slice(&0u16.to_le_bytes());
+        self.write(&trailer)?;
+
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+impl CentralEntry {
+    fn version_needed(&self) -> u16 {
+        if self.zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFLATE
+        }
+    }
+
+    fn central_header(&self) -> Result<Vec<u8>, Error> {
+        // in the central directory, only the values which don't fit go in the zip64 field
+        let mut wide = Vec::new();
+        let mut narrow = |value: u64| {
+            if self.zip64 && value >= 0xFFFF_FFFF {
+                wide.extend_from_slice(&value.to_le_bytes());
+                0xFFFF_FFFF
+            } else {
+                value as u32
+            }
+        };
+        let uncompressed = narrow(self.uncompressed);
+        let compressed = narrow(self.compressed);
+        let header_offset = narrow(self.header_offset);
+
+        let mut extra = Vec::new();
+        if !wide.is_empty() {
+            extra.extend_from_slice(&EXTRA_ZIP64.to_le_bytes());
+            extra.extend_from_slice(&u16::try_from(wide.len())?.to_le_bytes());
+            extra.extend_from_slice(&wide);
+        }
+        timestamp_extra(&mut extra, self.mtime);
+
+        let mut header = Vec::with_capacity(46 + self.name.len() + extra.len());
+        header.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        header.extend_from_slice(&(VERSION_MADE_BY_UNIX | VERSION_ZIP64).to_le_bytes());
+        header.extend_from_slice(&self.version_needed().to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&self.method.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.0.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.1.to_le_bytes());
+        header.extend_from_slice(&self.crc.to_le_bytes());
+        header.extend_from_slice(&compressed.to_le_bytes());
+        header.extend_from_slice(&uncompressed.to_le_bytes());
+        header.extend_from_slice(&u16::try_from(self.name.len())?.to_le_bytes());
+        header.extend_from_slice(&u16::try_from(extra.len())?.to_le_bytes());
+        // comment length, disk number, internal attributes
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&self.external_attributes.to_le_bytes());
+        header.extend_from_slice(&header_offset.to_le_bytes());
+        header.extend_from_slice(&self.name);
+        header.extend_from_slice(&extra);
+        Ok(header)
+    }
+}
+
+/// Devices, fifos and sockets can't be represented, and are left out.
+impl<W: Write> ArchiveSink for ZipWriter<W> {
+    fn add(&mut self, entry: &ArchiveEntry, content: &mut dyn Read) -> Result<(), Error> {
+        let stat = &entry.inode.stat;
+        match entry.enhanced {
+            Enhanced::RegularFile => ZipWriter::add(self, entry.path, stat, content),
+            Enhanced::Directory(_) => {
+                ZipWriter::add(self, &format!("{}/", entry.path), stat, content)
+            }
+            Enhanced::SymbolicLink(target) => ZipWriter::add(
+                self,
+                entry.path,
+                stat,
+                &mut io::Cursor::new(crate::path_bytes(target)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R> SuperBlock<R>
+where
+    R: ReadAt,
+{
+    /// Write everything under `path` to a zip, with names relative to `path`.
+    /// Devices, fifos and sockets can't be represented, and are left out.
+    pub fn export_zip<W: Write>(&self, path: &str, out: W, method: ZipMethod) -> Result<W, Error> {
+        let mut zip = ZipWriter::new(out, method);
+        self.export(path, &mut zip)?;
+        zip.finish()
+    }
+}
+
+/// An Info-ZIP extended timestamp, with only the mtime, which is all the central
+/// directory is allowed.
+fn timestamp_extra(extra: &mut Vec<u8>, mtime: Option<i32>) {
+    if let Some(mtime) = mtime {
+        extra.extend_from_slice(&EXTRA_TIMESTAMP.to_le_bytes());
+        extra.extend_from_slice(&5u16.to_le_bytes());
+        extra.push(1);
+        extra.extend_from_slice(&mtime.to_le_bytes());
+    }
+}
+
+/// MS-DOS `(time, date)`, in UTC, clamped to the 1980 to 2107 range it can represent.
+fn dos_time(time: &Time) -> (u16, u16) {
+    const MIN: i64 = 315_532_800; // 1980-01-01
+    const MAX: i64 = 4_354_819_198; // 2107-12-31 23:59:58
+    let secs = time.epoch_secs.clamp(MIN, MAX);
+
+    let days = secs.div_euclid(86_400);
+    let of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+
+    let time = (of_day / 3600) << 11 | (of_day / 60 % 60) << 5 | (of_day % 60 / 2);
+    let date = (year - 1980) << 9 | month << 5 | day;
+    (time as u16, date as u16)
+}
+
+/// Howard Hinnant's `civil_from_days`: the proleptic Gregorian `(year, month, day)`.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Read until `buf` is full, or there's nothing left.
+fn read_full(content: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match content.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if io::ErrorKind::Interrupted == e.kind() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
@@ -0,0 +1,9 @@
+# deleted.img
+ls /
+ls -l /
+stat <12>
+stat /kept.txt
+blocks /kept.txt
+icheck 1338 300
+ncheck 12 11 14
+cat /kept.txt
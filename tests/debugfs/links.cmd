@@ -0,0 +1,11 @@
+# links.img
+ls -l /
+ls -p /a
+cd a
+pwd
+ls
+stat top
+stat /top
+blocks <12>
+ncheck -c 12 21 13
+icheck 1 
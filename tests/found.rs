@@ -0,0 +1,30 @@
+use ext4::Checksums;
+use ext4::Options;
+use ext4::SuperBlock;
+
+fn open_found(name: &str) -> SuperBlock<std::fs::File> {
+    let file = std::fs::File::open(format!("tests/found/{}", name)).unwrap();
+    let options = Options {
+        checksums: Checksums::Enabled,
+        ..Options::default()
+    };
+    SuperBlock::new_with_options(file, &options).unwrap()
+}
+
+#[test]
+fn uninit_bg_group_checksums() {
+    let superblock = open_found("f_holedir3.img");
+    assert_eq!(1, superblock.block_group_count());
+    let group = superblock.block_group(0).unwrap();
+    assert_eq!(0xd98d, group.checksum);
+    assert_eq!(Some(true), group.checksum_valid());
+    assert_eq!(50, group.inode_table);
+    assert_eq!(32, group.inode_table_blocks);
+}
+
+#[test]
+fn extent_tree_unverified_without_metadata_csum() {
+    let superblock = open_found("f_holedir3.img");
+    let root = superblock.root().unwrap();
+    assert!(!superblock.open(&root).unwrap().extent_tree_verified());
+}
@@ -6,12 +6,11 @@ use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::io::Read;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::Stdio;
 
 use anyhow::Result;
-use positioned_io2::ReadAt;
-use tempfile::NamedTempFile;
 use tempfile::TempDir;
 
 #[test]
@@ -36,6 +35,12 @@ fn all_types() -> Result<()> {
 
             let part_reader = positioned_io2::Slice::new(&mut img, part.first_byte, Some(part.len));
             let superblock = ext4::SuperBlock::new(part_reader).unwrap();
+
+            for group in 0..superblock.block_group_count() {
+                let group = superblock.block_group(group).unwrap();
+                assert_eq!(Some(true), group.checksum_valid(), "{:?}", group);
+            }
+
             let root = superblock.root().unwrap();
             superblock
                 .walk(&root, "", &mut |fs, path, inode, enhanced| {
@@ -85,14 +90,70 @@ fn all_types() -> Result<()> {
     Ok(())
 }
 
-struct ReadAtTempFile {
-    inner: NamedTempFile,
+fn open_first_partition(path: &Path) -> Result<ext4::SuperBlock<positioned_io2::Slice<fs::File>>> {
+    let img = fs::File::open(path)?;
+    let partitions = bootsector::list_partitions(&img, &bootsector::Options::default())?;
+    let part = &partitions[0];
+    let part_reader = positioned_io2::Slice::new(img, part.first_byte, Some(part.len));
+    ext4::SuperBlock::new(part_reader)
 }
 
-impl ReadAt for ReadAtTempFile {
-    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.as_file().read_at(pos, buf)
+#[test]
+fn diff_images() -> Result<()> {
+    let assets = open_assets()?;
+    let normal = open_first_partition(&assets.path("all-types.img"))?;
+    let tiny = open_first_partition(&assets.path("all-types-tiny.img"))?;
+
+    assert_eq!(
+        Vec::<ext4::Difference>::new(),
+        normal.diff(&normal, &ext4::DiffOptions { content: true })?
+    );
+
+    // the images were generated at different times, but otherwise identically
+    let differences = normal.diff(&tiny, &ext4::DiffOptions { content: true })?;
+    assert!(!differences.is_empty());
+    for difference in differences {
+        assert_eq!(
+            ext4::Change::Changed(ext4::Changes::MTIME),
+            difference.change,
+            "{}",
+            difference.path
+        );
     }
+
+    Ok(())
+}
+
+#[test]
+fn device_numbers() -> Result<()> {
+    let assets = open_assets()?;
+    let fs = open_first_partition(&assets.path("all-types.img"))?;
+
+    for (path, major, minor, rdev) in &[
+        ("/char-device", 1, 3, 0x103),
+        ("/block-device", 7, 6, 0x706),
+        ("/extremely-minor-device", 0, 1023997, 0xf9f0_00fd),
+        ("/extremely-major-device", 4093, 0, 0xffd00),
+    ] {
+        let inode = fs.load_inode(fs.resolve_path(path)?.inode)?;
+        assert!(inode.stat.extracted_type.is_device());
+        assert_eq!(
+            Some(ext4::DeviceNumbers {
+                major: *major,
+                minor: *minor,
+                rdev: *rdev,
+            }),
+            inode.stat.device,
+            "{}",
+            path
+        );
+    }
+
+    let fifo = fs.load_inode(fs.resolve_path("/fifo-file")?.inode)?;
+    assert!(!fifo.stat.extracted_type.is_device());
+    assert_eq!(None, fifo.stat.device);
+
+    Ok(())
 }
 
 struct Assets {
@@ -102,7 +163,7 @@ struct Assets {
 fn open_assets() -> Result<Assets> {
     let tempdir = TempDir::new()?;
     let mut tar = std::process::Command::new("tar")
-        .args(&[
+        .args([
             OsStr::new("-C"),
             tempdir.path().as_os_str(),
             OsStr::new("-xz"),
@@ -121,6 +182,10 @@ fn open_assets() -> Result<Assets> {
 }
 
 impl Assets {
+    fn path(&self, name: &str) -> PathBuf {
+        self.tempdir.path().join(name)
+    }
+
     fn entries(&self) -> Result<Vec<PathBuf>> {
         fs::read_dir(self.tempdir.path())?
             .map(|e| -> Result<PathBuf> { Ok(e?.path()) })
@@ -0,0 +1,116 @@
+//! How much reading allocates. This is its own test binary, as the allocator is global,
+//! and anything else running at the same time would be counted too.
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::process::Stdio;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use tempfile::TempDir;
+
+/// The system allocator, keeping track of how much is allocated, and the most that has
+/// been since `peak_during` last started.
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(now, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// What `f` returns, and the most it had allocated at once, on top of what already was.
+fn peak_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let result = f();
+    (result, PEAK.load(Ordering::SeqCst) - before)
+}
+
+fn extract(name: &str) -> Result<TempDir> {
+    let tempdir = TempDir::new()?;
+    let mut tar = std::process::Command::new("tar")
+        .args([
+            OsStr::new("-C"),
+            tempdir.path().as_os_str(),
+            OsStr::new("-xz"),
+            OsStr::new(name),
+        ])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    io::copy(
+        &mut io::Cursor::new(&include_bytes!("../scripts/generate-images/small-images.tgz")[..]),
+        &mut tar.stdin.as_mut().expect("configured above"),
+    )?;
+
+    assert!(tar.wait()?.success());
+
+    Ok(tempdir)
+}
+
+#[test]
+fn walk_wide_directory() -> Result<()> {
+    let dir = extract("wide.img")?;
+    let fs = ext4::SuperBlock::new_with_options(
+        fs::File::open(dir.path().join("wide.img"))?,
+        &ext4::Options {
+            limits: ext4::Limits::untrusted(),
+            ..ext4::Options::default()
+        },
+    )?;
+    let wide = fs.load_inode(fs.resolve_path("/wide")?.inode)?;
+    let children = match fs.enhance(&wide)? {
+        ext4::Enhanced::Directory(entries) => entries
+            .into_iter()
+            .filter(|entry| "." != entry.name && ".." != entry.name)
+            .map(|entry| entry.inode)
+            .collect::<Vec<_>>(),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(3000, children.len());
+
+    // what a walk would hold, if it loaded every child at once
+    let (loaded, all_at_once) = peak_during(|| fs.load_inodes(&children));
+    assert!(loaded.iter().all(Result::is_ok));
+    drop(loaded);
+
+    let mut visited = 0;
+    let (walked, walking) = peak_during(|| {
+        fs.walk(&wide, "/wide", &mut |_, _, _, _| {
+            visited += 1;
+            Ok(true)
+        })
+    });
+    walked?;
+    assert_eq!(3001, visited);
+    assert!(
+        walking < all_at_once / 2,
+        "walking took {} bytes, loading every child {}",
+        walking,
+        all_at_once
+    );
+
+    Ok(())
+}
@@ -0,0 +1,3869 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::process::Stdio;
+
+use anyhow::Result;
+use tempfile::TempDir;
+
+struct Image {
+    // keep the directory alive as long as the file is open
+    _tempdir: TempDir,
+    superblock: ext4::SuperBlock<fs::File>,
+}
+
+fn open_image(name: &str) -> Result<Image> {
+    let tempdir = extract(name)?;
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .open(tempdir.path().join(name))?;
+
+    Ok(Image {
+        _tempdir: tempdir,
+        superblock: ext4::SuperBlock::new(file)?,
+    })
+}
+
+/// The whole image, for tests which want to damage it first.
+fn image_bytes(name: &str) -> Result<Vec<u8>> {
+    Ok(fs::read(extract(name)?.path().join(name))?)
+}
+
+fn extract(name: &str) -> Result<TempDir> {
+    let tempdir = TempDir::new()?;
+    let mut tar = std::process::Command::new("tar")
+        .args([
+            OsStr::new("-C"),
+            tempdir.path().as_os_str(),
+            OsStr::new("-xz"),
+            OsStr::new(name),
+        ])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    io::copy(
+        &mut io::Cursor::new(&include_bytes!("../scripts/generate-images/small-images.tgz")[..]),
+        &mut tar.stdin.as_mut().expect("configured above"),
+    )?;
+
+    assert!(tar.wait()?.success());
+
+    Ok(tempdir)
+}
+
+#[test]
+fn journal_transactions() -> Result<()> {
+    let image = open_image("journal.img")?;
+    let journal = image.superblock.journal()?;
+
+    assert_eq!(1, journal.start);
+    assert_eq!(1, journal.sequence);
+
+    let summary = journal
+        .transactions
+        .iter()
+        .map(|t| {
+            (
+                t.sequence,
+                t.needs_replay,
+                t.blocks.iter().map(|b| b.target).collect::<Vec<_>>(),
+                t.revoked.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            (1, true, vec![3000], vec![]),
+            (2, true, vec![3001, 3000], vec![]),
+            (3, true, vec![], vec![3001]),
+        ],
+        summary
+    );
+
+    let replayed = journal
+        .replay_blocks()
+        .into_iter()
+        .map(|b| b.target)
+        .collect::<Vec<_>>();
+    assert_eq!(vec![3000, 3000], replayed);
+
+    let second = &journal.transactions[1].blocks[1];
+    assert_eq!(vec![b'C'; 1024], image.superblock.journal_block(second)?);
+
+    Ok(())
+}
+
+#[test]
+fn journal_replay() -> Result<()> {
+    let image = open_image("journal.img")?;
+    let mut out = tempfile::tempfile()?;
+    image.superblock.replay_journal_to(&mut out)?;
+
+    let mut block = vec![0u8; 1024];
+    positioned_io2::ReadAt::read_exact_at(&out, 3000 * 1024, &mut block)?;
+    assert_eq!(vec![b'C'; 1024], block);
+    positioned_io2::ReadAt::read_exact_at(&out, 3001 * 1024, &mut block)?;
+    assert_eq!(vec![0u8; 1024], block);
+
+    let replayed = ext4::SuperBlock::new(out)?;
+    let journal = replayed.journal()?;
+    assert_eq!(0, journal.start);
+    assert_eq!(4, journal.sequence);
+    assert!(journal.transactions.iter().all(|t| !t.needs_replay));
+    assert_eq!(3, journal.transactions.len());
+
+    Ok(())
+}
+
+#[test]
+fn canonicalize() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let file = fs.resolve_path("/a/b/file")?.inode;
+
+    for path in &[
+        "/a/b/file",
+        "a/b/file",
+        "/a/rel",
+        "/a/chain",
+        "/a/long",
+        "/a/up/a/abs/file",
+        "/top/file",
+        "/a/b/../../a/b/file",
+        "/../a/./b//file",
+    ] {
+        let (canonical, entry) = fs.canonicalize(path)?;
+        assert_eq!("/a/b/file", canonical, "{}", path);
+        assert_eq!(file, entry.inode, "{}", path);
+    }
+
+    let (canonical, entry) = fs.canonicalize("/a/up")?;
+    assert_eq!("/", canonical);
+    assert_eq!(2, entry.inode);
+
+    assert!(fs.canonicalize("/a/loop").is_err());
+    assert!(fs.canonicalize("/a/b/file/nope").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn deleted_inodes() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+
+    let deleted = fs.deleted_inodes()?;
+    let found = deleted
+        .iter()
+        .map(|d| (d.inode.number, d.source, d.intact))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (12, ext4::DeletedSource::InodeTable, true),
+            (13, ext4::DeletedSource::InodeTable, false),
+            (13, ext4::DeletedSource::Journal { sequence: 1 }, true),
+        ],
+        found
+    );
+
+    assert_eq!(
+        Some(1500000000),
+        deleted[0].deleted_at.as_ref().map(|t| t.epoch_secs)
+    );
+    assert_eq!(deleted[1].deleted_at, deleted[2].deleted_at);
+    assert_eq!(deleted[0].deleted_at, deleted[0].inode.stat.deleted_at);
+    assert_eq!(None, fs.root()?.stat.deleted_at);
+    assert!(fs.tombstones()?.is_empty());
+
+    let mut content = Vec::new();
+    fs.open(&deleted[0].inode)?.read_to_end(&mut content)?;
+    assert_eq!(vec![b'D'; 3000], content);
+
+    let mut content = Vec::new();
+    fs.open(&deleted[2].inode)?.read_to_end(&mut content)?;
+    assert_eq!(vec![b'J'; 5000], content);
+    assert_eq!(
+        vec![ext4::DataExtent {
+            logical: 0,
+            physical: 1333,
+            len: 5,
+        }],
+        fs.data_extents(&deleted[2].inode)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn check() -> Result<()> {
+    let image = open_image("links.img")?;
+    assert_eq!(Vec::<ext4::Finding>::new(), image.superblock.check()?);
+    // the whole filesystem is checked, and its /lost+found found, wherever `/` is
+    let fs = image.superblock.with_root("/a")?;
+    assert_eq!(Vec::<ext4::Finding>::new(), fs.check()?);
+
+    let mut bytes = image_bytes("links.img")?;
+    {
+        // point s_last_orphan at /a/b/file, and fix up the superblock checksum
+        let sb = &mut bytes[1024..2048];
+        sb[0xE8..0xEC].copy_from_slice(&15u32.to_le_bytes());
+        let checksum = ext4::parse::ext4_style_crc32c_le(!0, &sb[..0x3FC]);
+        sb[0x3FC..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    assert_eq!(
+        vec![ext4::Finding {
+            phase: ext4::Phase::Orphans,
+            severity: ext4::Severity::Warning,
+            inode: Some(15),
+            message: "<15> is an orphan, and will be cleaned up on the next mount".to_string(),
+            region: None,
+        }],
+        fs.check()?
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn check_parallel() -> Result<()> {
+    for name in &[
+        "links.img",
+        "shared.img",
+        "deleted.img",
+        "scan.img",
+        "htree.img",
+    ] {
+        let image = open_image(name)?;
+        let fs = &image.superblock;
+        assert_eq!(fs.check()?, fs.check_parallel()?, "{}", name);
+    }
+
+    // too little room to load every inode ahead, so the rest are loaded as they're found
+    let bytes = image_bytes("htree.img")?;
+    let fs = ext4::SuperBlock::new_with_options(
+        &bytes[..],
+        &ext4::Options {
+            limits: ext4::Limits {
+                max_extent_entries: 64,
+                ..ext4::Limits::default()
+            },
+            ..ext4::Options::default()
+        },
+    )?;
+    assert_eq!(fs.check()?, fs.check_parallel()?);
+
+    Ok(())
+}
+
+#[test]
+fn verify() -> Result<()> {
+    /// Cancels once it has been asked `left` times.
+    struct Budget(std::cell::Cell<u32>);
+
+    impl ext4::Progress for Budget {
+        fn cancelled(&self) -> bool {
+            let left = self.0.get();
+            self.0.set(left.saturating_sub(1));
+            0 == left
+        }
+    }
+
+    let mut bytes = image_bytes("links.img")?;
+    // change /a/abs's mtime, in the inode table at block 45, so its checksum is wrong
+    bytes[45 * 1024 + 0x10] ^= 0xff;
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+
+    let mut cursor = fs.verify_cursor();
+    let whole = fs.verify(&mut cursor, &())?;
+    assert!(cursor.is_finished());
+    assert_eq!(
+        vec![(ext4::Phase::Inodes, Some(13))],
+        whole.iter().map(|f| (f.phase, f.inode)).collect::<Vec<_>>()
+    );
+
+    // a few inodes at a time, with the cursor only kept as a string in between
+    let mut stored = fs.verify_cursor().to_string();
+    let mut pieces = Vec::new();
+    let mut runs = 0;
+    loop {
+        let mut cursor = stored.parse::<ext4::VerifyCursor>()?;
+        if cursor.is_finished() {
+            break;
+        }
+        pieces.extend(fs.verify(&mut cursor, &Budget(std::cell::Cell::new(3)))?);
+        stored = cursor.to_string();
+        runs += 1;
+    }
+    assert!(runs > 3, "{}", runs);
+    assert_eq!(whole, pieces);
+
+    // just the checksum is wrong, so patching in the fix makes it right again
+    let region = whole[0].region.clone().expect("region");
+    assert_eq!((45 * 1024 + 0x7C, 8), (region.offset, region.len));
+    let offset = usize::try_from(region.offset)?;
+    let mut fixed = bytes.clone();
+    fixed[offset..offset + 8].copy_from_slice(&region.fix.expect("fix"));
+    let fixed = ext4::SuperBlock::new(&fixed[..])?;
+    assert!(fixed.verify(&mut fixed.verify_cursor(), &())?.is_empty());
+
+    let other = open_image("scan.img")?;
+    assert!(other
+        .superblock
+        .verify(&mut fs.verify_cursor(), &())
+        .is_err());
+    assert!("nonsense".parse::<ext4::VerifyCursor>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn chunk_file() -> Result<()> {
+    // 204800 bytes of 0, 1, .., 255, 0, 1, ..
+    let image = open_image("blocks-2k.img")?;
+    let fs = &image.superblock;
+    let data = fs.load_inode(fs.resolve_path("/data")?.inode)?;
+
+    let fixed = fs.chunk_file(&data, ext4::Chunker::Fixed(64 * 1024))?;
+    assert_eq!(
+        vec![(0, 65536), (65536, 65536), (131072, 65536), (196608, 8192)],
+        fixed.iter().map(|c| (c.offset, c.len)).collect::<Vec<_>>()
+    );
+    // `python3 -c 'import hashlib; print(hashlib.sha256(bytes(range(256)) * 256).hexdigest())'`
+    let whole = "7daca2095d0438260fa849183dfc67faa459fdf4936e1bc91eec6b281b27e4c2";
+    for chunk in &fixed[..3] {
+        let hex = chunk
+            .digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        assert_eq!(whole, hex);
+    }
+    assert_ne!(fixed[0].digest, fixed[3].digest);
+
+    let chunker = ext4::Chunker::Fastcdc {
+        min: 1024,
+        avg: 4096,
+        max: 16384,
+    };
+    let cdc = fs.chunk_file(&data, chunker)?;
+    assert!(cdc.len() > 1);
+    let mut offset = 0;
+    for (i, chunk) in cdc.iter().enumerate() {
+        assert_eq!(offset, chunk.offset);
+        assert!(chunk.len <= 16384);
+        assert!(chunk.len >= 1024 || i == cdc.len() - 1);
+        offset += u64::from(chunk.len);
+    }
+    assert_eq!(204800, offset);
+    assert_eq!(cdc, fs.chunk_file(&data, chunker)?);
+
+    assert!(fs.chunk_file(&data, ext4::Chunker::Fixed(0)).is_err());
+    Ok(())
+}
+
+#[test]
+fn locality() -> Result<()> {
+    // four groups of 1024 blocks, from block 1, and 256 inodes
+    let image = open_image("scan.img")?;
+    let fs = &image.superblock;
+    assert_eq!(0, fs.group_of_inode(1)?);
+    assert_eq!(0, fs.group_of_inode(256)?);
+    assert_eq!(1, fs.group_of_inode(257)?);
+    assert!(fs.group_of_inode(0).is_err());
+    assert!(fs.group_of_inode(4 * 256 + 1).is_err());
+    assert_eq!(0, fs.group_of_block(1)?);
+    assert_eq!(0, fs.group_of_block(1024)?);
+    assert_eq!(1, fs.group_of_block(1025)?);
+    assert_eq!(3, fs.group_of_block(4095)?);
+    assert!(fs.group_of_block(0).is_err());
+    assert!(fs.group_of_block(4096).is_err());
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    assert_eq!(
+        ext4::Locality {
+            inode: file.number,
+            inode_group: 0,
+            groups_per_flex: 16,
+            blocks: 1,
+            local_blocks: 1,
+            max_distance: 0,
+            total_distance: 0,
+        },
+        fs.locality(&file)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn load_block() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    assert_eq!(1024, fs.block_size());
+    assert_eq!(1024, fs.block_count());
+
+    // the target of /long
+    let block = fs.load_block(28)?;
+    assert_eq!(1024, block.len());
+    assert_eq!(b"././", &block[..4]);
+
+    assert!(fs.load_block(fs.block_count()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn physical_offset() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let bytes = image_bytes("links.img")?;
+
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let start = usize::try_from(fs.physical_offset(&file, 0)?.expect("not sparse"))?;
+    assert_eq!(b"hello\n", &bytes[start..start + 6]);
+    assert_eq!(Some(start as u64 + 4), fs.physical_offset(&file, 4)?);
+    assert_eq!(None, fs.physical_offset(&file, 6)?);
+
+    Ok(())
+}
+
+#[test]
+fn dir_summary() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let a = fs.load_inode(fs.resolve_path("/a")?.inode)?;
+    assert_eq!(
+        ext4::DirSummary {
+            entries: 7,
+            subdirs: 1,
+            bytes_of_names: 22,
+            htree: false,
+            levels: 0,
+        },
+        fs.dir_summary(&a)?
+    );
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    assert!(fs.dir_summary(&file).is_err());
+
+    let image = open_image("htree.img")?;
+    let fs = &image.superblock;
+    let big = fs.load_inode(fs.resolve_path("/big")?.inode)?;
+    assert_eq!(
+        ext4::DirSummary {
+            entries: 201,
+            subdirs: 1,
+            bytes_of_names: 200 * 8 + 3,
+            htree: true,
+            levels: 1,
+        },
+        fs.dir_summary(&big)?
+    );
+    match fs.enhance(&big)? {
+        ext4::Enhanced::Directory(entries) => assert_eq!(201 + 2, entries.len()),
+        _ => panic!("not a directory"),
+    }
+    match fs.classify(&big) {
+        // from the root of the index
+        ext4::Classified::Dir(big) => assert_eq!(2, big.parent()?),
+        _ => panic!("not a directory"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dir_index() -> Result<()> {
+    let image = open_image("htree.img")?;
+    let fs = &image.superblock;
+    assert_eq!(None, fs.dir_index(&fs.root()?)?);
+
+    let big = fs.load_inode(fs.resolve_path("/big")?.inode)?;
+    let tree = fs.dir_index(&big)?.expect("indexed");
+    assert_eq!(Some(ext4::HashVersion::HalfMd4), tree.hash_version);
+    assert_eq!(1, tree.levels);
+    assert_eq!(1, tree.nodes.len());
+    assert_eq!((0, 0), (tree.nodes[0].block, tree.nodes[0].depth));
+
+    // every block after the root is a leaf, and every entry is where it should be
+    assert_eq!(4, tree.leaves.len());
+    assert_eq!(
+        vec![1, 2, 3, 4],
+        tree.leaves
+            .iter()
+            .map(|leaf| leaf.block)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        201,
+        tree.leaves.iter().map(|leaf| leaf.entries).sum::<u32>()
+    );
+    assert_eq!(0, tree.leaves[0].first_hash);
+    assert_eq!(u32::MAX, tree.leaves[3].last_hash);
+    for (leaf, next) in tree.leaves.iter().zip(&tree.leaves[1..]) {
+        assert_eq!(leaf.last_hash + 1, next.first_hash);
+    }
+    for leaf in &tree.leaves {
+        assert_eq!(Some(0), leaf.misplaced, "{:?}", leaf);
+        assert_eq!(1024 - 12, leaf.capacity);
+        assert!(0 < leaf.used && leaf.used <= leaf.capacity, "{:?}", leaf);
+    }
+
+    // swap the blocks of the root's second and third entries, so lookups look for the
+    // names in each in the other
+    let mut bytes = image_bytes("htree.img")?;
+    let root = fs.physical_offset(&big, 0)?.expect("stored") as usize;
+    let (second, third) = (root + 0x2C, root + 0x34);
+    let block = bytes[second..second + 4].to_vec();
+    bytes.copy_within(third..third + 4, second);
+    bytes[third..third + 4].copy_from_slice(&block);
+
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    let tree = fs.dir_index(&fs.load_inode(big.number)?)?.expect("indexed");
+    assert_eq!(
+        vec![1, 3, 2, 4],
+        tree.leaves
+            .iter()
+            .map(|leaf| leaf.block)
+            .collect::<Vec<_>>()
+    );
+    for leaf in &tree.leaves {
+        let expected = if 1 == leaf.block || 4 == leaf.block {
+            0
+        } else {
+            leaf.entries
+        };
+        assert_eq!(Some(expected), leaf.misplaced, "{:?}", leaf);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn name_decoding() -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let image = open_image("names.img")?;
+    let file = image.superblock.into_inner();
+    let open = |names| {
+        ext4::SuperBlock::new_with_options(
+            &file,
+            &ext4::Options {
+                names,
+                ..ext4::Options::default()
+            },
+        )
+    };
+
+    // the root's listing, and /link's xattrs, aren't utf-8
+    let fs = open(ext4::NameDecoding::Strict)?;
+    assert!(fs.resolve_path("/café").is_err());
+    assert!(fs.load_inode(14).is_err());
+
+    let fs = open(ext4::NameDecoding::Lossy)?;
+    assert_eq!(12, fs.resolve_path("/café")?.inode);
+    assert_eq!(13, fs.resolve_path("/caf\u{fffd}")?.inode);
+    let link = fs.load_inode(fs.resolve_path("/link")?.inode)?;
+    assert_eq!(
+        Some(&b"latin-1".to_vec()),
+        link.stat.xattrs.get("user.caf\u{fffd}")
+    );
+    match fs.enhance(&link)? {
+        ext4::Enhanced::SymbolicLink(target) => {
+            assert_eq!(b"caf\xe9", target.as_os_str().as_bytes())
+        }
+        _ => panic!("not a symlink"),
+    }
+
+    let raw = ext4::NameDecoding::Raw;
+    let fs = open(raw)?;
+    assert_eq!(12, fs.resolve_path("/caf\u{c3}\u{a9}")?.inode);
+    let entry = fs.resolve_path("/caf\u{e9}")?;
+    assert_eq!(13, entry.inode);
+    assert_eq!(Some(b"caf\xe9".to_vec()), raw.to_bytes(&entry.name));
+    assert_eq!(None, raw.to_bytes("café\u{2603}"));
+    let mut content = String::new();
+    fs.open(&fs.load_inode(13)?)?.read_to_string(&mut content)?;
+    assert_eq!("latin-1\n", content);
+    let link = fs.load_inode(fs.resolve_path("/link")?.inode)?;
+    assert!(link.stat.xattrs.contains_key("user.caf\u{e9}"));
+    match fs.enhance(&link)? {
+        ext4::Enhanced::SymbolicLink(target) => {
+            assert_eq!(b"caf\xe9", target.as_os_str().as_bytes())
+        }
+        _ => panic!("not a symlink"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_link() -> Result<()> {
+    use ext4::ondisk::{RawBlockGroup, RawSuperblock};
+    use std::os::unix::ffi::OsStrExt;
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let rel = fs.load_inode(fs.resolve_path("/a/rel")?.inode)?;
+    assert!(rel.is_fast_symlink());
+    assert_eq!(std::path::Path::new("b/file"), fs.read_link(&rel)?);
+    let long = fs.load_inode(fs.resolve_path("/a/long")?.inode)?;
+    assert!(!long.is_fast_symlink());
+    let target = fs.read_link(&long)?;
+    assert_eq!(long.stat.size, target.as_os_str().len() as u64);
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    assert!(!file.is_fast_symlink());
+    assert!(fs.read_link(&file).is_err());
+
+    // even when the String can't say what it is
+    let image = open_image("names.img")?;
+    let fs = ext4::SuperBlock::new_with_options(
+        image.superblock.into_inner(),
+        &ext4::Options {
+            names: ext4::NameDecoding::Lossy,
+            ..ext4::Options::default()
+        },
+    )?;
+    let link = fs.load_inode(14)?;
+    assert_eq!(b"caf\xe9", fs.read_link(&link)?.as_os_str().as_bytes());
+
+    // i_blocks counts the xattr block, which doesn't make the link slow
+    let image = open_image("ealink.img")?;
+    let fs = &image.superblock;
+    let link = fs.load_inode(fs.resolve_path("/link")?.inode)?;
+    assert!(link.stat.xattrs.contains_key("user.note"));
+    assert!(link.is_fast_symlink());
+    assert_eq!(std::path::Path::new("target"), fs.read_link(&link)?);
+
+    // a short target may still be in a block, as i_blocks says; ext2 has no checksums
+    let image = open_image("indirect.img")?;
+    let long = image.superblock.resolve_path("/long")?.inode;
+    let mut bytes = image_bytes("indirect.img")?;
+    let sb = RawSuperblock::from_slice(&bytes[1024..2048])?;
+    let group = RawBlockGroup::from_slice(&bytes[2048..2048 + RawBlockGroup::SMALL_SIZE])?;
+    let inode_size = usize::from(sb.s_inode_size);
+    let start = group.bg_inode_table_lo as usize * 1024 + (long as usize - 1) * inode_size;
+    bytes[start + 4..start + 8].copy_from_slice(&10u32.to_le_bytes());
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    let long = fs.load_inode(long)?;
+    assert!(!long.is_fast_symlink());
+    assert_eq!(b"./././././", fs.read_link(&long)?.as_os_str().as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn directory_block_checksums() -> Result<()> {
+    // each of /big's leaf blocks ends in its own checksum
+    let image = open_image("htree.img")?;
+    let fs = &image.superblock;
+    let big = fs.load_inode(fs.resolve_path("/big")?.inode)?;
+    assert!(big.checksum_prefix().is_some());
+    let third = usize::try_from(fs.physical_offset(&big, 2 * 1024)?.expect("allocated"))?;
+
+    let mut bytes = image_bytes("htree.img")?;
+    // the first letter of the first name in the block
+    bytes[third + 8] ^= 0x20;
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    let err = fs.enhance(&big).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("directory checksum mismatch"),
+        "{:?}",
+        err
+    );
+
+    Ok(())
+}
+
+#[test]
+fn copy_raw() -> Result<()> {
+    for name in &["links.img", "layout.img", "layout-4k.img"] {
+        let image = open_image(name)?;
+        let fs = &image.superblock;
+        fs.walk(&fs.root()?, "", &mut |fs, path, inode, enhanced| {
+            if let ext4::Enhanced::RegularFile = enhanced {
+                let mut expected = Vec::new();
+                fs.open(inode)?.read_to_end(&mut expected)?;
+                let mut copied = Vec::new();
+                assert_eq!(inode.stat.size, fs.copy_raw(inode, &mut copied)?);
+                assert_eq!(expected, copied, "{}:{}", name, path);
+            }
+            Ok(true)
+        })?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn shared_extents() -> Result<()> {
+    let image = open_image("links.img")?;
+    assert_eq!(
+        Vec::<ext4::SharedExtent>::new(),
+        image.superblock.shared_extents()?
+    );
+
+    // /two's extent points at /one's block
+    let image = open_image("shared.img")?;
+    let fs = &image.superblock;
+    assert_eq!(
+        vec![ext4::SharedExtent {
+            physical: 24,
+            len: 1,
+            inodes: vec![12, 13],
+        }],
+        fs.shared_extents()?
+    );
+    assert_eq!(
+        vec![ext4::Finding {
+            phase: ext4::Phase::Tree,
+            severity: ext4::Severity::Error,
+            inode: Some(13),
+            message: "<12>, <13> claim block 24".to_string(),
+            region: None,
+        }],
+        fs.check()?
+    );
+
+    // which is fine, if the filesystem says it's deduplicated
+    let mut bytes = image_bytes("shared.img")?;
+    {
+        let sb = &mut bytes[1024..2048];
+        let ro_compat = u32::from_le_bytes(sb[0x64..0x68].try_into()?) | 0x4000;
+        sb[0x64..0x68].copy_from_slice(&ro_compat.to_le_bytes());
+        let checksum = ext4::parse::ext4_style_crc32c_le(!0, &sb[..0x3FC]);
+        sb[0x3FC..].copy_from_slice(&checksum.to_le_bytes());
+    }
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    assert_eq!(1, fs.shared_extents()?.len());
+    assert_eq!(Vec::<ext4::Finding>::new(), fs.check()?);
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Counter {
+    entries: std::cell::RefCell<Vec<String>>,
+    bytes: std::cell::Cell<u64>,
+}
+
+impl ext4::Progress for Counter {
+    fn entry(&self, path: &str) {
+        self.entries.borrow_mut().push(path.to_string());
+    }
+
+    fn read(&self, bytes: u64) {
+        self.bytes.set(self.bytes.get() + bytes);
+    }
+}
+
+#[test]
+fn progress() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let counter = Counter::default();
+
+    let mut visited = 0;
+    fs.walk_with_progress(&fs.root()?, "", &counter, &mut |fs, _, inode, _| {
+        visited += 1;
+        if ext4::FileType::RegularFile == inode.stat.extracted_type {
+            let mut reader = ext4::ProgressReader::new(fs.open(inode)?, &counter);
+            io::copy(&mut reader, &mut io::sink())?;
+        }
+        Ok(true)
+    })?;
+
+    let entries = counter.entries.borrow();
+    assert_eq!(visited, entries.len());
+    assert!(entries.contains(&"/a/b/file".to_string()));
+    // "hello\n" is the only regular file
+    assert_eq!(6, counter.bytes.get());
+
+    Ok(())
+}
+
+#[test]
+fn cancel() -> Result<()> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let cancel = AtomicBool::new(false);
+
+    let mut visited = 0;
+    let err = fs
+        .walk_with_progress(&fs.root()?, "", &cancel, &mut |_, _, _, _| {
+            visited += 1;
+            if 3 == visited {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            Ok(true)
+        })
+        .unwrap_err();
+    assert_eq!(3, visited);
+    assert!(matches!(
+        err.downcast_ref::<ext4::ParseError>(),
+        Some(ext4::ParseError::Cancelled)
+    ));
+
+    let err = fs
+        .diff_with_progress(fs, &ext4::DiffOptions::default(), &cancel)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ext4::ParseError>(),
+        Some(ext4::ParseError::Cancelled)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn path_cache() -> Result<()> {
+    let image = open_image("links.img")?;
+    let file = image.superblock.into_inner();
+    let fs = ext4::SuperBlock::new_with_options(
+        &file,
+        &ext4::Options {
+            path_cache: 2,
+            ..ext4::Options::default()
+        },
+    )?;
+
+    for _ in 0..2 {
+        assert_eq!(15, fs.resolve_path("/a/b/file")?.inode);
+        assert_eq!("/a/b/file", fs.canonicalize("/top/../b/file")?.0);
+        assert!(fs.resolve_path("/a/b/missing").is_err());
+    }
+
+    fs.clear_path_cache();
+    assert_eq!(15, fs.resolve_path("a/b/file")?.inode);
+
+    Ok(())
+}
+
+#[test]
+fn load_inodes() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let wanted = [15, 2, 0, 17, 11];
+    let loaded = fs.load_inodes(&wanted);
+    assert_eq!(wanted.len(), loaded.len());
+
+    for (&number, inode) in wanted.iter().zip(loaded) {
+        if 0 == number {
+            assert!(inode.is_err());
+            continue;
+        }
+        let inode = inode?;
+        assert_eq!(number, inode.number);
+        assert_eq!(fs.load_inode(number)?.stat.size, inode.stat.size);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_vectored_at() -> Result<()> {
+    let data = (0..=255u8).cycle().take(256 * 1024).collect::<Vec<u8>>();
+
+    let mut a = [0u8; 4];
+    let mut b = [0u8; 4];
+    let mut c = [0u8; 2];
+    let mut far = [0u8; 3];
+    let mut requests: Vec<(u64, &mut [u8])> = vec![
+        (10, &mut a[..]),
+        (200 * 1024 + 1, &mut far[..]),
+        (12, &mut b[..]),
+        (11, &mut c[..]),
+    ];
+    ext4::read_vectored_at(&&data[..], &mut requests)?;
+
+    assert_eq!([10, 11, 12, 13], a);
+    assert_eq!([12, 13, 14, 15], b);
+    assert_eq!([11, 12], c);
+    assert_eq!([1, 2, 3], far);
+
+    let mut past_the_end = [0u8; 2];
+    assert!(ext4::read_vectored_at(
+        &&data[..],
+        &mut [(data.len() as u64 - 1, &mut past_the_end[..])]
+    )
+    .is_err());
+    // nowhere near the data, and at the very end of where anything could be
+    let mut end = [0u8; 2];
+    assert!(ext4::read_vectored_at(
+        &&data[..],
+        &mut [
+            (u64::MAX - 1, &mut past_the_end[..]),
+            (u64::MAX, &mut end[..])
+        ]
+    )
+    .is_err());
+
+    Ok(())
+}
+
+/// Rejects reads the way a device opened with O_DIRECT would.
+struct Strict<'a> {
+    data: &'a [u8],
+    sector_size: usize,
+}
+
+impl ext4::ReadAt for Strict<'_> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.sector_size;
+        if 0 != pos as usize % sector
+            || 0 != buf.len() % sector
+            || 0 != buf.as_ptr() as usize % sector
+        {
+            return Err(io::Error::from_raw_os_error(22));
+        }
+        self.data.read_at(pos, buf)
+    }
+}
+
+#[test]
+fn aligned_reader() -> Result<()> {
+    let bytes = image_bytes("links.img")?;
+    let strict = Strict {
+        data: &bytes,
+        sector_size: 4096,
+    };
+    assert!(ext4::SuperBlock::new(&strict).is_err());
+
+    let fs = ext4::SuperBlock::new(ext4::AlignedReader::new(strict, 4096))?;
+    let inode = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let mut content = String::new();
+    fs.open(&inode)?.read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+
+    Ok(())
+}
+
+/// Hangs on reads of one byte of the image, like a bad sector on a dying disc.
+struct Stuck {
+    data: Vec<u8>,
+    at: u64,
+}
+
+impl ext4::ReadAt for Stuck {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if (pos..pos + buf.len() as u64).contains(&self.at) {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+        }
+        self.data.read_at(pos, buf)
+    }
+}
+
+#[test]
+fn timeout_reader() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let at = fs.physical_offset(&file, 0)?.expect("not sparse");
+
+    let stuck = Stuck {
+        data: image_bytes("links.img")?,
+        at,
+    };
+    let deadline = std::time::Duration::from_millis(100);
+    let fs = ext4::SuperBlock::new(ext4::TimeoutReader::new(stuck, deadline))?;
+    // anything else still reads
+    let long = fs.load_inode(fs.resolve_path("/a/long")?.inode)?;
+    assert!(matches!(
+        fs.enhance(&long)?,
+        ext4::Enhanced::SymbolicLink(_)
+    ));
+
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let mut content = String::new();
+    let err = fs.open(&file)?.read_to_string(&mut content).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    Ok(())
+}
+
+#[test]
+fn split_reader() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+    let deleted = fs.deleted_inodes()?;
+    let reader = fs.open(&deleted[0].inode)?;
+
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&reader);
+
+    // 3000 bytes, across three 1024-byte blocks
+    let parts = reader.split(&[0..1000, 1000..2500, 2500..4000, 5000..6000]);
+    let lens = parts
+        .into_iter()
+        .map(|mut part| {
+            let mut content = Vec::new();
+            part.read_to_end(&mut content)?;
+            assert!(content.iter().all(|&b| b'D' == b));
+            Ok(content.len())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(vec![1000, 1500, 500, 0], lens);
+
+    let mut buf = [0u8; 100];
+    use ext4::ReadAt;
+    reader.read_exact_at(1000, &mut buf)?;
+    reader.read_exact_at(2900, &mut buf)?;
+    assert!(buf.iter().all(|&b| b'D' == b));
+    assert_eq!(0, reader.read_at(3000, &mut buf)?);
+
+    Ok(())
+}
+
+#[test]
+fn ondisk() -> Result<()> {
+    use ext4::ondisk::{RawBlockGroup, RawInode, RawSuperblock};
+
+    let image = open_image("links.img")?;
+    let bytes = image_bytes("links.img")?;
+
+    let sb = RawSuperblock::from_slice(&bytes[RawSuperblock::OFFSET as usize..])?;
+    assert_eq!(1024, RawSuperblock::SIZE);
+    assert_eq!(0xEF53, sb.s_magic);
+    assert_eq!(
+        image.superblock.block_count(),
+        u64::from(sb.s_blocks_count_lo)
+    );
+    assert_eq!(0, sb.s_log_block_size);
+    assert!(RawSuperblock::from_slice(&bytes[..100]).is_err());
+
+    // with 1k blocks, the descriptors start in block 2
+    assert_eq!(64, RawBlockGroup::SIZE);
+    let group = RawBlockGroup::from_slice(&bytes[2048..2048 + RawBlockGroup::SMALL_SIZE])?;
+    assert_eq!(0, group.bg_inode_table_hi);
+
+    let inode_size = usize::from(sb.s_inode_size);
+    let root = (group.bg_inode_table_lo as usize) * 1024 + inode_size;
+    assert_eq!(160, RawInode::SIZE);
+    let raw = RawInode::from_slice(&bytes[root..root + inode_size])?;
+    let root = image.superblock.load_inode(2)?;
+    assert_eq!(0o040000, raw.i_mode & 0o170000);
+    assert_eq!(root.stat.size, u64::from(raw.i_size_lo));
+    assert_eq!(root.stat.link_count, raw.i_links_count);
+
+    Ok(())
+}
+
+#[test]
+fn ondisk_round_trip() -> Result<()> {
+    use ext4::ondisk::{RawBlockGroup, RawInode, RawSuperblock};
+
+    for name in &["links.img", "deleted.img", "journal.img"] {
+        let bytes = image_bytes(name)?;
+        let sb_bytes = &bytes[1024..1024 + RawSuperblock::SIZE];
+        let sb = RawSuperblock::from_slice(sb_bytes)?;
+        assert_eq!(sb_bytes, &sb.to_bytes()[..]);
+
+        let mut written = vec![0u8; RawSuperblock::SIZE];
+        sb.write_into(&mut written)?;
+        assert_eq!(sb, RawSuperblock::from_slice(&written)?);
+
+        let block_size = 1024usize << sb.s_log_block_size;
+        let desc_size = if 0 == sb.s_desc_size {
+            RawBlockGroup::SMALL_SIZE
+        } else {
+            usize::from(sb.s_desc_size)
+        };
+        let inode_size = usize::from(sb.s_inode_size);
+        let groups = (sb.s_blocks_count_lo - sb.s_first_data_block + sb.s_blocks_per_group - 1)
+            / sb.s_blocks_per_group;
+        let descriptors = (sb.s_first_data_block as usize + 1) * block_size;
+
+        for group in 0..groups as usize {
+            let start = descriptors + group * desc_size;
+            let original = &bytes[start..start + desc_size];
+            let desc = RawBlockGroup::from_slice(original)?;
+            let mut written = original.to_vec();
+            written.iter_mut().for_each(|b| *b = 0xff);
+            desc.write_into(&mut written)?;
+            assert_eq!(original, &written[..]);
+
+            let table = desc.bg_inode_table_lo as usize * block_size;
+            for index in 0..sb.s_inodes_per_group as usize {
+                let start = table + index * inode_size;
+                let original = &bytes[start..start + inode_size];
+                let inode = RawInode::from_slice(original)?;
+                let mut written = original.to_vec();
+                inode.write_into(&mut written)?;
+                assert_eq!(original, &written[..]);
+                assert_eq!(inode, RawInode::from_slice(&written)?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ondisk_dirents_and_extents() -> Result<()> {
+    use ext4::ondisk::{
+        RawBlockGroup, RawDirEntry, RawExtent, RawExtentHeader, RawInode, RawSuperblock,
+    };
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let bytes = image_bytes("links.img")?;
+
+    let sb = RawSuperblock::from_slice(&bytes[1024..])?;
+    let group = RawBlockGroup::from_slice(&bytes[2048..])?;
+    let inode_size = usize::from(sb.s_inode_size);
+    let root = group.bg_inode_table_lo as usize * 1024 + inode_size;
+    let raw = RawInode::from_slice(&bytes[root..root + inode_size])?;
+
+    let header = RawExtentHeader::from_slice(&raw.i_block)?;
+    assert_eq!(RawExtentHeader::MAGIC, header.eh_magic);
+    assert_eq!(0, header.eh_depth);
+    assert_eq!(
+        &raw.i_block[..RawExtentHeader::SIZE],
+        &header.to_bytes()[..]
+    );
+
+    let root = fs.load_inode(2)?;
+    let expected = fs.data_extents(&root)?;
+    assert_eq!(expected.len(), usize::from(header.eh_entries));
+    let extent = RawExtent::from_slice(&raw.i_block[RawExtentHeader::SIZE..])?;
+    assert_eq!(expected[0].logical, extent.ee_block);
+    assert_eq!(expected[0].physical, extent.start());
+    assert_eq!(expected[0].len, extent.ee_len);
+
+    let block = extent.start() as usize * 1024;
+    let block = &bytes[block..block + 1024];
+    let mut names = Vec::new();
+    let mut pos = 0;
+    while pos < block.len() {
+        let entry = RawDirEntry::from_slice(&block[pos..])?;
+        let len = RawDirEntry::HEADER_SIZE + entry.name.len();
+        assert_eq!(&block[pos..pos + len], &entry.to_bytes()[..len]);
+        assert_eq!(usize::from(entry.rec_len), entry.to_bytes().len());
+        if 0 != entry.inode {
+            names.push(String::from_utf8(entry.name)?);
+        }
+        pos += usize::from(entry.rec_len);
+    }
+
+    let mut expected = match fs.enhance(&root)? {
+        ext4::Enhanced::Directory(entries) => entries,
+        other => panic!("root isn't a directory: {:?}", other),
+    }
+    .into_iter()
+    .map(|entry| entry.name)
+    .collect::<Vec<_>>();
+    expected.sort();
+    names.sort();
+    assert_eq!(expected, names);
+
+    assert!(RawDirEntry::from_slice(&[1, 0, 0, 0, 12, 0, 9, 1, b'a']).is_err());
+
+    Ok(())
+}
+
+/// Parse the superblock of `bytes`, let `edit` change it, then write it back with a fixed checksum.
+fn patch_superblock<F>(bytes: &mut [u8], edit: F) -> Result<()>
+where
+    F: FnOnce(&mut ext4::ondisk::RawSuperblock),
+{
+    let sb = &mut bytes[1024..2048];
+    let mut raw = ext4::ondisk::RawSuperblock::from_slice(sb)?;
+    edit(&mut raw);
+    raw.s_checksum = ext4::parse::ext4_style_crc32c_le(!0, &raw.to_bytes()[..0x3FC]);
+    raw.write_into(sb)?;
+    Ok(())
+}
+
+#[test]
+fn warnings() -> Result<()> {
+    use ext4::Warning;
+
+    let mut bytes = image_bytes("blocks-64k.img")?;
+    patch_superblock(&mut bytes, |sb| {
+        sb.s_feature_compat |= 0x8000_0000;
+        sb.s_volume_name = *b"data\0\0\0\0stale\0\0\0";
+    })?;
+    // /data's mtime, with more than a second of nanoseconds; there are no checksums
+    let extra = 34 * 65536 + 0xb00 + 0x88;
+    bytes[extra..extra + 4].copy_from_slice(&(1_000_000_005u32 << 2).to_le_bytes());
+
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    assert_eq!(
+        vec![
+            Warning::UnknownCompatibleFeatures(0x8000_0000),
+            Warning::TextAfterNul {
+                field: "s_volume_name"
+            },
+        ],
+        fs.take_warnings()
+    );
+    assert_eq!(Some("data".to_string()), fs.info().volume_name);
+
+    let data = fs.load_inode(fs.resolve_path("/data")?.inode)?;
+    assert_eq!(Some(999_999_999), data.stat.mtime.nanos);
+    fs.load_inode(data.number)?;
+    let clamped = Warning::NanosecondsClamped {
+        inode: data.number,
+        field: "mtime",
+        nanos: 1_000_000_005,
+    };
+    assert_eq!(vec![clamped.clone()], fs.warnings());
+    assert_eq!(
+        "<12>'s mtime has 1000000005 nanoseconds, clamped to 999999999",
+        clamped.to_string()
+    );
+    assert_eq!(vec![clamped], fs.take_warnings());
+    fs.load_inode(data.number)?;
+    assert!(fs.warnings().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn creator_os() -> Result<()> {
+    use ext4::ondisk::{RawBlockGroup, RawSuperblock};
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let mut expected = Vec::new();
+    fs.open(&file)?.read_to_end(&mut expected)?;
+
+    let mut bytes = image_bytes("links.img")?;
+    let sb = RawSuperblock::from_slice(&bytes[1024..2048])?;
+    let group = RawBlockGroup::from_slice(&bytes[2048..2048 + RawBlockGroup::SMALL_SIZE])?;
+    let inode_size = usize::from(sb.s_inode_size);
+    let start = group.bg_inode_table_lo as usize * 1024 + (file.number as usize - 1) * inode_size;
+    // the Hurd's h_i_mode_high, but the top of the xattr block's number for Linux, whose
+    // layout FreeBSD shares, and which has a checksum to break
+    bytes[start + 0x76] = 0xFF;
+
+    let any = ext4::Options {
+        creators: ext4::Creators::Any,
+        ..ext4::Options::default()
+    };
+    for &(raw, creator_os, readable) in &[
+        (1, ext4::CreatorOs::Hurd, true),
+        (3, ext4::CreatorOs::FreeBsd, false),
+    ] {
+        patch_superblock(&mut bytes, |sb| sb.s_creator_os = raw)?;
+        assert!(ext4::SuperBlock::new(&bytes[..]).is_err());
+
+        let fs = ext4::SuperBlock::new_with_options(&bytes[..], &any)?;
+        assert_eq!(creator_os, fs.info().creator_os);
+        let inode = fs.load_inode(file.number);
+        assert_eq!(readable, inode.is_ok(), "{:?}", creator_os);
+        if let Ok(inode) = inode {
+            assert_eq!(file.stat.uid, inode.stat.uid);
+            let mut content = Vec::new();
+            fs.open(&inode)?.read_to_end(&mut content)?;
+            assert_eq!(expected, content);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn error_history() -> Result<()> {
+    let image = open_image("links.img")?;
+    assert_eq!(None, image.superblock.error_history());
+
+    let mut bytes = image_bytes("links.img")?;
+    patch_superblock(&mut bytes, |sb| {
+        sb.s_error_count = 3;
+        sb.s_first_error_time = 1_600_000_000;
+        sb.s_first_error_ino = 12;
+        sb.s_first_error_func[..11].copy_from_slice(b"ext4_lookup");
+        sb.s_first_error_line = 1701;
+        sb.s_first_error_errcode = 1;
+        sb.s_last_error_time = 1;
+        sb.s_last_error_time_hi = 1;
+        sb.s_last_error_block = 99;
+    })?;
+
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    assert_eq!(
+        Some(ext4::ErrorHistory {
+            count: 3,
+            first: Some(ext4::ErrorRecord {
+                time: ext4::Time {
+                    epoch_secs: 1_600_000_000,
+                    nanos: None,
+                },
+                inode: Some(12),
+                block: None,
+                function: "ext4_lookup".to_string(),
+                line: 1701,
+                code: 1,
+            }),
+            last: Some(ext4::ErrorRecord {
+                time: ext4::Time {
+                    epoch_secs: (1 << 32) + 1,
+                    nanos: None,
+                },
+                inode: None,
+                block: Some(99),
+                function: String::new(),
+                line: 0,
+                code: 0,
+            }),
+        }),
+        fs.error_history()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn info() -> Result<()> {
+    let image = open_image("links.img")?;
+    let info = image.superblock.info();
+    let created = Some(ext4::Time {
+        epoch_secs: 1_500_000_000,
+        nanos: None,
+    });
+    assert_eq!(b"links\0", &info.uuid[..6]);
+    assert_eq!(ext4::CreatorOs::Linux, info.creator_os);
+    assert_eq!(None, info.volume_name);
+    assert_eq!(created, info.created);
+    assert_eq!(None, info.last_mounted);
+    assert_eq!(None, info.last_mount_point);
+    assert_eq!(0, info.mount_count);
+    assert_eq!(None, info.max_mount_count);
+    assert_eq!(created, info.last_checked);
+    assert_eq!(None, info.check_interval);
+    assert_eq!(
+        ext4::DefaultMountOptions::USER_XATTR | ext4::DefaultMountOptions::ACL,
+        info.default_mount_options
+    );
+    assert_eq!(None, info.default_journal_mode);
+    assert_eq!(None, info.mount_options);
+    assert_eq!(ext4::SuperBlockFlags::SIGNED_DIRECTORY_HASH, info.flags);
+
+    let mut bytes = image_bytes("links.img")?;
+    patch_superblock(&mut bytes, |sb| {
+        sb.s_volume_name[..4].copy_from_slice(b"boot");
+        sb.s_last_mounted[..5].copy_from_slice(b"/boot");
+        sb.s_mnt_count = 7;
+        sb.s_max_mnt_count = 20;
+        sb.s_kbytes_written = 12345;
+        sb.s_default_mount_opts |= 0x0060;
+        sb.s_mount_opts[..12].copy_from_slice(b"nodelalloc,i");
+    })?;
+    let info = ext4::SuperBlock::new(&bytes[..])?.info();
+    assert_eq!(Some("boot"), info.volume_name.as_deref());
+    assert_eq!(Some("/boot"), info.last_mount_point.as_deref());
+    assert_eq!(7, info.mount_count);
+    assert_eq!(Some(20), info.max_mount_count);
+    assert_eq!(12345, info.kbytes_written);
+    assert_eq!(
+        Some(ext4::JournalMode::Writeback),
+        info.default_journal_mode
+    );
+    assert_eq!(Some("nodelalloc,i"), info.mount_options.as_deref());
+
+    Ok(())
+}
+
+#[test]
+fn dirhash() -> Result<()> {
+    use ext4::HashVersion::*;
+
+    // from `debugfs -R 'dx_hash -h N -s 6c696e6b-7300-4000-8000-000000000001 NAME'`
+    let seed = [0x6b6e_696c, 0x0040_0073, 0x0000_0080, 0x0100_0000];
+    let long = &b"a-rather-long-file-name-which-is-longer-than-thirty-two-bytes"[..];
+    let cafe = "caf\u{e9}".as_bytes();
+    for &(version, name, hash, minor_hash) in &[
+        (Legacy, &b"hello"[..], 0x3225_2546, 0),
+        (Legacy, cafe, 0x96ca_5a2c, 0),
+        (Legacy, long, 0x90a9_9c58, 0),
+        (HalfMd4, &b"hello"[..], 0x6e20_2936, 0x6298_b4d9),
+        (HalfMd4, cafe, 0x83d2_10fa, 0x613a_bed0),
+        (HalfMd4, long, 0x38d8_bacc, 0x3544_fd81),
+        (Tea, &b"hello"[..], 0x698f_7086, 0xf3cf_1c22),
+        (Tea, cafe, 0xbaca_75c2, 0x0695_9285),
+        (Tea, long, 0x35d3_7f18, 0x82d5_5de8),
+        (LegacyUnsigned, cafe, 0x6dde_4230, 0),
+        (LegacyUnsigned, long, 0x90a9_9c58, 0),
+        (HalfMd4Unsigned, cafe, 0xf81f_93b6, 0xe305_52a2),
+        (HalfMd4Unsigned, long, 0x38d8_bacc, 0x3544_fd81),
+        (TeaUnsigned, cafe, 0x8bac_1bb6, 0x2f35_85fb),
+        (TeaUnsigned, long, 0x35d3_7f18, 0x82d5_5de8),
+    ] {
+        assert_eq!(
+            ext4::DirHash { hash, minor_hash },
+            ext4::dirhash(version, &seed, name)?,
+            "{:?} {:?}",
+            version,
+            String::from_utf8_lossy(name)
+        );
+    }
+
+    // a zero seed means the default
+    assert_eq!(
+        ext4::DirHash {
+            hash: 0x1746_da32,
+            minor_hash: 0x4200_13b5
+        },
+        ext4::dirhash(HalfMd4, &[0; 4], b"hello")?
+    );
+    assert_eq!(
+        ext4::DirHash {
+            hash: 0x6f5b_b1a8,
+            minor_hash: 0x2319_17c2
+        },
+        ext4::dirhash(Tea, &[0; 4], b"hello")?
+    );
+
+    let image = open_image("links.img")?;
+    let info = image.superblock.info();
+    assert_eq!(Some(HalfMd4), info.default_hash_version);
+    assert_eq!(seed, info.hash_seed);
+    assert_eq!(
+        ext4::dirhash(HalfMd4, &seed, b"hello")?,
+        image.superblock.dirhash(b"hello")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn dir_records() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+    let root = fs.root()?;
+
+    let records = fs.dir_records(&root, false)?;
+    assert_eq!(
+        vec![
+            (0, 12, 2, &b"."[..]),
+            (12, 12, 2, b".."),
+            (24, 64, 11, b"lost+found"),
+            (88, 924, 14, b"kept.txt"),
+        ],
+        records
+            .iter()
+            .map(|r| (r.offset, r.rec_len, r.inode, &r.name[..]))
+            .collect::<Vec<_>>()
+    );
+    assert!(records.iter().all(|r| 35 == r.block));
+    assert_eq!(Some(ext4::FileType::RegularFile), records[3].file_type);
+
+    // delete kept.txt in place, as the kernel does for the first entry in a block
+    let mut bytes = image_bytes("deleted.img")?;
+    bytes[35 * 1024 + 88..][..4].copy_from_slice(&0u32.to_le_bytes());
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    let root = fs.root()?;
+
+    assert_eq!(3, fs.dir_records(&root, false)?.len());
+    let records = fs.dir_records(&root, true)?;
+    assert_eq!(4, records.len());
+    assert!(records[3].deleted());
+    assert_eq!(b"kept.txt", &records[3].name[..]);
+
+    assert!(fs.dir_records(&fs.load_inode(14)?, false).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn carve_directory() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+
+    let carved = fs.carve_directory(&fs.root()?)?;
+    assert_eq!(
+        vec![
+            (44, 12, &b"deleted.txt"[..], ext4::Confidence::High),
+            (64, 13, b"journalled.txt", ext4::Confidence::High),
+        ],
+        carved
+            .iter()
+            .map(|c| (
+                c.record.offset,
+                c.record.inode,
+                &c.record.name[..],
+                c.confidence
+            ))
+            .collect::<Vec<_>>()
+    );
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    assert!(fs.carve_directory(&fs.root()?)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn high_water_mark() -> Result<()> {
+    let image = open_image("links.img")?;
+    let mark = image.superblock.high_water_mark()?;
+    assert_eq!(75, mark.blocks);
+    assert_eq!(75 * 1024, mark.len);
+    assert_eq!(1024 - 75, mark.reclaimable);
+    assert_eq!(962, mark.free);
+
+    let whole = image_bytes("deleted.img")?;
+    let full = ext4::SuperBlock::new(&whole[..])?;
+    let mark = full.high_water_mark()?;
+    assert_eq!(1339, mark.blocks);
+    assert_eq!(4096 - 1339, mark.reclaimable);
+    assert_eq!(2765, mark.free);
+
+    // everything in use is still there, with the free tail cut off
+    let len = usize::try_from(mark.len)?;
+    let options = ext4::Options {
+        len: Some(mark.len),
+        truncation: ext4::Truncation::Tolerate,
+        ..ext4::Options::default()
+    };
+    let cut = ext4::SuperBlock::new_with_options(&whole[..len], &options)?;
+    assert_eq!(Some(1339), cut.first_missing_block());
+
+    let contents = |fs: &ext4::SuperBlock<&[u8]>| -> Result<Vec<(String, Vec<u8>)>> {
+        let mut contents = Vec::new();
+        fs.walk(&fs.root()?, "", &mut |fs, path, inode, _| {
+            let mut content = Vec::new();
+            if ext4::FileType::RegularFile == inode.stat.extracted_type {
+                fs.open(inode)?.read_to_end(&mut content)?;
+            }
+            contents.push((path.to_string(), content));
+            Ok(true)
+        })?;
+        Ok(contents)
+    };
+    assert_eq!(contents(&full)?, contents(&cut)?);
+
+    Ok(())
+}
+
+#[test]
+fn wipe_free_space() -> Result<()> {
+    let original = image_bytes("deleted.img")?;
+    let fs = ext4::SuperBlock::new(&original[..])?;
+    let mut wipe = ext4::Wipe {
+        directory_slack: false,
+        dry_run: true,
+    };
+    // the free blocks, 1330-1337 and 1339-4095
+    assert_eq!(
+        2765 * 1024,
+        fs.wipe_free_space(io::Cursor::new(Vec::new()), &wipe)?
+    );
+
+    wipe.directory_slack = true;
+    let total = fs.wipe_free_space(io::Cursor::new(Vec::new()), &wipe)?;
+    assert!(total > 2765 * 1024, "{}", total);
+
+    wipe.dry_run = false;
+    let mut wiped = original.clone();
+    assert_eq!(
+        total,
+        fs.wipe_free_space(io::Cursor::new(&mut wiped[..]), &wipe)?
+    );
+    assert!(wiped[1339 * 1024..].iter().all(|&b| 0 == b));
+
+    let after = ext4::SuperBlock::new(&wiped[..])?;
+    assert_eq!(2, fs.carve_directory(&fs.root()?)?.len());
+    assert!(after.carve_directory(&after.root()?)?.is_empty());
+    assert_eq!(fs.check()?, after.check()?);
+    let mut kept = String::new();
+    after
+        .open(&after.load_inode(after.resolve_path("/kept.txt")?.inode)?)?
+        .read_to_string(&mut kept)?;
+    assert!(!kept.is_empty());
+
+    // the index is left alone, so every name can still be found through it
+    let original = image_bytes("htree.img")?;
+    let fs = ext4::SuperBlock::new(&original[..])?;
+    let mut wiped = original.clone();
+    fs.wipe_free_space(io::Cursor::new(&mut wiped[..]), &wipe)?;
+    let after = ext4::SuperBlock::new(&wiped[..])?;
+    assert_eq!(fs.check()?, after.check()?);
+    let big = |fs: &ext4::SuperBlock<&[u8]>| -> Result<Option<ext4::DxTree>> {
+        fs.dir_index(&fs.load_inode(fs.resolve_path("/big")?.inode)?)
+    };
+    assert_eq!(big(&fs)?, big(&after)?);
+
+    Ok(())
+}
+
+#[test]
+fn unallocated() -> Result<()> {
+    // from `dumpe2fs`
+    let image = open_image("links.img")?;
+    assert_eq!(
+        vec![29..42, 75..1024],
+        image.superblock.unallocated_ranges()?
+    );
+
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+    assert_eq!(vec![1330..1338, 1339..4096], fs.unallocated_ranges()?);
+    assert_eq!(
+        Some(&1339),
+        fs.unallocated_blocks()?.collect::<Vec<_>>().get(8)
+    );
+
+    let mut reader = fs.unallocated()?;
+    assert_eq!((8 + 2757) * 1024, reader.len());
+    assert_eq!(Some(1330 * 1024), reader.physical_offset(0));
+    assert_eq!(Some(1339 * 1024 + 5), reader.physical_offset(8 * 1024 + 5));
+    assert_eq!(None, reader.physical_offset(reader.len()));
+
+    let bytes = image_bytes("deleted.img")?;
+    let mut free = Vec::new();
+    reader.read_to_end(&mut free)?;
+    assert_eq!(&bytes[1330 * 1024..1338 * 1024], &free[..8 * 1024]);
+    assert_eq!(&bytes[1339 * 1024..], &free[8 * 1024..]);
+
+    Ok(())
+}
+
+#[test]
+fn block_map() -> Result<()> {
+    use ext4::BlockUse::*;
+
+    // from `dumpe2fs`, and `debugfs`'s `ex`
+    let image = open_image("deleted.img")?;
+    let map = image.superblock.block_map()?;
+    assert_eq!(
+        vec![
+            (0, 1, Boot),
+            (1, 1, Superblock),
+            (2, 32, GroupDescriptors),
+            (34, 1, BlockBitmap),
+            (35, 1, Data(2)),
+            (36, 12, Data(11)),
+            (48, 2, Journal),
+            (50, 1, InodeBitmap),
+            (51, 15, Journal),
+            (66, 256, InodeTable),
+            // the resize inode's, which isn't in the tree
+            (322, 1, Unknown),
+            (323, 1007, Journal),
+            (1330, 8, Free),
+            (1338, 1, Data(14)),
+            (1339, 2757, Free),
+        ],
+        map.iter()
+            .map(|run| (run.start, run.len, run.usage))
+            .collect::<Vec<_>>()
+    );
+
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let map = fs.block_map()?;
+    assert_eq!(fs.block_count(), map.iter().map(|run| run.len).sum::<u64>());
+    assert!(map.windows(2).all(|w| w[0].start + w[0].len == w[1].start));
+    assert_eq!(
+        fs.unallocated_blocks()?.count() as u64,
+        map.iter()
+            .filter(|run| Free == run.usage)
+            .map(|run| run.len)
+            .sum::<u64>()
+    );
+    let top = fs.resolve_path("/top")?.inode;
+    assert!(map.iter().all(|run| Data(top) != run.usage));
+
+    Ok(())
+}
+
+#[test]
+fn export_metadata() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+    let bytes = image_bytes("deleted.img")?;
+
+    let mut out = io::Cursor::new(Vec::new());
+    // the superblock, descriptors, bitmaps, inode table, and the two directories
+    assert_eq!(
+        1 + 32 + 1 + 1 + 256 + 1 + 12,
+        fs.export_metadata(&mut out, &ext4::MetadataExport::default())?
+    );
+    let exported = out.into_inner();
+    assert_eq!(bytes.len(), exported.len());
+    assert_eq!(&bytes[1024..48 * 1024], &exported[1024..48 * 1024]);
+    assert_eq!(
+        &bytes[66 * 1024..322 * 1024],
+        &exported[66 * 1024..322 * 1024]
+    );
+
+    let copy = ext4::SuperBlock::new(&exported[..])?;
+    let kept = copy.load_inode(copy.resolve_path("/kept.txt")?.inode)?;
+    assert_eq!(10, kept.stat.size);
+    let mut content = Vec::new();
+    copy.open(&kept)?.read_to_end(&mut content)?;
+    assert_eq!(vec![0u8; 10], content);
+    assert!(exported[323 * 1024..1330 * 1024].iter().all(|&b| 0 == b));
+
+    let mut out = io::Cursor::new(bytes.clone());
+    let options = ext4::MetadataExport {
+        journal: true,
+        omit_names: true,
+        scrub: true,
+    };
+    assert_eq!(
+        1 + 32 + 1 + 256 + 1 + 1024,
+        fs.export_metadata(&mut out, &options)?
+    );
+    let scrubbed = out.into_inner();
+    assert_eq!(
+        &bytes[323 * 1024..1330 * 1024],
+        &scrubbed[323 * 1024..1330 * 1024]
+    );
+    assert!(scrubbed[1338 * 1024..1339 * 1024].iter().all(|&b| 0 == b));
+    assert!(scrubbed[36 * 1024..48 * 1024].iter().all(|&b| 0 == b));
+
+    Ok(())
+}
+
+#[test]
+fn ingest_stream() -> Result<()> {
+    // the image, straight out of the archive, through a pipe
+    let mut tar = std::process::Command::new("tar")
+        .args(["-xzO", "links.img"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = tar.stdin.take().expect("configured above");
+    let feeder = std::thread::spawn(move || {
+        io::copy(
+            &mut io::Cursor::new(
+                &include_bytes!("../scripts/generate-images/small-images.tgz")[..],
+            ),
+            &mut stdin,
+        )
+    });
+
+    let spool_dir = TempDir::new()?;
+    let stdout = tar.stdout.take().expect("configured above");
+    let fs = ext4::ingest_stream(stdout, spool_dir.path())?;
+    // the spool file is already gone
+    assert_eq!(0, fs::read_dir(spool_dir.path())?.count());
+
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    let mut content = String::new();
+    fs.open(&file)?.read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+
+    let spool = fs.into_inner();
+    assert!(spool.spooled() <= 1024 * 1024);
+    assert_eq!(1024 * 1024, spool.finish()?);
+    assert_eq!(1024 * 1024, spool.spooled());
+
+    feeder.join().expect("no panic")?;
+    assert!(tar.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn redact() -> Result<()> {
+    let bytes = image_bytes("links.img")?;
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+
+    let mut out = io::Cursor::new(bytes.clone());
+    let redaction = ext4::Redaction { rename: true };
+    // `/a/b`, and `/a/b/file`
+    assert_eq!(
+        2,
+        fs.redact(&mut out, &redaction, |path, _| path.starts_with("/a/b"))?
+    );
+    let redacted = out.into_inner();
+
+    // the directory blocks' checksums are checked as they're read
+    let fs = ext4::SuperBlock::new(&redacted[..])?;
+    assert!(fs.resolve_path("/a/b").is_err());
+    let file = fs.load_inode(fs.resolve_path("/a/0/0000")?.inode)?;
+    assert_eq!(6, file.stat.size);
+    let mut content = Vec::new();
+    fs.open(&file)?.read_to_end(&mut content)?;
+    assert_eq!(vec![0u8; 6], content);
+    fs.resolve_path("/lost+found")?;
+
+    Ok(())
+}
+
+#[test]
+fn dumpe2fs() -> Result<()> {
+    // `TZ=UTC dumpe2fs $image`, from e2fsprogs 1.47
+    for (name, expected) in &[
+        ("scan.img", include_str!("dumpe2fs/scan.txt")),
+        ("deleted.img", include_str!("dumpe2fs/deleted.txt")),
+    ] {
+        let image = open_image(name)?;
+        let mut out = Vec::new();
+        image.superblock.dumpe2fs(&mut out)?;
+        let out = String::from_utf8(out)?;
+        for (ours, theirs) in out.lines().zip(expected.lines()) {
+            assert_eq!(theirs, ours, "in {}", name);
+        }
+        assert_eq!(*expected, out, "{}", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn debugfs() -> Result<()> {
+    // `TZ=UTC debugfs -f $name.cmd $name.img`, from e2fsprogs 1.47, with stderr dropped
+    for (name, script, expected, failures) in &[
+        (
+            "deleted.img",
+            include_str!("debugfs/deleted.cmd"),
+            include_str!("debugfs/deleted.txt"),
+            0,
+        ),
+        (
+            "links.img",
+            include_str!("debugfs/links.cmd"),
+            include_str!("debugfs/links.txt"),
+            1,
+        ),
+    ] {
+        let image = open_image(name)?;
+        let mut debugfs = ext4::Debugfs::new(&image.superblock);
+        let mut out = Vec::new();
+        let mut errors = Vec::new();
+        assert_eq!(
+            *failures,
+            debugfs.run_script(script, &mut out, &mut errors)?,
+            "{}",
+            String::from_utf8_lossy(&errors)
+        );
+        let out = String::from_utf8(out)?;
+        for (ours, theirs) in out.lines().zip(expected.lines()) {
+            assert_eq!(theirs, ours, "in {}", name);
+        }
+        assert_eq!(*expected, out, "{}", name);
+    }
+
+    let image = open_image("deleted.img")?;
+    let mut debugfs = ext4::Debugfs::new(&image.superblock);
+    let dir = TempDir::new()?;
+    let dest = dir.path().join("kept");
+    debugfs.run(
+        &format!("dump -p /kept.txt \"{}\"", dest.display()),
+        &mut io::sink(),
+    )?;
+    assert_eq!(b"KKKKKKKKKK", &fs::read(&dest)?[..]);
+    assert!(debugfs.run("cd /kept.txt", &mut io::sink()).is_err());
+    assert!(debugfs.run("mkdir /new", &mut io::sink()).is_err());
+    Ok(())
+}
+
+#[test]
+fn timeline() -> Result<()> {
+    let image = open_image("deleted.img")?;
+    let fs = &image.superblock;
+
+    let live = fs.timeline(false)?;
+    assert_eq!(
+        vec!["/", "/lost+found", "/kept.txt"],
+        live.iter().map(|e| e.path.as_str()).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "0|/kept.txt|14|r/rrw-r--r--|0|0|10|1500000000|1500000000|1792111753|1500000000",
+        live[2].body_line()
+    );
+    assert_eq!(Some("d/drwxr-xr-x"), live[0].body_line().split('|').nth(3));
+
+    let all = fs.timeline(true)?;
+    let deleted = all.iter().filter(|e| e.deleted).collect::<Vec<_>>();
+    assert_eq!(live.len() + deleted.len(), all.len());
+    assert_eq!(12, deleted[0].inode);
+    assert!(deleted[0]
+        .body_line()
+        .starts_with("0|$OrphanFiles/OrphanFile-12 (deleted)|12|r/rrw-r--r--|0|0|3000|"));
+
+    Ok(())
+}
+
+#[test]
+fn export_zip() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    /// The names in the central directory, and the count from the end record.
+    fn names(zip: &[u8]) -> (Vec<String>, u16) {
+        let mut names = Vec::new();
+        for pos in 0..zip.len() - 4 {
+            if zip[pos..pos + 4] == [0x50, 0x4b, 0x01, 0x02] {
+                let len = usize::from(u16::from_le_bytes([zip[pos + 28], zip[pos + 29]]));
+                names.push(String::from_utf8_lossy(&zip[pos + 46..pos + 46 + len]).to_string());
+            }
+        }
+        let end = zip.len() - 22;
+        assert_eq!([0x50, 0x4b, 0x05, 0x06], zip[end..end + 4]);
+        (names, u16::from_le_bytes([zip[end + 10], zip[end + 11]]))
+    }
+
+    let stored = fs.export_zip("/a", Vec::new(), ext4::ZipMethod::Store)?;
+    let (stored_names, count) = names(&stored);
+    assert_eq!(
+        vec!["abs", "b/", "b/file", "chain", "long", "loop", "rel", "up"],
+        {
+            let mut sorted = stored_names.clone();
+            sorted.sort();
+            sorted
+        }
+    );
+    assert_eq!(8, count);
+    assert!(stored.windows(6).any(|window| b"hello\n" == window));
+
+    let deflated = fs.export_zip("/a", Vec::new(), ext4::ZipMethod::Deflate)?;
+    assert_eq!((stored_names, count), names(&deflated));
+
+    // someone else's unzip can inflate it, and the CRCs match
+    let dir = TempDir::new()?;
+    let zip = dir.path().join("a.zip");
+    fs::write(&zip, &deflated)?;
+    let python = |args: &[&OsStr]| -> Result<()> {
+        let status = std::process::Command::new("python3")
+            .args([OsStr::new("-m"), OsStr::new("zipfile")])
+            .args(args)
+            .status()?;
+        assert!(status.success(), "{:?}", args);
+        Ok(())
+    };
+    python(&[OsStr::new("-t"), zip.as_os_str()])?;
+    let out = dir.path().join("out");
+    python(&[OsStr::new("-e"), zip.as_os_str(), out.as_os_str()])?;
+    assert_eq!(b"hello\n", &fs::read(out.join("b/file"))?[..]);
+
+    Ok(())
+}
+
+#[test]
+fn export() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    struct Names(Vec<String>);
+    impl ext4::ArchiveSink for Names {
+        fn add(&mut self, entry: &ext4::ArchiveEntry, content: &mut dyn Read) -> Result<()> {
+            let mut data = Vec::new();
+            content.read_to_end(&mut data)?;
+            self.0.push(format!("{} {}", entry.path, data.len()));
+            Ok(())
+        }
+    }
+
+    let mut names = Names(Vec::new());
+    fs.export("/a/b", &mut names)?;
+    assert_eq!(vec!["file 6"], names.0);
+
+    let tar = fs.export_tar("/a", Vec::new())?;
+    assert_eq!(0, tar.len() % 512);
+
+    let dir = TempDir::new()?;
+    let mut child = std::process::Command::new("tar")
+        .args([OsStr::new("-C"), dir.path().as_os_str(), OsStr::new("-x")])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    io::copy(
+        &mut io::Cursor::new(&tar),
+        &mut child.stdin.as_mut().expect("configured above"),
+    )?;
+    drop(child.stdin.take());
+    assert!(child.wait()?.success());
+
+    assert_eq!(b"hello\n", &fs::read(dir.path().join("b/file"))?[..]);
+    assert_eq!(
+        std::path::Path::new("b/file"),
+        fs::read_link(dir.path().join("rel"))?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn export_oci_layer() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let (layer, info) = fs.export_oci_layer("/a", Vec::new())?;
+    assert_eq!(layer.len() as u64, info.size);
+
+    // an upper directory with nothing overlay-specific in it is just a tar
+    assert_eq!(fs.export_tar("/a", Vec::new())?, layer);
+
+    let mut sum = std::process::Command::new("sha256sum")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    io::copy(
+        &mut io::Cursor::new(&layer),
+        &mut sum.stdin.as_mut().expect("configured above"),
+    )?;
+    drop(sum.stdin.take());
+    let output = sum.wait_with_output()?;
+    assert!(output.status.success());
+    let hex = String::from_utf8(output.stdout)?;
+    assert_eq!(format!("sha256:{}", &hex[..64]), info.diff_id, "{:?}", hex);
+
+    Ok(())
+}
+
+#[test]
+fn distro() -> Result<()> {
+    let image = open_image("distro.img")?;
+    let fs = &image.superblock;
+
+    let distro = fs.distro()?;
+
+    let os = distro.os_release.expect("os-release present");
+    assert_eq!("/usr/lib/os-release", os.path);
+    assert_eq!(Some("debian"), os.id.as_deref());
+    assert_eq!(vec!["ubuntu", "mint"], os.id_like);
+    assert_eq!(Some("12"), os.version_id.as_deref());
+    assert_eq!(Some("bookworm"), os.version_codename.as_deref());
+    assert_eq!(
+        Some("Debian GNU/Linux 12 (bookworm)"),
+        os.pretty_name.as_deref()
+    );
+    assert_eq!(
+        Some("https://www.debian.org/ \"quoted\""),
+        os.fields.get("HOME_URL").map(|s| s.as_str())
+    );
+
+    let passwd = distro.passwd.expect("passwd present");
+    assert_eq!(
+        vec![("root", 0, "/bin/bash"), ("faux", 1000, "/bin/zsh")],
+        passwd
+            .iter()
+            .map(|user| (user.name.as_str(), user.uid, user.shell.as_str()))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("Chris West,,,", passwd[1].gecos);
+
+    let group = distro.group.expect("group present");
+    assert_eq!(3, group.len());
+    assert_eq!("sudo", group[1].name);
+    assert_eq!(27, group[1].gid);
+    assert_eq!(vec!["faux", "other"], group[1].members);
+    assert!(group[2].members.is_empty());
+
+    let dpkg = distro.dpkg.expect("dpkg status present");
+    assert_eq!(2, dpkg.len());
+    assert_eq!("libc6", dpkg[0].name);
+    assert_eq!("2.36-9", dpkg[0].version);
+    assert_eq!(Some("amd64"), dpkg[0].architecture.as_deref());
+    assert_eq!(Some("glibc"), dpkg[0].source.as_deref());
+    assert!(dpkg[0].is_installed());
+    assert_eq!("gone", dpkg[1].name);
+    assert!(!dpkg[1].is_installed());
+
+    let rpm = distro.rpm.expect("rpm database present");
+    assert_eq!("/usr/lib/sysimage/rpm", rpm.path);
+    assert_eq!(vec![("rpmdb.sqlite".to_string(), 4096)], rpm.files);
+    assert_eq!(Some(ext4::RpmBackend::Sqlite), rpm.backend());
+
+    // none of it is there
+    let empty = open_image("links.img")?.superblock.distro()?;
+    assert_eq!(None, empty.os_release);
+    assert_eq!(None, empty.passwd);
+    assert_eq!(None, empty.rpm);
+
+    Ok(())
+}
+
+#[test]
+fn owner_names() -> Result<()> {
+    let image = open_image("distro.img")?;
+    let fs = &image.superblock;
+
+    assert_eq!(Some("root"), fs.name_for_uid(0)?.as_deref());
+    assert_eq!(Some("faux"), fs.name_for_uid(1000)?.as_deref());
+    assert_eq!(None, fs.name_for_uid(27)?);
+    assert_eq!(Some("sudo"), fs.name_for_gid(27)?.as_deref());
+    assert_eq!("faux", fs.display_uid(1000)?);
+    assert_eq!("4242", fs.display_gid(4242)?);
+
+    // no /etc at all
+    let image = open_image("links.img")?;
+    assert_eq!(None, image.superblock.name_for_uid(0)?);
+    assert_eq!("0", image.superblock.display_gid(0)?);
+
+    Ok(())
+}
+
+#[test]
+fn walk_order() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let walk = |sort| -> Result<Vec<(String, u32)>> {
+        let mut seen = Vec::new();
+        fs.walk_with_options(
+            &fs.root()?,
+            "",
+            &ext4::WalkOptions {
+                sort,
+                ..Default::default()
+            },
+            &(),
+            &mut |_, path, inode, _| {
+                seen.push((path.to_string(), inode.number));
+                Ok(true)
+            },
+        )?;
+        Ok(seen)
+    };
+
+    let by_name = walk(ext4::WalkOrder::Name)?;
+    assert_eq!(
+        vec![
+            "",
+            "/a",
+            "/a/abs",
+            "/a/b",
+            "/a/b/file",
+            "/a/chain",
+            "/a/long",
+            "/a/loop",
+            "/a/rel",
+            "/a/up",
+            "/lost+found",
+            "/top"
+        ],
+        by_name
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    // lost+found is made before anything else, so comes first
+    let by_inode = walk(ext4::WalkOrder::Inode)?;
+    assert_eq!(
+        vec![2, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21],
+        by_inode.iter().map(|(_, inode)| *inode).collect::<Vec<_>>()
+    );
+
+    let mut on_disk = walk(ext4::WalkOrder::Disk)?;
+    on_disk.sort();
+    assert_eq!(by_name, on_disk);
+
+    Ok(())
+}
+
+#[test]
+fn directory_inside_itself() -> Result<()> {
+    // /a/self is /a
+    let bytes = image_bytes("cycle.img")?;
+    let limits = ext4::Limits::untrusted();
+    let fs = ext4::SuperBlock::new_with_options(
+        &bytes[..],
+        &ext4::Options {
+            limits,
+            ..ext4::Options::default()
+        },
+    )?;
+    let too_deep = |err: anyhow::Error| {
+        assert!(
+            matches!(
+                err.root_cause().downcast_ref::<ext4::ParseError>(),
+                Some(ext4::ParseError::LimitExceeded { what, .. }) if what.contains("depth")
+            ),
+            "{:?}",
+            err
+        )
+    };
+
+    let mut events = fs.events();
+    let mut entered = 0;
+    let err = loop {
+        match events.next_event() {
+            Ok(ext4::Event::EnterDir { .. }) => entered += 1,
+            Ok(ext4::Event::End) => panic!("the cycle ended"),
+            Ok(_) => (),
+            Err(e) => break e,
+        }
+    };
+    too_deep(err);
+    assert!(entered > limits.max_walk_depth, "{}", entered);
+    assert!(matches!(events.next_event()?, ext4::Event::End));
+
+    too_deep(fs.export_tar("/a", Vec::new()).unwrap_err());
+    too_deep(
+        fs.export_zip("/a", Vec::new(), ext4::ZipMethod::Store)
+            .unwrap_err(),
+    );
+    too_deep(fs.export_oci_layer("/a", Vec::new()).unwrap_err());
+
+    Ok(())
+}
+
+#[test]
+fn events() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let options = ext4::WalkOptions {
+        sort: ext4::WalkOrder::Name,
+        ..Default::default()
+    };
+    let mut events = fs.events_with_options(fs.root()?.number, "", &options, &());
+    let mut seen = Vec::new();
+    loop {
+        match events.next_event()? {
+            ext4::Event::EnterDir { path, .. } => seen.push(format!("> {}", path)),
+            ext4::Event::Entry { path, inode, .. } => {
+                assert_ne!(ext4::FileType::Directory, inode.stat.extracted_type);
+                seen.push(path);
+            }
+            ext4::Event::LeaveDir { path } => seen.push(format!("< {}", path)),
+            ext4::Event::Corruption { path, error } => panic!("{}: {:?}", path, error),
+            ext4::Event::End => break,
+        }
+    }
+    assert!(matches!(events.next_event()?, ext4::Event::End));
+
+    assert_eq!(
+        vec![
+            "> ",
+            "> /a",
+            "/a/abs",
+            "> /a/b",
+            "/a/b/file",
+            "< /a/b",
+            "/a/chain",
+            "/a/long",
+            "/a/loop",
+            "/a/rel",
+            "/a/up",
+            "< /a",
+            "> /lost+found",
+            "< /lost+found",
+            "/top",
+            "< ",
+        ],
+        seen
+    );
+
+    Ok(())
+}
+
+#[test]
+fn with_root() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = image.superblock.with_root("/top")?;
+
+    // /top -> a/abs -> /a/b
+    assert_eq!(14, fs.root()?.number);
+    assert_eq!(15, fs.resolve_path("/file")?.inode);
+    assert_eq!(14, fs.resolve_path("/")?.inode);
+    assert!(fs.resolve_path("/a").is_err());
+
+    // `..` stops at the new root
+    let (path, entry) = fs.canonicalize("/../../file")?;
+    assert_eq!("/file", path);
+    assert_eq!(15, entry.inode);
+
+    let mut names = Vec::new();
+    fs.walk(&fs.root()?, "", &mut |_, path, _, _| {
+        names.push(path.to_string());
+        Ok(true)
+    })?;
+    assert_eq!(vec!["", "/file"], names);
+
+    // relative to the current root, and by inode
+    let image = open_image("links.img")?;
+    let fs = image.superblock.with_root(12)?.with_root("b")?;
+    assert_eq!(14, fs.root()?.number);
+
+    let image = open_image("links.img")?;
+    assert!(image.superblock.with_root("/a/b/file").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn confined_resolution() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+
+    let lstat = ext4::ResolveOptions {
+        follow_final: false,
+        ..Default::default()
+    };
+    // /top -> a/abs -> /a/b, so its parent is /a
+    assert_eq!(("/a/abs".to_string(), 13), {
+        let (path, entry) = fs.resolve_with_options("/top/../abs", &lstat)?;
+        (path, entry.inode)
+    });
+    assert_eq!(21, fs.resolve_with_options("/top", &lstat)?.1.inode);
+    // a trailing slash follows it anyway
+    assert_eq!(14, fs.resolve_with_options("/top/", &lstat)?.1.inode);
+
+    let beneath = ext4::ResolveOptions {
+        confinement: ext4::Confinement::Beneath,
+        ..Default::default()
+    };
+    assert_eq!(15, fs.resolve_with_options("/a/rel", &beneath)?.1.inode);
+    assert_eq!(2, fs.resolve_with_options("/a/up", &beneath)?.1.inode);
+    assert!(fs.resolve_with_options("/..", &beneath).is_err());
+    // a/abs -> /a/b
+    assert!(fs.resolve_with_options("/top", &beneath).is_err());
+    assert_eq!(2, fs.resolve_with_options("/..", &lstat)?.1.inode);
+
+    // an absolute link is relative to the moved root, and `..` can't leave it
+    let image = open_image("links.img")?;
+    let fs = image.superblock.with_root("/a")?;
+    assert!(fs
+        .resolve_with_options("/abs", &Default::default())
+        .is_err());
+    let (path, entry) = fs.resolve_with_options("/../up/../b/file", &lstat)?;
+    assert_eq!(("/b/file", 15), (path.as_str(), entry.inode));
+    assert_eq!(12, fs.resolve_with_options("/..", &lstat)?.1.inode);
+
+    Ok(())
+}
+
+#[test]
+fn encrypted() -> Result<()> {
+    let image = open_image("encrypted.img")?;
+    let fs = &image.superblock;
+
+    let walk = |encrypted| -> Result<Vec<String>> {
+        let mut seen = Vec::new();
+        fs.walk_with_options(
+            &fs.root()?,
+            "",
+            &ext4::WalkOptions {
+                sort: ext4::WalkOrder::Name,
+                encrypted,
+                ..Default::default()
+            },
+            &(),
+            &mut |fs, path, inode, _| {
+                let mut content = Vec::new();
+                if ext4::FileType::RegularFile == inode.stat.extracted_type {
+                    fs.open(inode)?.read_to_end(&mut content)?;
+                }
+                seen.push(format!(
+                    "{} {} {:?}",
+                    path,
+                    inode.is_encrypted(),
+                    String::from_utf8(content)?
+                ));
+                Ok(true)
+            },
+        )?;
+        Ok(seen)
+    };
+
+    assert!(walk(ext4::EncryptedPolicy::Fail).is_err());
+    assert_eq!(
+        vec![
+            " false \"\"",
+            "/lost+found false \"\"",
+            "/plain false \"\"",
+            "/plain/ok false \"ok\\n\""
+        ],
+        walk(ext4::EncryptedPolicy::Skip)?
+    );
+    // the names aren't really encrypted, so "file" can be recognised in base64
+    assert_eq!(
+        vec!["/secret true \"\"", "/secret/ZmlsZQ true \"hidden\\n\""],
+        walk(ext4::EncryptedPolicy::YieldCiphertext)?[4..]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn truncated() -> Result<()> {
+    let image = open_image("links.img")?;
+    let file = image.superblock.into_inner();
+    let len = ext4::image_len(&file)?;
+
+    let open = |len| {
+        ext4::SuperBlock::new_with_options(
+            &file,
+            &ext4::Options {
+                len: Some(len),
+                ..ext4::Options::default()
+            },
+        )
+    };
+    assert_eq!(2, open(len)?.root()?.number);
+
+    let err = open(len / 2).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ext4::ParseError>(),
+        Some(ext4::ParseError::Truncated { expected, found })
+            if *expected == len && *found == len / 2
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn truncated_tolerated() -> Result<()> {
+    let whole = image_bytes("journal.img")?;
+    // the journal, <8>, is at 48-49, 51-65, then 323-1329; cut it off at 400
+    let bytes = &whole[..400 * 1024];
+    let options = ext4::Options {
+        len: Some(400 * 1024),
+        truncation: ext4::Truncation::Tolerate,
+        ..ext4::Options::default()
+    };
+    let fs = ext4::SuperBlock::new_with_options(bytes, &options)?;
+    assert_eq!(Some(400), fs.first_missing_block());
+
+    let journal = fs.load_inode(8)?;
+    let mut content = Vec::new();
+    fs.open(&journal)?.read_to_end(&mut content)?;
+    assert_eq!(1024 * 1024, content.len());
+
+    let full = ext4::SuperBlock::new(&whole[..])?;
+    let mut expected = Vec::new();
+    full.open(&full.load_inode(8)?)?
+        .read_to_end(&mut expected)?;
+    let present = (17 + 400 - 323) * 1024;
+    assert_eq!(expected[..present], content[..present]);
+    assert!(content[present..].iter().all(|&b| 0 == b));
+
+    let findings = fs.check()?;
+    assert!(findings.iter().any(|f| f
+        .message
+        .contains("blocks 400-4095, 3696 of 4096, are missing")));
+
+    Ok(())
+}
+
+#[test]
+fn limits() -> Result<()> {
+    let bytes = image_bytes("links.img")?;
+    let open = |limits| {
+        ext4::SuperBlock::new_with_options(
+            &bytes[..],
+            &ext4::Options {
+                limits,
+                ..ext4::Options::default()
+            },
+        )
+    };
+    let exceeded = |err: anyhow::Error, wanted: &str| {
+        assert!(
+            matches!(
+                err.root_cause().downcast_ref::<ext4::ParseError>(),
+                Some(ext4::ParseError::LimitExceeded { what, .. }) if what.contains(wanted)
+            ),
+            "{:?}",
+            err
+        )
+    };
+
+    let fs = open(ext4::Limits::default())?;
+    fs.resolve_path("/a/b/file")?;
+
+    exceeded(
+        open(ext4::Limits {
+            max_groups: 0,
+            ..ext4::Limits::default()
+        })
+        .unwrap_err(),
+        "block groups",
+    );
+    exceeded(
+        open(ext4::Limits {
+            max_inode_size: 128,
+            ..ext4::Limits::default()
+        })
+        .unwrap_err(),
+        "inode size",
+    );
+
+    let fs = open(ext4::Limits {
+        max_directory_size: 100,
+        ..ext4::Limits::default()
+    })?;
+    exceeded(fs.resolve_path("/a/b/file").unwrap_err(), "directory");
+
+    let fs = open(ext4::Limits {
+        max_extent_entries: 0,
+        ..ext4::Limits::default()
+    })?;
+    exceeded(fs.open(&fs.root()?).err().expect("error"), "extents");
+
+    let fs = open(ext4::Limits::untrusted())?;
+    fs.walk(&fs.root()?, "", &mut |_, _, _, _| Ok(true))?;
+
+    // `/a/b/file` is three deep
+    let fs = open(ext4::Limits {
+        max_walk_depth: 2,
+        ..ext4::Limits::default()
+    })?;
+    exceeded(
+        fs.walk(&fs.root()?, "", &mut |_, _, _, _| Ok(true))
+            .unwrap_err(),
+        "depth",
+    );
+
+    Ok(())
+}
+
+/// Remembers where it was asked to read, and how much.
+struct Recording<'a> {
+    bytes: &'a [u8],
+    reads: std::sync::Mutex<Vec<std::ops::Range<u64>>>,
+}
+
+impl ext4::ReadAt for Recording<'_> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.bytes.read_at(pos, buf)?;
+        self.reads.lock().unwrap().push(pos..pos + read as u64);
+        Ok(read)
+    }
+}
+
+#[test]
+fn lazy_groups() -> Result<()> {
+    let bytes = image_bytes("scan.img")?;
+    let recording = Recording {
+        bytes: &bytes,
+        reads: Default::default(),
+    };
+    // 1k blocks, so the table is in the third block; four groups of 64 bytes
+    let table = 2048..2048 + 4 * 64;
+    let reads_of_table = || {
+        recording
+            .reads
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|read| read.start < table.end && table.start < read.end)
+            .count()
+    };
+
+    let fs = ext4::SuperBlock::open_minimal(&recording)?;
+    assert_eq!(4, fs.block_group_count());
+    assert_eq!(0, reads_of_table());
+
+    assert_eq!(3 * 1024 + 1, fs.block_group(3)?.first_block);
+    assert_eq!(1, reads_of_table());
+    // read along with the other
+    assert_eq!(2 * 1024 + 1, fs.block_group(2)?.first_block);
+    assert_eq!(1, reads_of_table());
+    assert!(fs.block_group(4).is_err());
+
+    // opening normally reads them all, once
+    recording.reads.lock().unwrap().clear();
+    let fs = ext4::SuperBlock::new(&recording)?;
+    assert_eq!(1, reads_of_table());
+    fs.block_group(3)?;
+    assert_eq!(1, reads_of_table());
+
+    Ok(())
+}
+
+/// Change an image's superblock, and fix its checksum.
+fn edit_superblock(image: &mut [u8], edit: impl FnOnce(&mut [u8])) {
+    let superblock = &mut image[1024..2048];
+    edit(superblock);
+    let checksum = ext4::parse::ext4_style_crc32c_le(!0, &superblock[..1020]);
+    superblock[1020..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+#[test]
+fn refresh() -> Result<()> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("live.img");
+    let mut bytes = image_bytes("links.img")?;
+    fs::write(&path, &bytes)?;
+
+    let mut fs = ext4::SuperBlock::new(fs::File::open(&path)?)?;
+    let label = fs.info().volume_name;
+
+    // relabel it, as tune2fs would
+    edit_superblock(&mut bytes, |superblock| {
+        superblock[0x78..0x88].copy_from_slice(b"relabelled\0\0\0\0\0\0")
+    });
+    fs::write(&path, &bytes)?;
+
+    assert_eq!(label, fs.info().volume_name);
+    fs.refresh()?;
+    assert_eq!(Some("relabelled"), fs.info().volume_name.as_deref());
+    fs.resolve_path("/a/b/file")?;
+
+    // a different filesystem entirely
+    fs::write(&path, image_bytes("journal.img")?)?;
+    assert!(fs.refresh().is_err());
+    assert_eq!(Some("relabelled"), fs.info().volume_name.as_deref());
+
+    Ok(())
+}
+
+#[test]
+fn consistency() -> Result<()> {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("live.img");
+    let mut bytes = image_bytes("links.img")?;
+    fs::write(&path, &bytes)?;
+
+    let mut fs = ext4::SuperBlock::new_with_options(
+        fs::File::open(&path)?,
+        &ext4::Options {
+            consistency: ext4::Consistency::Checked,
+            ..ext4::Options::default()
+        },
+    )?;
+    fs.resolve_path("/a")?;
+    fs.ensure_unchanged()?;
+
+    // as if the kernel had written a megabyte, and written back the superblock
+    edit_superblock(&mut bytes, |superblock| {
+        let written = u64::from_le_bytes(superblock[0x178..0x180].try_into().unwrap());
+        superblock[0x178..0x180].copy_from_slice(&(written + 1024).to_le_bytes());
+    });
+    fs::write(&path, &bytes)?;
+
+    let changed = |err: anyhow::Error| {
+        matches!(
+            err.root_cause().downcast_ref::<ext4::ParseError>(),
+            Some(ext4::ParseError::Changed)
+        )
+    };
+    assert!(changed(fs.ensure_unchanged().unwrap_err()));
+    assert!(changed(fs.resolve_path("/a/b").unwrap_err()));
+
+    fs.refresh()?;
+    fs.resolve_path("/a/b")?;
+
+    Ok(())
+}
+
+#[test]
+fn tombstones() -> Result<()> {
+    let mut bytes = image_bytes("deleted.img")?;
+    let table = {
+        let fs = ext4::SuperBlock::new(&bytes[..])?;
+        fs.block_group(0)?.inode_table
+    };
+
+    // wipe the mode of the deleted <12>, as some tools do; its 256-byte inode is 11th
+    let offset = usize::try_from(table)? * 1024 + 11 * 256;
+    bytes[offset..offset + 2].copy_from_slice(&[0, 0]);
+
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+    assert_eq!(
+        vec![ext4::Tombstone {
+            number: 12,
+            deleted_at: ext4::Time {
+                epoch_secs: 1500000000,
+                nanos: None,
+            },
+        }],
+        fs.tombstones()?
+    );
+    assert!(fs
+        .deleted_inodes()?
+        .iter()
+        .all(|deleted| ext4::DeletedSource::InodeTable != deleted.source
+            || 12 != deleted.inode.number));
+
+    Ok(())
+}
+
+#[test]
+fn lost_found() -> Result<()> {
+    let image = open_image("lost.img")?;
+    let fs = &image.superblock;
+
+    let lost = fs.lost_found_entries()?;
+    assert_eq!(1, lost.len());
+    assert_eq!("#13", lost[0].entry.name);
+    assert_eq!(13, lost[0].entry.inode);
+    // /lost+found's own block, logged after /dir's, only calls it #13
+    assert_eq!(Some("precious.txt"), lost[0].original_name.as_deref());
+
+    let paths = |include_lost_found| -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        fs.walk_with_options(
+            &fs.root()?,
+            "",
+            &ext4::WalkOptions {
+                sort: ext4::WalkOrder::Name,
+                include_lost_found,
+                ..Default::default()
+            },
+            &(),
+            &mut |_, path, _, _| {
+                paths.push(path.to_string());
+                Ok(true)
+            },
+        )?;
+        Ok(paths)
+    };
+    assert_eq!(vec!["", "/lost+found", "/lost+found/#13"], paths(true)?);
+    assert_eq!(vec![""], paths(false)?);
+
+    // nothing's lost here, and there's no journal to look in
+    assert!(open_image("links.img")?
+        .superblock
+        .lost_found_entries()?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn names_equal() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let dir = fs.load_inode(fs.resolve_path("/a/b")?.inode)?;
+
+    assert!(fs.names_equal(&dir, b"file", b"file")?);
+    assert!(!fs.names_equal(&dir, b"file", b"FILE")?);
+    assert!(!fs.names_equal(&dir, b"file", b"file ")?);
+
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    assert!(fs.names_equal(&file, b"file", b"file").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn scan_for_superblocks() -> Result<()> {
+    let image = image_bytes("scan.img")?;
+    // as if it was in a partition, 3MiB into a disc
+    let lead = 3 * 1024 * 1024;
+    let mut disc = vec![0u8; lead];
+    disc.extend_from_slice(&image);
+
+    let found = ext4::scan_for_superblocks(&disc[..], 0..disc.len() as u64)?;
+    assert_eq!(
+        vec![(1024, 0), (1025 * 1024, 1), (3073 * 1024, 3)],
+        found
+            .iter()
+            .map(|c| (c.found_at - lead as u64, c.group))
+            .collect::<Vec<_>>()
+    );
+    for candidate in &found {
+        assert_eq!(lead as u64, candidate.offset);
+        assert_eq!(1024, candidate.block_size);
+        assert_eq!(4096, candidate.blocks_count);
+        assert_eq!(Some("scan"), candidate.volume_name.as_deref());
+        assert_eq!(Some(true), candidate.checksum_valid);
+    }
+
+    // with the primary gone, the backups still know where the filesystem starts
+    let primary = lead + 1024;
+    disc[primary..primary + 1024].fill(0);
+    let found = ext4::scan_for_superblocks(&disc[..], 2 * 1024 * 1024..disc.len() as u64)?;
+    assert_eq!(
+        vec![1, 3],
+        found.iter().map(|c| c.group).collect::<Vec<_>>()
+    );
+    assert!(found.iter().all(|c| lead as u64 == c.offset));
+
+    // the range is respected
+    let after = (lead + 1025 * 1024 + 512) as u64;
+    let found = ext4::scan_for_superblocks(&disc[..], after..disc.len() as u64)?;
+    assert_eq!(vec![3], found.iter().map(|c| c.group).collect::<Vec<_>>());
+
+    Ok(())
+}
+
+#[test]
+fn patch_superblock_copies() -> Result<()> {
+    let mut bytes = image_bytes("scan.img")?;
+    patch_superblock(&mut bytes, |sb| {
+        sb.s_error_count = 2;
+        sb.s_last_error_time = 1_600_000_000;
+        sb.s_last_error_ino = 12;
+    })?;
+    let original = bytes.clone();
+    let fs = ext4::SuperBlock::new(&original[..])?;
+    assert!(fs.error_history().is_some());
+    // since the copy was taken, the kernel has marked it as having errors
+    patch_superblock(&mut bytes, |sb| sb.s_state |= 0x2)?;
+    assert!(ext4::SuperBlock::new(&bytes[..]).is_err());
+
+    let patch = ext4::SuperblockPatch {
+        volume_name: Some("evidence-7".to_string()),
+        last_mount_point: Some("/cases/7".to_string()),
+        clear_error_history: true,
+        clear_errors: true,
+    };
+    let mut out = io::Cursor::new(bytes);
+    // the primary, and the backups in groups 1 and 3
+    assert_eq!(3, fs.patch_superblock(&mut out, &patch)?);
+    let bytes = out.into_inner();
+
+    let found = ext4::scan_for_superblocks(&bytes[..], 0..bytes.len() as u64)?;
+    assert_eq!(3, found.len());
+    for candidate in &found {
+        assert_eq!(Some("evidence-7"), candidate.volume_name.as_deref());
+        assert_eq!(Some(true), candidate.checksum_valid);
+    }
+
+    // clean, and without errors
+    assert_eq!(
+        0x1,
+        ext4::ondisk::RawSuperblock::from_slice(&bytes[1024..2048])?.s_state
+    );
+    let patched = ext4::SuperBlock::new(&bytes[..])?;
+    let info = patched.info();
+    assert_eq!(Some("evidence-7"), info.volume_name.as_deref());
+    assert_eq!(Some("/cases/7"), info.last_mount_point.as_deref());
+    assert_eq!(None, patched.error_history());
+
+    let too_long = ext4::SuperblockPatch {
+        volume_name: Some("x".repeat(17)),
+        ..Default::default()
+    };
+    assert!(fs
+        .patch_superblock(io::Cursor::new(bytes.clone()), &too_long)
+        .is_err());
+
+    // another filesystem's image is refused
+    let other = image_bytes("links.img")?;
+    assert!(fs.patch_superblock(io::Cursor::new(other), &patch).is_err());
+
+    Ok(())
+}
+
+/// LVM's checksum: CRC32, seeded, and without the final inversion.
+#[cfg(feature = "lvm")]
+fn lvm_crc(buf: &[u8]) -> u32 {
+    !crc::crc32::update(!0xf597_a6cf, &crc::crc32::IEEE_TABLE, buf)
+}
+
+#[cfg(feature = "lvm")]
+#[test]
+fn lvm() -> Result<()> {
+    let image = image_bytes("links.img")?;
+    let extent = 256 * 1024;
+    let pe_start = 16 * 1024;
+    let (mda, mda_size) = (4096, 8192);
+
+    // the filesystem's second half first, so the segments are out of order
+    let mut pv = vec![0u8; pe_start + 4 * extent];
+    pv[pe_start..pe_start + 2 * extent].copy_from_slice(&image[2 * extent..]);
+    pv[pe_start + 2 * extent..].copy_from_slice(&image[..2 * extent]);
+
+    let text = r#"# Generated by LVM2
+vg {
+id = "Vgvgvg-0000-0000-0000-0000-0000-000000"
+seqno = 3
+status = ["RESIZEABLE", "READ", "WRITE"]
+extent_size = 512 # 256 Kilobytes
+physical_volumes {
+pv0 {
+id = "Pvpvpv-0000-0000-0000-0000-0000-000000"
+device = "/dev/sda2" # Hint only
+pe_start = 32
+pe_count = 4
+}
+}
+logical_volumes {
+root {
+id = "Rootlv-0000-0000-0000-0000-0000-000000"
+segment_count = 2
+segment2 {
+start_extent = 2
+extent_count = 2
+type = "striped"
+stripe_count = 1 # linear
+stripes = [
+"pv0", 0
+]
+}
+segment1 {
+start_extent = 0
+extent_count = 2
+type = "striped"
+stripe_count = 1
+stripes = ["pv0", 2]
+}
+}
+elsewhere {
+id = "Elselv-0000-0000-0000-0000-0000-000000"
+segment_count = 1
+segment1 {
+start_extent = 0
+extent_count = 1
+type = "striped"
+stripe_count = 1
+stripes = ["pv1", 0]
+}
+}
+}
+}
+contents = "Text Format Volume Group"
+version = 1
+"#;
+
+    // the label, in sector 1, then the pv_header
+    let label = &mut pv[512..1024];
+    label[..8].copy_from_slice(b"LABELONE");
+    label[8..16].copy_from_slice(&1u64.to_le_bytes());
+    label[20..24].copy_from_slice(&32u32.to_le_bytes());
+    label[24..32].copy_from_slice(b"LVM2 001");
+    label[32..64].copy_from_slice(b"Pvpvpv00000000000000000000000000");
+    let mut locns = label[72..].chunks_mut(8);
+    for value in [pe_start, 0, 0, 0, mda, mda_size, 0, 0] {
+        locns
+            .next()
+            .unwrap()
+            .copy_from_slice(&(value as u64).to_le_bytes());
+    }
+    let crc = lvm_crc(&label[20..]);
+    label[16..20].copy_from_slice(&crc.to_le_bytes());
+
+    // the text wraps around the end of the metadata area
+    let offset = mda_size - 300;
+    pv[mda + offset..mda + mda_size].copy_from_slice(&text.as_bytes()[..300]);
+    pv[mda + 512..mda + 512 + text.len() - 300].copy_from_slice(&text.as_bytes()[300..]);
+    let header = &mut pv[mda..mda + 512];
+    header[4..20].copy_from_slice(b" LVM2 x[5A%r0N*>");
+    header[20..24].copy_from_slice(&1u32.to_le_bytes());
+    header[24..32].copy_from_slice(&(mda as u64).to_le_bytes());
+    header[32..40].copy_from_slice(&(mda_size as u64).to_le_bytes());
+    header[40..48].copy_from_slice(&(offset as u64).to_le_bytes());
+    header[48..56].copy_from_slice(&(text.len() as u64).to_le_bytes());
+    header[56..60].copy_from_slice(&lvm_crc(text.as_bytes()).to_le_bytes());
+    let crc = lvm_crc(&header[4..]);
+    header[..4].copy_from_slice(&crc.to_le_bytes());
+
+    let err = ext4::SuperBlock::new(&pv[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("LVM2 physical volume"));
+
+    let found = ext4::lvm::physical_volume(&pv[..])?;
+    assert_eq!("Pvpvpv-0000-0000-0000-0000-0000-000000", found.uuid);
+    assert_eq!("vg", found.vg_name);
+    assert_eq!(
+        vec![("root", 1024 * 1024), ("elsewhere", 256 * 1024)],
+        found
+            .logical_volumes
+            .iter()
+            .map(|lv| (lv.name.as_str(), lv.size))
+            .collect::<Vec<_>>()
+    );
+
+    let root = &found.logical_volumes[0];
+    assert_eq!(
+        vec![
+            Some(pe_start as u64 + 2 * extent as u64),
+            Some(pe_start as u64)
+        ],
+        root.segments.iter().map(|s| s.physical).collect::<Vec<_>>()
+    );
+    let fs = ext4::SuperBlock::new(root.open(&pv[..])?)?;
+    let mut content = String::new();
+    fs.open(&fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?)?
+        .read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+    assert!(fs.check()?.is_empty());
+
+    assert!(found.logical_volumes[1].open(&pv[..]).is_err());
+
+    // crafted metadata is refused, rather than overflowing the stack, or a size
+    let with_text = |text: &str| -> Vec<u8> {
+        let mut pv = pv.clone();
+        pv[mda + 512..mda + mda_size].fill(0);
+        pv[mda + 512..mda + 512 + text.len()].copy_from_slice(text.as_bytes());
+        let header = &mut pv[mda..mda + 512];
+        header[40..48].copy_from_slice(&512u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        header[56..60].copy_from_slice(&lvm_crc(text.as_bytes()).to_le_bytes());
+        let crc = lvm_crc(&header[4..]);
+        header[..4].copy_from_slice(&crc.to_le_bytes());
+        pv
+    };
+    let refused = |text: &str, wanted: &str| {
+        let err = ext4::lvm::physical_volume(&with_text(text)[..]).unwrap_err();
+        assert!(format!("{:#}", err).contains(wanted), "{:#}", err);
+    };
+    refused(
+        &format!("{}{}", "x {\n".repeat(1000), "}\n".repeat(1000)),
+        "deep",
+    );
+    refused(
+        &format!(
+            "vg {{\nstatus = {}{}\n}}\n",
+            "[".repeat(1000),
+            "]".repeat(1000)
+        ),
+        "deep",
+    );
+    refused(
+        &text.replace("start_extent = 2", "start_extent = 9223372036854775807"),
+        "invalid size",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn fingerprint() -> Result<()> {
+    let small = open_image("layout.img")?;
+    let big = open_image("layout-4k.img")?;
+    let other = open_image("links.img")?;
+    assert_ne!(small.superblock.block_size(), big.superblock.block_size());
+
+    let fingerprint = small.superblock.fingerprint()?;
+    assert!(fingerprint.starts_with("sha256:"));
+    assert_eq!(fingerprint, big.superblock.fingerprint()?);
+    assert_ne!(fingerprint, other.superblock.fingerprint()?);
+
+    // one byte of /dir/file's content
+    let mut bytes = image_bytes("layout.img")?;
+    let content = bytes
+        .windows(1024)
+        .position(|window| window.iter().all(|&b| b'F' == b))
+        .expect("file content");
+    bytes[content] = b'G';
+    let changed = ext4::SuperBlock::new(&bytes[..])?;
+    assert_ne!(fingerprint, changed.fingerprint()?);
+
+    Ok(())
+}
+
+/// Sector zero's table of four entries, of `(type, first sector, sectors)`.
+fn mbr(sector: &mut [u8], entries: &[(u8, u32, u32)]) {
+    for (i, (kind, start, sectors)) in entries.iter().enumerate() {
+        let entry = &mut sector[446 + 16 * i..446 + 16 * (i + 1)];
+        entry[4] = *kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+    sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+}
+
+#[test]
+fn disk() -> Result<()> {
+    let image = image_bytes("links.img")?;
+    let mib = 1024 * 1024;
+
+    // p1 is the filesystem, p2 is empty, and p3 is extended, holding p5, another copy
+    let mut raw = vec![0u8; 5 * mib];
+    mbr(
+        &mut raw[..512],
+        &[(0x83, 2048, 2048), (0x83, 4096, 2048), (0x05, 6144, 4096)],
+    );
+    mbr(&mut raw[6144 * 512..6145 * 512], &[(0x83, 2048, 2048)]);
+    raw[mib..2 * mib].copy_from_slice(&image);
+    raw[4 * mib..].copy_from_slice(&image);
+
+    let disk = ext4::Disk::open(&raw[..])?;
+    assert_eq!(
+        vec![("p1", mib as u64), ("p5", 4 * mib as u64)],
+        disk.volumes()
+            .iter()
+            .map(|volume| (volume.name.as_str(), volume.offset))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec!["p2"],
+        disk.unopened()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let (volume, entry) = disk.resolve("p5:/a/b/file")?;
+    let mut content = String::new();
+    volume
+        .fs
+        .open(&volume.fs.load_inode(entry.inode)?)?
+        .read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+    assert!(disk.resolve("p2:/a/b/file").is_err());
+    assert!(disk.resolve("/a/b/file").is_err());
+
+    assert_eq!(
+        vec!["p1:/a/b/file", "p5:/a/b/file"],
+        disk.find(|path, _| path.ends_with("/file"))?
+    );
+    let mut roots = Vec::new();
+    disk.walk(&mut |_, path, inode, _| {
+        if 2 == inode.number {
+            roots.push(path.to_string());
+        }
+        Ok(true)
+    })?;
+    assert_eq!(vec!["p1:", "p5:"], roots);
+
+    // the same filesystem, as the only partition in a GUID partition table
+    let mut raw = vec![0u8; 2 * mib + 512 * 34];
+    mbr(&mut raw[..512], &[(0xEE, 1, u32::MAX)]);
+    let entries = &mut raw[1024..1024 + 128 * 128];
+    entries[..16].copy_from_slice(&[0x0F; 16]);
+    entries[32..40].copy_from_slice(&2048u64.to_le_bytes());
+    entries[40..48].copy_from_slice(&4095u64.to_le_bytes());
+    let entries_crc = crc::crc32::checksum_ieee(entries);
+    let header = &mut raw[512..512 + 92];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x1_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc::crc32::checksum_ieee(header);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    raw[mib..2 * mib].copy_from_slice(&image);
+
+    let disk = ext4::Disk::open(&raw[..])?;
+    assert_eq!(1, disk.volumes().len());
+    assert!(disk.unopened().is_empty());
+    disk.resolve("p1:/a/b/file")?;
+
+    // sectors too far out to be bytes, even with good checksums
+    let seal = |raw: &mut Vec<u8>, entries_at: u64, last: u64| {
+        raw[1024 + 40..1024 + 48].copy_from_slice(&last.to_le_bytes());
+        let entries_crc = crc::crc32::checksum_ieee(&raw[1024..1024 + 128 * 128]);
+        let header = &mut raw[512..512 + 92];
+        header[72..80].copy_from_slice(&entries_at.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        header[16..20].fill(0);
+        let crc = crc::crc32::checksum_ieee(header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    };
+    let mut far = raw.clone();
+    seal(&mut far, u64::MAX / 256, 4095);
+    let err = ext4::Disk::open(&far[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("past the end"));
+    seal(&mut far, 2, u64::MAX);
+    let err = ext4::Disk::open(&far[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("past the end"));
+
+    // and as a bare filesystem
+    let disk = ext4::Disk::open(&image[..])?;
+    assert_eq!("disk", disk.volumes()[0].name);
+    disk.resolve("disk:/a/b/file")?;
+
+    Ok(())
+}
+
+#[test]
+fn luks() -> Result<()> {
+    use ext4::luks::LuksHeader;
+
+    let image = image_bytes("links.img")?;
+    // not real encryption: each byte is xor'd with its sector number
+    let sector_size = 4096;
+    let scramble = |sector: u64, data: &mut [u8]| -> io::Result<()> {
+        for b in data {
+            *b ^= sector as u8;
+        }
+        Ok(())
+    };
+
+    let payload_offset = 16 * 1024;
+    let mut volume = vec![0u8; payload_offset];
+    volume[..6].copy_from_slice(b"LUKS\xba\xbe");
+    volume[6..8].copy_from_slice(&2u16.to_be_bytes());
+    volume[8..16].copy_from_slice(&(16u64 * 1024).to_be_bytes());
+    volume[168..172].copy_from_slice(b"luks");
+    let json = format!(
+        r#"{{"keyslots":{{}},"tokens":{{}},"segments":{{"0":{{"type":"crypt","offset":"{}","size":"dynamic","iv_tweak":"0","encryption":"aes-xts-plain64","sector_size":{},"flags":[]}}}},"digests":{{}},"config":{{"json_size":"12288","keyslots_size":"0"}}}}"#,
+        payload_offset, sector_size
+    );
+    volume[4096..4096 + json.len()].copy_from_slice(json.as_bytes());
+    // the checksum covers the whole header, with itself zeroed
+    volume[72..78].copy_from_slice(b"sha256");
+    let seal = |volume: &mut Vec<u8>| -> Result<()> {
+        volume[448..512].fill(0);
+        let digest = verity_digests(b"", &[&volume[..16 * 1024]])?.remove(0);
+        volume[448..480].copy_from_slice(&digest);
+        Ok(())
+    };
+    seal(&mut volume)?;
+    for (sector, data) in image.chunks(sector_size).enumerate() {
+        let mut data = data.to_vec();
+        scramble(sector as u64, &mut data)?;
+        volume.extend_from_slice(&data);
+    }
+
+    let err = ext4::SuperBlock::new(&volume[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("LUKS encrypted volume"));
+
+    let header = LuksHeader::detect(&volume[..])?.expect("it's LUKS");
+    assert_eq!(
+        LuksHeader {
+            version: 2,
+            uuid: "luks".to_string(),
+            cipher: "aes-xts-plain64".to_string(),
+            payload_offset: payload_offset as u64,
+            sector_size: sector_size as u32,
+        },
+        header
+    );
+    let fs = ext4::SuperBlock::new(header.open(&volume[..], scramble))?;
+    let mut content = String::new();
+    fs.open(&fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?)?
+        .read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+    assert!(fs.check()?.is_empty());
+
+    // a header changed since it was written is refused
+    let mut tampered = volume.clone();
+    tampered[4096 + 2] ^= 1;
+    let err = LuksHeader::detect(&tampered[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("checksum"), "{:#}", err);
+
+    // as is JSON nested too deep to parse, rather than overflowing the stack
+    let mut nested = volume[..payload_offset].to_vec();
+    let deep = "[".repeat(10_000);
+    nested[4096..].fill(0);
+    nested[4096..4096 + deep.len()].copy_from_slice(deep.as_bytes());
+    seal(&mut nested)?;
+    let err = LuksHeader::detect(&nested[..]).unwrap_err();
+    assert!(format!("{:#}", err).contains("deep"), "{:#}", err);
+
+    // LUKS1 has a fixed header, and always 512 byte sectors
+    let mut luks1 = vec![0u8; 4096];
+    luks1[..6].copy_from_slice(b"LUKS\xba\xbe");
+    luks1[6..8].copy_from_slice(&1u16.to_be_bytes());
+    luks1[8..11].copy_from_slice(b"aes");
+    luks1[40..56].copy_from_slice(b"cbc-essiv:sha256");
+    luks1[104..108].copy_from_slice(&4096u32.to_be_bytes());
+    let header = LuksHeader::detect(&luks1[..])?.expect("it's LUKS");
+    assert_eq!(
+        (1, "aes-cbc-essiv:sha256", 2 * 1024 * 1024, 512),
+        (
+            header.version,
+            header.cipher.as_str(),
+            header.payload_offset,
+            header.sector_size
+        )
+    );
+
+    assert!(LuksHeader::detect(&image[..])?.is_none());
+
+    Ok(())
+}
+
+/// The salted sha256 of each block, as dm-verity uses, from `sha256sum`.
+fn verity_digests(salt: &[u8], blocks: &[&[u8]]) -> Result<Vec<Vec<u8>>> {
+    let dir = TempDir::new()?;
+    let mut paths = Vec::new();
+    for (index, block) in blocks.iter().enumerate() {
+        let path = dir.path().join(index.to_string());
+        fs::write(&path, [salt, block].concat())?;
+        paths.push(path);
+    }
+    let output = std::process::Command::new("sha256sum")
+        .args(&paths)
+        .output()?;
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| {
+            let hex = &line[..64];
+            Ok((0..32)
+                .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()?)
+        })
+        .collect()
+}
+
+#[test]
+fn verity() -> Result<()> {
+    use ext4::verity::VerityParams;
+    use ext4::verity::VerityReader;
+    use ext4::ReadAt;
+
+    let mut data = image_bytes("links.img")?;
+    let salt = b"pepper".to_vec();
+
+    // 256 data blocks, 128 hashes per block: two blocks at level 0, and one above
+    let blocks = data.chunks(4096).collect::<Vec<_>>();
+    let level0 = verity_digests(&salt, &blocks)?.concat();
+    let level1 = verity_digests(&salt, &level0.chunks(4096).collect::<Vec<_>>())?.concat();
+    let mut level1_block = level1.clone();
+    level1_block.resize(4096, 0);
+    let root = verity_digests(&salt, &[&level1_block])?.remove(0);
+
+    // the superblock, then the top level, then the bottom
+    let mut hash = vec![0u8; 4096];
+    hash[..8].copy_from_slice(b"verity\0\0");
+    hash[8..12].copy_from_slice(&1u32.to_le_bytes());
+    hash[12..16].copy_from_slice(&1u32.to_le_bytes());
+    hash[32..38].copy_from_slice(b"sha256");
+    hash[64..68].copy_from_slice(&4096u32.to_le_bytes());
+    hash[68..72].copy_from_slice(&4096u32.to_le_bytes());
+    hash[72..80].copy_from_slice(&256u64.to_le_bytes());
+    hash[80..82].copy_from_slice(&(salt.len() as u16).to_le_bytes());
+    hash[88..88 + salt.len()].copy_from_slice(&salt);
+    hash.extend_from_slice(&level1_block);
+    hash.extend_from_slice(&level0);
+
+    let params = VerityParams::read_superblock(&hash[..], 0)?;
+    assert_eq!(4096, params.tree_offset);
+    assert_eq!(256, params.data_blocks);
+
+    let reader = VerityReader::new(&data[..], &hash[..], params.clone(), &root)?;
+    let fs = ext4::SuperBlock::new(reader)?;
+    let mut content = String::new();
+    fs.open(&fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?)?
+        .read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+    assert!(fs.check()?.is_empty());
+
+    let wrong_root = [0u8; 32];
+    assert!(
+        VerityReader::new(&data[..], &hash[..], params.clone(), &wrong_root)
+            .and_then(ext4::SuperBlock::new)
+            .is_err()
+    );
+
+    // a changed data block can't be read, but everything else can
+    data[200 * 4096 + 7] ^= 1;
+    let reader = VerityReader::new(&data[..], &hash[..], params, &root)?;
+    let mut buf = [0u8; 16];
+    let err = reader.read_exact_at(200 * 4096, &mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    reader.read_exact_at(199 * 4096, &mut buf)?;
+
+    Ok(())
+}
+
+#[test]
+fn ancestry() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let a = fs.resolve_path("/a")?.inode;
+    let b = fs.load_inode(fs.resolve_path("/a/b")?.inode)?;
+    assert_eq!(a, fs.parent_of(&b)?);
+    assert_eq!(vec![a, 2], fs.ancestry(&b)?);
+    assert_eq!(2, fs.parent_of(&fs.root()?)?);
+    assert_eq!(Vec::<u32>::new(), fs.ancestry(&fs.root()?)?);
+
+    let file = fs.load_inode(fs.resolve_path("/a/b/file")?.inode)?;
+    assert!(fs.parent_of(&file).is_err());
+    assert!(fs.ancestry(&file).is_err());
+
+    let fs = image.superblock.with_root("/a")?;
+    assert_eq!(vec![a], fs.ancestry(&b)?);
+    let fs = fs.with_root("b")?;
+    assert_eq!(Vec::<u32>::new(), fs.ancestry(&b)?);
+    // not inside /a/b
+    assert!(fs.ancestry(&fs.load_inode(a)?).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn classify() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let load = |path| -> Result<ext4::Inode> {
+        let entry = fs.resolve_with_options(
+            path,
+            &ext4::ResolveOptions {
+                follow_final: false,
+                ..Default::default()
+            },
+        )?;
+        fs.load_inode(entry.1.inode)
+    };
+
+    let file = load("/a/b/file")?;
+    assert!(fs.open(&file)?.extent_tree_verified());
+    match fs.classify(&file) {
+        ext4::Classified::File(file) => {
+            let mut content = String::new();
+            file.open()?.read_to_string(&mut content)?;
+            assert_eq!("hello\n", content);
+        }
+        _ => panic!("not a file"),
+    }
+
+    let dir = load("/a/b")?;
+    match fs.classify(&dir) {
+        ext4::Classified::Dir(dir) => {
+            let mut names = dir
+                .entries()?
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(vec![".", "..", "file"], names);
+            let names = dir
+                .list(false)?
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>();
+            assert_eq!(vec!["file"], names);
+            assert_eq!(fs.resolve_path("/a")?.inode, dir.parent()?);
+        }
+        _ => panic!("not a directory"),
+    }
+    match fs.classify(&fs.root()?) {
+        ext4::Classified::Dir(root) => assert_eq!(2, root.parent()?),
+        _ => panic!("not a directory"),
+    }
+
+    let link = load("/a/long")?;
+    let classified = fs.classify(&link);
+    assert_eq!(link.number, classified.inode().number);
+    match classified {
+        ext4::Classified::Symlink(link) => {
+            assert!(link.target()?.to_string_lossy().ends_with("/./b/file"))
+        }
+        _ => panic!("not a symlink"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dirents() -> Result<()> {
+    let image = open_image("links.img")?;
+    let fs = &image.superblock;
+    let dir = fs.load_inode(fs.resolve_path("/a/b")?.inode)?;
+    let extents = fs.data_extents(&dir)?;
+    assert_eq!(1, extents.len());
+    let mut block = fs.load_block(extents[0].physical)?;
+
+    let names = |entries: Vec<ext4::parse::Dirent>| {
+        let mut names = entries
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).expect("utf-8"))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    let entries = ext4::parse::dirents(&block, true, dir.checksum_prefix())?;
+    assert!(entries
+        .iter()
+        .all(|entry| entry.file_type.is_some() && 0 != entry.inode));
+    assert_eq!(vec![".", "..", "file"], names(entries));
+
+    // damage the name of `.`, which only the checksum notices
+    block[8] = b'!';
+    assert!(ext4::parse::dirents(&block, true, dir.checksum_prefix()).is_err());
+    assert_eq!(
+        vec!["!", "..", "file"],
+        names(ext4::parse::dirents(&block, true, None)?)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn extent_tree() -> Result<()> {
+    fn header(entries: u16, depth: u16) -> Vec<u8> {
+        let mut header = Vec::new();
+        for field in &[0xf30a, entries, 4, depth, 0, 0] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header
+    }
+
+    // the inode holds an index, pointing at a leaf in block 7, holding two extents
+    let mut core = [0u8; 60];
+    let mut root = header(1, 1);
+    root.extend_from_slice(&0u32.to_le_bytes());
+    root.extend_from_slice(&7u32.to_le_bytes());
+    root.extend_from_slice(&[0; 4]);
+    core[..root.len()].copy_from_slice(&root);
+
+    let mut leaf = header(2, 0);
+    for (logical, len, physical) in &[(10u32, 3u16, 0x1_0000_0020u64), (0, 2, 50)] {
+        leaf.extend_from_slice(&logical.to_le_bytes());
+        leaf.extend_from_slice(&len.to_le_bytes());
+        leaf.extend_from_slice(&((physical >> 32) as u16).to_le_bytes());
+        leaf.extend_from_slice(&(*physical as u32).to_le_bytes());
+    }
+    leaf.resize(1024, 0);
+
+    let mut loaded = Vec::new();
+    let extents = ext4::parse::extent_tree(
+        core,
+        |block| {
+            loaded.push(block);
+            Ok(leaf.clone())
+        },
+        None,
+    )?;
+    assert_eq!(vec![7], loaded);
+    assert_eq!(
+        vec![
+            ext4::DataExtent {
+                logical: 0,
+                physical: 50,
+                len: 2,
+            },
+            ext4::DataExtent {
+                logical: 10,
+                physical: 0x1_0000_0020,
+                len: 3,
+            },
+        ],
+        extents
+    );
+
+    // a leaf can't claim to have more entries than room
+    let mut overfull = leaf.clone();
+    overfull[4..6].copy_from_slice(&1u16.to_le_bytes());
+    assert!(ext4::parse::extent_tree(core, |_| Ok(overfull.clone()), None).is_err());
+
+    // the leaf has no checksum, so fails if they're expected
+    assert!(ext4::parse::extent_tree(core, |_| Ok(leaf.clone()), Some(0)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn block_sizes() -> Result<()> {
+    let data = (0..=255u8).collect::<Vec<_>>().repeat(800);
+
+    // the root's last record stops at the checksum tail, if there is one
+    for &(name, block_size, root_end) in &[
+        ("blocks-2k.img", 2048, 2048 - 12),
+        ("blocks-64k.img", 65536, 65536),
+    ] {
+        let image = open_image(name)?;
+        let fs = &image.superblock;
+        assert_eq!(block_size, fs.block_size());
+
+        let file = fs.load_inode(fs.resolve_path("/data")?.inode)?;
+        let mut content = Vec::new();
+        fs.open(&file)?.read_to_end(&mut content)?;
+        assert_eq!(data, content, "{}", name);
+        let mut copied = Vec::new();
+        fs.copy_raw(&file, &mut copied)?;
+        assert_eq!(data, copied, "{}", name);
+
+        let dir = fs.load_inode(fs.resolve_path("/dir")?.inode)?;
+        assert_eq!(201, fs.dir_summary(&dir)?.entries, "{}", name);
+        assert_eq!("/data", fs.canonicalize("/dir/link")?.0, "{}", name);
+
+        let records = fs.dir_records(&fs.root()?, false)?;
+        let last = records.last().expect("the root has records");
+        assert_eq!(root_end, last.offset + last.rec_len, "{}", name);
+
+        // every block but the first is empty: one unused record, filling it
+        let lost = fs.load_inode(fs.resolve_path("/lost+found")?.inode)?;
+        assert!(lost.stat.size > u64::from(block_size), "{}", name);
+        match fs.enhance(&lost)? {
+            ext4::Enhanced::Directory(entries) => assert_eq!(2, entries.len(), "{}", name),
+            _ => panic!("not a directory"),
+        }
+        assert!(fs.carve_directory(&lost)?.is_empty(), "{}", name);
+
+        let mut visited = 0;
+        fs.walk(&fs.root()?, "", &mut |_, _, _, _| {
+            visited += 1;
+            Ok(true)
+        })?;
+        // the root, /lost+found, /data, /dir, and its 201 entries
+        assert_eq!(1 + 3 + 201, visited, "{}", name);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn good_old_rev() -> Result<()> {
+    // old mkfs leaves the fields revision 0 hasn't got as zeros; this one fills them in
+    let mut bytes = image_bytes("old.img")?;
+    patch_superblock(&mut bytes, |sb| {
+        assert_eq!(0, sb.s_rev_level);
+        sb.s_first_ino = 0;
+        sb.s_inode_size = 0;
+    })?;
+    let fs = ext4::SuperBlock::new(&bytes[..])?;
+
+    // no `filetype` feature, so what the entries point at comes from their inodes, and
+    // no extents, so the directories' blocks come from their block maps
+    use ext4::FileType::*;
+    let root = fs.root()?;
+    let entries = match fs.enhance(&root)? {
+        ext4::Enhanced::Directory(entries) => entries,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(
+        vec![
+            (".", Directory),
+            ("..", Directory),
+            ("lost+found", Directory),
+            ("dir", Directory),
+            ("link", SymbolicLink),
+        ],
+        entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.file_type))
+            .collect::<Vec<_>>()
+    );
+
+    let hello = fs.load_inode(fs.resolve_path("/dir/hello.txt")?.inode)?;
+    assert_eq!(RegularFile, hello.stat.extracted_type);
+    let mut content = Vec::new();
+    fs.open(&hello)?.read_to_end(&mut content)?;
+    assert_eq!(b"hello\n", &content[..]);
+
+    let dir = fs.load_inode(fs.resolve_path("/dir")?.inode)?;
+    assert_eq!(root.number, fs.parent_of(&dir)?);
+    assert_eq!(
+        std::path::Path::new("dir/hello.txt"),
+        fs.read_link(&fs.load_inode(fs.resolve_path("/link")?.inode)?)?
+    );
+
+    // an entry whose inode has no type is left out, rather than failing the listing
+    let link = fs.resolve_path("/link")?.inode;
+    let offset =
+        fs.block_group(0)?.inode_table * u64::from(fs.block_size()) + u64::from(link - 1) * 128;
+    let mut broken = bytes.clone();
+    broken[offset as usize..][..2].copy_from_slice(&[0, 0]);
+    let fs = ext4::SuperBlock::new(&broken[..])?;
+    let names = match fs.enhance(&fs.root()?)? {
+        ext4::Enhanced::Directory(entries) => entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>(),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(vec![".", "..", "lost+found", "dir"], names);
+    assert!(fs
+        .take_warnings()
+        .contains(&ext4::Warning::UnknownFileType { inode: link }));
+
+    patch_superblock(&mut bytes, |sb| sb.s_rev_level = 2)?;
+    assert!(ext4::SuperBlock::new(&bytes[..]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn indirect_blocks() -> Result<()> {
+    use ext4::BlockUse::*;
+    use ext4::ReadAt;
+
+    let image = open_image("indirect.img")?;
+    let fs = &image.superblock;
+    let extents = |inode: &ext4::Inode| -> Result<Vec<(u32, u64, u16)>> {
+        Ok(fs
+            .data_extents(inode)?
+            .iter()
+            .map(|extent| (extent.logical, extent.physical, extent.len))
+            .collect())
+    };
+
+    // the direct blocks, then past the single, and double, indirect blocks, from `debugfs`
+    let big = fs.load_inode(fs.resolve_path("/big")?.inode)?;
+    assert_eq!(
+        vec![(0, 54, 12), (12, 67, 256), (268, 325, 33)],
+        extents(&big)?
+    );
+    let mut content = Vec::new();
+    fs.open(&big)?.read_to_end(&mut content)?;
+    assert_eq!(
+        (0..300 * 1024 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>(),
+        content
+    );
+    assert!(!fs.open(&big)?.extent_tree_verified());
+
+    // one block, under the triple indirect block, after a hole
+    let sparse = fs.load_inode(fs.resolve_path("/sparse")?.inode)?;
+    assert_eq!(vec![(71680, 364, 1)], extents(&sparse)?);
+    let mut buf = [0u8; 7];
+    fs.open(&sparse)?
+        .read_exact_at(70 * 1024 * 1024, &mut buf)?;
+    assert_eq!(b"triple\n", &buf);
+    assert_eq!(None, fs.physical_offset(&sparse, 1024 * 1024)?);
+
+    // directories, and symlinks too long for the inode, have block maps too
+    let long = fs.load_inode(fs.resolve_path("/long")?.inode)?;
+    assert_eq!(
+        std::path::Path::new(&format!("{}dir/hello.txt", "./".repeat(31))),
+        fs.read_link(&long)?
+    );
+    let hello = fs.load_inode(fs.resolve_path("/dir/hello.txt")?.inode)?;
+    let mut content = String::new();
+    fs.open(&hello)?.read_to_string(&mut content)?;
+    assert_eq!("hello\n", content);
+
+    // the indirect blocks are the map's, not the file's
+    let map = fs
+        .block_map()?
+        .iter()
+        .map(|run| (run.start, run.len, run.usage))
+        .collect::<Vec<_>>();
+    for run in &[
+        (66, 1, ExtentTree(big.number)),
+        (323, 2, ExtentTree(big.number)),
+        (325, 33, Data(big.number)),
+        (361, 3, ExtentTree(sparse.number)),
+        (364, 1, Data(sparse.number)),
+    ] {
+        assert!(map.contains(run), "{:?}", run);
+    }
+    assert!(fs.check()?.is_empty());
+
+    // blocks 100-111, except a hole at 5, then 200 and 201 from the indirect block 7; the
+    // double indirect block is past the end of the file, so isn't loaded
+    let mut core = [0u8; 60];
+    for (index, block) in (100u32..112).chain([7, 9]).enumerate() {
+        core[index * 4..][..4].copy_from_slice(&block.to_le_bytes());
+    }
+    core[5 * 4..][..4].copy_from_slice(&[0; 4]);
+    let mut indirect = vec![0u8; 1024];
+    indirect[..8].copy_from_slice(&[200, 0, 0, 0, 201, 0, 0, 0]);
+
+    let mut loaded = Vec::new();
+    let mapped = ext4::parse::block_map(
+        core,
+        |block| {
+            loaded.push(block);
+            Ok(indirect.clone())
+        },
+        1024,
+        14 * 1024 + 1,
+    )?;
+    assert_eq!(vec![7], loaded);
+    assert_eq!(
+        vec![(0, 100, 5), (6, 106, 6), (12, 200, 2)],
+        mapped
+            .iter()
+            .map(|extent| (extent.logical, extent.physical, extent.len))
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ext3() -> Result<()> {
+    let image = open_image("ext3.img")?;
+    let fs = &image.superblock;
+
+    let file = fs.load_inode(fs.resolve_path("/file")?.inode)?;
+    let mut content = Vec::new();
+    fs.open(&file)?.read_to_end(&mut content)?;
+    assert_eq!(vec![b'E'; 2000], content);
+
+    // the journal has no extents either; most of it is past its double indirect block
+    let journal = fs.journal()?;
+    assert_eq!(
+        vec![(1, true, vec![3000])],
+        journal
+            .transactions
+            .iter()
+            .map(|t| {
+                (
+                    t.sequence,
+                    t.needs_replay,
+                    t.blocks.iter().map(|b| b.target).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![b'A'; 1024],
+        fs.journal_block(&journal.transactions[0].blocks[0])?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn overlay() -> Result<()> {
+    let base = open_image("distro.img")?;
+    let update = open_image("layout.img")?;
+    let loose = TempDir::new()?;
+    let dir = loose.path();
+    fs::write(dir.join(".wh.usr"), b"")?;
+    fs::create_dir(dir.join("etc"))?;
+    fs::write(dir.join("etc/.wh.group"), b"")?;
+    fs::write(dir.join("etc/hostname"), b"box\n")?;
+    fs::create_dir(dir.join("var"))?;
+    fs::write(dir.join("var/.wh..wh..opq"), b"")?;
+    fs::write(dir.join("var/new"), b"")?;
+    // a file, over the update's directory
+    fs::write(dir.join("empty"), b"")?;
+    // a symlink out of the layer, which mustn't be followed
+    let outside = TempDir::new()?;
+    fs::write(outside.path().join("secret"), b"")?;
+    std::os::unix::fs::symlink(outside.path(), dir.join("host"))?;
+
+    let overlay = ext4::Overlay::new(&base.superblock)
+        .with_image(&update.superblock)
+        .with_dir(dir);
+
+    let names = |path: &str| -> Result<Vec<(String, usize)>> {
+        Ok(overlay
+            .read_dir(path)?
+            .into_iter()
+            .map(|(name, entry)| (name, entry.layer()))
+            .collect())
+    };
+    let owned = |names: &[(&str, usize)]| -> Vec<(String, usize)> {
+        names
+            .iter()
+            .map(|&(name, layer)| (name.to_string(), layer))
+            .collect()
+    };
+    assert_eq!(
+        owned(&[
+            ("dir", 1),
+            ("empty", 2),
+            ("etc", 2),
+            ("host", 2),
+            ("lost+found", 1),
+            ("var", 2)
+        ]),
+        names("/")?
+    );
+    assert_eq!(
+        owned(&[("hostname", 2), ("os-release", 0), ("passwd", 0)]),
+        names("/etc")?
+    );
+    assert_eq!(owned(&[("new", 2)]), names("var")?);
+
+    assert!(overlay.lookup("/usr")?.is_none());
+    assert!(overlay.lookup("/usr/bin")?.is_none());
+    assert!(overlay.lookup("/etc/group")?.is_none());
+    assert!(overlay.lookup("/empty/anything")?.is_none());
+    assert!(overlay.read_dir("/empty").is_err());
+    assert!(overlay.lookup("/host/secret")?.is_none());
+    assert!(overlay.lookup("/host/../host/secret")?.is_none());
+    assert_eq!(
+        ext4::FileType::SymbolicLink,
+        overlay.lookup("/host")?.expect("loose").file_type()
+    );
+    assert!(overlay.read_dir("/host").is_err());
+
+    let passwd = overlay
+        .lookup("/etc/../etc/passwd")?
+        .expect("from the base");
+    assert_eq!(0, passwd.layer());
+    assert_eq!(ext4::FileType::RegularFile, passwd.file_type());
+    let mut content = String::new();
+    passwd.open()?.read_to_string(&mut content)?;
+    assert!(content.starts_with("root:"), "{:?}", content);
+
+    let hostname = overlay.lookup("etc/hostname")?.expect("loose");
+    content.clear();
+    hostname.open()?.read_to_string(&mut content)?;
+    assert_eq!("box\n", content);
+
+    let dir = overlay.lookup("/dir")?.expect("from the update");
+    assert_eq!(1, dir.layer());
+    assert_eq!(ext4::FileType::Directory, dir.file_type());
+
+    Ok(())
+}
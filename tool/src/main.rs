@@ -1,3 +1,4 @@
+extern crate blake3;
 extern crate bootsector;
 extern crate cast;
 extern crate clap;
@@ -5,33 +6,141 @@ extern crate ext4;
 #[macro_use]
 extern crate anyhow;
 extern crate hexdump;
+extern crate indicatif;
+extern crate positioned_io2;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate sha2;
 
+mod output;
+mod progress;
+mod shell;
+
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::fs;
+use std::io;
+use std::io::BufRead;
 use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::path::Path;
 
 use anyhow::Context;
 use anyhow::Error;
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgGroup, SubCommand};
 use ext4::{ReadAt, SuperBlock};
+use serde::Serialize;
+use sha2::Digest;
+
+use output::{Format, Output};
+use progress::Bar;
+
+/// An entry in the filesystem, in the json formats.
+#[derive(Serialize)]
+struct EntryRecord<'a> {
+    path: &'a str,
+    inode: u32,
+    #[serde(rename = "type")]
+    file_type: &'a ext4::FileType,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    /// The names of the owners, from the image's own passwd and group, if asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    size: u64,
+    links: u16,
+    atime: &'a ext4::Time,
+    ctime: &'a ext4::Time,
+    mtime: &'a ext4::Time,
+    btime: Option<&'a ext4::Time>,
+    xattrs: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<(u16, u32)>,
+}
+
+impl<'a> EntryRecord<'a> {
+    fn new(path: &'a str, inode: &'a ext4::Inode, enhanced: &'a ext4::Enhanced) -> Self {
+        let stat = &inode.stat;
+        let mut xattrs = stat.xattrs.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+        xattrs.sort_unstable();
+        EntryRecord {
+            path: if path.is_empty() { "/" } else { path },
+            inode: inode.number,
+            file_type: &stat.extracted_type,
+            mode: stat.file_mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            user: None,
+            group: None,
+            size: stat.size,
+            links: stat.link_count,
+            atime: &stat.atime,
+            ctime: &stat.ctime,
+            mtime: &stat.mtime,
+            btime: stat.btime.as_ref(),
+            xattrs,
+            target: match *enhanced {
+                ext4::Enhanced::SymbolicLink(ref target) => Some(target.to_string_lossy()),
+                _ => None,
+            },
+            device: match *enhanced {
+                ext4::Enhanced::CharacterDevice(major, minor)
+                | ext4::Enhanced::BlockDevice(major, minor) => Some((major, minor)),
+                _ => None,
+            },
+        }
+    }
+}
 
-fn dump_ls<R>(fs: SuperBlock<R>) -> Result<(), Error>
+fn dump_ls<R>(
+    fs: SuperBlock<R>,
+    names: bool,
+    options: &ext4::WalkOptions,
+    out: &mut Output,
+) -> Result<(), Error>
 where
     R: ReadAt,
 {
     let root = &fs.root()?;
-    fs.walk(root, "", &mut |_, path, inode, enhanced| {
-        println!(
-            "<{}> {}: {:?} {:?}",
-            inode.number, path, enhanced, inode.stat
-        );
+    fs.walk_with_options(root, "", options, &(), &mut |fs, path, inode, enhanced| {
+        let mut record = EntryRecord::new(path, inode, enhanced);
+        if names {
+            record.user = Some(fs.display_uid(inode.stat.uid)?);
+            record.group = Some(fs.display_gid(inode.stat.gid)?);
+        }
+        out.record(&record, || {
+            print!(
+                "<{}> {}: {:?} {:?}",
+                inode.number, path, enhanced, inode.stat
+            );
+            if let (Some(user), Some(group)) = (&record.user, &record.group) {
+                print!(" {}:{}", user, group);
+            }
+            println!();
+            Ok(())
+        })?;
         Ok(true)
     })
     .map(|_| ())?; // we don't care about the returned "true"
     Ok(())
 }
 
-fn head_all<R>(fs: SuperBlock<R>, bytes: usize) -> Result<(), Error>
+#[derive(Serialize)]
+struct HeadRecord<'a> {
+    path: &'a str,
+    size: u64,
+    /// Invalid utf-8 is replaced.
+    head: String,
+}
+
+fn head_all<R>(fs: SuperBlock<R>, bytes: usize, out: &mut Output) -> Result<(), Error>
 where
     R: ReadAt,
 {
@@ -41,6 +150,20 @@ where
             return Ok(true);
         }
 
+        if Format::Text != out.format {
+            let mut head = Vec::new();
+            fs.open(inode)?
+                .take(u64::try_from(bytes)?)
+                .read_to_end(&mut head)?;
+            let record = HeadRecord {
+                path,
+                size: inode.stat.size,
+                head: String::from_utf8_lossy(&head).to_string(),
+            };
+            out.record(&record, || unreachable!())?;
+            return Ok(true);
+        }
+
         if 0 == inode.stat.size {
             println!("==> (empty) {}  <==", path);
             return Ok(true);
@@ -67,49 +190,1398 @@ where
     Ok(())
 }
 
-fn on_fs(file: &str, work: Command) -> Result<(), Error> {
-    let mut reader = fs::File::open(file)?;
-    match bootsector::list_partitions(&mut reader, &bootsector::Options::default()) {
-        Ok(partitions) => {
-            for part in partitions {
-                work.exec(ext4::SuperBlock::new(bootsector::open_partition(
-                    &mut reader,
-                    &part,
-                )?)?)?;
+fn dump_groups<R>(fs: SuperBlock<R>, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    for number in 0..fs.block_group_count() {
+        let group = fs.block_group(number)?;
+        out.record(&group, || print_group(&group))?;
+    }
+    Ok(())
+}
+
+fn dump_superblock<R>(fs: SuperBlock<R>, compat: bool, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let info = fs.info();
+    out.record(&info, || {
+        if compat {
+            fs.dumpe2fs(&mut io::stdout().lock())
+        } else {
+            println!("{:#?}", info);
+            Ok(())
+        }
+    })
+}
+
+fn debugfs<R>(fs: SuperBlock<R>, request: &DebugfsRequest, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    ensure!(
+        Format::Text == out.format,
+        "debugfs only supports text output"
+    );
+    let mut debugfs = ext4::Debugfs::new(&fs);
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    match request {
+        DebugfsRequest::Request(line) => debugfs.run(line, &mut stdout),
+        DebugfsRequest::Script(script) => {
+            let failed = debugfs.run_script(script, &mut stdout, &mut io::stderr())?;
+            ensure!(0 == failed, "{} commands failed", failed);
+            Ok(())
+        }
+    }
+}
+
+fn print_group(group: &ext4::BlockGroup) -> Result<(), Error> {
+    print!(
+        "Group {}: (Blocks {}-{})",
+        group.number, group.first_block, group.last_block
+    );
+
+    if let Some(computed) = group.computed_checksum {
+        print!(" csum 0x{:04x}", group.checksum);
+        if computed != group.checksum {
+            print!(" (EXPECTED 0x{:04x})", computed);
+        }
+    }
+
+    let mut flags = Vec::new();
+    if group.flags.contains(ext4::BlockGroupFlags::INODE_UNINIT) {
+        flags.push("INODE_UNINIT");
+    }
+    if group.flags.contains(ext4::BlockGroupFlags::BLOCK_UNINIT) {
+        flags.push("BLOCK_UNINIT");
+    }
+    if group.flags.contains(ext4::BlockGroupFlags::ITABLE_ZEROED) {
+        flags.push("ITABLE_ZEROED");
+    }
+    if !flags.is_empty() {
+        print!(" [{}]", flags.join(", "));
+    }
+    println!();
+
+    let relative = |block: u64| {
+        if block >= group.first_block && block <= group.last_block {
+            format!(" (+{})", block - group.first_block)
+        } else {
+            String::new()
+        }
+    };
+
+    print!(
+        "  Block bitmap at {}{}",
+        group.block_bitmap,
+        relative(group.block_bitmap)
+    );
+    if let Some(csum) = group.block_bitmap_checksum {
+        print!(", csum 0x{:08x}", csum);
+    }
+    println!();
+
+    print!(
+        "  Inode bitmap at {}{}",
+        group.inode_bitmap,
+        relative(group.inode_bitmap)
+    );
+    if let Some(csum) = group.inode_bitmap_checksum {
+        print!(", csum 0x{:08x}", csum);
+    }
+    println!();
+
+    println!(
+        "  Inode table at {}-{}{}",
+        group.inode_table,
+        group.inode_table + group.inode_table_blocks - 1,
+        relative(group.inode_table)
+    );
+    println!(
+        "  {} free blocks, {} free inodes, {} directories, {} unused inodes",
+        group.free_blocks_count,
+        group.free_inodes_count,
+        group.used_dirs_count,
+        group.itable_unused
+    );
+    Ok(())
+}
+
+fn print_journal(journal: &ext4::Journal) -> Result<(), Error> {
+    println!(
+        "journal: {} blocks of {} bytes, log starts at {}, recovery starts at {} (sequence {}), features: {:?}",
+        journal.max_len,
+        journal.block_size,
+        journal.first,
+        journal.start,
+        journal.sequence,
+        journal.incompatible_features
+    );
+    for transaction in &journal.transactions {
+        print!(
+            "transaction {}: log blocks {}-{}, {} blocks, {} revoked",
+            transaction.sequence,
+            transaction.log_start,
+            transaction.log_end,
+            transaction.blocks.len(),
+            transaction.revoked.len()
+        );
+        if let Some(ref time) = transaction.commit_time {
+            print!(", committed at {}", time.epoch_secs);
+        }
+        if transaction.needs_replay {
+            print!(", needs replay");
+        }
+        println!();
+        for block in &transaction.blocks {
+            println!("  block {} logged at {}", block.target, block.log_block);
+        }
+        for block in &transaction.revoked {
+            println!("  revoked {}", block);
+        }
+    }
+    Ok(())
+}
+
+fn journal<R>(fs: SuperBlock<R>, action: &JournalAction, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    match action {
+        JournalAction::List => {
+            let journal = fs.journal()?;
+            out.record(&journal, || print_journal(&journal))?;
+        }
+        JournalAction::ReplayTo(ref path) => {
+            let out = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .with_context(|| anyhow!("creating {}", path))?;
+            fs.replay_journal_to(out)?;
+        }
+        JournalAction::ExtractBlock(target) => {
+            ensure!(
+                Format::Text == out.format,
+                "--extract-block only supports text output"
+            );
+            let journal = fs.journal()?;
+            let mut found = false;
+            for transaction in &journal.transactions {
+                for block in &transaction.blocks {
+                    if block.target != *target {
+                        continue;
+                    }
+                    found = true;
+                    println!(
+                        "==> transaction {}, log block {}{} <==",
+                        transaction.sequence,
+                        block.log_block,
+                        if transaction.needs_replay {
+                            ", needs replay"
+                        } else {
+                            ""
+                        }
+                    );
+                    hexdump::hexdump(&fs.journal_block(block)?);
+                }
+            }
+            if !found {
+                bail!("block {} is not in the journal", target);
             }
         }
-        Err(_) => work.exec(ext4::SuperBlock::new(reader)?)?,
+    }
+    Ok(())
+}
+
+fn block<R>(fs: SuperBlock<R>, first: u64, count: u64, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    ensure!(
+        Format::Text == out.format,
+        "block only supports text output"
+    );
+    for number in first..first.saturating_add(count) {
+        let data = fs.load_block(number)?;
+        println!("==> block {} <==", number);
+        hexdump::hexdump(&data);
+    }
+    Ok(())
+}
+
+/// Write an inode's content to stdout; for a symlink, that's its target, byte for byte.
+fn icat<R>(fs: SuperBlock<R>, inode: u32) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let inode = fs.load_inode(inode)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    match inode.stat.extracted_type {
+        ext4::FileType::RegularFile | ext4::FileType::Directory => {
+            let bar = Bar::new();
+            io::copy(
+                &mut ext4::ProgressReader::new(fs.open(&inode)?, &bar),
+                &mut stdout,
+            )?;
+        }
+        ext4::FileType::SymbolicLink => stdout.write_all(&path_bytes(&fs.read_link(&inode)?))?,
+        _ => bail!(
+            "inode {} is a {:?}, which has no content",
+            inode.number,
+            inode.stat.extracted_type
+        ),
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TailAmount {
+    Lines(u64),
+    Bytes(u64),
+}
+
+/// Print the end of a file. Only the blocks near the end are read, so this is cheap on huge logs.
+fn tail<R>(fs: SuperBlock<R>, path: &str, amount: TailAmount) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let (path, entry) = fs.canonicalize(path)?;
+    let inode = fs.load_inode(entry.inode)?;
+    ensure!(
+        ext4::FileType::RegularFile == inode.stat.extracted_type,
+        "{} is not a regular file",
+        path
+    );
+
+    let size = inode.stat.size;
+    let mut reader = fs.open(&inode)?;
+    let start = match amount {
+        TailAmount::Bytes(bytes) => size.saturating_sub(bytes),
+        TailAmount::Lines(lines) => start_of_last_lines(&mut reader, size, lines)?,
+    };
+
+    reader.seek(io::SeekFrom::Start(start))?;
+    io::copy(&mut reader, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+/// Where to write an archive: a file, or stdout.
+fn archive_out(out: Option<&str>) -> Result<Box<dyn Write>, Error> {
+    Ok(match out {
+        Some(out) => Box::new(io::BufWriter::new(
+            fs::File::create(out).with_context(|| anyhow!("creating {}", out))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+fn zip<R>(
+    fs: SuperBlock<R>,
+    path: &str,
+    method: ext4::ZipMethod,
+    options: &ext4::WalkOptions,
+    out: Option<&str>,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let mut zip = ext4::ZipWriter::new(archive_out(out)?, method);
+    fs.export_with_options(path, &mut zip, options)?;
+    zip.finish()?;
+    Ok(())
+}
+
+fn tar<R>(
+    fs: SuperBlock<R>,
+    path: &str,
+    options: &ext4::WalkOptions,
+    out: Option<&str>,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let mut tar = ext4::TarWriter::new(archive_out(out)?);
+    fs.export_with_options(path, &mut tar, options)?;
+    tar.finish()?;
+    Ok(())
+}
+
+fn oci_layer<R>(
+    fs: SuperBlock<R>,
+    path: &str,
+    options: &ext4::WalkOptions,
+    out: Option<&str>,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let mut writer = ext4::OciLayerWriter::new(archive_out(out)?);
+    fs.export_with_options(path, &mut writer, options)?;
+    let (_, layer) = writer.finish()?;
+    eprintln!("{} {}", layer.diff_id, layer.size);
+    Ok(())
+}
+
+/// Search backwards from the end of the file for the start of the last `lines` lines.
+fn start_of_last_lines<Rd>(reader: &mut Rd, size: u64, lines: u64) -> Result<u64, Error>
+where
+    Rd: Read + Seek,
+{
+    if 0 == lines {
+        return Ok(size);
+    }
+
+    let mut chunk = vec![0u8; 64 * 1024];
+    let mut newlines = 0;
+    let mut end = size;
+
+    while end > 0 {
+        let start = end.saturating_sub(u64::try_from(chunk.len())?);
+        let chunk = &mut chunk[..usize::try_from(end - start)?];
+        reader.seek(io::SeekFrom::Start(start))?;
+        reader.read_exact(chunk)?;
+
+        for (pos, byte) in (start..end).rev().zip(chunk.iter().rev()) {
+            // a newline at the very end finishes the last line; it doesn't start a new one
+            if b'\n' != *byte || pos + 1 == size {
+                continue;
+            }
+            newlines += 1;
+            if lines == newlines {
+                return Ok(pos + 1);
+            }
+        }
+
+        end = start;
+    }
+
+    Ok(0)
+}
+
+fn digest<Rd: Read>(algo: HashAlgo, mut reader: Rd) -> Result<String, Error> {
+    Ok(match algo {
+        HashAlgo::Sha256 => {
+            let mut hasher = sha2::Sha256::new();
+            io::copy(&mut reader, &mut hasher)?;
+            hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        }
+        HashAlgo::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut reader, &mut hasher)?;
+            hasher.finalize().to_hex().to_string()
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct MatchRecord<'a> {
+    path: &'a str,
+    /// Numbered from one, like grep.
+    line_number: u64,
+    line: String,
+}
+
+fn grep<R>(
+    fs: SuperBlock<R>,
+    pattern: &str,
+    path: &str,
+    binary_skip: bool,
+    out: &mut Output,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let pattern = regex::bytes::Regex::new(pattern)?;
+    let start = fs.load_inode(fs.resolve_path(path)?.inode)?;
+    let path = path.trim_end_matches('/');
+    fs.walk(&start, path, &mut |fs, path, inode, _| {
+        if ext4::FileType::RegularFile != inode.stat.extracted_type {
+            return Ok(true);
+        }
+
+        let mut reader = io::BufReader::new(fs.open(inode)?);
+        // like grep, guess a file is binary if there's a NUL near the start
+        let binary = reader.fill_buf()?.contains(&0);
+        if binary && binary_skip {
+            return Ok(true);
+        }
+
+        let mut line = Vec::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            if 0 == reader.read_until(b'\n', &mut line)? {
+                break;
+            }
+            line_number += 1;
+
+            let content = line.strip_suffix(b"\n").unwrap_or(&line);
+            if !pattern.is_match(content) {
+                continue;
+            }
+
+            let record = MatchRecord {
+                path,
+                line_number,
+                line: String::from_utf8_lossy(content).to_string(),
+            };
+            out.record(&record, || {
+                if binary {
+                    println!("Binary file {} matches", path);
+                } else {
+                    println!("{}:{}", path, record.line);
+                }
+                Ok(())
+            })?;
+
+            if binary && Format::Text == out.format {
+                break;
+            }
+        }
+        Ok(true)
+    })
+    .map(|_| ())?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct HashRecord<'a> {
+    path: &'a str,
+    algorithm: &'static str,
+    digest: String,
+}
+
+fn hash<R>(fs: SuperBlock<R>, path: &str, algo: HashAlgo, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let start = fs.load_inode(fs.resolve_path(path)?.inode)?;
+    let path = path.trim_end_matches('/');
+    let bar = Bar::new();
+    fs.walk_with_progress(&start, path, &bar, &mut |fs, path, inode, _| {
+        if ext4::FileType::RegularFile != inode.stat.extracted_type {
+            return Ok(true);
+        }
+
+        let record = HashRecord {
+            path,
+            algorithm: match algo {
+                HashAlgo::Sha256 => "sha256",
+                HashAlgo::Blake3 => "blake3",
+            },
+            digest: digest(algo, ext4::ProgressReader::new(fs.open(inode)?, &bar))?,
+        };
+        bar.suspend(|| {
+            out.record(&record, || {
+                println!("{}  {}", record.digest, path);
+                Ok(())
+            })
+        })?;
+        Ok(true)
+    })
+    .map(|_| ())?; // we don't care about the returned "true"
+    Ok(())
+}
+
+fn print_deleted(found: &ext4::DeletedInode) -> Result<(), Error> {
+    let source = match found.source {
+        ext4::DeletedSource::InodeTable => "inode table".to_string(),
+        ext4::DeletedSource::Journal { sequence } => {
+            format!("journal transaction {}", sequence)
+        }
+    };
+    print!(
+        "<{}> {:?}, {} bytes, from {}",
+        found.inode.number, found.inode.stat.extracted_type, found.inode.stat.size, source
+    );
+    if let Some(ref time) = found.deleted_at {
+        print!(", deleted at {}", time.epoch_secs);
+    }
+    if found.intact {
+        print!(", recoverable");
+    }
+    println!();
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct DeletedRecord<'a> {
+    inode: u32,
+    #[serde(rename = "type")]
+    file_type: &'a ext4::FileType,
+    size: u64,
+    source: ext4::DeletedSource,
+    deleted_at: Option<&'a ext4::Time>,
+    intact: bool,
+}
+
+fn recover<R>(fs: SuperBlock<R>, action: &RecoverAction, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let deleted = fs.deleted_inodes()?;
+    match *action {
+        RecoverAction::List => {
+            for found in deleted {
+                let record = DeletedRecord {
+                    inode: found.inode.number,
+                    file_type: &found.inode.stat.extracted_type,
+                    size: found.inode.stat.size,
+                    source: found.source,
+                    deleted_at: found.deleted_at.as_ref(),
+                    intact: found.intact,
+                };
+                out.record(&record, || print_deleted(&found))?;
+            }
+        }
+        RecoverAction::Extract {
+            inode,
+            out: ref path,
+        } => {
+            let found = match deleted
+                .iter()
+                .find(|found| found.inode.number == inode && found.intact)
+            {
+                Some(found) => found,
+                None => bail!("no recoverable copy of inode <{}> found", inode),
+            };
+            let mut file = fs::File::create(path).with_context(|| anyhow!("creating {}", path))?;
+            let bar = Bar::new();
+            io::copy(
+                &mut ext4::ProgressReader::new(fs.open(&found.inode)?, &bar),
+                &mut file,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct LinkRecord<'a> {
+    path: &'a str,
+    target: &'a str,
+}
+
+fn readlink<R>(fs: SuperBlock<R>, path: &str, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    // like the kernel, follow links in the directories leading up to the name, but not the name
+    let path = path.trim_end_matches('/');
+    let options = ext4::ResolveOptions {
+        follow_final: false,
+        ..Default::default()
+    };
+    let (_, entry) = fs.resolve_with_options(path, &options)?;
+
+    let inode = fs.load_inode(entry.inode)?;
+    if ext4::FileType::SymbolicLink != inode.stat.extracted_type {
+        bail!("{} is not a symbolic link", path);
+    }
+    let target = fs.read_link(&inode)?;
+    out.record(
+        &LinkRecord {
+            path,
+            target: &target.to_string_lossy(),
+        },
+        || {
+            // the target's bytes, as readlink(1) writes them, even if they aren't utf-8
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(&path_bytes(&target))?;
+            stdout.write_all(b"\n")?;
+            Ok(())
+        },
+    )
+}
+
+/// A path's bytes, to write out as they are; off unix, where paths aren't bytes, as utf-8.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<[u8]> {
+    Cow::Owned(path.to_string_lossy().into_owned().into_bytes())
+}
+
+#[derive(Serialize)]
+struct ResolvedRecord<'a> {
+    path: &'a str,
+    inode: u32,
+}
+
+fn resolve<R>(fs: SuperBlock<R>, path: &str, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let (canonical, entry) = fs.canonicalize(path)?;
+    let record = ResolvedRecord {
+        path: &canonical,
+        inode: entry.inode,
+    };
+    out.record(&record, || {
+        println!("<{}> {}", entry.inode, canonical);
+        Ok(())
+    })
+}
+
+fn distro<R>(fs: SuperBlock<R>, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let distro = fs.distro()?;
+    out.record(&distro, || {
+        match distro.os_release {
+            Some(ref os) => println!(
+                "os: {} ({} {})",
+                os.pretty_name.as_deref().unwrap_or("unnamed"),
+                os.id.as_deref().unwrap_or("linux"),
+                os.version_id.as_deref().unwrap_or("-"),
+            ),
+            None => println!("os: unknown"),
+        }
+        if let Some(ref passwd) = distro.passwd {
+            println!("users: {}", passwd.len());
+        }
+        if let Some(ref group) = distro.group {
+            println!("groups: {}", group.len());
+        }
+        if let Some(ref dpkg) = distro.dpkg {
+            let installed = dpkg.iter().filter(|package| package.is_installed()).count();
+            println!(
+                "dpkg packages: {} installed, {} listed",
+                installed,
+                dpkg.len()
+            );
+        }
+        if let Some(ref rpm) = distro.rpm {
+            match rpm.backend() {
+                Some(backend) => println!("rpm database: {} ({:?})", rpm.path, backend),
+                None => println!("rpm database: {}", rpm.path),
+            }
+        }
+        Ok(())
+    })
+}
+
+fn timeline<R>(fs: SuperBlock<R>, deleted: bool, out: &mut Output) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    for entry in fs.timeline(deleted)? {
+        out.record(&entry, || {
+            println!("{}", entry.body_line());
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn verify<R>(
+    fs: SuperBlock<R>,
+    cursor_file: Option<&str>,
+    seconds: Option<u64>,
+    out: &mut Output,
+) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let stored = match cursor_file {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(stored) => Some(stored),
+            Err(e) if io::ErrorKind::NotFound == e.kind() => None,
+            Err(e) => return Err(Error::new(e).context(anyhow!("reading {}", path))),
+        },
+        None => None,
+    };
+    let mut cursor = match stored {
+        Some(stored) => stored.parse::<ext4::VerifyCursor>()?,
+        None => fs.verify_cursor(),
+    };
+
+    let findings = match seconds {
+        Some(seconds) => {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(seconds);
+            fs.verify(&mut cursor, &deadline)?
+        }
+        None => fs.verify(&mut cursor, &())?,
+    };
+    for finding in &findings {
+        out.record(finding, || {
+            println!("{}: {}", phase_title(finding.phase), finding.message);
+            print_region("  ", &finding.region);
+            Ok(())
+        })?;
+    }
+
+    let (done, groups) = cursor.groups_done();
+    if Format::Text == out.format {
+        println!("{} of {} groups verified", done, groups);
+    }
+    if let Some(path) = cursor_file {
+        // the next run starts again from the beginning
+        if cursor.is_finished() {
+            cursor = fs.verify_cursor();
+        }
+        fs::write(path, format!("{}\n", cursor)).with_context(|| anyhow!("writing {}", path))?;
+    }
+    Ok(())
+}
+
+const CHANGE_NAMES: &[(ext4::Changes, &str)] = &[
+    (ext4::Changes::FILE_TYPE, "type"),
+    (ext4::Changes::MODE, "mode"),
+    (ext4::Changes::OWNER, "owner"),
+    (ext4::Changes::SIZE, "size"),
+    (ext4::Changes::MTIME, "mtime"),
+    (ext4::Changes::XATTRS, "xattrs"),
+    (ext4::Changes::TARGET, "target"),
+    (ext4::Changes::CONTENT, "content"),
+];
+
+fn change_names(changes: ext4::Changes) -> Vec<&'static str> {
+    CHANGE_NAMES
+        .iter()
+        .filter(|(flag, _)| changes.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[derive(Serialize)]
+struct DifferenceRecord<'a> {
+    path: &'a str,
+    change: &'static str,
+    fields: Vec<&'static str>,
+}
+
+fn diff(
+    first: &str,
+    second: &str,
+    location: Location,
+    content: bool,
+    out: &mut Output,
+) -> Result<(), Error> {
+    let first = open_single(first, location.clone())?;
+    let second = open_single(second, location)?;
+    let differences = {
+        let bar = Bar::new();
+        first.diff_with_progress(&second, &ext4::DiffOptions { content }, &bar)?
+    };
+
+    for difference in differences {
+        let (change, fields) = match difference.change {
+            ext4::Change::Added => ("added", Vec::new()),
+            ext4::Change::Removed => ("removed", Vec::new()),
+            ext4::Change::Changed(changes) => ("changed", change_names(changes)),
+        };
+        let record = DifferenceRecord {
+            path: &difference.path,
+            change,
+            fields,
+        };
+        out.record(&record, || {
+            match difference.change {
+                ext4::Change::Added => println!("+ {}", difference.path),
+                ext4::Change::Removed => println!("- {}", difference.path),
+                ext4::Change::Changed(_) => {
+                    println!("M {} ({})", difference.path, record.fields.join(", "))
+                }
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn phase_title(phase: ext4::Phase) -> &'static str {
+    match phase {
+        ext4::Phase::Superblock => "Pass 1: Checking the superblock and journal",
+        ext4::Phase::Groups => "Pass 2: Checking group descriptors",
+        ext4::Phase::Inodes => "Pass 2b: Checking every inode in use",
+        ext4::Phase::Tree => "Pass 3: Checking directory structure",
+        ext4::Phase::References => "Pass 4: Checking reference counts",
+        ext4::Phase::Orphans => "Pass 5: Checking the orphan list",
+        ext4::Phase::LostAndFound => "Pass 6: Checking /lost+found",
+    }
+}
+
+/// Where a finding is in the image, and what would fix it, if that's known.
+fn print_region(indent: &str, region: &Option<ext4::Region>) {
+    if let Some(region) = region {
+        print!("{}at byte {}, {} bytes", indent, region.offset, region.len);
+        if let Some(fix) = &region.fix {
+            let hex = fix.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            print!(", should be {}", hex);
+        }
+        println!();
+    }
+}
+
+/// List every superblock in the image, wherever it is.
+fn scan(file: &str, out: &mut Output) -> Result<(), Error> {
+    let reader = fs::File::open(file).with_context(|| anyhow!("opening '{}'", file))?;
+    let len = ext4::image_len(&reader).with_context(|| anyhow!("finding the length"))?;
+    for candidate in ext4::scan_for_superblocks(&reader, 0..len)? {
+        out.record(&candidate, || {
+            let uuid = candidate
+                .uuid
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            println!(
+                "offset {}: {} superblock at {}, {} blocks of {} bytes, uuid {}{}{}",
+                candidate.offset,
+                match candidate.group {
+                    0 => "primary".to_string(),
+                    group => format!("group {} backup", group),
+                },
+                candidate.found_at,
+                candidate.blocks_count,
+                candidate.block_size,
+                uuid,
+                match candidate.volume_name {
+                    Some(ref name) => format!(", label {:?}", name),
+                    None => String::new(),
+                },
+                match candidate.checksum_valid {
+                    Some(false) => ", bad checksum",
+                    _ => "",
+                },
+            );
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Check every filesystem in the image, returning whether any errors were found.
+fn fsck(file: &str, location: Location, out: &mut Output) -> Result<bool, Error> {
+    let mut damaged = false;
+    for (partition, reader) in readers(file, &location)? {
+        let name = match partition {
+            Some(partition) => format!("{} (partition {})", file, partition),
+            None => file.to_string(),
+        };
+
+        let findings = location.load(reader)?.check()?;
+        let errors = findings
+            .iter()
+            .filter(|finding| ext4::Severity::Error == finding.severity)
+            .count();
+        damaged |= 0 != errors;
+
+        if Format::Text == out.format {
+            println!("{}", name);
+        }
+
+        let mut phase = None;
+        for finding in &findings {
+            out.record(finding, || {
+                if Some(finding.phase) != phase {
+                    println!("{}", phase_title(finding.phase));
+                    phase = Some(finding.phase);
+                }
+                let severity = match finding.severity {
+                    ext4::Severity::Warning => "warning",
+                    ext4::Severity::Error => "error",
+                };
+                println!("  {}: {}", severity, finding.message);
+                print_region("    ", &finding.region);
+                Ok(())
+            })?;
+        }
+
+        if Format::Text == out.format {
+            println!(
+                "{}: {} errors, {} warnings",
+                name,
+                errors,
+                findings.len() - errors
+            );
+        }
+    }
+    Ok(damaged)
+}
+
+/// The region of an image file that a filesystem occupies.
+type Region = positioned_io2::Slice<fs::File>;
+
+/// Where, inside an image, to look for filesystems.
+#[derive(Clone, Default)]
+struct Location {
+    /// Only consider this partition (as numbered by the partition table, from 0).
+    partition: Option<usize>,
+    /// Skip this many bytes, from the start of the partition (if any), or of the image.
+    offset: Option<u64>,
+    /// Treat this directory, a path or an inode like `<12>`, as the root.
+    root: Option<String>,
+    /// Open images which are shorter than their filesystem.
+    allow_truncated: bool,
+}
+
+/// How to walk the filesystem, from `--sort` and `--encrypted`.
+fn walk_options(matches: &clap::ArgMatches) -> ext4::WalkOptions {
+    ext4::WalkOptions {
+        sort: match matches.value_of("sort") {
+            Some("name") => ext4::WalkOrder::Name,
+            Some("inode") => ext4::WalkOrder::Inode,
+            _ => ext4::WalkOrder::Disk,
+        },
+        encrypted: match matches.value_of("encrypted") {
+            Some("skip") => ext4::EncryptedPolicy::Skip,
+            Some("ciphertext") => ext4::EncryptedPolicy::YieldCiphertext,
+            _ => ext4::EncryptedPolicy::Fail,
+        },
+        include_lost_found: !matches.is_present("skip-lost-found"),
+    }
+}
+
+impl Location {
+    fn from_matches(matches: &clap::ArgMatches) -> Location {
+        Location {
+            partition: matches
+                .value_of("partition")
+                .map(|s| s.parse::<usize>().unwrap()),
+            offset: matches
+                .value_of("offset")
+                .map(|s| s.parse::<u64>().unwrap()),
+            root: matches.value_of("root").map(|s| s.to_string()),
+            allow_truncated: matches.is_present("allow-truncated"),
+        }
+    }
+
+    /// Load a filesystem, and move its root, if that was asked for.
+    fn open(&self, reader: Region) -> Result<SuperBlock<Region>, Error> {
+        let fs = self.load(reader)?;
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return Ok(fs),
+        };
+        let inode = root
+            .strip_prefix('<')
+            .and_then(|s| s.strip_suffix('>'))
+            .and_then(|s| s.parse::<u32>().ok());
+        match inode {
+            Some(inode) => fs.with_root(inode),
+            None => fs.with_root(root.as_str()),
+        }
+        .with_context(|| anyhow!("moving the root to {}", root))
+    }
+
+    /// Load a filesystem, checking it fits in the image, or partition, it's in.
+    fn load(&self, reader: Region) -> Result<SuperBlock<Region>, Error> {
+        let options = ext4::Options {
+            len: positioned_io2::Size::size(&reader)?,
+            truncation: if self.allow_truncated {
+                ext4::Truncation::Tolerate
+            } else {
+                ext4::Truncation::Fail
+            },
+            ..ext4::Options::default()
+        };
+        SuperBlock::new_with_options(reader, &options)
+    }
+}
+
+/// The candidate filesystems in an image, with the partition number each came from, if any.
+fn readers(file: &str, location: &Location) -> Result<Vec<(Option<usize>, Region)>, Error> {
+    let reader = fs::File::open(file).with_context(|| anyhow!("opening '{}'", file))?;
+    let offset = location.offset.unwrap_or(0);
+
+    let partitions = bootsector::list_partitions(&reader, &bootsector::Options::default());
+
+    if let Some(wanted) = location.partition {
+        let partitions = partitions.with_context(|| anyhow!("reading partition table"))?;
+        let part = match partitions.into_iter().find(|part| part.id == wanted) {
+            Some(part) => part,
+            None => bail!("there is no partition {}", wanted),
+        };
+        ensure!(
+            offset < part.len,
+            "offset {} is beyond the end of partition {} ({} bytes)",
+            offset,
+            wanted,
+            part.len
+        );
+        return Ok(vec![(
+            Some(part.id),
+            positioned_io2::Slice::new(reader, part.first_byte + offset, Some(part.len - offset)),
+        )]);
+    }
+
+    let len = ext4::image_len(&reader).with_context(|| anyhow!("finding the length"))?;
+    if location.offset.is_some() {
+        ensure!(
+            offset < len,
+            "offset {} is beyond the end of the image ({} bytes)",
+            offset,
+            len
+        );
+        return Ok(vec![(
+            None,
+            positioned_io2::Slice::new(reader, offset, Some(len - offset)),
+        )]);
+    }
+
+    match partitions {
+        Ok(partitions) => partitions
+            .into_iter()
+            .map(|part| {
+                Ok((
+                    Some(part.id),
+                    bootsector::open_partition(reader.try_clone()?, &part)?,
+                ))
+            })
+            .collect(),
+        Err(_) => Ok(vec![(
+            None,
+            positioned_io2::Slice::new(reader, 0, Some(len)),
+        )]),
+    }
+}
+
+/// Open the first ext4 filesystem in an image, for commands which need exactly one.
+fn open_single(file: &str, location: Location) -> Result<SuperBlock<Region>, Error> {
+    let mut last_error = None;
+    for (_, reader) in readers(file, &location)? {
+        match location.open(reader) {
+            Ok(fs) => return Ok(fs),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| anyhow!("no partitions found"))
+        .context(anyhow!("while processing '{}'", file)))
+}
+
+fn on_fs(file: &str, location: Location, work: Command, out: &mut Output) -> Result<(), Error> {
+    let readers = readers(file, &location)?;
+    let announce = readers.len() > 1;
+    for (partition, reader) in readers {
+        if let (true, Some(partition)) = (announce, partition) {
+            eprintln!("==> {}: partition {} <==", file, partition);
+        }
+        work.exec(location.open(reader)?, out)?;
     }
     Ok(())
 }
 
 fn for_each_input(matches: &clap::ArgMatches, work: Command) -> Result<(), Error> {
     let file = matches.value_of("file").unwrap();
-    on_fs(file, work).with_context(|| anyhow!("while processing '{}'", file))?;
+    let mut out = Output::new(Format::from_matches(matches));
+    on_fs(file, Location::from_matches(matches), work, &mut out)
+        .with_context(|| anyhow!("while processing '{}'", file))?;
+    out.finish();
     Ok(())
 }
 
+#[derive(Clone, PartialEq, Eq)]
+enum JournalAction {
+    List,
+    ReplayTo(String),
+    ExtractBlock(u64),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum DebugfsRequest {
+    /// `-R`: one command.
+    Request(String),
+    /// `-f`: the content of a file of commands.
+    Script(String),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum RecoverAction {
+    List,
+    Extract { inode: u32, out: String },
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
+enum HashAlgo {
+    Sha256,
+    Blake3,
+}
+
+#[derive(Clone, PartialEq, Eq)]
 enum Command {
-    DumpLs,
-    HeadAll { bytes: usize },
+    Block {
+        first: u64,
+        count: u64,
+    },
+    Debugfs(DebugfsRequest),
+    Distro,
+    DumpGroups,
+    DumpLs {
+        names: bool,
+        options: ext4::WalkOptions,
+    },
+    DumpSuperblock {
+        compat: bool,
+    },
+    Grep {
+        pattern: String,
+        path: String,
+        binary_skip: bool,
+    },
+    Hash {
+        path: String,
+        algo: HashAlgo,
+    },
+    HeadAll {
+        bytes: usize,
+    },
+    Journal(JournalAction),
+    ReadLink {
+        path: String,
+    },
+    Recover(RecoverAction),
+    Resolve {
+        path: String,
+    },
+    Timeline {
+        deleted: bool,
+    },
+    Verify {
+        cursor: Option<String>,
+        seconds: Option<u64>,
+    },
 }
 
 impl Command {
-    fn exec<R: ReadAt>(self, fs: SuperBlock<R>) -> Result<(), Error> {
-        match self {
-            Command::DumpLs => dump_ls(fs),
-            Command::HeadAll { bytes } => head_all(fs, bytes),
+    fn exec<R: ReadAt>(&self, fs: SuperBlock<R>, out: &mut Output) -> Result<(), Error> {
+        match *self {
+            Command::Block { first, count } => block(fs, first, count, out),
+            Command::Debugfs(ref request) => debugfs(fs, request, out),
+            Command::Distro => distro(fs, out),
+            Command::DumpGroups => dump_groups(fs, out),
+            Command::DumpLs { names, ref options } => dump_ls(fs, names, options, out),
+            Command::DumpSuperblock { compat } => dump_superblock(fs, compat, out),
+            Command::Grep {
+                ref pattern,
+                ref path,
+                binary_skip,
+            } => grep(fs, pattern, path, binary_skip, out),
+            Command::Hash { ref path, algo } => hash(fs, path, algo, out),
+            Command::HeadAll { bytes } => head_all(fs, bytes, out),
+            Command::Journal(ref action) => journal(fs, action, out),
+            Command::ReadLink { ref path } => readlink(fs, path, out),
+            Command::Recover(ref action) => recover(fs, action, out),
+            Command::Resolve { ref path } => resolve(fs, path, out),
+            Command::Timeline { deleted } => timeline(fs, deleted, out),
+            Command::Verify {
+                ref cursor,
+                seconds,
+            } => verify(fs, cursor.as_deref(), seconds, out),
         }
     }
 }
 
 fn main() -> Result<(), Error> {
     let paths_arg = Arg::with_name("file").required(true);
+    let sort_arg = Arg::with_name("sort")
+        .long("sort")
+        .possible_values(&["disk", "name", "inode"])
+        .default_value("disk")
+        .help("the order to visit each directory's entries in; disk order varies between images");
+    let encrypted_arg = Arg::with_name("encrypted")
+        .long("encrypted")
+        .possible_values(&["fail", "skip", "ciphertext"])
+        .default_value("fail")
+        .help("what to do with encrypted directories and files, which can't be decrypted");
+    let skip_lost_found_arg = Arg::with_name("skip-lost-found")
+        .long("skip-lost-found")
+        .help("leave out the filesystem's /lost+found");
+    let names_arg = Arg::with_name("names")
+        .long("names")
+        .help("show owners by name, from the image's own /etc/passwd and /etc/group");
 
     let matches = App::new("ext4tool")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(SubCommand::with_name("dump-ls").arg(&paths_arg))
+        .arg(
+            Arg::with_name("partition")
+                .long("partition")
+                .value_name("N")
+                .global(true)
+                .help("only look at this partition, numbered from 0")
+                .validator(|s| {
+                    s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid partition number '{}': {}", s, e))
+                }),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .global(true)
+                .possible_values(&["text", "json", "ndjson"])
+                .default_value("text")
+                .help("how to print results; json is one array, ndjson is one record per line"),
+        )
+        .arg(
+            Arg::with_name("offset")
+                .long("offset")
+                .value_name("BYTES")
+                .global(true)
+                .help("the filesystem starts this far into the image (or the partition)")
+                .validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid offset '{}': {}", s, e))
+                }),
+        )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .value_name("DIR")
+                .global(true)
+                .help("treat this directory, a path or an inode like <12>, as the root"),
+        )
+        .arg(
+            Arg::with_name("allow-truncated")
+                .long("allow-truncated")
+                .global(true)
+                .help(
+                    "open images shorter than their filesystem; missing file data reads as zeros",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("block")
+                .about("hexdump raw filesystem blocks")
+                .arg(
+                    Arg::with_name("count")
+                        .long("count")
+                        .value_name("M")
+                        .default_value("1")
+                        .help("dump this many consecutive blocks")
+                        .validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid block count '{}': {}", s, e))
+                        }),
+                )
+                .arg(&paths_arg)
+                .arg(Arg::with_name("block").required(true).validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid block number '{}': {}", s, e))
+                })),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .arg(
+                    Arg::with_name("content")
+                        .long("content")
+                        .help("compare the contents of files, not just their metadata"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("the same as --format json"),
+                )
+                .arg(Arg::with_name("first").required(true))
+                .arg(Arg::with_name("second").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("debugfs")
+                .about("run debugfs's read-only commands, like `stat <12>`, or `ls -l /etc`")
+                .arg(
+                    Arg::with_name("request")
+                        .short("R")
+                        .value_name("REQUEST")
+                        .help("run one command"),
+                )
+                .arg(
+                    Arg::with_name("cmd_file")
+                        .short("f")
+                        .value_name("CMD_FILE")
+                        .help("run each line of a file, echoing it, as debugfs -f does"),
+                )
+                .group(
+                    ArgGroup::with_name("commands")
+                        .args(&["request", "cmd_file"])
+                        .required(true),
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("distro")
+                .about("identify the installed distribution, its users, and its packages")
+                .arg(&paths_arg),
+        )
+        .subcommand(SubCommand::with_name("dump-groups").arg(&paths_arg))
+        .subcommand(
+            SubCommand::with_name("dump-ls")
+                .arg(&names_arg)
+                .arg(&sort_arg)
+                .arg(&encrypted_arg)
+                .arg(&skip_lost_found_arg)
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("dump-superblock")
+                .about("describe the filesystem, from its superblock")
+                .arg(
+                    Arg::with_name("compat")
+                        .long("compat")
+                        .help("print the superblock and groups exactly as dumpe2fs does"),
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("grep")
+                .about("print lines of files matching a regular expression")
+                .arg(
+                    Arg::with_name("binary-skip")
+                        .long("binary-skip")
+                        .help("don't search files which look binary"),
+                )
+                .arg(&paths_arg)
+                .arg(Arg::with_name("pattern").required(true).validator(|s| {
+                    regex::bytes::Regex::new(&s)
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid pattern '{}': {}", s, e))
+                }))
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("hash")
+                .arg(
+                    Arg::with_name("algo")
+                        .long("algo")
+                        .possible_values(&["sha256", "blake3"])
+                        .default_value("sha256"),
+                )
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("check the filesystem for damage, without changing it")
+                .after_help(
+                    "Exits with 0 if no errors were found (warnings are fine), \
+                     4 if the filesystem is damaged, and 8 if it couldn't be checked.",
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("the same as --format json"),
+                )
+                .arg(&paths_arg),
+        )
         .subcommand(
             SubCommand::with_name("head-all")
                 .arg(
@@ -125,16 +1597,411 @@ fn main() -> Result<(), Error> {
                 )
                 .arg(&paths_arg),
         )
+        .subcommand(
+            SubCommand::with_name("icat")
+                .about("write the content of an inode, by number, to stdout")
+                .arg(&paths_arg)
+                .arg(Arg::with_name("inode").required(true).validator(|s| {
+                    s.parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid inode number '{}': {}", s, e))
+                })),
+        )
+        .subcommand(
+            SubCommand::with_name("journal")
+                .arg(Arg::with_name("list").long("list"))
+                .arg(
+                    Arg::with_name("replay-to")
+                        .long("replay-to")
+                        .value_name("OUT.img")
+                        .help("write a copy of the filesystem with the journal replayed"),
+                )
+                .arg(
+                    Arg::with_name("extract-block")
+                        .long("extract-block")
+                        .value_name("N")
+                        .help("dump every version of a filesystem block found in the journal")
+                        .validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid block number '{}': {}", s, e))
+                        }),
+                )
+                .group(ArgGroup::with_name("action").args(&["list", "replay-to", "extract-block"]))
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("readlink")
+                .about("print the target of a symbolic link")
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("recover")
+                .about("find deleted files, and try to extract them")
+                .arg(Arg::with_name("list").long("list").conflicts_with("inode"))
+                .arg(
+                    Arg::with_name("inode")
+                        .long("inode")
+                        .value_name("N")
+                        .requires("out")
+                        .validator(|s| {
+                            s.parse::<u32>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid inode number '{}': {}", s, e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .requires("inode"),
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("search a damaged disc for superblocks, to find where filesystems start")
+                .after_help(
+                    "Each candidate's offset can be passed to --offset. The image's \
+                     partitions are ignored; the whole file is searched.",
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("shell")
+                .about("explore the filesystem interactively, debugfs-style")
+                .arg(&names_arg)
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("tail")
+                .about("print the end of a file, without reading the rest of it")
+                .arg(
+                    Arg::with_name("lines")
+                        .short("n")
+                        .long("lines")
+                        .value_name("N")
+                        .default_value("10")
+                        .help("print the last N lines")
+                        .validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid line count '{}': {}", s, e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("bytes")
+                        .short("c")
+                        .long("bytes")
+                        .value_name("BYTES")
+                        .conflicts_with("lines")
+                        .help("print the last BYTES bytes, instead of lines")
+                        .validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid byte count '{}': {}", s, e))
+                        }),
+                )
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("oci-layer")
+                .about("write an overlay's upper directory as an OCI image layer")
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("where to write the layer, instead of stdout"),
+                )
+                .arg(&sort_arg)
+                .arg(&encrypted_arg)
+                .arg(&skip_lost_found_arg)
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("tar")
+                .about("write a directory, and everything in it, to a tar file")
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("where to write the tar, instead of stdout"),
+                )
+                .arg(&sort_arg)
+                .arg(&encrypted_arg)
+                .arg(&skip_lost_found_arg)
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("zip")
+                .about("write a directory, and everything in it, to a zip file")
+                .arg(
+                    Arg::with_name("store")
+                        .long("store")
+                        .help("don't compress the files"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("where to write the zip, instead of stdout"),
+                )
+                .arg(&sort_arg)
+                .arg(&encrypted_arg)
+                .arg(&skip_lost_found_arg)
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("timeline")
+                .about("print every file's times, as a body file for mactime")
+                .arg(
+                    Arg::with_name("deleted")
+                        .long("deleted")
+                        .help("also include deleted inodes which haven't been reused"),
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("check each inode's, and group's, checksums, in pieces if asked")
+                .arg(
+                    Arg::with_name("cursor")
+                        .long("cursor")
+                        .value_name("FILE")
+                        .help("carry on from where the last run, with this file, got to"),
+                )
+                .arg(
+                    Arg::with_name("seconds")
+                        .long("seconds")
+                        .value_name("N")
+                        .help("stop after this long, to carry on next time")
+                        .validator(|s| {
+                            s.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|e| format!("invalid number of seconds '{}': {}", s, e))
+                        }),
+                )
+                .arg(&paths_arg),
+        )
+        .subcommand(
+            SubCommand::with_name("resolve")
+                .about("follow every symbolic link in a path, and print where it ends up")
+                .arg(&paths_arg)
+                .arg(Arg::with_name("path").required(true)),
+        )
         .get_matches();
 
     match matches.subcommand() {
-        ("dump-ls", Some(matches)) => for_each_input(matches, Command::DumpLs),
+        ("block", Some(matches)) => for_each_input(
+            matches,
+            Command::Block {
+                first: matches.value_of("block").unwrap().parse::<u64>().unwrap(),
+                count: matches.value_of("count").unwrap().parse::<u64>().unwrap(),
+            },
+        ),
+        ("diff", Some(matches)) => {
+            let mut out = Output::new(if matches.is_present("json") {
+                Format::Json
+            } else {
+                Format::from_matches(matches)
+            });
+            diff(
+                matches.value_of("first").unwrap(),
+                matches.value_of("second").unwrap(),
+                Location::from_matches(matches),
+                matches.is_present("content"),
+                &mut out,
+            )?;
+            out.finish();
+            Ok(())
+        }
+        ("debugfs", Some(matches)) => {
+            let request = if let Some(line) = matches.value_of("request") {
+                DebugfsRequest::Request(line.to_string())
+            } else {
+                let path = matches.value_of("cmd_file").unwrap();
+                DebugfsRequest::Script(
+                    fs::read_to_string(path).with_context(|| anyhow!("reading {}", path))?,
+                )
+            };
+            for_each_input(matches, Command::Debugfs(request))
+        }
+        ("distro", Some(matches)) => for_each_input(matches, Command::Distro),
+        ("dump-groups", Some(matches)) => for_each_input(matches, Command::DumpGroups),
+        ("dump-ls", Some(matches)) => for_each_input(
+            matches,
+            Command::DumpLs {
+                names: matches.is_present("names"),
+                options: walk_options(matches),
+            },
+        ),
+        ("dump-superblock", Some(matches)) => for_each_input(
+            matches,
+            Command::DumpSuperblock {
+                compat: matches.is_present("compat"),
+            },
+        ),
+        ("fsck", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            let mut out = Output::new(if matches.is_present("json") {
+                Format::Json
+            } else {
+                Format::from_matches(matches)
+            });
+            let damaged = match fsck(file, Location::from_matches(matches), &mut out) {
+                Ok(damaged) => damaged,
+                Err(e) => {
+                    eprintln!(
+                        "Error: {:#}",
+                        e.context(anyhow!("while checking '{}'", file))
+                    );
+                    std::process::exit(8);
+                }
+            };
+            out.finish();
+            if damaged {
+                std::process::exit(4);
+            }
+            Ok(())
+        }
+        ("grep", Some(matches)) => for_each_input(
+            matches,
+            Command::Grep {
+                pattern: matches.value_of("pattern").unwrap().to_string(),
+                path: matches.value_of("path").unwrap().to_string(),
+                binary_skip: matches.is_present("binary-skip"),
+            },
+        ),
+        ("hash", Some(matches)) => for_each_input(
+            matches,
+            Command::Hash {
+                path: matches.value_of("path").unwrap().to_string(),
+                algo: match matches.value_of("algo").unwrap() {
+                    "sha256" => HashAlgo::Sha256,
+                    "blake3" => HashAlgo::Blake3,
+                    _ => unreachable!(),
+                },
+            },
+        ),
         ("head-all", Some(matches)) => for_each_input(
             matches,
             Command::HeadAll {
                 bytes: matches.value_of("bytes").unwrap().parse::<usize>().unwrap(),
             },
         ),
+        ("icat", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            icat(
+                open_single(file, Location::from_matches(matches))?,
+                matches.value_of("inode").unwrap().parse::<u32>().unwrap(),
+            )
+        }
+        ("journal", Some(matches)) => for_each_input(
+            matches,
+            Command::Journal(if let Some(path) = matches.value_of("replay-to") {
+                JournalAction::ReplayTo(path.to_string())
+            } else if let Some(block) = matches.value_of("extract-block") {
+                JournalAction::ExtractBlock(block.parse::<u64>().unwrap())
+            } else {
+                JournalAction::List
+            }),
+        ),
+        ("shell", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            shell::run(
+                open_single(file, Location::from_matches(matches))?,
+                matches.is_present("names"),
+            )
+        }
+        ("readlink", Some(matches)) => for_each_input(
+            matches,
+            Command::ReadLink {
+                path: matches.value_of("path").unwrap().to_string(),
+            },
+        ),
+        ("recover", Some(matches)) => for_each_input(
+            matches,
+            Command::Recover(match matches.value_of("inode") {
+                Some(inode) => RecoverAction::Extract {
+                    inode: inode.parse::<u32>().unwrap(),
+                    out: matches.value_of("out").unwrap().to_string(),
+                },
+                None => RecoverAction::List,
+            }),
+        ),
+        ("scan", Some(matches)) => {
+            let mut out = Output::new(Format::from_matches(matches));
+            scan(matches.value_of("file").unwrap(), &mut out)?;
+            out.finish();
+            Ok(())
+        }
+        ("resolve", Some(matches)) => for_each_input(
+            matches,
+            Command::Resolve {
+                path: matches.value_of("path").unwrap().to_string(),
+            },
+        ),
+        ("timeline", Some(matches)) => for_each_input(
+            matches,
+            Command::Timeline {
+                deleted: matches.is_present("deleted"),
+            },
+        ),
+        ("verify", Some(matches)) => for_each_input(
+            matches,
+            Command::Verify {
+                cursor: matches.value_of("cursor").map(|s| s.to_string()),
+                seconds: matches.value_of("seconds").map(|s| s.parse().unwrap()),
+            },
+        ),
+        ("tail", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            tail(
+                open_single(file, Location::from_matches(matches))?,
+                matches.value_of("path").unwrap(),
+                match matches.value_of("bytes") {
+                    Some(bytes) => TailAmount::Bytes(bytes.parse::<u64>().unwrap()),
+                    None => TailAmount::Lines(matches.value_of("lines").unwrap().parse().unwrap()),
+                },
+            )
+        }
+        ("oci-layer", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            oci_layer(
+                open_single(file, Location::from_matches(matches))?,
+                matches.value_of("path").unwrap(),
+                &walk_options(matches),
+                matches.value_of("out"),
+            )
+        }
+        ("tar", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            tar(
+                open_single(file, Location::from_matches(matches))?,
+                matches.value_of("path").unwrap(),
+                &walk_options(matches),
+                matches.value_of("out"),
+            )
+        }
+        ("zip", Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            zip(
+                open_single(file, Location::from_matches(matches))?,
+                matches.value_of("path").unwrap(),
+                if matches.is_present("store") {
+                    ext4::ZipMethod::Store
+                } else {
+                    ext4::ZipMethod::Deflate
+                },
+                &walk_options(matches),
+                matches.value_of("out"),
+            )
+        }
         (_, _) => unreachable!(),
     }
 }
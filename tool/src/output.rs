@@ -0,0 +1,59 @@
+use anyhow::Error;
+use serde::Serialize;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    /// A single array of every record.
+    Json,
+    /// One record per line.
+    Ndjson,
+}
+
+impl Format {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Format {
+        match matches.value_of("format") {
+            Some("json") => Format::Json,
+            Some("ndjson") => Format::Ndjson,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// Prints each record as text, or as json, depending on the requested format.
+/// A json array is kept open until `finish` is called, so it can span multiple partitions.
+pub struct Output {
+    pub format: Format,
+    count: usize,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+        Output { format, count: 0 }
+    }
+
+    /// `text` is only called when the format is `Text`, and should do the printing itself.
+    pub fn record<T, F>(&mut self, value: &T, text: F) -> Result<(), Error>
+    where
+        T: Serialize,
+        F: FnOnce() -> Result<(), Error>,
+    {
+        match self.format {
+            Format::Text => text()?,
+            Format::Json => print!(
+                "{}{}",
+                if 0 == self.count { "[\n  " } else { ",\n  " },
+                serde_json::to_string(value)?
+            ),
+            Format::Ndjson => println!("{}", serde_json::to_string(value)?),
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) {
+        if Format::Json == self.format {
+            println!("{}", if 0 == self.count { "[]" } else { "\n]" });
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::cell::Cell;
+
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+
+/// A spinner on stderr, counting entries and bytes, and showing the current path.
+/// It isn't drawn at all if stderr isn't a terminal.
+pub struct Bar {
+    bar: ProgressBar,
+    entries: Cell<u64>,
+    bytes: Cell<u64>,
+    path: Cell<Option<String>>,
+}
+
+impl Bar {
+    pub fn new() -> Bar {
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(
+            ProgressStyle::with_template("{spinner} [{elapsed}] {msg}").expect("static template"),
+        );
+        bar.enable_steady_tick(std::time::Duration::from_millis(100));
+        Bar {
+            bar,
+            entries: Cell::new(0),
+            bytes: Cell::new(0),
+            path: Cell::new(None),
+        }
+    }
+
+    fn update(&self) {
+        let path = self.path.take();
+        self.bar.set_message(format!(
+            "{} entries, {} read: {}",
+            self.entries.get(),
+            HumanBytes(self.bytes.get()),
+            path.as_deref().unwrap_or("")
+        ));
+        self.path.set(path);
+    }
+
+    /// Hide the bar while printing, so the output doesn't get mixed up with it.
+    pub fn suspend<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        self.bar.suspend(f)
+    }
+}
+
+impl ext4::Progress for Bar {
+    fn entry(&self, path: &str) {
+        self.entries.set(self.entries.get() + 1);
+        self.path
+            .set(Some(if path.is_empty() { "/" } else { path }.to_string()));
+        self.update();
+    }
+
+    fn read(&self, bytes: u64) {
+        self.bytes.set(self.bytes.get() + bytes);
+        self.update();
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
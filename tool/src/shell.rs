@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+use anyhow::Context;
+use anyhow::Error;
+use ext4::{Enhanced, Inode, ReadAt, SuperBlock};
+
+const HELP: &str = "\
+cd [PATH]              change the current directory
+pwd                    print the current directory
+ls [-l] [PATH]         list a directory
+stat PATH              show an inode's metadata
+cat PATH               print a file
+get PATH [DEST]        copy a file out of the image
+icheck BLOCK...        find the inode whose data is in each block
+ncheck INODE...        find the paths of each inode
+help                   this
+quit                   leave
+
+PATHs are relative to the current directory, or can be an inode number, like <12>.";
+
+struct Shell<R> {
+    fs: SuperBlock<R>,
+    cwd: String,
+    /// Show owners by name, not number.
+    names: bool,
+}
+
+/// Read commands from stdin until it runs out, or the user quits.
+pub fn run<R>(fs: SuperBlock<R>, names: bool) -> Result<(), Error>
+where
+    R: ReadAt,
+{
+    let mut shell = Shell {
+        fs,
+        cwd: "/".to_string(),
+        names,
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("ext4:{}> ", shell.cwd);
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                println!();
+                return Ok(());
+            }
+        };
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        if "quit" == command || "exit" == command {
+            return Ok(());
+        }
+
+        if let Err(e) = shell.exec(command, args) {
+            eprintln!("{}: {:#}", command, e);
+        }
+    }
+}
+
+impl<R> Shell<R>
+where
+    R: ReadAt,
+{
+    fn exec(&mut self, command: &str, args: &[&str]) -> Result<(), Error> {
+        match command {
+            "cd" => self.cd(args.first().copied().unwrap_or("/")),
+            "pwd" => {
+                println!("{}", self.cwd);
+                Ok(())
+            }
+            "ls" => match args {
+                ["-l"] => self.ls(".", true),
+                ["-l", path] => self.ls(path, true),
+                [] => self.ls(".", false),
+                [path] => self.ls(path, false),
+                _ => bail!("usage: ls [-l] [PATH]"),
+            },
+            "stat" => match args {
+                [path] => self.stat(path),
+                _ => bail!("usage: stat PATH"),
+            },
+            "cat" => match args {
+                [path] => {
+                    let inode = self.inode(path, true)?;
+                    io::copy(&mut self.fs.open(&inode)?, &mut io::stdout().lock())?;
+                    Ok(())
+                }
+                _ => bail!("usage: cat PATH"),
+            },
+            "get" => match args {
+                [path] => self.get(path, path.rsplit('/').next().expect("always one")),
+                [path, dest] => self.get(path, dest),
+                _ => bail!("usage: get PATH [DEST]"),
+            },
+            "icheck" => self.icheck(args),
+            "ncheck" => self.ncheck(args),
+            "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => bail!("unknown command; try 'help'"),
+        }
+    }
+
+    /// Make a path absolute, relative to the current directory.
+    fn absolute(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.cwd.trim_end_matches('/'), path)
+        }
+    }
+
+    /// Find the inode a path refers to; following it if it's a link, if requested.
+    fn inode(&self, path: &str, follow: bool) -> Result<Inode, Error> {
+        if let Some(number) = path.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+            return self.fs.load_inode(number.parse()?);
+        }
+
+        let options = ext4::ResolveOptions {
+            follow_final: follow,
+            ..Default::default()
+        };
+        let (_, entry) = self
+            .fs
+            .resolve_with_options(&self.absolute(path), &options)?;
+        self.fs.load_inode(entry.inode)
+    }
+
+    /// The user and group, by name if that's been asked for, and they've got one.
+    fn owners(&self, inode: &Inode) -> Result<(String, String), Error> {
+        let (uid, gid) = (inode.stat.uid, inode.stat.gid);
+        if !self.names {
+            return Ok((uid.to_string(), gid.to_string()));
+        }
+        Ok((self.fs.display_uid(uid)?, self.fs.display_gid(gid)?))
+    }
+
+    fn cd(&mut self, path: &str) -> Result<(), Error> {
+        let (canonical, entry) = self.fs.canonicalize(&self.absolute(path))?;
+        ensure!(
+            ext4::FileType::Directory == entry.file_type,
+            "{} is not a directory",
+            canonical
+        );
+        self.cwd = canonical;
+        Ok(())
+    }
+
+    fn ls(&self, path: &str, long: bool) -> Result<(), Error> {
+        let mut entries = match self.fs.enhance(&self.inode(path, true)?)? {
+            Enhanced::Directory(entries) => entries,
+            _ => bail!("{} is not a directory", path),
+        };
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if !long {
+            for entry in entries {
+                println!("{}", entry.name);
+            }
+            return Ok(());
+        }
+
+        let inodes = self
+            .fs
+            .load_inodes(&entries.iter().map(|entry| entry.inode).collect::<Vec<_>>());
+        for (entry, inode) in entries.into_iter().zip(inodes) {
+            let inode = inode?;
+            let (user, group) = self.owners(&inode)?;
+            println!(
+                "{:>8} {:06o} {:>5} {:>5} {:>10} {}",
+                inode.number, inode.stat.file_mode, user, group, inode.stat.size, entry.name
+            );
+        }
+        Ok(())
+    }
+
+    fn stat(&self, path: &str) -> Result<(), Error> {
+        let inode = self.inode(path, false)?;
+        let stat = &inode.stat;
+        let (user, group) = self.owners(&inode)?;
+        println!("Inode: {}   Type: {:?}", inode.number, stat.extracted_type);
+        println!(
+            "Mode: {:04o}   Links: {}   User: {}   Group: {}",
+            stat.file_mode & 0o7777,
+            stat.link_count,
+            user,
+            group
+        );
+        println!("Size: {}", stat.size);
+        println!("atime: {}", format_time(&stat.atime));
+        println!("ctime: {}", format_time(&stat.ctime));
+        println!("mtime: {}", format_time(&stat.mtime));
+        if let Some(ref btime) = stat.btime {
+            println!("btime: {}", format_time(btime));
+        }
+
+        let mut xattrs = stat.xattrs.keys().collect::<Vec<_>>();
+        xattrs.sort();
+        for name in xattrs {
+            println!("xattr: {} ({} bytes)", name, stat.xattrs[name].len());
+        }
+
+        match self.fs.enhance(&inode)? {
+            Enhanced::SymbolicLink(target) => println!("Target: {}", target.display()),
+            Enhanced::CharacterDevice(major, minor) | Enhanced::BlockDevice(major, minor) => {
+                println!("Device: {}, {}", major, minor)
+            }
+            Enhanced::RegularFile | Enhanced::Directory(_) => {
+                for extent in self.fs.data_extents(&inode)? {
+                    println!(
+                        "Extent: ({}-{}): {}-{}",
+                        extent.logical,
+                        extent.logical + u32::from(extent.len) - 1,
+                        extent.physical,
+                        extent.physical + u64::from(extent.len) - 1
+                    );
+                }
+            }
+            Enhanced::Fifo | Enhanced::Socket => (),
+        }
+        Ok(())
+    }
+
+    fn get(&self, path: &str, dest: &str) -> Result<(), Error> {
+        let inode = self.inode(path, true)?;
+        let mut out = fs::File::create(dest).with_context(|| anyhow!("creating {}", dest))?;
+        io::copy(&mut self.fs.open(&inode)?, &mut out)?;
+        Ok(())
+    }
+
+    fn icheck(&self, args: &[&str]) -> Result<(), Error> {
+        ensure!(!args.is_empty(), "usage: icheck BLOCK...");
+        let blocks = args
+            .iter()
+            .map(|block| block.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut owners = HashMap::new();
+        let root = self.fs.root()?;
+        self.fs.walk(&root, "", &mut |fs, _, inode, _| {
+            // devices, short links, and anything else we can't map can't own blocks
+            for extent in fs.data_extents(inode).unwrap_or_default() {
+                let end = extent.physical + u64::from(extent.len);
+                for block in &blocks {
+                    if *block >= extent.physical && *block < end {
+                        owners.insert(*block, inode.number);
+                    }
+                }
+            }
+            Ok(true)
+        })?;
+
+        println!("Block\tInode number");
+        for block in blocks {
+            match owners.get(&block) {
+                Some(inode) => println!("{}\t{}", block, inode),
+                None => println!("{}\t<block not found>", block),
+            }
+        }
+        Ok(())
+    }
+
+    fn ncheck(&self, args: &[&str]) -> Result<(), Error> {
+        ensure!(!args.is_empty(), "usage: ncheck INODE...");
+        let inodes = args
+            .iter()
+            .map(|inode| inode.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        println!("Inode\tPathname");
+        let root = self.fs.root()?;
+        self.fs.walk(&root, "", &mut |_, path, inode, _| {
+            if inodes.contains(&inode.number) {
+                println!(
+                    "{}\t{}",
+                    inode.number,
+                    if path.is_empty() { "/" } else { path }
+                );
+            }
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+fn format_time(time: &ext4::Time) -> String {
+    match time.nanos {
+        Some(nanos) => format!("{}.{:09}", time.epoch_secs, nanos),
+        None => time.epoch_secs.to_string(),
+    }
+}